@@ -0,0 +1,182 @@
+//! Trees over the roots of other trees
+//! The leaves of an aggregate tree are the roots of datasets, so its root, the super-root, commits
+//! to all of them at once and can be published in their place, for example once a day for all
+//! uploads of the day. A file is then proven in two steps: in its dataset by the dataset's own
+//! proof, and the dataset in the aggregate by a proof of the aggregate tree.
+//!
+//! A dataset root is hashed as the contents of a leaf, with the leaf prefix, so the inner nodes of
+//! the aggregate tree cannot be passed off as dataset roots.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, decode_hash, Hash};
+use crate::merkle_tree::{MerkleProof, MerkleTree};
+use merkleproofs_verify::leaf_digest;
+
+#[derive(Debug)]
+pub struct AggregateTree {
+    /// Roots of the datasets, by index
+    roots: Vec<Hash>,
+    tree: MerkleTree,
+}
+
+/// Proof that a file is in a dataset and that the dataset is in an aggregate tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AggregateProof {
+    pub dataset_root: String,
+    /// Proof of the file in its dataset
+    pub file: MerkleProof,
+    /// Proof of the dataset root in the aggregate tree
+    pub dataset: MerkleProof,
+}
+
+/// Hex-encoded leaf hash of a dataset root in an aggregate tree
+fn root_leaf_hash(root: &Hash) -> String {
+    hex::encode(leaf_digest(root))
+}
+
+impl AggregateTree {
+    /// Builds the tree over the hex-encoded roots of the datasets, in order
+    pub fn build(roots: &[String]) -> Result<Self, MerkleError> {
+        let roots = roots
+            .iter()
+            .map(|root| decode_hash(root).ok_or_else(|| MerkleError::InvalidHash(root.clone())))
+            .collect::<Result<Vec<Hash>, _>>()?;
+        let tree = MerkleTree::from_leaves(roots.iter().map(|root| leaf_digest(root)).collect());
+        Ok(Self { roots, tree })
+    }
+
+    /// The super-root over all datasets
+    pub fn root(&self) -> String {
+        self.tree.root().expect("Aggregate trees are built")
+    }
+
+    pub fn dataset_count(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Index of the dataset with the root
+    pub fn find_dataset(&self, root: &str) -> Option<usize> {
+        let root = decode_hash(root)?;
+        self.roots.iter().position(|known| *known == root)
+    }
+
+    /// Proof of the root of the dataset at the index in the aggregate tree
+    pub fn dataset_proof(&self, index: usize) -> Result<MerkleProof, MerkleError> {
+        self.tree.get_merkle_proof(index)
+    }
+
+    /// Combines the proof of a file in the dataset at the index with the proof of the dataset
+    /// Fails if the file proof does not lead to the dataset's root for the contents.
+    pub fn prove(
+        &self,
+        index: usize,
+        content: &str,
+        file: MerkleProof,
+    ) -> Result<AggregateProof, MerkleError> {
+        let dataset = self.dataset_proof(index)?;
+        let dataset_root = hex::encode(self.roots[index]);
+        if !file.verify(content, &dataset_root) {
+            return Err(MerkleError::RootMismatch);
+        }
+        Ok(AggregateProof {
+            dataset_root,
+            file,
+            dataset,
+        })
+    }
+}
+
+impl AggregateProof {
+    /// Checks that the contents are in the dataset, and the dataset in the tree with the
+    /// super-root
+    pub fn verify(&self, content: &str, super_root: &str) -> bool {
+        let Some(dataset_root) = decode_hash(&self.dataset_root) else {
+            return false;
+        };
+        self.file.verify(content, &self.dataset_root)
+            && self.dataset.is_well_formed()
+            && ct_eq(
+                &self
+                    .dataset
+                    .root_from_leaf_hash(&root_leaf_hash(&dataset_root)),
+                super_root,
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn dataset(prefix: &str, count: usize) -> (Vec<String>, MerkleTree) {
+        let files: Vec<String> = (0..count).map(|i| format!("{}{}", prefix, i)).collect();
+        let mut tree = MerkleTree::new();
+        tree.build(&files);
+        (files, tree)
+    }
+
+    #[test]
+    fn files_are_proven_against_the_super_root() {
+        let datasets: Vec<_> = [("a", 3), ("b", 1), ("c", 6)]
+            .iter()
+            .map(|&(prefix, count)| dataset(prefix, count))
+            .collect();
+        let roots: Vec<String> = datasets
+            .iter()
+            .map(|(_, tree)| tree.root().unwrap())
+            .collect();
+        let aggregate = AggregateTree::build(&roots).unwrap();
+        let super_root = aggregate.root();
+        assert_eq!(aggregate.dataset_count(), 3);
+        assert_eq!(aggregate.find_dataset(&roots[2]), Some(2));
+
+        for (index, (files, tree)) in datasets.iter().enumerate() {
+            for (position, file) in files.iter().enumerate() {
+                let proof = aggregate
+                    .prove(index, file, tree.get_merkle_proof(position).unwrap())
+                    .unwrap();
+                assert!(proof.verify(file, &super_root));
+                assert!(!proof.verify("other", &super_root));
+                assert!(!proof.verify(file, &roots[index]));
+            }
+        }
+
+        // A file proof of another dataset is rejected
+        let other = datasets[0].1.get_merkle_proof(0).unwrap();
+        assert_eq!(
+            aggregate.prove(2, "a0", other),
+            Err(MerkleError::RootMismatch)
+        );
+        assert!(AggregateTree::build(&["not hex".to_string()]).is_err());
+    }
+
+    #[test]
+    fn inner_nodes_are_not_dataset_roots() {
+        let roots: Vec<String> = (0..4)
+            .map(|i| dataset(&i.to_string(), 2).1.root().unwrap())
+            .collect();
+        let aggregate = AggregateTree::build(&roots).unwrap();
+
+        // The node over the first two datasets, claimed as a dataset of a two-leaf tree
+        let (files, tree) = dataset("0", 2);
+        let inner = MerkleTree::from_leaves(
+            roots[..2]
+                .iter()
+                .map(|root| leaf_digest(&decode_hash(root).unwrap()))
+                .collect(),
+        );
+        let forged = AggregateProof {
+            dataset_root: inner.root().unwrap(),
+            file: tree.get_merkle_proof(0).unwrap(),
+            dataset: MerkleProof {
+                leaf_index: 0,
+                leaf_count: 2,
+                nodes: aggregate.dataset_proof(0).unwrap().nodes[1..].to_vec(),
+            },
+        };
+        assert!(!forged.verify(&files[0], &aggregate.root()));
+    }
+}
@@ -0,0 +1,203 @@
+//! Airdrop and allowlist proof sets
+//! Builds a tree over `(address, amount)` or arbitrary key/value entries and exports the proof of
+//! every entry keyed by its address or key. Leaves are the hex-encoded ABI encoding of the entry,
+//! i.e. `abi.encode(address, uint256)` or `abi.encode(string, string)`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::merkle_tree::{empty_root, MerkleProof, MerkleTree};
+
+/// A single entry of the proof set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claim {
+    pub index: usize,
+    pub value: String,
+    /// The hex-encoded ABI encoding of the entry, which is the leaf of the tree
+    pub leaf: String,
+    pub proof: MerkleProof,
+}
+
+/// The root and all claims, keyed by address or key
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProofSet {
+    pub root: String,
+    pub claims: BTreeMap<String, Claim>,
+}
+
+/// Builds a proof set over `(address, amount)` entries
+/// Addresses are normalized to lowercase and amounts are decimal `uint256` values that fit in a `u128`
+pub fn build_address_amount(
+    entries: &[(String, String)],
+) -> Result<ProofSet, Box<dyn std::error::Error>> {
+    let mut keyed = Vec::new();
+    for (address, amount) in entries {
+        let address = normalize_address(address)?;
+        let amount_value: u128 = amount
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid amount for {}: {}", address, amount))?;
+        let leaf = encode_address_amount(&address, amount_value)?;
+        keyed.push((address, amount_value.to_string(), leaf));
+    }
+    build_proof_set(keyed)
+}
+
+/// Builds a proof set over arbitrary key/value entries
+pub fn build_key_value(
+    entries: &[(String, String)],
+) -> Result<ProofSet, Box<dyn std::error::Error>> {
+    let keyed = entries
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone(), encode_key_value(key, value)))
+        .collect();
+    build_proof_set(keyed)
+}
+
+fn build_proof_set(
+    entries: Vec<(String, String, String)>,
+) -> Result<ProofSet, Box<dyn std::error::Error>> {
+    let leaves: Vec<String> = entries.iter().map(|(_, _, leaf)| leaf.clone()).collect();
+    let mut tree = MerkleTree::new();
+    tree.build(&leaves);
+
+    let mut claims = BTreeMap::new();
+    for (index, (key, value, leaf)) in entries.into_iter().enumerate() {
+        let proof = tree.get_merkle_proof(index)?;
+        let claim = Claim {
+            index,
+            value,
+            leaf,
+            proof,
+        };
+        if claims.insert(key.clone(), claim).is_some() {
+            return Err(format!("Duplicate entry: {}", key).into());
+        }
+    }
+
+    Ok(ProofSet {
+        root: tree.root().unwrap_or_else(empty_root),
+        claims,
+    })
+}
+
+/// Checks an address and converts it to lowercase with a `0x` prefix
+fn normalize_address(address: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let trimmed = address.trim();
+    let hex_address = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or(trimmed);
+    if hex_address.len() != 40 || hex::decode(hex_address).is_err() {
+        return Err(format!("Invalid address: {}", address).into());
+    }
+    Ok(format!("0x{}", hex_address.to_lowercase()))
+}
+
+/// `abi.encode(address, uint256)`, hex-encoded
+fn encode_address_amount(
+    address: &str,
+    amount: u128,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let address_bytes = hex::decode(address.trim_start_matches("0x"))?;
+    let mut encoded = vec![0u8; 64];
+    encoded[12..32].copy_from_slice(&address_bytes);
+    encoded[48..64].copy_from_slice(&amount.to_be_bytes());
+    Ok(hex::encode(encoded))
+}
+
+/// `abi.encode(string, string)`, hex-encoded
+fn encode_key_value(key: &str, value: &str) -> String {
+    let key_tail = encode_dynamic_bytes(key.as_bytes());
+    let mut encoded = Vec::new();
+    encoded.extend(encode_word(64)); // Offset of the key
+    encoded.extend(encode_word(64 + key_tail.len() as u128)); // Offset of the value
+    encoded.extend(key_tail);
+    encoded.extend(encode_dynamic_bytes(value.as_bytes()));
+    hex::encode(encoded)
+}
+
+/// Length word followed by the bytes, right-padded to a multiple of 32
+fn encode_dynamic_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_word(bytes.len() as u128).to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded.resize(32 + bytes.len().div_ceil(32) * 32, 0);
+    encoded
+}
+
+fn encode_word(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::root_from_proof;
+
+    const ADDRESS_1: &str = "0x00000000000000000000000000000000000000A1";
+    const ADDRESS_2: &str = "0x00000000000000000000000000000000000000b2";
+
+    #[test]
+    fn address_amount_encoding() {
+        let encoded = encode_address_amount(&normalize_address(ADDRESS_1).unwrap(), 1000).unwrap();
+        assert_eq!(
+            encoded,
+            format!(
+                "{}{}{:064x}",
+                "0".repeat(24),
+                &ADDRESS_1[2..].to_lowercase(),
+                1000
+            )
+        );
+    }
+
+    #[test]
+    fn key_value_encoding() {
+        let encoded = encode_key_value("a", "bc");
+        let expected = [
+            format!("{:064x}", 64),
+            format!("{:064x}", 128),
+            format!("{:064x}", 1),
+            format!("{:0<64}", "61"),
+            format!("{:064x}", 2),
+            format!("{:0<64}", "6263"),
+        ]
+        .concat();
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn address_amount_proofs_verify() {
+        let entries = vec![
+            (ADDRESS_1.to_string(), "100".to_string()),
+            (ADDRESS_2.to_string(), "200".to_string()),
+        ];
+        let proof_set = build_address_amount(&entries).unwrap();
+
+        assert_eq!(proof_set.claims.len(), 2);
+        let claim = &proof_set.claims[&ADDRESS_1.to_lowercase()];
+        assert_eq!(claim.index, 0);
+        assert_eq!(claim.value, "100");
+        assert_eq!(root_from_proof(&claim.leaf, &claim.proof), proof_set.root);
+        let claim = &proof_set.claims[ADDRESS_2];
+        assert_eq!(root_from_proof(&claim.leaf, &claim.proof), proof_set.root);
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let bad_address = vec![("0x1234".to_string(), "1".to_string())];
+        assert!(build_address_amount(&bad_address).is_err());
+
+        let bad_amount = vec![(ADDRESS_1.to_string(), "-1".to_string())];
+        assert!(build_address_amount(&bad_amount).is_err());
+
+        let duplicate = vec![
+            ("key".to_string(), "1".to_string()),
+            ("key".to_string(), "2".to_string()),
+        ];
+        assert!(build_key_value(&duplicate).is_err());
+    }
+}
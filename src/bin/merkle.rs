@@ -0,0 +1,237 @@
+use clap::Arg;
+use clap::ArgAction;
+use clap::Command;
+use merkleproofs::airdrop;
+use merkleproofs::hashing::{ct_eq, file_digest, hash_file, HashAlgorithm, TreeHasher};
+use merkleproofs::merkle_tree::MerkleProof;
+use merkleproofs::multiformats;
+use merkleproofs::root_builder::RootBuilder;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Standalone tool for building trees and proofs over local directories
+/// Leaves are the file contents of a directory, in alphabetical order of file names,
+/// which matches the order the client uses when uploading "all" files.
+/// Example: cargo run --bin merkle -- root client_storage
+/// Example: cargo run --bin merkle -- proof client_storage file02.txt > proof.json
+/// Example: cargo run --bin merkle -- verify client_storage/file02.txt proof.json <root>
+/// Example: cargo run --bin merkle -- airdrop allowlist.csv > proofs.json
+/// Example: cargo run --features blake3 --bin merkle -- root client_storage --hash blake3
+/// Example: cargo run --bin merkle -- root client_storage --format cid
+fn main() {
+    let matches = Command::new("Merkle")
+        .version("1.0")
+        .about("Builds Merkle roots and proofs over local files")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("root")
+                .about("Prints the root hash of a directory")
+                .arg(Arg::new("dir").help("The directory").required(true))
+                .arg(hash_arg())
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("How to print the root: hex, multihash (base58btc) or cid (CIDv1)")
+                        .value_parser(["hex", "multihash", "cid"])
+                        .default_value("hex"),
+                ),
+        )
+        .subcommand(
+            Command::new("proof")
+                .about("Prints the Merkle proof of a file in a directory as JSON")
+                .arg(Arg::new("dir").help("The directory").required(true))
+                .arg(
+                    Arg::new("file")
+                        .help("The name of the file within the directory")
+                        .required(true),
+                )
+                .arg(hash_arg()),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Verifies a file against a proof and a root hash")
+                .arg(Arg::new("file").help("The file to verify").required(true))
+                .arg(
+                    Arg::new("proof")
+                        .help("Path to a JSON proof produced by the proof command")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("root")
+                        .help("The expected root hash")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("airdrop")
+                .about("Builds an airdrop/allowlist tree and prints all proofs as JSON, keyed by address")
+                .arg(
+                    Arg::new("entries")
+                        .help("CSV file with one 'address,amount' entry per line")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("key_value")
+                        .long("key-value")
+                        .help("Treat the entries as arbitrary 'key,value' pairs")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("root", sub_m)) => {
+            let dir = sub_m.get_one::<String>("dir").unwrap();
+            let algorithm = hash_algorithm(sub_m);
+            let (root, _) = directory_root(Path::new(dir), algorithm, None);
+            let formatted = match sub_m.get_one::<String>("format").unwrap().as_str() {
+                "multihash" => multiformats::multihash_string(algorithm, &root),
+                "cid" => multiformats::cid_v1(algorithm, multiformats::RAW_CODEC, &root),
+                _ => Some(root),
+            };
+            let Some(formatted) = formatted else {
+                eprintln!("{} hashes have no multihash code", algorithm.name());
+                process::exit(1);
+            };
+            println!("{}", formatted);
+        }
+        Some(("proof", sub_m)) => {
+            let dir = sub_m.get_one::<String>("dir").unwrap();
+            let file = sub_m.get_one::<String>("file").unwrap();
+            let algorithm = hash_algorithm(sub_m);
+            let names: Vec<String> = directory_files(Path::new(dir))
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            let Some(index) = names.iter().position(|name| name == file) else {
+                eprintln!("File '{}' not found in {}", file, dir);
+                process::exit(1);
+            };
+            let (_, proof) = directory_root(Path::new(dir), algorithm, Some(index));
+            let proof = ProofFile {
+                algorithm,
+                proof: proof.expect("Failed to generate proof"),
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&proof).expect("Failed to serialize proof")
+            );
+        }
+        Some(("verify", sub_m)) => {
+            let file = sub_m.get_one::<String>("file").unwrap();
+            let proof_path = sub_m.get_one::<String>("proof").unwrap();
+            let root = sub_m.get_one::<String>("root").unwrap();
+
+            let proof_data = fs::read_to_string(proof_path).expect("Unable to read proof");
+            let ProofFile { algorithm, proof } = serde_json::from_str(&proof_data)
+                .or_else(|_| {
+                    serde_json::from_str(&proof_data).map(|proof| ProofFile {
+                        algorithm: HashAlgorithm::default(),
+                        proof,
+                    })
+                })
+                .expect("Invalid proof format");
+            let leaf_hash = hash_file(file, algorithm).expect("Unable to read file");
+
+            let calculated = algorithm.root_from_leaf_hash(&leaf_hash, &proof);
+            if proof.is_well_formed() && ct_eq(&calculated, root) {
+                println!("File '{}' is verified and correct.", file);
+            } else {
+                println!("File '{}' verification failed.", file);
+                println!("Calculated hash: {}", calculated);
+                println!("Expected root hash: {}", root);
+                process::exit(1);
+            }
+        }
+        Some(("airdrop", sub_m)) => {
+            let entries_path = sub_m.get_one::<String>("entries").unwrap();
+            let data = fs::read_to_string(entries_path).expect("Unable to read entries");
+            let entries: Vec<(String, String)> = data
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| match line.split_once(',') {
+                    Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+                    None => {
+                        eprintln!("Invalid entry: {}", line);
+                        process::exit(1);
+                    }
+                })
+                .collect();
+
+            let proof_set = if sub_m.get_flag("key_value") {
+                airdrop::build_key_value(&entries)
+            } else {
+                airdrop::build_address_amount(&entries)
+            };
+            match proof_set {
+                Ok(proof_set) => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&proof_set).expect("Failed to serialize proofs")
+                ),
+                Err(e) => {
+                    eprintln!("Failed to build proof set: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => unreachable!("A subcommand is required"),
+    }
+}
+
+/// A proof as the proof command prints it, with the hash algorithm of its tree
+/// A bare proof, as the server sends it with a file, is a SHA-256 proof.
+#[derive(Serialize, Deserialize)]
+struct ProofFile {
+    algorithm: HashAlgorithm,
+    proof: MerkleProof,
+}
+
+fn hash_arg() -> Arg {
+    Arg::new("hash")
+        .long("hash")
+        .help("The hash algorithm of the tree: sha256, or blake3 with the blake3 feature")
+        .default_value("sha256")
+}
+
+fn hash_algorithm(matches: &clap::ArgMatches) -> HashAlgorithm {
+    let name = matches.get_one::<String>("hash").unwrap();
+    name.parse().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+/// The files of a directory with their paths, sorted by name
+fn directory_files(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).expect("Failed to read directory") {
+        let path = entry.expect("Failed to read directory entry").path();
+        if path.is_file() {
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            files.push((name, path));
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+/// The root of the tree over all files of a directory, sorted by name, and the proof of the file
+/// at the index if one is given
+/// Files are hashed one at a time and only the right edge of the tree is kept, so directories of
+/// any size fit in memory.
+fn directory_root(
+    dir: &Path,
+    algorithm: HashAlgorithm,
+    proving: Option<usize>,
+) -> (String, Option<MerkleProof>) {
+    let mut builder = RootBuilder::with_algorithm(algorithm);
+    if let Some(index) = proving {
+        builder = builder.proving(index);
+    }
+    for (_, path) in directory_files(dir) {
+        builder.push_digest(file_digest(&path, algorithm).expect("Unable to read file"));
+    }
+    builder.finish()
+}
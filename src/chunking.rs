@@ -0,0 +1,57 @@
+//! Per-file chunk trees
+//! A file is split into fixed-size chunks and a tree is built over them, so single chunks can be
+//! proven against the file's chunk root. Leaves are the raw chunk bytes.
+
+use crate::merkle_tree::MerkleTree;
+
+/// Default size of a chunk in bytes
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Splits contents into chunks
+/// Empty contents consist of a single empty chunk, so every file has a chunk root
+pub fn split_chunks(content: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    if content.is_empty() {
+        return vec![content];
+    }
+    content.chunks(chunk_size.max(1)).collect()
+}
+
+/// Builds the chunk tree of the contents
+pub fn chunk_tree(content: &[u8], chunk_size: usize) -> MerkleTree {
+    let mut tree = MerkleTree::new();
+    tree.build(&split_chunks(content, chunk_size));
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{leaf_hash, root_from_proof};
+
+    #[test]
+    fn split_into_chunks() {
+        let chunks = split_chunks(b"abcde", 2);
+        assert_eq!(chunks, vec![&b"ab"[..], b"cd", b"e"]);
+        assert_eq!(split_chunks(b"", 2), vec![&b""[..]]);
+    }
+
+    #[test]
+    fn chunk_leaves_are_hashes_of_the_chunk_bytes() {
+        let tree = chunk_tree(&[0xff, 0x00, 0x10], 2);
+        assert_eq!(tree.get_leaf(0), Some(leaf_hash([0xff, 0x00])));
+        assert_eq!(tree.get_leaf(1), Some(leaf_hash([0x10])));
+    }
+
+    #[test]
+    fn chunk_proofs_verify_against_chunk_root() {
+        let content = b"This is a file that spans several chunks";
+        let tree = chunk_tree(content, 8);
+        let root = tree.root().unwrap();
+
+        for (index, chunk) in split_chunks(content, 8).iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            assert_eq!(root_from_proof(chunk, &proof), root);
+        }
+    }
+}
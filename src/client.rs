@@ -1,45 +1,178 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
 use clap::Arg;
 use clap::ArgAction;
+use clap::ArgMatches;
 use clap::Command;
-use merkleproofs::client_state::ClientState;
+use ed25519_dalek::SigningKey;
+use futures_util::stream::{self, StreamExt};
+use indicatif::ProgressBar;
+use merkleproofs::chunking::{self, DEFAULT_CHUNK_SIZE};
+use merkleproofs::client_config::{self, ClientConfig, Profile};
+use merkleproofs::client_state::{ChunkedFile, ClientState, PendingUpload, ShardSet, ShardedFile};
+use merkleproofs::encryption;
+use merkleproofs::erasure;
+use merkleproofs::error::{ClientError, MerkleError, ProtocolError, StorageError, EXIT_USAGE};
+use merkleproofs::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use merkleproofs::manifest::Manifest;
 use merkleproofs::merkle_tree::calculate_hash;
+use merkleproofs::merkle_tree::empty_root;
+use merkleproofs::merkle_tree::leaf_hash;
 use merkleproofs::merkle_tree::MerkleTree;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use merkleproofs::mirror::{self, MirrorConfig};
+use merkleproofs::protocol::{
+    check_leaf_order, ChangeKind, ChunkQuery, ChunkResponse, FileData, FileQuery, FileResponse,
+    LeafEntry, RootVersion, SampleQuery, StateBlob, UploadPart, UploadRequest,
+};
+use merkleproofs::server::{proof_routes, AppState, Server, ServerConfig};
+use merkleproofs::sorted_tree::SortedMerkleTree;
+use merkleproofs::storage;
+use merkleproofs::transport::{
+    self, CertFingerprint, ClientIdentity, ConnectOptions, TlsOptions, Transport, TransportError,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::Path;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// The directory where the client state and uploaded files are stored  
+#[path = "client/progress.rs"]
+mod progress;
+#[cfg(feature = "tui")]
+#[path = "client/tui.rs"]
+mod tui;
+
+/// The directory where the client state and uploaded files are stored, unless the profile names another
 const STORAGE_DIR: &str = "client_storage";
 /// The file where the client state is stored
 const STATE_STORAGE: &str = "state.json";
-
-#[derive(Serialize, Deserialize)]
-struct UploadRequest {
-    root_hash: String,
-    files: Vec<FileData>,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct FileData {
-    name: String,
-    content: String,
-}
+/// The directory next to the client state where the trees of the datasets are cached
+const TREE_CACHE: &str = "tree_cache";
+/// Timeout, retries and certificates of requests to servers, set once from the command line
+static CONNECT_OPTIONS: OnceLock<ConnectOptions> = OnceLock::new();
+/// The configuration profile selected on the command line
+static PROFILE: OnceLock<Profile> = OnceLock::new();
+/// The named dataset selected on the command line; the default dataset if not set
+static DATASET: OnceLock<String> = OnceLock::new();
 
 /// Main function that sets up the client
 /// Example: cargo run --bin client -- upload http://127.0.0.1:8000 file1.txt file2.txt
 /// Example: cargo run --bin client -- upload http://127.0.0.1:8000 all
+/// Example: cargo run --bin client -- upload --encrypt http://127.0.0.1:8000 all
+/// Example: cargo run --bin client -- upload --keep http://127.0.0.1:8000 all
 /// Example: cargo run --bin client -- verify http://127.0.0.1:8000 1
+/// Example: cargo run --bin client -- verify --snapshot before-migration http://127.0.0.1:8000 1
+/// Example: cargo run --bin client -- verify --version 0 http://127.0.0.1:8000 1
+/// Example: cargo run --bin client -- verify_all --jobs 32 http://127.0.0.1:8000
+/// Example: cargo run --bin client -- verify_all --timeout 60 --retries 5 http://127.0.0.1:8000
+/// Example: cargo run --bin client -- --profile prod verify 1
+/// Example: cargo run --bin client -- verify --ca-cert ca.pem --client-cert client.pem --client-key client.key https://proofs.internal 1
+/// Example: cargo run --bin client -- snapshot before-migration
+/// Example: cargo run --bin client -- upload --dataset photos http://127.0.0.1:8001 all
+/// Example: cargo run --bin client -- verify --dataset photos http://127.0.0.1:8001 0
+/// Example: cargo run --bin client -- download http://127.0.0.1:8000 1
+/// Example: cargo run --bin client -- prove_absent http://127.0.0.1:8000 some/other/file.txt
+/// Example: cargo run --bin client -- sample --count 32 http://127.0.0.1:8000
+/// Example: cargo run --bin client -- diff http://127.0.0.1:8000 before-migration
+/// Example: cargo run --bin client -- audit_log http://127.0.0.1:8000
 /// Example: cargo run --bin client -- delete_all http://127.0.0.1:8000
+/// Example: cargo run --bin client -- upload_sharded --servers http://127.0.0.1:8000,http://127.0.0.1:8001,http://127.0.0.1:8002 --data-shards 2 all
+/// Example: cargo run --bin client -- reconstruct 1
+/// Example: cargo run --bin client -- share 8100 all
+/// Example: cargo run --bin client -- serve --dir ./my_files --port 8000
+/// Example: cargo run --bin client -- serve --dir ./mirror --port 8001 --mirror-of http://127.0.0.1:8000
+/// Example: cargo run --bin client -- serve --dir ./my_files --import /srv/archive --import-root /srv
+/// Example: cargo run --bin client -- verify local:./my_files 1
+/// Example: MERKLE_SYNC_PASSPHRASE=... cargo run --bin client -- sync_push http://127.0.0.1:8000
+/// Example: MERKLE_SYNC_PASSPHRASE=... cargo run --bin client -- sync_pull http://127.0.0.1:8000
+/// Example: cargo run --bin client -- manifest keygen signing.key
+/// Example: cargo run --bin client -- manifest export --format cbor --sign-key signing.key
+/// Example: cargo run --bin client -- manifest verify manifest.cbor
+/// Example: cargo run --bin client --features tui -- tui http://127.0.0.1:8000
 #[tokio::main]
 async fn main() {
-    let matches = Command::new("Merkle Client")
+    let command = Command::new("Merkle Client")
         .version("1.0")
         .about("Uploads files to a server or verifies a file")
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("The profile of the configuration file to use; 'default' if not given")
+                .global(true),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("The configuration file; ~/.config/merkleproofs/config.toml by default")
+                .global(true),
+        )
+        .arg(
+            Arg::new("dataset")
+                .long("dataset")
+                .help("The named dataset to work on; the default dataset if not given")
+                .global(true),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .help("Seconds each attempt of a request to the server may take; no limit by default")
+                .value_parser(clap::value_parser!(u64))
+                .global(true),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .help("How many times a request that failed transiently is sent again, with exponential backoff")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("2")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ca_cert")
+                .long("ca-cert")
+                .help("PEM certificate of a CA to trust in addition to the system's, for servers with a private CA")
+                .global(true),
+        )
+        .arg(
+            Arg::new("client_cert")
+                .long("client-cert")
+                .help("PEM certificate to present to servers that require mutual TLS; needs --client-key")
+                .global(true),
+        )
+        .arg(
+            Arg::new("client_key")
+                .long("client-key")
+                .help("PKCS#8 PEM private key of the client certificate")
+                .global(true),
+        )
+        .arg(
+            Arg::new("pin_cert")
+                .long("pin-cert")
+                .help("SHA-256 fingerprint of a server certificate to accept, refusing all others; may be repeated")
+                .value_parser(clap::value_parser!(CertFingerprint))
+                .action(ArgAction::Append)
+                .global(true),
+        )
         .subcommand(
             Command::new("upload")
+                .allow_missing_positional(true)
                 .about("Uploads files to the server")
-                .arg(Arg::new("server_url").help("The server URL").required(true))
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("encrypt")
+                        .long("encrypt")
+                        .help("Encrypt files with convergent encryption before uploading")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(keep_arg())
+                .arg(
+                    Arg::new("part_size")
+                        .long("part-size")
+                        .help("Files larger than this many bytes (8 MiB by default) are sent ahead in parts of this size")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("8388608"),
+                )
                 .arg(
                     Arg::new("files")
                         .help("List of files to upload, or 'all' to upload all files in the storage directory")
@@ -49,246 +182,2025 @@ async fn main() {
         )
         .subcommand(
             Command::new("verify")
+                .allow_missing_positional(true)
                 .about("Verifies a file from the server")
-                .arg(Arg::new("server_url").help("The server URL").required(true))
+                .arg(server_url_arg())
                 .arg(
                     Arg::new("file_index")
                         .help("The index of the file to verify")
+                        .value_parser(clap::value_parser!(usize))
                         .required(true),
+                )
+                .arg(
+                    Arg::new("snapshot")
+                        .long("snapshot")
+                        .help("Verify against the root of a named snapshot instead of the latest root"),
+                )
+                .arg(
+                    Arg::new("version")
+                        .long("version")
+                        .help("Verify against a historical root version instead of the latest root")
+                        .value_parser(clap::value_parser!(usize))
+                        .conflicts_with("snapshot"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify_all")
+                .about("Verifies every uploaded file against the stored root")
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("jobs")
+                        .long("jobs")
+                        .help("How many files are downloaded and verified at once")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("8"),
                 ),
         )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Saves the current root hash under a name for later audits")
+                .arg(Arg::new("name").help("The snapshot name").required(true)),
+        )
+        .subcommand(Command::new("snapshots").about("Lists the saved snapshots"))
+        .subcommand(Command::new("datasets").about("Lists the named datasets"))
+        .subcommand(
+            Command::new("download")
+                .allow_missing_positional(true)
+                .about("Downloads a file, writing it only once it is verified")
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("file")
+                        .help("The index or name of the file to download")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .help("Where to write the file, instead of the local storage"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Write the file even if it fails verification")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("prove_absent")
+                .allow_missing_positional(true)
+                .about("Checks the server's proof that a file was never uploaded")
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("file")
+                        .help("Path to a local file whose contents should be absent")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("sample")
+                .about("Checks that the server still has its files by verifying a random sample of them")
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .help("How many files to sample")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("16"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .allow_missing_positional(true)
+                .about("Lists the files that changed between two roots, without downloading them")
+                .arg(server_url_arg())
+                .arg(
+                    Arg::new("from")
+                        .help("The root to compare from, or the name of a snapshot")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("to")
+                        .help("The root to compare to, or the name of a snapshot; defaults to the latest root"),
+                ),
+        )
+        .subcommand(
+            Command::new("audit_log")
+                .about("Checks that every root the client has seen is in the server's transparency log")
+                .arg(server_url_arg()),
+        )
         .subcommand(
             Command::new("delete_all")
                 .about("Deletes all files and state from the server")
-                .arg(Arg::new("server_url").help("The server URL").required(true)),
+                .arg(server_url_arg()),
+        )
+        .subcommand(
+            Command::new("upload_sharded")
+                .about("Uploads erasure-coded shards of files to multiple servers")
+                .arg(
+                    Arg::new("servers")
+                        .long("servers")
+                        .help("Comma-separated server URLs, one per shard")
+                        .required(true)
+                        .value_delimiter(','),
+                )
+                .arg(
+                    Arg::new("data_shards")
+                        .long("data-shards")
+                        .help("How many shards are needed to reconstruct a file")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true),
+                )
+                .arg(keep_arg())
+                .arg(
+                    Arg::new("files")
+                        .help("List of files to upload, or 'all' to upload all files in the storage directory")
+                        .required(true)
+                        .action(ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("reconstruct")
+                .about("Reconstructs a file from verified shards of the sharded upload")
+                .arg(
+                    Arg::new("file_index")
+                        .help("The index of the file to reconstruct")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("share")
+                .about("Serves local files and their proofs to a peer for cross-verification")
+                .arg(
+                    Arg::new("port")
+                        .help("The port to listen on")
+                        .value_parser(clap::value_parser!(u16))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("files")
+                        .help("List of files to share, or 'all' to share all files in the storage directory")
+                        .required(true)
+                        .action(ArgAction::Append),
+                ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("serve")
+                .about("Runs the full server in-process over a local directory")
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .help("Directory whose files are served; uploads are stored there too")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .help("The port to listen on")
+                        .value_parser(clap::value_parser!(u16))
+                        .default_value("8000"),
+                )
+                .arg(
+                    Arg::new("idempotency_window")
+                        .long("idempotency-window")
+                        .help("Seconds a retried upload or delete is recognized by its idempotency key")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("86400"),
+                )
+                .arg(
+                    Arg::new("proof_cache")
+                        .long("proof-cache")
+                        .help("How many file proofs to cache; 0 disables the cache")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10000"),
+                )
+                .arg(
+                    Arg::new("storage")
+                        .long("storage")
+                        .help("The storage backend; the directory is passed to it as its location")
+                        .default_value(storage::FILESYSTEM_BACKEND),
+                )
+                .arg(
+                    Arg::new("import")
+                        .long("import")
+                        .help("A directory whose files are ingested as a new version at startup"),
+                )
+                .arg(
+                    Arg::new("import_root")
+                        .long("import-root")
+                        .help("Directory whose subdirectories may be ingested through POST /import"),
+                )
+                .arg(
+                    Arg::new("mirror_of")
+                        .long("mirror-of")
+                        .help("A peer server whose dataset this one mirrors and regularly cross-verifies"),
+                )
+                .arg(
+                    Arg::new("mirror_interval")
+                        .long("mirror-interval")
+                        .help("Seconds between two checks of the peer")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("300"),
+                )
+                .arg(
+                    Arg::new("mirror_sample")
+                        .long("mirror-sample")
+                        .help("How many files every check downloads from the peer and compares")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("mirror_webhook")
+                        .long("mirror-webhook")
+                        .help("URL checks that find a divergence are posted to"),
+                ),
+        )
+        .subcommand(
+            Command::new("sync_push")
+                .about("Pushes the client state, encrypted with a passphrase, to the server")
+                .arg(server_url_arg())
+                .arg(passphrase_arg()),
+        )
+        .subcommand(
+            Command::new("manifest")
+                .about("Exports and verifies signed dataset manifests")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Writes a manifest of the uploaded dataset")
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .help("The manifest format")
+                                .value_parser(["json", "cbor"])
+                                .default_value("json"),
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .long("out")
+                                .help("Where to write the manifest; defaults to manifest.<format>"),
+                        )
+                        .arg(
+                            Arg::new("sign_key")
+                                .long("sign-key")
+                                .help("File with a hex-encoded ed25519 secret key to sign the manifest with"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Checks a manifest's root and signatures")
+                        .arg(
+                            Arg::new("path")
+                                .help("The manifest; files ending in .cbor are read as CBOR, others as JSON")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    Command::new("keygen")
+                        .about("Generates an ed25519 key for signing manifests")
+                        .arg(
+                            Arg::new("path")
+                                .help("Where to write the hex-encoded secret key")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("sync_pull")
+                .about("Pulls the client state pushed from another device, replacing the local one")
+                .arg(server_url_arg())
+                .arg(passphrase_arg()),
+        );
+    #[cfg(feature = "tui")]
+    let command = command.subcommand(
+        Command::new("tui")
+            .about("Opens a terminal dashboard to audit the uploaded dataset")
+            .arg(server_url_arg()),
+    );
+    #[cfg(feature = "eth")]
+    let command = command.subcommand(
+        Command::new("verify_onchain")
+            .allow_missing_positional(true)
+            .about("Verifies a file from the server against a root stored in an Ethereum contract")
+            .arg(server_url_arg())
+            .arg(
+                Arg::new("file_index")
+                    .help("The index of the file to verify")
+                    .value_parser(clap::value_parser!(usize))
+                    .required(true),
+            )
+            .arg(
+                Arg::new("rpc_url")
+                    .long("rpc-url")
+                    .help("The Ethereum JSON-RPC endpoint")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("contract")
+                    .long("contract")
+                    .help("The address of the contract holding the root")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("slot")
+                    .long("slot")
+                    .help("The storage slot of the root")
+                    .default_value("0"),
+            ),
+    );
+    let matches = command.get_matches();
+    if let Some(dataset) = matches.get_one::<String>("dataset") {
+        DATASET
+            .set(dataset.clone())
+            .expect("The dataset is set once");
+    }
+    let profile = load_profile(
+        matches.get_one::<String>("config"),
+        matches.get_one::<String>("profile"),
+    );
+    let profile = PROFILE.get_or_init(|| {
+        profile.unwrap_or_else(|e| exit_on_error(Err(e), "Failed to load the configuration"))
+    });
+    CONNECT_OPTIONS
+        .set(ConnectOptions {
+            timeout: matches
+                .get_one::<u64>("timeout")
+                .map(|&seconds| Duration::from_secs(seconds)),
+            retries: *matches.get_one::<u32>("retries").unwrap(),
+            auth_token: profile.auth_token.clone(),
+            tls: exit_on_error(
+                tls_options(&matches, profile),
+                "Failed to load certificates",
+            ),
+            ..ConnectOptions::default()
+        })
+        .expect("Connect options are set once");
 
     match matches.subcommand() {
         Some(("upload", sub_m)) => {
-            let server_url = sub_m.get_one::<String>("server_url").unwrap();
+            let server_url = server_url(sub_m);
             let files: Vec<String> = sub_m
                 .get_many::<String>("files")
                 .unwrap()
                 .map(|s| s.to_string())
                 .collect();
-            upload_files(server_url, &files)
-                .await
-                .expect("Failed to upload files");
+            let encrypt = sub_m.get_flag("encrypt");
+            let part_size = *sub_m.get_one::<usize>("part_size").unwrap();
+            let keep = sub_m.get_flag("keep");
+            exit_on_error(
+                upload_files(server_url, &files, encrypt, part_size, keep).await,
+                "Failed to upload files",
+            );
         }
         Some(("verify", sub_m)) => {
-            let server_url = sub_m.get_one::<String>("server_url").unwrap();
-            let file_index: usize = sub_m
-                .get_one::<String>("file_index")
-                .unwrap()
-                .parse()
-                .expect("File index must be a number");
-            verify_file(server_url, file_index)
-                .await
-                .expect("Failed to verify file");
+            let server_url = server_url(sub_m);
+            let file_index = *sub_m.get_one::<usize>("file_index").unwrap();
+            let snapshot = sub_m.get_one::<String>("snapshot");
+            let version = sub_m.get_one::<usize>("version").copied();
+            exit_on_error(
+                verify_file(
+                    server_url,
+                    file_index,
+                    snapshot.map(|s| s.as_str()),
+                    version,
+                )
+                .await,
+                "Failed to verify file",
+            );
+        }
+        Some(("verify_all", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let jobs = *sub_m.get_one::<usize>("jobs").unwrap();
+            exit_on_error(verify_all(server_url, jobs).await, "Failed to verify files");
+        }
+        Some(("snapshot", sub_m)) => {
+            let name = sub_m.get_one::<String>("name").unwrap();
+            exit_on_error(create_snapshot(name), "Failed to save snapshot");
+        }
+        Some(("snapshots", _)) => exit_on_error(list_snapshots(), "Failed to list snapshots"),
+        Some(("datasets", _)) => exit_on_error(list_datasets(), "Failed to list datasets"),
+        Some(("download", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let file = sub_m.get_one::<String>("file").unwrap();
+            let out = sub_m.get_one::<String>("out").map(String::as_str);
+            exit_on_error(
+                download_file(server_url, file, out, sub_m.get_flag("force")).await,
+                "Failed to download file",
+            );
+        }
+        Some(("prove_absent", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let file = sub_m.get_one::<String>("file").unwrap();
+            exit_on_error(
+                prove_absent(server_url, file).await,
+                "Failed to check non-membership proof",
+            );
+        }
+        Some(("sample", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let count = *sub_m.get_one::<usize>("count").unwrap();
+            exit_on_error(
+                sample_files(server_url, count).await,
+                "Failed to sample files",
+            );
+        }
+        Some(("diff", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let from = sub_m.get_one::<String>("from").unwrap();
+            let to = sub_m.get_one::<String>("to");
+            exit_on_error(
+                diff_roots(server_url, from, to.map(|s| s.as_str())).await,
+                "Failed to diff roots",
+            );
+        }
+        Some(("audit_log", sub_m)) => {
+            let server_url = server_url(sub_m);
+            exit_on_error(
+                audit_log(server_url).await,
+                "Failed to audit the transparency log",
+            );
         }
         Some(("delete_all", sub_m)) => {
-            let server_url = sub_m.get_one::<String>("server_url").unwrap();
-            delete_all_server_data(server_url)
-                .await
-                .expect("Failed to delete all server data");
+            let server_url = server_url(sub_m);
+            exit_on_error(
+                delete_all_server_data(server_url).await,
+                "Failed to delete all server data",
+            );
+        }
+        Some(("upload_sharded", sub_m)) => {
+            let servers: Vec<String> = sub_m
+                .get_many::<String>("servers")
+                .unwrap()
+                .map(|s| s.to_string())
+                .collect();
+            let data_shards = *sub_m.get_one::<usize>("data_shards").unwrap();
+            let files: Vec<String> = sub_m
+                .get_many::<String>("files")
+                .unwrap()
+                .map(|s| s.to_string())
+                .collect();
+            exit_on_error(
+                upload_sharded(&servers, data_shards, &files, sub_m.get_flag("keep")).await,
+                "Failed to upload shards",
+            );
+        }
+        Some(("reconstruct", sub_m)) => {
+            let file_index = *sub_m.get_one::<usize>("file_index").unwrap();
+            exit_on_error(
+                reconstruct_file(file_index).await,
+                "Failed to reconstruct file",
+            );
+        }
+        Some(("share", sub_m)) => {
+            let port = *sub_m.get_one::<u16>("port").unwrap();
+            let files: Vec<String> = sub_m
+                .get_many::<String>("files")
+                .unwrap()
+                .map(|s| s.to_string())
+                .collect();
+            exit_on_error(share_files(port, &files).await, "Failed to share files");
+        }
+        Some(("serve", sub_m)) => {
+            let dir = sub_m.get_one::<String>("dir").unwrap();
+            let port = *sub_m.get_one::<u16>("port").unwrap();
+            let idempotency_window = *sub_m.get_one::<u64>("idempotency_window").unwrap();
+            let proof_cache_capacity = *sub_m.get_one::<usize>("proof_cache").unwrap();
+            let mirror = sub_m
+                .get_one::<String>("mirror_of")
+                .map(|peer| MirrorConfig {
+                    peer: peer.clone(),
+                    interval: Duration::from_secs(
+                        *sub_m.get_one::<u64>("mirror_interval").unwrap(),
+                    ),
+                    sample_size: *sub_m.get_one::<usize>("mirror_sample").unwrap(),
+                    webhook: sub_m.get_one::<String>("mirror_webhook").cloned(),
+                });
+            let served = serve_directory(
+                ServerConfig {
+                    storage_backend: sub_m.get_one::<String>("storage").unwrap().clone(),
+                    storage_dir: dir.into(),
+                    idempotency_window: Duration::from_secs(idempotency_window),
+                    proof_cache_capacity,
+                    import_root: sub_m.get_one::<String>("import_root").map(|dir| dir.into()),
+                    ..ServerConfig::default()
+                },
+                port,
+                sub_m.get_one::<String>("import").map(|dir| dir.as_str()),
+                mirror,
+            )
+            .await;
+            exit_on_error(served, &format!("Failed to serve directory {}", dir));
+        }
+        Some(("sync_push", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let passphrase = sub_m.get_one::<String>("passphrase").unwrap();
+            exit_on_error(
+                push_state(server_url, passphrase).await,
+                "Failed to push client state",
+            );
+        }
+        Some(("manifest", sub_m)) => match sub_m.subcommand() {
+            Some(("export", export_m)) => {
+                let format = export_m.get_one::<String>("format").unwrap();
+                let out = export_m
+                    .get_one::<String>("out")
+                    .cloned()
+                    .unwrap_or_else(|| format!("manifest.{}", format));
+                let sign_key = export_m.get_one::<String>("sign_key");
+                exit_on_error(
+                    export_manifest(format, &out, sign_key.map(|s| s.as_str())),
+                    "Failed to export manifest",
+                );
+            }
+            Some(("verify", verify_m)) => {
+                let path = verify_m.get_one::<String>("path").unwrap();
+                exit_on_error(verify_manifest(path), "Failed to verify manifest");
+            }
+            Some(("keygen", keygen_m)) => {
+                let path = keygen_m.get_one::<String>("path").unwrap();
+                exit_on_error(generate_signing_key(path), "Failed to generate key");
+            }
+            _ => {
+                eprintln!("Unknown manifest command");
+                std::process::exit(EXIT_USAGE);
+            }
+        },
+        Some(("sync_pull", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let passphrase = sub_m.get_one::<String>("passphrase").unwrap();
+            exit_on_error(
+                pull_state(server_url, passphrase).await,
+                "Failed to pull client state",
+            );
+        }
+        #[cfg(feature = "tui")]
+        Some(("tui", sub_m)) => {
+            let server_url = server_url(sub_m);
+            exit_on_error(
+                run_dashboard(server_url).await,
+                "Failed to run the dashboard",
+            );
+        }
+        #[cfg(feature = "eth")]
+        Some(("verify_onchain", sub_m)) => {
+            let server_url = server_url(sub_m);
+            let file_index = *sub_m.get_one::<usize>("file_index").unwrap();
+            let rpc_url = sub_m.get_one::<String>("rpc_url").unwrap();
+            let contract = sub_m.get_one::<String>("contract").unwrap();
+            let slot = sub_m.get_one::<String>("slot").unwrap();
+            exit_on_error(
+                verify_file_onchain(server_url, file_index, rpc_url, contract, slot).await,
+                "Failed to verify file",
+            );
+        }
+        _ => {
+            eprintln!("Unknown command");
+            std::process::exit(EXIT_USAGE);
         }
-        _ => eprintln!("Unknown command"),
     }
 }
 
-fn ensure_storage_dir_exists() {
-    if !Path::new(STORAGE_DIR).exists() {
-        fs::create_dir_all(STORAGE_DIR).expect("Failed to create storage directory");
+/// Prints why a command failed, with a hint at what to do about it, and exits with the error's
+/// exit status
+fn exit_on_error<T>(result: Result<T, ClientError>, context: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}: {}", context, e);
+            if let Some(hint) = hint(&e) {
+                eprintln!("{}", hint);
+            }
+            std::process::exit(e.exit_code());
+        }
     }
 }
 
+/// What the user can do about an error, if it is one they can fix
+fn hint(error: &ClientError) -> Option<String> {
+    match error {
+        ClientError::File { path, source } if source.kind() == io::ErrorKind::NotFound => {
+            Some(format!(
+                "Files are read from and written to {}, the storage directory of the profile. Check that {} exists.",
+                storage_dir().display(),
+                path.display()
+            ))
+        }
+        ClientError::State(_) => Some(format!(
+            "The client state in {} is not valid. Restore it from a backup or with sync_pull, or move it away to start with an empty state.",
+            storage_dir().join(STATE_STORAGE).display()
+        )),
+        ClientError::Transport(TransportError::Unavailable(_)) => Some(
+            "Check the server URL and that the server is running, or allow more time with --timeout and --retries."
+                .to_string(),
+        ),
+        ClientError::Config(_) => Some(
+            "Fix the configuration file, or choose another one with --config or another profile with --profile."
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+/// Loads the profile from the configuration file
+/// Servers build their trees with SHA-256, so profiles asking for another algorithm are refused.
+fn load_profile(config: Option<&String>, name: Option<&String>) -> Result<Profile, ClientError> {
+    let path = match config {
+        Some(path) => PathBuf::from(path),
+        None => match client_config::default_path() {
+            Some(path) => path,
+            None => return Ok(Profile::default()),
+        },
+    };
+    let profile = ClientConfig::load(path)?.profile(name.map(String::as_str))?;
+    match profile.hash_algorithm {
+        Some(algorithm) if algorithm != HashAlgorithm::Sha256 => Err(ClientError::Config(format!(
+            "Servers hash with sha256, but the profile asks for {}",
+            algorithm.name()
+        ))),
+        _ => Ok(profile),
+    }
+}
+
+/// The certificates given on the command line, or else the profile's
+fn tls_options(matches: &ArgMatches, profile: &Profile) -> Result<TlsOptions, ClientError> {
+    let path = |arg: &str, configured: &Option<PathBuf>| {
+        matches
+            .get_one::<String>(arg)
+            .map(PathBuf::from)
+            .or_else(|| configured.clone())
+    };
+    let ca_certs = match path("ca_cert", &profile.ca_cert) {
+        Some(ca_cert) => vec![read_file(&ca_cert)?],
+        None => Vec::new(),
+    };
+    let client_identity = match (
+        path("client_cert", &profile.client_cert),
+        path("client_key", &profile.client_key),
+    ) {
+        (Some(certificate), Some(key)) => Some(ClientIdentity {
+            certificate: read_file(&certificate)?,
+            key: read_file(&key)?,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(ClientError::InvalidArgument(
+                "A client certificate and its key must be given together".to_string(),
+            ))
+        }
+    };
+    let pinned_certs = match matches.get_many::<CertFingerprint>("pin_cert") {
+        Some(pins) => pins.copied().collect(),
+        None => profile.pinned_certs.clone(),
+    };
+
+    let tls = TlsOptions {
+        ca_certs,
+        client_identity,
+        pinned_certs,
+    };
+    tls.check().map_err(|e| match e {
+        TransportError::Invalid(reason) => ClientError::InvalidArgument(reason),
+        e => e.into(),
+    })?;
+    Ok(tls)
+}
+
+/// The server URL argument, which defaults to the profile's server URL
+fn server_url_arg() -> Arg {
+    Arg::new("server_url").help("The server URL; the profile's server URL if not given")
+}
+
+/// The server URL given to the command, or else the profile's
+fn server_url(sub_m: &ArgMatches) -> &str {
+    sub_m
+        .get_one::<String>("server_url")
+        .map(String::as_str)
+        .or(profile().server_url.as_deref())
+        .unwrap_or_else(|| {
+            eprintln!("No server URL given, and the profile has none");
+            std::process::exit(EXIT_USAGE);
+        })
+}
+
+/// The configuration profile in use
+fn profile() -> &'static Profile {
+    PROFILE.get_or_init(Profile::default)
+}
+
+/// The directory of the files to upload and the client state
+fn storage_dir() -> &'static Path {
+    profile()
+        .storage_dir
+        .as_deref()
+        .unwrap_or(Path::new(STORAGE_DIR))
+}
+
+/// Loads the client state of the dataset selected on the command line
+fn load_state() -> Result<ClientState, ClientError> {
+    ClientState::load_dataset(
+        storage_dir().join(STATE_STORAGE),
+        DATASET.get().map(String::as_str),
+    )
+}
+
+/// Saves the client state as the dataset selected on the command line
+fn save_state(state: &ClientState) -> Result<(), ClientError> {
+    state.save_dataset(
+        storage_dir().join(STATE_STORAGE),
+        DATASET.get().map(String::as_str),
+    )
+}
+
+/// Runs the terminal dashboard over the stored client state
+#[cfg(feature = "tui")]
+async fn run_dashboard(server_url: &str) -> Result<(), ClientError> {
+    let state = load_state()?;
+    let transport = connect(server_url).await?;
+    Ok(tui::run(server_url, transport, state).await?)
+}
+
+/// The passphrase protecting a synced state, read from the environment unless given explicitly
+fn passphrase_arg() -> Arg {
+    Arg::new("passphrase")
+        .long("passphrase")
+        .env("MERKLE_SYNC_PASSPHRASE")
+        .help("The passphrase the state is encrypted with")
+        .required(true)
+}
+
+/// Whether local files are kept after they were uploaded
+fn keep_arg() -> Arg {
+    Arg::new("keep")
+        .long("keep")
+        .help("Keep the local files after a successful upload instead of deleting them")
+        .action(ArgAction::SetTrue)
+}
+
+fn ensure_storage_dir_exists() -> Result<(), ClientError> {
+    fs::create_dir_all(storage_dir()).map_err(|source| ClientError::File {
+        path: storage_dir().to_path_buf(),
+        source,
+    })
+}
+
+/// Reads a local file, naming it in the error if it cannot be read
+fn read_file(path: &Path) -> Result<Vec<u8>, ClientError> {
+    fs::read(path).map_err(|source| ClientError::File {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
 /// Uploads files to the server
-async fn upload_files(server_url: &str, file_paths: &[String]) -> Result<(), reqwest::Error> {
-    ensure_storage_dir_exists();
+/// With `encrypt`, the contents are replaced by their convergent ciphertexts before the tree is built
+async fn upload_files(
+    server_url: &str,
+    file_paths: &[String],
+    encrypt: bool,
+    part_size: usize,
+    keep: bool,
+) -> Result<(), ClientError> {
+    ensure_storage_dir_exists()?;
 
     // Read file contents and prepare file data
-    let files = if file_paths.len() == 1 && file_paths[0] == "all" {
-        read_all_files_from_storage()
+    let mut files = if file_paths.len() == 1 && file_paths[0] == "all" {
+        read_all_files_from_storage()?
     } else {
-        read_specified_files(file_paths)
+        read_specified_files(file_paths)?
     };
 
-    // Compute Merkle tree root
-    let file_contents: Vec<String> = files
-        .clone()
-        .iter()
-        .map(|file| file.content.clone())
-        .collect();
-    let mut tree = MerkleTree::new();
+    let mut file_keys = HashMap::new();
+    if encrypt {
+        for file in files.iter_mut() {
+            let (key, ciphertext) = encryption::encrypt(&file.content)
+                .map_err(|e| ClientError::Encoding(e.to_string()))?;
+            file.content = ciphertext.into_bytes();
+            file_keys.insert(file.name.clone(), key);
+        }
+    }
 
-    tree.build(&file_contents);
-    let root_hash = tree
-        .root()
-        .clone()
-        .unwrap_or_else(|| "empty_root".to_string());
+    // Compute the Merkle tree root over the previously uploaded files followed by the new ones
+    let mut state = load_state()?;
+    let mut leaves = state.leaves.clone();
+    let hashing = progress::bytes(content_size(&files), "Hashing");
+    for file in &files {
+        hashing.set_message(format!("Hashing '{}'", file.name));
+        leaves.push(LeafEntry::new(file));
+        hashing.inc(file.content.len() as u64);
+    }
+    hashing.finish_and_clear();
+    if let Err(e) = check_leaf_order(&state.leaves, &leaves, &files) {
+        return Err(ClientError::InvalidArgument(format!(
+            "Refusing to upload: {}",
+            e
+        )));
+    }
+    let leaf_hashes: Vec<String> = leaves.iter().map(|leaf| leaf.leaf_hash.clone()).collect();
 
-    // Save the client state
-    let state = ClientState::new(root_hash.clone());
-    match state.save(Path::new(STORAGE_DIR).join(STATE_STORAGE)) {
-        Ok(_) => println!("Client state saved successfully."),
-        Err(e) => eprintln!("Failed to save client state: {}", e),
+    // The cached tree already holds the uploaded leaves, so only the new ones are decoded
+    let mut tree = MerkleTree::new();
+    match cached_tree(&state) {
+        Some(cached) => {
+            let mut digests: Vec<_> = cached.leaves().copied().collect();
+            for hash in &leaf_hashes[digests.len()..] {
+                digests.push(
+                    tree.algorithm()
+                        .decode_digest(hash)
+                        .ok_or_else(|| MerkleError::InvalidHash(hash.clone()))?,
+                );
+            }
+            tree.build_from_digests(digests);
+        }
+        None => tree.build_from_leaf_hashes(leaf_hashes.clone())?,
     }
+    let root_hash = tree.root().clone().unwrap_or_else(empty_root);
 
     // Prepare the upload request with file data
-    let request = UploadRequest {
+    let mut request = UploadRequest {
         root_hash: root_hash.clone(),
+        base_version: state.root_history.last().map(|v| v.version),
+        leaves: leaves.clone(),
         files: files.clone(),
+        staged: None,
     };
 
-    let response = Client::new()
-        .post(format!("{}/upload", server_url))
-        .json(&request)
-        .send()
-        .await?;
+    // An interrupted upload of the same files is resumed with its idempotency key
+    let pending = match state.pending_upload.take() {
+        Some(pending) if pending.root_hash == root_hash => {
+            println!("Resuming the interrupted upload.");
+            pending
+        }
+        _ => PendingUpload {
+            idempotency_key: idempotency_key(),
+            root_hash: root_hash.clone(),
+            sent: BTreeMap::new(),
+        },
+    };
+    let key = pending.idempotency_key.clone();
+    state.pending_upload = Some(pending);
+    save_state(&state)?;
 
-    let status = response.status();
-    let body = response.text().await?;
+    let transport = connect(server_url).await?;
+    let uploading = progress::bytes(content_size(&request.files), "Uploading");
 
-    println!("Response status: {:?}", status);
-    println!("Response body: {:?}", body);
+    // Files too large for one request are sent ahead in parts, staged under the idempotency key
+    let part_size = part_size.max(1);
+    for file in request.files.iter_mut() {
+        if file.content.len() <= part_size {
+            continue;
+        }
+        let sent = send_parts(transport.as_ref(), file, part_size, &mut state, &uploading).await;
+        match sent {
+            Ok(()) => {}
+            Err(e @ TransportError::Server { .. }) => {
+                eprintln!("Upload failed. Local files were not deleted.");
+                return Err(e.into());
+            }
+            Err(e) => {
+                eprintln!("Upload interrupted. Run it again to resume.");
+                return Err(e.into());
+            }
+        }
+        file.content = Vec::new();
+        request.staged = Some(key.clone());
+    }
 
-    // If upload was successful, delete local files
-    if status.is_success() {
-        delete_uploaded_files(&files);
-        println!("All uploaded files have been deleted from local storage.");
-    } else {
-        eprintln!("Upload failed. Local files were not deleted.");
+    let uploaded = transport.upload(&request, &key).await;
+    uploading.finish_and_clear();
+    let uploaded = match uploaded {
+        Ok(uploaded) => uploaded,
+        Err(e @ TransportError::Server { .. }) => {
+            eprintln!("Upload failed. Local files were not deleted.");
+            return Err(e.into());
+        }
+        Err(e) => {
+            eprintln!("Upload interrupted. Run it again to resume.");
+            return Err(e.into());
+        }
+    };
+    println!(
+        "{}: version {} with root hash {}",
+        uploaded.message, uploaded.version, uploaded.root_hash
+    );
+
+    // The upload was successful, save the new root and delete local files
+    state.sorted_root = SortedMerkleTree::from_leaf_hashes(leaf_hashes)?
+        .root()
+        .unwrap_or_default();
+    state.root_history.push(RootVersion {
+        version: state.root_history.len(),
+        root_hash: root_hash.clone(),
+        leaf_count: leaves.len(),
+    });
+    state.root_hash = root_hash;
+    state.leaves = leaves;
+    state.pending_upload = None;
+    state.file_keys.extend(file_keys);
+    let first_index = state.leaves.len() - files.len();
+    state
+        .chunk_trees
+        .extend(files.iter().enumerate().map(|(offset, file)| {
+            let chunk_tree = chunking::chunk_tree(&file.content, DEFAULT_CHUNK_SIZE);
+            let chunked_file = ChunkedFile {
+                name: file.name.clone(),
+                chunk_size: DEFAULT_CHUNK_SIZE,
+                chunk_count: chunking::split_chunks(&file.content, DEFAULT_CHUNK_SIZE).len(),
+                chunk_root: chunk_tree.root().unwrap_or_else(empty_root),
+            };
+            (first_index + offset, chunked_file)
+        }));
+    match save_state(&state) {
+        Ok(_) => println!("Client state saved successfully."),
+        Err(e) => eprintln!("Failed to save client state: {}", e),
+    }
+    if let Err(e) = cache_tree(&tree) {
+        eprintln!("Failed to cache the tree: {}", e);
     }
 
+    delete_uploaded_files(&files, keep);
+
+    Ok(())
+}
+
+/// Where the tree of the dataset selected on the command line is cached
+/// Dataset names are hex-encoded, so any name is a valid file name.
+fn tree_cache_path() -> PathBuf {
+    let name = match DATASET.get() {
+        Some(dataset) => format!("merkle_tree.{}.json", hex::encode(dataset)),
+        None => "merkle_tree.json".to_string(),
+    };
+    storage_dir().join(TREE_CACHE).join(name)
+}
+
+/// The cached tree, if it is still the tree of the stored leaves and root
+fn cached_tree(state: &ClientState) -> Option<MerkleTree> {
+    let tree = MerkleTree::load(tree_cache_path()).ok()?;
+    let current = tree.leaf_count() == state.leaves.len()
+        && tree
+            .root()
+            .is_some_and(|root| ct_eq(&root, &state.root_hash));
+    current.then_some(tree)
+}
+
+/// Caches the tree of the uploaded files next to the client state
+fn cache_tree(tree: &MerkleTree) -> Result<(), ClientError> {
+    let path = tree_cache_path();
+    let dir = storage_dir().join(TREE_CACHE);
+    fs::create_dir_all(&dir).map_err(|source| ClientError::File { path: dir, source })?;
+    tree.save(path)?;
+    Ok(())
+}
+
+/// Total size of the contents of the files, in bytes
+fn content_size(files: &[FileData]) -> u64 {
+    files.iter().map(|file| file.content.len() as u64).sum()
+}
+
+/// Sends the contents of a file ahead of its upload in parts of at most `part_size` bytes
+/// Sending starts after the bytes the server acknowledged for the pending upload, and every
+/// acknowledged part is recorded in the saved state. If the server no longer has the earlier
+/// parts, for example after a restart, the file is sent again from the start.
+async fn send_parts(
+    transport: &dyn Transport,
+    file: &FileData,
+    part_size: usize,
+    state: &mut ClientState,
+    progress: &ProgressBar,
+) -> Result<(), TransportError> {
+    let pending = state
+        .pending_upload
+        .as_ref()
+        .expect("Parts are sent for a pending upload");
+    let key = pending.idempotency_key.clone();
+    let mut offset = pending
+        .sent
+        .get(&file.name)
+        .copied()
+        .filter(|&sent| sent <= file.content.len())
+        .unwrap_or(0);
+    let mut resumed = offset > 0;
+    progress.set_message(format!("Uploading '{}'", file.name));
+    progress.inc(offset as u64);
+
+    while offset < file.content.len() {
+        let part = UploadPart {
+            name: file.name.clone(),
+            offset,
+            content: file.content[offset..file.content.len().min(offset + part_size)].to_vec(),
+        };
+        match transport.upload_part(&key, &part).await {
+            Ok(staged) => {
+                progress.inc(staged.received.saturating_sub(offset) as u64);
+                offset = staged.received;
+            }
+            Err(TransportError::Server { .. }) if resumed => {
+                progress.suspend(|| {
+                    println!(
+                        "The server no longer has the parts of '{}', sending it again.",
+                        file.name
+                    )
+                });
+                progress.set_position(progress.position().saturating_sub(offset as u64));
+                offset = 0;
+                resumed = false;
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+        resumed = false;
+
+        if let Some(pending) = state.pending_upload.as_mut() {
+            pending.sent.insert(file.name.clone(), offset);
+        }
+        if let Err(e) = save_state(state) {
+            progress.suspend(|| eprintln!("Failed to save upload progress: {}", e));
+        }
+    }
     Ok(())
 }
 
-/// Deletes the uploaded files from the local storage
-fn delete_uploaded_files(files: &[FileData]) {
+/// Deletes the uploaded files from the local storage, unless they are to be kept
+fn delete_uploaded_files(files: &[FileData], keep: bool) {
+    if keep {
+        println!("The uploaded files were kept in local storage.");
+        return;
+    }
     for file in files {
-        let path = Path::new(STORAGE_DIR).join(&file.name);
+        let path = storage_dir().join(&file.name);
         if let Err(e) = fs::remove_file(&path) {
             eprintln!("Failed to delete file {}: {}", file.name, e);
         } else {
             println!("Deleted local file: {}", file.name);
         }
     }
+    println!("All uploaded files have been deleted from local storage.");
 }
 
 /// Reads all files from the local storage
-fn read_all_files_from_storage() -> Vec<FileData> {
-    let storage_path = Path::new(STORAGE_DIR);
+/// File names must be UTF-8, as they are sent to the server as strings.
+fn read_all_files_from_storage() -> Result<Vec<FileData>, ClientError> {
+    let storage_path = storage_dir();
+    let unreadable = |source| ClientError::File {
+        path: storage_path.to_path_buf(),
+        source,
+    };
     let mut files = Vec::new();
 
-    for entry in fs::read_dir(storage_path).expect("Failed to read storage directory") {
-        let entry = entry.expect("Failed to read directory entry");
-        let path = entry.path();
-        if path.is_file() && path.file_name().unwrap() != STATE_STORAGE {
-            let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
-            let content = fs::read_to_string(&path).expect("Unable to read file");
+    for entry in fs::read_dir(storage_path).map_err(unreadable)? {
+        let path = entry.map_err(unreadable)?.path();
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        if path.is_file() && file_name != STATE_STORAGE {
+            let Some(file_name) = file_name.to_str() else {
+                return Err(ClientError::InvalidArgument(format!(
+                    "File name {} is not UTF-8; rename it to upload it",
+                    path.display()
+                )));
+            };
             files.push(FileData {
-                name: file_name,
-                content,
+                name: file_name.to_string(),
+                content: read_file(&path)?,
             });
         }
     }
 
     // Sort the files by name
     files.sort_by(|a, b| a.name.cmp(&b.name));
-    files
+    Ok(files)
 }
 
 /// Reads specified files from the local storage
-fn read_specified_files(file_paths: &[String]) -> Vec<FileData> {
+fn read_specified_files(file_paths: &[String]) -> Result<Vec<FileData>, ClientError> {
     file_paths
         .iter()
         .map(|file_name| {
-            let path = Path::new(STORAGE_DIR).join(file_name);
-            let content = fs::read_to_string(&path).expect("Unable to read file");
-            FileData {
+            Ok(FileData {
                 name: file_name.clone(),
-                content,
-            }
+                content: read_file(&storage_dir().join(file_name))?,
+            })
         })
         .collect()
 }
 
-/// Verifies a file by its index
-async fn verify_file(server_url: &str, file_index: usize) -> Result<(), reqwest::Error> {
-    let client = Client::new();
+/// A fresh key identifying an upload or delete across its retries
+fn idempotency_key() -> String {
+    let mut key = [0u8; 16];
+    OsRng.fill_bytes(&mut key);
+    hex::encode(key)
+}
 
-    let response = client
-        .get(format!("{}/file/{}", server_url, file_index))
-        .send()
-        .await?;
+/// Connects to the server with the timeout and retries given on the command line
+async fn connect(server_url: &str) -> Result<Arc<dyn Transport>, TransportError> {
+    let options = CONNECT_OPTIONS.get().cloned().unwrap_or_default();
+    transport::connect_with(server_url, &options).await
+}
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_message = response.text().await?;
-        println!("Server error: {} - {}", status, error_message);
+/// Verifies every uploaded file against the stored root, `jobs` files at a time
+/// At most `jobs` downloads are in flight, and results are collected in index order, so the
+/// report does not depend on which downloads finish first
+async fn verify_all(server_url: &str, jobs: usize) -> Result<(), ClientError> {
+    let state = load_state()?;
+    if state.leaves.is_empty() {
+        println!("No uploaded files to verify.");
         return Ok(());
     }
+    let transport = connect(server_url).await?;
+    // Pinned to the stored root, so uploads by other devices meanwhile do not fail the check
+    let query = FileQuery {
+        root: Some(state.root_hash.clone()),
+        version: None,
+    };
+
+    let verifying = progress::files(state.leaves.len() as u64, "Verifying");
+    let failures: Vec<(usize, &LeafEntry, String)> = stream::iter(state.leaves.iter().enumerate())
+        .map(|(index, leaf)| {
+            let transport = transport.as_ref();
+            let query = &query;
+            let root = &state.root_hash;
+            async move {
+                let failure = match transport.fetch_file(index, query).await {
+                    Ok(file) if file.name != leaf.name => {
+                        Some(format!("server has '{}' at this index", file.name))
+                    }
+                    Ok(file) if !ct_eq(&leaf.leaf_hash, &leaf_hash(&file.content)) => {
+                        Some("contents differ from the uploaded file".to_string())
+                    }
+                    Ok(file) if file.proof.leaf_index != index => {
+                        Some(format!("proof is for index {}", file.proof.leaf_index))
+                    }
+                    Ok(file) if !file.proof.verify(&file.content, root) => {
+                        Some("proof does not verify".to_string())
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(e.to_string()),
+                };
+                failure.map(|reason| (index, leaf, reason))
+            }
+        })
+        .buffered(jobs.max(1))
+        .inspect(|_| verifying.inc(1))
+        .filter_map(|failure| async move { failure })
+        .collect()
+        .await;
+    verifying.finish_and_clear();
 
-    let response_data: serde_json::Value = response.json().await?;
-    println!("Received response: {}", response_data);
+    println!(
+        "{} of {} files verified against root {}.",
+        state.leaves.len() - failures.len(),
+        state.leaves.len(),
+        state.root_hash
+    );
+    if !failures.is_empty() {
+        println!("{} files failed:", failures.len());
+        for (index, leaf, reason) in &failures {
+            println!("  {} '{}': {}", index, leaf.name, reason);
+        }
+        return Err(ClientError::Verification(format!(
+            "{} of {} files failed verification",
+            failures.len(),
+            state.leaves.len()
+        )));
+    }
+    Ok(())
+}
 
-    let proof: Vec<(String, bool)> =
-        serde_json::from_value(response_data["proof"].clone()).unwrap_or_else(|_| Vec::new());
-    let content: String =
-        serde_json::from_value(response_data["content"].clone()).unwrap_or_default();
-    let file_name: String =
-        serde_json::from_value(response_data["name"].clone()).unwrap_or_default();
+/// Fetches a file and its Merkle proof by index, printing the response
+/// The query can ask for a proof valid for a historical root instead of the latest one
+async fn fetch_file(
+    transport: &dyn Transport,
+    file_index: usize,
+    query: &FileQuery,
+) -> Result<FileResponse, TransportError> {
+    let file = transport.fetch_file(file_index, query).await?;
+    println!(
+        "Received response: {}",
+        serde_json::to_string(&file).unwrap_or_default()
+    );
+    Ok(file)
+}
 
-    let stored_state = ClientState::load(Path::new(STORAGE_DIR).join(STATE_STORAGE))
-        .expect("Failed to load client state");
+/// Verifies a file by its index
+/// With a snapshot name or a root version, the file is verified against that historical root
+/// instead of the latest one
+async fn verify_file(
+    server_url: &str,
+    file_index: usize,
+    snapshot: Option<&str>,
+    version: Option<usize>,
+) -> Result<(), ClientError> {
+    let transport = connect(server_url).await?;
 
-    // Calculate the hash of the content
-    let mut current_hash = calculate_hash(&content);
+    let stored_state = load_state()?;
 
-    // Verify the Merkle proof
-    for (sibling, is_right) in proof.iter() {
-        let combined = if *is_right {
-            format!("{}{}", current_hash, sibling)
+    let (expected_root, query) = match (snapshot, version) {
+        (Some(name), _) => match stored_state.snapshots.get(name) {
+            Some(snapshot) => (
+                snapshot.root_hash.clone(),
+                FileQuery {
+                    root: Some(snapshot.root_hash.clone()),
+                    version: None,
+                },
+            ),
+            None => {
+                return Err(ClientError::InvalidArgument(format!(
+                    "Snapshot '{}' not found",
+                    name
+                )))
+            }
+        },
+        (None, Some(version)) => match stored_state.root_history.get(version) {
+            Some(root_version) => (
+                root_version.root_hash.clone(),
+                FileQuery {
+                    root: None,
+                    version: Some(version),
+                },
+            ),
+            None => {
+                return Err(ClientError::InvalidArgument(format!(
+                    "Root version {} not found",
+                    version
+                )))
+            }
+        },
+        (None, None) => (stored_state.root_hash.clone(), FileQuery::default()),
+    };
+
+    let FileResponse {
+        name: file_name,
+        content,
+        proof,
+        ..
+    } = fetch_file(transport.as_ref(), file_index, &query).await?;
+
+    // The file must be the one the client uploaded at this index
+    if let Some(leaf) = stored_state.leaves.get(file_index) {
+        let reason = if leaf.name != file_name {
+            format!("expected '{}' at this index", leaf.name)
+        } else if !ct_eq(&leaf.leaf_hash, &leaf_hash(&content)) {
+            "contents differ from the uploaded file".to_string()
         } else {
-            format!("{}{}", sibling, current_hash)
+            String::new()
         };
-        current_hash = calculate_hash(&combined);
+        if !reason.is_empty() {
+            return Err(ClientError::Verification(format!(
+                "File '{}' at index {} verification failed: {}",
+                file_name, file_index, reason
+            )));
+        }
     }
 
-    if current_hash == stored_state.root_hash {
+    // The proof must be for this index, and lead from the content to the root
+    if proof.leaf_index == file_index && proof.verify(&content, &expected_root) {
         println!(
             "File '{}' at index {} is verified and correct.",
             file_name, file_index
         );
+        if let Some(key) = stored_state.file_keys.get(&file_name) {
+            match encryption::decrypt(key, &content) {
+                Ok(plaintext) => {
+                    println!("Decrypted content: {}", String::from_utf8_lossy(&plaintext))
+                }
+                Err(e) => eprintln!("Failed to decrypt file '{}': {}", file_name, e),
+            }
+        }
     } else {
-        println!(
-            "File '{}' at index {} verification failed.",
+        println!("Calculated hash: {}", proof.root(&content));
+        println!("Stored root hash: {}", expected_root);
+        return Err(ClientError::Verification(format!(
+            "File '{}' at index {} verification failed",
             file_name, file_index
+        )));
+    }
+
+    Ok(())
+}
+
+/// Downloads a file, by its index or name, into the local storage or to `out`
+/// Files uploaded with a chunk root are fetched chunk by chunk and every chunk is verified against
+/// it, other files are verified against the stored root with their proof. The download goes to a
+/// temporary file that only replaces the destination once all of it is verified, unless `force`
+/// is set, in which case failures are reported and the file is written anyway.
+async fn download_file(
+    server_url: &str,
+    file: &str,
+    out: Option<&str>,
+    force: bool,
+) -> Result<(), ClientError> {
+    let state = load_state()?;
+    let file_index = match file.parse::<usize>() {
+        Ok(index) => Some(index),
+        Err(_) => state.leaves.iter().position(|leaf| leaf.name == file),
+    };
+    let name = file_index.and_then(|index| state.leaves.get(index).map(|leaf| leaf.name.clone()));
+    let (Some(file_index), Some(name)) = (file_index, name) else {
+        return Err(ClientError::InvalidArgument(format!(
+            "No uploaded file '{}'",
+            file
+        )));
+    };
+
+    let path = match out {
+        Some(out) => PathBuf::from(out),
+        None => {
+            ensure_storage_dir_exists()?;
+            storage_dir().join(&name)
+        }
+    };
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut output = tempfile::NamedTempFile::new_in(directory)?;
+
+    let transport = connect(server_url).await?;
+    let verified = match state.chunked_file(file_index) {
+        Some(chunked_file) => {
+            download_chunks(
+                transport.as_ref(),
+                file_index,
+                chunked_file,
+                output.as_file_mut(),
+                force,
+            )
+            .await?
+        }
+        None => {
+            let FileResponse { content, proof, .. } = transport
+                .fetch_file(file_index, &FileQuery::default())
+                .await?;
+            let verified = proof.leaf_index == file_index
+                && proof.verify(&content, &state.root_hash)
+                && ct_eq(&state.leaves[file_index].leaf_hash, &leaf_hash(&content));
+            if !verified {
+                eprintln!("'{}' failed verification against the stored root.", name);
+            }
+            if verified || force {
+                output.write_all(&content)?;
+            }
+            verified
+        }
+    };
+    if !verified && !force {
+        return Err(ClientError::Verification(format!(
+            "Download of '{}' aborted, nothing was written",
+            name
+        )));
+    }
+
+    // Encrypted uploads are stored as ciphertext; decrypt once the whole file is downloaded
+    if let Some(key) = state.file_keys.get(&name) {
+        let ciphertext = fs::read(output.path())?;
+        let plaintext = encryption::decrypt(key, &ciphertext)
+            .map_err(|e| ClientError::Encoding(format!("Failed to decrypt '{}': {}", name, e)))?;
+        fs::write(output.path(), plaintext)?;
+    }
+
+    output.persist(&path).map_err(|e| e.error)?;
+    if !verified {
+        // Written as asked, but still a failed verification
+        return Err(ClientError::Verification(format!(
+            "File '{}' written to {} WITHOUT passing verification",
+            name,
+            path.display()
+        )));
+    }
+    println!(
+        "File '{}' downloaded and verified to {}.",
+        name,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Downloads the chunks of a file into the output, verifying each against the file's chunk root
+/// Returns whether all chunks were verified; unless `force` is set, stops at the first that is not.
+async fn download_chunks(
+    transport: &dyn Transport,
+    file_index: usize,
+    chunked_file: &ChunkedFile,
+    output: &mut fs::File,
+    force: bool,
+) -> Result<bool, ClientError> {
+    let query = ChunkQuery {
+        size: Some(chunked_file.chunk_size),
+    };
+    let mut verified = true;
+    for chunk_index in 0..chunked_file.chunk_count {
+        let ChunkResponse { content, proof, .. } = transport
+            .fetch_chunk(file_index, chunk_index, &query)
+            .await?;
+
+        if proof.leaf_index != chunk_index || !proof.verify(&content, &chunked_file.chunk_root) {
+            eprintln!(
+                "Chunk {} of '{}' failed verification.",
+                chunk_index, chunked_file.name
+            );
+            verified = false;
+            if !force {
+                return Ok(false);
+            }
+        }
+
+        output.write_all(&content)?;
+        println!(
+            "Chunk {}/{} received.",
+            chunk_index + 1,
+            chunked_file.chunk_count
+        );
+    }
+    Ok(verified)
+}
+
+/// Asks the server to prove that no uploaded file has the same contents as the given file
+/// The proof is checked against the sorted root kept in the client state
+async fn prove_absent(server_url: &str, file_path: &str) -> Result<(), ClientError> {
+    let content = read_file(Path::new(file_path))?;
+    let leaf_hash = leaf_hash(&content);
+
+    let state = load_state()?;
+
+    let transport = connect(server_url).await?;
+    let proof = transport.prove_absent(&leaf_hash).await?.proof;
+
+    if !proof.verify(&leaf_hash, &state.sorted_root) {
+        println!("Stored sorted root hash: {}", state.sorted_root);
+        return Err(ClientError::Verification(format!(
+            "Non-membership proof for '{}' failed verification",
+            file_path
+        )));
+    }
+    println!("File '{}' was never uploaded.", file_path);
+
+    Ok(())
+}
+
+/// Checks a random sample of files against the stored root
+/// The server proves all sampled files with one multiproof
+async fn sample_files(server_url: &str, count: usize) -> Result<(), ClientError> {
+    let state = load_state()?;
+
+    // A fresh seed, so the server cannot know in advance which files will be asked for
+    let mut seed = [0u8; 16];
+    OsRng.fill_bytes(&mut seed);
+    let query = SampleQuery {
+        n: count,
+        seed: hex::encode(seed),
+    };
+
+    let transport = connect(server_url).await?;
+    let sample = transport.sample(&query).await?;
+
+    if !ct_eq(&sample.root, &state.root_hash) {
+        println!("Server sampled root {}", sample.root);
+        println!("Stored root hash: {}", state.root_hash);
+        return Err(ClientError::Verification(
+            "Server sampled a different root".to_string(),
+        ));
+    }
+    sample
+        .verify(query.n, &query.seed)
+        .map_err(|e| ClientError::Verification(format!("Sample verification failed: {}", e)))?;
+    println!(
+        "All {} sampled files of {} are available and verified.",
+        sample.samples.len(),
+        sample.leaf_count
+    );
+
+    Ok(())
+}
+
+async fn diff_roots(server_url: &str, from: &str, to: Option<&str>) -> Result<(), ClientError> {
+    let state = load_state()?;
+    // Snapshot names stand for their root
+    let resolve = |root: &str| {
+        state
+            .snapshots
+            .get(root)
+            .map(|snapshot| snapshot.root_hash.clone())
+            .unwrap_or_else(|| root.to_string())
+    };
+    let from = resolve(from);
+    let to = to.map(resolve).unwrap_or_else(|| state.root_hash.clone());
+
+    let transport = connect(server_url).await?;
+    let diff = transport.diff(&from, &to).await?;
+
+    println!(
+        "Version {} ({} files) -> version {} ({} files)",
+        diff.from.version, diff.from.leaf_count, diff.to.version, diff.to.leaf_count
+    );
+    if diff.changes.is_empty() {
+        println!("No files changed.");
+    }
+    for change in diff.changes {
+        let marker = match change.change {
+            ChangeKind::Added => '+',
+            ChangeKind::Removed => '-',
+        };
+        println!("{} {} {}", marker, change.index, change.name);
+    }
+
+    Ok(())
+}
+
+async fn audit_log(server_url: &str) -> Result<(), ClientError> {
+    let state = load_state()?;
+    let transport = connect(server_url).await?;
+
+    let head = transport.log_head().await?;
+    println!("Log of {} roots with root {}", head.size, head.root);
+
+    let mut consistent = true;
+    for known in &state.root_history {
+        let entry = match transport.log_entry(known.version).await {
+            Ok(entry) => entry,
+            Err(e @ TransportError::Server { .. }) => {
+                println!("Version {} is missing from the log: {}", known.version, e);
+                consistent = false;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !ct_eq(&entry.entry.root_hash, &known.root_hash) {
+            println!(
+                "Version {} is logged with root {}, but the client saw {}.",
+                known.version, entry.entry.root_hash, known.root_hash
+            );
+            consistent = false;
+        } else if !entry.verify() {
+            println!(
+                "Inclusion proof of version {} failed verification.",
+                known.version
+            );
+            consistent = false;
+        }
+    }
+
+    if !consistent {
+        return Err(ClientError::Verification(
+            "The log does not hold every root seen by the client".to_string(),
+        ));
+    }
+    println!(
+        "All {} roots seen by the client are in the log.",
+        state.root_history.len()
+    );
+
+    Ok(())
+}
+
+/// Saves the current root hash as a named snapshot
+fn create_snapshot(name: &str) -> Result<(), ClientError> {
+    ensure_storage_dir_exists()?;
+    let mut state = load_state()?;
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    state.snapshot(name, created_at);
+    save_state(&state)?;
+    println!("Snapshot '{}' saved with root {}.", name, state.root_hash);
+    Ok(())
+}
+
+/// Prints all saved snapshots
+fn list_snapshots() -> Result<(), ClientError> {
+    let state = load_state()?;
+    if state.snapshots.is_empty() {
+        println!("No snapshots saved.");
+    }
+    for (name, snapshot) in &state.snapshots {
+        println!(
+            "{}: {} (created at {})",
+            name, snapshot.root_hash, snapshot.created_at
+        );
+    }
+    Ok(())
+}
+
+/// Prints the named datasets with their roots and file counts
+fn list_datasets() -> Result<(), ClientError> {
+    let state = ClientState::load(storage_dir().join(STATE_STORAGE))?;
+    if state.datasets.is_empty() {
+        println!("No named datasets.");
+    }
+    for (name, dataset) in &state.datasets {
+        println!(
+            "{}: {} ({} files)",
+            name,
+            dataset.root_hash,
+            dataset.leaves.len()
+        );
+    }
+    Ok(())
+}
+
+/// Verifies a file by its index against the root anchored in an Ethereum contract
+/// The locally stored root is not used
+#[cfg(feature = "eth")]
+async fn verify_file_onchain(
+    server_url: &str,
+    file_index: usize,
+    rpc_url: &str,
+    contract: &str,
+    slot: &str,
+) -> Result<(), ClientError> {
+    let transport = connect(server_url).await?;
+
+    let file = fetch_file(transport.as_ref(), file_index, &FileQuery::default()).await?;
+
+    match merkleproofs::eth::verify_against_onchain_root(
+        &file.content,
+        &file.proof,
+        rpc_url,
+        contract,
+        slot,
+    )
+    .await
+    {
+        Ok((_, true)) => println!(
+            "File '{}' at index {} is verified against the on-chain root.",
+            file.name, file_index
+        ),
+        Ok((onchain_root, false)) => {
+            println!("On-chain root hash: {}", onchain_root);
+            return Err(ClientError::Verification(format!(
+                "File '{}' at index {} verification failed",
+                file.name, file_index
+            )));
+        }
+        Err(e) => {
+            return Err(TransportError::Unavailable(format!(
+                "Failed to fetch the on-chain root: {}",
+                e
+            ))
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+/// Uploads erasure-coded shards of the files, shard `i` of every file going to `servers[i]`
+/// Each server builds its own tree over its shards; all roots are kept in the client state
+async fn upload_sharded(
+    servers: &[String],
+    data_shards: usize,
+    file_paths: &[String],
+    keep: bool,
+) -> Result<(), ClientError> {
+    ensure_storage_dir_exists()?;
+
+    if data_shards == 0 || data_shards >= servers.len() {
+        return Err(ClientError::InvalidArgument(
+            "Data shard count must be between 1 and the number of servers minus one".to_string(),
+        ));
+    }
+    let parity_shards = servers.len() - data_shards;
+
+    let files = if file_paths.len() == 1 && file_paths[0] == "all" {
+        read_all_files_from_storage()?
+    } else {
+        read_specified_files(file_paths)?
+    };
+
+    // Shards per server, in file order
+    let mut server_files: Vec<Vec<FileData>> = vec![Vec::new(); servers.len()];
+    for file in &files {
+        let shards = erasure::encode(&file.content, data_shards, parity_shards)
+            .map_err(|e| ClientError::Encoding(e.to_string()))?;
+        for (i, shard) in shards.into_iter().enumerate() {
+            server_files[i].push(FileData {
+                name: format!("{}.shard{}", file.name, i),
+                content: hex::encode(shard).into_bytes(),
+            });
+        }
+    }
+
+    let mut roots = Vec::new();
+    let mut rejected = None;
+    for (server_url, shard_files) in servers.iter().zip(server_files) {
+        let mut tree = MerkleTree::new();
+        tree.build(&shard_files);
+        let root_hash = tree.root().unwrap_or_else(empty_root);
+
+        // Every server holds only this shard set, so the upload starts a fresh dataset
+        let request = UploadRequest {
+            root_hash: root_hash.clone(),
+            base_version: None,
+            leaves: shard_files.iter().map(LeafEntry::new).collect(),
+            files: shard_files,
+            staged: None,
+        };
+        let transport = connect(server_url).await?;
+        let key = idempotency_key();
+        match transport.upload(&request, &key).await {
+            Ok(_) => println!("Server {} accepted the upload.", server_url),
+            Err(e @ TransportError::Server { .. }) => {
+                eprintln!("Server {} rejected the upload: {}", server_url, e);
+                rejected = Some(e);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        roots.push(root_hash);
+    }
+
+    let mut state = load_state()?;
+    state.shard_set = Some(ShardSet {
+        servers: servers.to_vec(),
+        roots,
+        data_shards,
+        files: files
+            .iter()
+            .map(|file| ShardedFile {
+                name: file.name.clone(),
+                length: file.content.len(),
+                hash: calculate_hash(&file.content),
+            })
+            .collect(),
+    });
+    save_state(&state)?;
+    println!("Client state saved successfully.");
+
+    if let Some(e) = rejected {
+        eprintln!("Local files were not deleted.");
+        return Err(e.into());
+    }
+    delete_uploaded_files(&files, keep);
+
+    Ok(())
+}
+
+/// Reconstructs a file of the sharded upload from any `data_shards` verified shards
+/// Shards whose proof does not match their server's root are treated as missing
+async fn reconstruct_file(file_index: usize) -> Result<(), ClientError> {
+    let state = load_state()?;
+    let Some(shard_set) = state.shard_set else {
+        return Err(ClientError::InvalidArgument(
+            "No sharded upload found in the client state".to_string(),
+        ));
+    };
+    let Some(file) = shard_set.files.get(file_index) else {
+        return Err(ClientError::InvalidArgument(format!(
+            "No sharded file at index {}",
+            file_index
+        )));
+    };
+
+    let mut shards = Vec::new();
+    for (server_url, root) in shard_set.servers.iter().zip(&shard_set.roots) {
+        let fetched = match connect(server_url).await {
+            Ok(transport) => {
+                fetch_file(transport.as_ref(), file_index, &FileQuery::default()).await
+            }
+            Err(e) => Err(e),
+        };
+        let shard = match fetched {
+            Ok(proven)
+                if proven.proof.leaf_index == file_index
+                    && proven.proof.verify(&proven.content, root) =>
+            {
+                hex::decode(&proven.content).ok()
+            }
+            Ok(_) => {
+                eprintln!("Shard from {} failed verification.", server_url);
+                None
+            }
+            Err(e) => {
+                eprintln!("Failed to fetch shard from {}: {}", server_url, e);
+                None
+            }
+        };
+        shards.push(shard);
+    }
+
+    let available = shards.iter().filter(|shard| shard.is_some()).count();
+    println!(
+        "Verified {} of {} shards ({} needed).",
+        available,
+        shards.len(),
+        shard_set.data_shards
+    );
+
+    let parity_shards = shard_set.servers.len() - shard_set.data_shards;
+    let content = erasure::reconstruct(shards, shard_set.data_shards, parity_shards, file.length)
+        .map_err(|e| ClientError::Encoding(e.to_string()))?;
+
+    if !ct_eq(&calculate_hash(&content), &file.hash) {
+        return Err(ClientError::Verification(format!(
+            "Reconstructed file '{}' does not match its hash",
+            file.name
+        )));
+    }
+
+    ensure_storage_dir_exists()?;
+    let path = storage_dir().join(&file.name);
+    fs::write(&path, &content)?;
+    println!("File '{}' reconstructed and verified.", file.name);
+
+    Ok(())
+}
+
+/// Serves the given local files to peers, who can verify them with the regular `verify` command
+/// Only the read-only proof routes of the server are exposed; nothing is uploaded or deleted
+async fn share_files(port: u16, file_paths: &[String]) -> Result<(), ClientError> {
+    ensure_storage_dir_exists()?;
+
+    let files = if file_paths.len() == 1 && file_paths[0] == "all" {
+        read_all_files_from_storage()?
+    } else {
+        read_specified_files(file_paths)?
+    };
+    let file_count = files.len();
+
+    let state = Arc::new(AppState::new());
+    let version = state.insert_files(files).await?;
+    println!(
+        "Sharing {} files with root hash {} on port {}",
+        file_count, version.root_hash, port
+    );
+
+    warp::serve(proof_routes(state))
+        .run(([0, 0, 0, 0], port))
+        .await;
+    Ok(())
+}
+
+/// Runs the server over a local directory until interrupted
+async fn serve_directory(
+    config: ServerConfig,
+    port: u16,
+    import: Option<&str>,
+    mirror: Option<MirrorConfig>,
+) -> Result<(), ClientError> {
+    let server = Server::builder()
+        .config(config)
+        .bind(([0, 0, 0, 0], port))
+        .build()
+        .await
+        .map_err(|e| match e.downcast::<StorageError>() {
+            Ok(e) => ClientError::Storage(*e),
+            // The address could not be bound
+            Err(e) => ClientError::Io(io::Error::other(e.to_string())),
+        })?;
+    let state = server.state().clone();
+    if let Some(import) = import {
+        // The import is of a local directory, so its failures are not the server's
+        let version = state
+            .import_dir(Path::new(import))
+            .await
+            .map_err(|e| match e {
+                ProtocolError::Storage(e) => ClientError::Storage(e),
+                e => ClientError::InvalidArgument(format!("Failed to import {}: {}", import, e)),
+            })?;
+        println!(
+            "Imported {} as version {} with root hash {}",
+            import, version.version, version.root_hash
+        );
+    }
+    println!(
+        "Serving {} files with root hash {} on port {}",
+        state.file_count().await,
+        state.root_hash().await.unwrap_or_else(empty_root),
+        port
+    );
+
+    if let Some(mirror) = mirror {
+        println!(
+            "Cross-verifying against {} every {} seconds",
+            mirror.peer,
+            mirror.interval.as_secs()
         );
-        println!("Calculated hash: {}", current_hash);
-        println!("Stored root hash: {}", stored_state.root_hash);
+        mirror::spawn(state, mirror);
     }
 
+    server.run().await;
     Ok(())
 }
 
 /// Sends a request to the server to delete all data and state
-async fn delete_all_server_data(server_url: &str) -> Result<(), reqwest::Error> {
-    let client = Client::new();
-    let response = client
-        .delete(format!("{}/delete_all", server_url))
-        .send()
+async fn delete_all_server_data(server_url: &str) -> Result<(), ClientError> {
+    let transport = connect(server_url).await?;
+    let key = idempotency_key();
+    transport.delete_all(&key).await?;
+    println!("All server data has been deleted successfully.");
+
+    Ok(())
+}
+
+/// Encrypts the client state with the passphrase and stores it on the server
+/// The server only sees the ciphertext and an id derived from the passphrase
+async fn push_state(server_url: &str, passphrase: &str) -> Result<(), ClientError> {
+    let state_path = storage_dir().join(STATE_STORAGE);
+    let state = ClientState::load(&state_path)?;
+    let data = serde_json::to_string(&state)?;
+    let blob = encryption::encrypt_with_passphrase(passphrase, &data)
+        .map_err(|e| ClientError::Encoding(e.to_string()))?;
+
+    let transport = connect(server_url).await?;
+    let id = encryption::sync_id(passphrase);
+    transport.push_state(&id, &StateBlob { blob }).await?;
+    println!("Client state pushed successfully.");
+
+    Ok(())
+}
+
+/// Fetches the state pushed with the same passphrase and replaces the local client state with it
+async fn pull_state(server_url: &str, passphrase: &str) -> Result<(), ClientError> {
+    let transport = connect(server_url).await?;
+    let StateBlob { blob } = transport
+        .pull_state(&encryption::sync_id(passphrase))
         .await?;
+    let data = encryption::decrypt_with_passphrase(passphrase, &blob)
+        .map_err(|e| ClientError::Encoding(format!("Failed to decrypt client state: {}", e)))?;
+    let state: ClientState = serde_json::from_str(&data)?;
 
-    if response.status().is_success() {
-        println!("All server data has been deleted successfully.");
+    ensure_storage_dir_exists()?;
+    state.save(storage_dir().join(STATE_STORAGE))?;
+    println!("Client state pulled successfully.");
+
+    Ok(())
+}
+
+/// Writes a manifest of the uploaded dataset, optionally signed
+fn export_manifest(format: &str, out: &str, sign_key: Option<&str>) -> Result<(), ClientError> {
+    let state = load_state()?;
+    if state.leaves.is_empty() {
+        return Err(ClientError::InvalidArgument(
+            "No uploaded files to describe".to_string(),
+        ));
+    }
+
+    let mut manifest = Manifest::new(state.leaves)?;
+    if !ct_eq(&manifest.root_hash, &state.root_hash) {
+        return Err(ClientError::Verification(
+            "The stored leaves do not match the stored root hash".to_string(),
+        ));
+    }
+    if let Some(path) = sign_key {
+        let key: [u8; 32] =
+            hex::decode(String::from_utf8_lossy(&read_file(Path::new(path))?).trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| {
+                    ClientError::InvalidKey(format!(
+                        "{} must hold a hex-encoded 32-byte secret key",
+                        path
+                    ))
+                })?;
+        manifest.sign(&SigningKey::from_bytes(&key));
+    }
+
+    let data = match format {
+        "cbor" => manifest.to_cbor(),
+        _ => manifest.to_json().map(String::into_bytes),
+    };
+    let data = data.map_err(|e| ClientError::Encoding(e.to_string()))?;
+    fs::write(out, data).map_err(|source| ClientError::File {
+        path: out.into(),
+        source,
+    })?;
+    println!("Manifest written to {}.", out);
+    Ok(())
+}
+
+/// Checks a manifest on its own, without the client state or a server
+fn verify_manifest(path: &str) -> Result<(), ClientError> {
+    let data = read_file(Path::new(path))?;
+    let manifest = if path.ends_with(".cbor") {
+        Manifest::from_cbor(&data)
     } else {
-        eprintln!(
-            "Failed to delete server data. Status: {:?}",
-            response.status()
-        );
+        Manifest::from_json(&String::from_utf8_lossy(&data))
+    }
+    .map_err(|e| ClientError::Verification(format!("Invalid manifest: {}", e)))?;
+
+    manifest.verify().map_err(ClientError::Verification)?;
+    println!(
+        "Manifest is valid: {} files with root hash {}.",
+        manifest.files.len(),
+        manifest.root_hash
+    );
+    for signature in &manifest.signatures {
+        println!("Signed by {}", signature.public_key);
     }
+    Ok(())
+}
 
+/// Writes a new random ed25519 secret key and prints its public key
+fn generate_signing_key(path: &str) -> Result<(), ClientError> {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let key = SigningKey::from_bytes(&secret);
+    fs::write(path, hex::encode(secret)).map_err(|source| ClientError::File {
+        path: path.into(),
+        source,
+    })?;
+    println!(
+        "Secret key written to {}. Public key: {}",
+        path,
+        hex::encode(key.verifying_key().as_bytes())
+    );
     Ok(())
 }
@@ -0,0 +1,27 @@
+//! Progress bars of long-running client commands
+//! Bars are drawn on stderr and hidden when it is not a terminal, so piped output stays as it was.
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// A bar over a number of bytes, such as the bytes hashed or uploaded
+pub fn bytes(total: u64, message: &str) -> ProgressBar {
+    bar(
+        total,
+        message,
+        "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+    )
+}
+
+/// A bar over a number of files, such as the files verified
+pub fn files(total: u64, message: &str) -> ProgressBar {
+    bar(total, message, "{msg} [{bar:40}] {pos}/{len} files ({eta})")
+}
+
+fn bar(total: u64, message: &str, template: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template(template)
+        .expect("Progress templates are valid")
+        .progress_chars("=> ");
+    ProgressBar::new(total)
+        .with_style(style)
+        .with_message(message.to_string())
+}
@@ -0,0 +1,266 @@
+//! Terminal dashboard for auditing the uploaded dataset
+//! Shows the dataset's roots and the verification status of every file. Files are verified in
+//! the background, so the interface stays responsive while a large dataset is audited.
+
+use merkleproofs::client_state::ClientState;
+use merkleproofs::hashing::ct_eq;
+use merkleproofs::merkle_tree::leaf_hash;
+use merkleproofs::protocol::{FileQuery, LeafEntry};
+use merkleproofs::transport::Transport;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for a key press before redrawing
+const TICK: Duration = Duration::from_millis(100);
+
+/// Outcome of verifying the file at an index
+type CheckResult = (usize, Result<(), String>);
+
+enum FileStatus {
+    Unverified,
+    Verifying,
+    Verified,
+    Failed(String),
+}
+
+struct App {
+    server_url: String,
+    state: ClientState,
+    statuses: Vec<FileStatus>,
+    table: TableState,
+    transport: Arc<dyn Transport>,
+    results: (
+        mpsc::UnboundedSender<CheckResult>,
+        mpsc::UnboundedReceiver<CheckResult>,
+    ),
+}
+
+/// Runs the dashboard until the user quits
+pub async fn run(
+    server_url: &str,
+    transport: Arc<dyn Transport>,
+    state: ClientState,
+) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(server_url, transport, state).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// Fetches a file and checks it against the uploaded leaf and the root, without printing
+async fn check_file(
+    transport: Arc<dyn Transport>,
+    file_index: usize,
+    leaf: Option<LeafEntry>,
+    root: String,
+) -> Result<(), String> {
+    let file = transport
+        .fetch_file(file_index, &FileQuery::default())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(leaf) = leaf {
+        if leaf.name != file.name || !ct_eq(&leaf.leaf_hash, &leaf_hash(&file.content)) {
+            return Err(format!("Expected '{}' at this index", leaf.name));
+        }
+    }
+    if file.proof.leaf_index != file_index || !file.proof.verify(&file.content, &root) {
+        return Err("Proof does not match the root".to_string());
+    }
+    Ok(())
+}
+
+impl App {
+    fn new(server_url: &str, transport: Arc<dyn Transport>, state: ClientState) -> Self {
+        let statuses = state
+            .leaves
+            .iter()
+            .map(|_| FileStatus::Unverified)
+            .collect();
+        let mut table = TableState::default();
+        if !state.leaves.is_empty() {
+            table.select(Some(0));
+        }
+        Self {
+            server_url: server_url.to_string(),
+            state,
+            statuses,
+            table,
+            transport,
+            results: mpsc::unbounded_channel(),
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            while let Ok((index, result)) = self.results.1.try_recv() {
+                self.statuses[index] = match result {
+                    Ok(()) => FileStatus::Verified,
+                    Err(reason) => FileStatus::Failed(reason),
+                };
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Enter | KeyCode::Char('v') => {
+                    if let Some(index) = self.table.selected() {
+                        self.verify(index);
+                    }
+                }
+                KeyCode::Char('a') => {
+                    for index in 0..self.statuses.len() {
+                        self.verify(index);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Starts verifying a file in the background
+    fn verify(&mut self, index: usize) {
+        if index >= self.statuses.len() || matches!(self.statuses[index], FileStatus::Verifying) {
+            return;
+        }
+        self.statuses[index] = FileStatus::Verifying;
+        let check = check_file(
+            self.transport.clone(),
+            index,
+            self.state.leaves.get(index).cloned(),
+            self.state.root_hash.clone(),
+        );
+        let results = self.results.0.clone();
+        tokio::spawn(async move {
+            let _ = results.send((index, check.await));
+        });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [summary_area, main_area, progress_area, help_area] = Layout::vertical([
+            Constraint::Length(6),
+            Constraint::Min(5),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [files_area, history_area] =
+            Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)])
+                .areas(main_area);
+
+        let snapshots: Vec<&str> = self.state.snapshots.keys().map(|s| s.as_str()).collect();
+        let summary = Paragraph::new(vec![
+            Line::from(format!("Server: {}", self.server_url)),
+            Line::from(format!("Root: {}", self.state.root_hash)),
+            Line::from(format!(
+                "Files: {}  Versions: {}",
+                self.state.leaves.len(),
+                self.state.root_history.len()
+            )),
+            Line::from(format!("Snapshots: {}", snapshots.join(", "))),
+        ])
+        .block(Block::bordered().title("Dataset"));
+        frame.render_widget(summary, summary_area);
+
+        let rows = self
+            .state
+            .leaves
+            .iter()
+            .zip(&self.statuses)
+            .enumerate()
+            .map(|(index, (leaf, status))| {
+                let (text, color) = match status {
+                    FileStatus::Unverified => ("unverified".to_string(), Color::Gray),
+                    FileStatus::Verifying => ("verifying".to_string(), Color::Yellow),
+                    FileStatus::Verified => ("verified".to_string(), Color::Green),
+                    FileStatus::Failed(reason) => (format!("failed: {}", reason), Color::Red),
+                };
+                Row::new(vec![
+                    index.to_string(),
+                    leaf.name.clone(),
+                    leaf.size.to_string(),
+                    text,
+                ])
+                .style(Style::default().fg(color))
+            });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Percentage(40),
+                Constraint::Length(10),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(Row::new(vec!["Index", "Name", "Size", "Status"]).bold())
+        .highlight_style(Style::default().reversed())
+        .block(Block::bordered().title("Files"));
+        frame.render_stateful_widget(table, files_area, &mut self.table);
+
+        let history = List::new(self.state.root_history.iter().rev().map(|version| {
+            format!(
+                "v{} ({} files) {}",
+                version.version, version.leaf_count, version.root_hash
+            )
+        }))
+        .block(Block::bordered().title("Root history"));
+        frame.render_widget(history, history_area);
+
+        let total = self.statuses.len();
+        let done = self
+            .statuses
+            .iter()
+            .filter(|s| matches!(s, FileStatus::Verified | FileStatus::Failed(_)))
+            .count();
+        let failed = self
+            .statuses
+            .iter()
+            .filter(|s| matches!(s, FileStatus::Failed(_)))
+            .count();
+        let verified_bytes: usize = self
+            .state
+            .leaves
+            .iter()
+            .zip(&self.statuses)
+            .filter(|(_, s)| matches!(s, FileStatus::Verified))
+            .map(|(leaf, _)| leaf.size)
+            .sum();
+        let ratio = if total == 0 {
+            0.0
+        } else {
+            done as f64 / total as f64
+        };
+        let gauge = Gauge::default()
+            .ratio(ratio)
+            .label(format!(
+                "{}/{} checked, {} failed, {} bytes verified",
+                done, total, failed, verified_bytes
+            ))
+            .gauge_style(Style::default().fg(if failed > 0 { Color::Red } else { Color::Green }))
+            .block(Block::bordered().title("Progress"));
+        frame.render_widget(gauge, progress_area);
+
+        frame.render_widget(
+            Paragraph::new("↑/↓ select  enter verify selected  a verify all  q quit"),
+            help_area,
+        );
+    }
+}
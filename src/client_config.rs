@@ -0,0 +1,195 @@
+//! Client configuration file with named profiles
+//! The file is `merkleproofs/config.toml` in the user's configuration directory
+//! (`$XDG_CONFIG_HOME`, or `~/.config`) and holds one table per profile:
+//!
+//! ```toml
+//! [profiles.default]
+//! server_url = "https://proofs.example.com"
+//! storage_dir = "/home/me/merkle_storage"
+//! auth_token = "secret"
+//! hash_algorithm = "sha256"
+//! ca_cert = "/etc/merkleproofs/ca.pem"
+//! client_cert = "/etc/merkleproofs/client.pem"
+//! client_key = "/etc/merkleproofs/client.key"
+//! pinned_certs = ["3f:a2:..."]
+//! ```
+//!
+//! Every field is optional, and arguments given on the command line take precedence.
+
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::ClientError;
+use crate::hashing::HashAlgorithm;
+use crate::transport::CertFingerprint;
+
+/// The profile used when none is selected
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Server used when a command is given no server URL
+    pub server_url: Option<String>,
+    /// Directory of the files to upload and the client state, instead of `client_storage`
+    pub storage_dir: Option<PathBuf>,
+    /// Sent as a bearer token with every HTTP request, for servers behind a proxy that checks it
+    pub auth_token: Option<String>,
+    /// Hash algorithm of the server's trees, by name
+    #[serde(default, deserialize_with = "algorithm_by_name")]
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// PEM certificate of a CA trusted in addition to the system's roots
+    pub ca_cert: Option<PathBuf>,
+    /// PEM certificate presented to servers that require mutual TLS
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key of the client certificate
+    pub client_key: Option<PathBuf>,
+    /// SHA-256 fingerprints of the only server certificates accepted
+    #[serde(default, deserialize_with = "fingerprints")]
+    pub pinned_certs: Vec<CertFingerprint>,
+}
+
+fn algorithm_by_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HashAlgorithm>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn fingerprints<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<CertFingerprint>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|fingerprint| fingerprint.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Where the configuration file is looked for, if the configuration directory is known
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("merkleproofs").join("config.toml"))
+}
+
+impl ClientConfig {
+    pub fn parse(text: &str) -> Result<Self, ClientError> {
+        let config: Self = toml::from_str(text).map_err(|e| ClientError::Config(e.to_string()))?;
+        // Tokens go into a header, which only takes visible ASCII
+        for (name, profile) in &config.profiles {
+            if let Some(token) = &profile.auth_token {
+                if token.is_empty() || !token.bytes().all(|byte| byte.is_ascii_graphic()) {
+                    return Err(ClientError::Config(format!(
+                        "The auth token of profile '{}' must be visible ASCII",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the configuration file; a missing file is an empty configuration
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ClientError> {
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| match e {
+                ClientError::Config(reason) => {
+                    ClientError::Config(format!("{}: {}", path.as_ref().display(), reason))
+                }
+                e => e,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(ClientError::File {
+                path: path.as_ref().to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    /// The profile with the name, or the default profile when no name is given
+    /// A named profile must exist; a missing default profile is an empty one.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, ClientError> {
+        match name {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ClientError::Config(format!("No profile named '{}'", name))),
+            None => Ok(self
+                .profiles
+                .get(DEFAULT_PROFILE)
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn profiles_are_selected_by_name() {
+        let config = ClientConfig::parse(
+            r#"
+            [profiles.default]
+            server_url = "http://127.0.0.1:8000"
+
+            [profiles.prod]
+            server_url = "https://proofs.example.com"
+            storage_dir = "/data/merkle"
+            auth_token = "secret"
+            hash_algorithm = "sha256"
+            ca_cert = "/etc/merkleproofs/ca.pem"
+            pinned_certs = ["00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff"]
+            "#,
+        )
+        .unwrap();
+
+        let default = config.profile(None).unwrap();
+        assert_eq!(default.server_url.as_deref(), Some("http://127.0.0.1:8000"));
+        assert_eq!(default.storage_dir, None);
+
+        let prod = config.profile(Some("prod")).unwrap();
+        assert_eq!(prod.storage_dir, Some(PathBuf::from("/data/merkle")));
+        assert_eq!(prod.auth_token.as_deref(), Some("secret"));
+        assert_eq!(prod.hash_algorithm, Some(HashAlgorithm::Sha256));
+        assert_eq!(
+            prod.ca_cert,
+            Some(PathBuf::from("/etc/merkleproofs/ca.pem"))
+        );
+        assert_eq!(prod.pinned_certs.len(), 1);
+        assert!(default.pinned_certs.is_empty());
+
+        assert!(config.profile(Some("staging")).is_err());
+        assert_eq!(
+            ClientConfig::default().profile(None).unwrap(),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn invalid_configurations_are_reported() {
+        assert!(ClientConfig::parse("[profiles.a]\nserver = \"typo\"").is_err());
+        assert!(ClientConfig::parse("[profiles.a]\nhash_algorithm = \"md5\"").is_err());
+        assert!(ClientConfig::parse("[profiles.a]\nauth_token = \"two words\"").is_err());
+        assert!(ClientConfig::parse("[profiles.a]\npinned_certs = [\"abc\"]").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let missing = ClientConfig::load(dir.path().join("config.toml")).unwrap();
+        assert_eq!(missing, ClientConfig::default());
+    }
+}
@@ -1,32 +1,317 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::error::ClientError;
+use crate::merkle_tree::empty_root;
+use crate::protocol::{LeafEntry, RootVersion};
+
+/// What the client knows about its uploads
+/// The fields are those of the default dataset; named datasets are kept in `datasets`, each with
+/// its own root, files and history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientState {
     pub root_hash: String,
+    /// Root of the tree over the same leaves sorted by hash, for non-membership proofs
+    #[serde(default)]
+    pub sorted_root: String,
+    /// The ordered leaves of all uploaded files
+    #[serde(default)]
+    pub leaves: Vec<LeafEntry>,
+    /// Every root the dataset has had, oldest first
+    #[serde(default)]
+    pub root_history: Vec<RootVersion>,
+    /// Convergent encryption keys of encrypted uploads, by file name
+    #[serde(default)]
+    pub file_keys: HashMap<String, String>,
+    /// The most recent erasure-coded upload, if any
+    #[serde(default)]
+    pub shard_set: Option<ShardSet>,
+    /// Chunk roots of the uploaded files, by leaf index
+    /// Files uploaded without one, such as erasure-coded files, have no entry.
+    #[serde(default)]
+    pub chunk_trees: BTreeMap<usize, ChunkedFile>,
+    /// Named point-in-time copies of the root hash
+    #[serde(default)]
+    pub snapshots: BTreeMap<String, Snapshot>,
+    /// An upload that was started but not yet confirmed by the server
+    #[serde(default)]
+    pub pending_upload: Option<PendingUpload>,
+    /// States of the named datasets, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub datasets: BTreeMap<String, ClientState>,
+}
+
+/// An upload in progress, kept until the server confirms it
+/// Running the same upload again resumes it: the upload is sent with the same idempotency key, so
+/// the server answers it with the original response if it already completed, and large files
+/// continue from the last part the server acknowledged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingUpload {
+    pub idempotency_key: String,
+    /// Root of the dataset after the upload, which identifies the uploaded files and their order
+    pub root_hash: String,
+    /// Bytes of the contents of large files the server acknowledged, by file name
+    #[serde(default)]
+    pub sent: BTreeMap<String, usize>,
+}
+
+/// The chunk tree of a single uploaded file, used to verify chunks while downloading
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkedFile {
+    pub name: String,
+    pub chunk_size: usize,
+    pub chunk_count: usize,
+    pub chunk_root: String,
+}
+
+/// A root hash saved under a name, for later point-in-time audits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub root_hash: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// An erasure-coded upload spread over multiple servers
+/// Shard `i` of every file is stored on `servers[i]`, whose tree has the root `roots[i]`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardSet {
+    pub servers: Vec<String>,
+    pub roots: Vec<String>,
+    pub data_shards: usize,
+    pub files: Vec<ShardedFile>,
+}
+
+/// What is needed to reassemble and check a single erasure-coded file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardedFile {
+    pub name: String,
+    pub length: usize,
+    pub hash: String,
 }
 
 impl ClientState {
     pub fn new(root_hash: String) -> Self {
-        Self { root_hash }
+        Self {
+            root_hash,
+            sorted_root: String::new(),
+            leaves: Vec::new(),
+            root_history: Vec::new(),
+            file_keys: HashMap::new(),
+            shard_set: None,
+            chunk_trees: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            pending_upload: None,
+            datasets: BTreeMap::new(),
+        }
     }
 
     /// Loads the client state from a file
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ClientError> {
         if path.as_ref().exists() {
-            let data = fs::read_to_string(path)?;
+            let data = fs::read_to_string(&path).map_err(|source| ClientError::File {
+                path: path.as_ref().to_path_buf(),
+                source,
+            })?;
             let state = serde_json::from_str(&data)?;
             Ok(state)
         } else {
-            Ok(Self::new("".to_string())) // Default empty root hash
+            Ok(Self::new(empty_root())) // Nothing uploaded yet
         }
     }
 
+    /// Loads the state of the named dataset from a file, or the whole state for the default dataset
+    /// A dataset nothing was uploaded to yet starts empty.
+    pub fn load_dataset<P: AsRef<Path>>(path: P, name: Option<&str>) -> Result<Self, ClientError> {
+        let mut state = Self::load(path)?;
+        Ok(match name {
+            Some(name) => state
+                .datasets
+                .remove(name)
+                .unwrap_or_else(|| Self::new(empty_root())),
+            None => state,
+        })
+    }
+
+    /// Saves the state as the named dataset, keeping the other datasets stored in the file, or as
+    /// the whole state for the default dataset
+    pub fn save_dataset<P: AsRef<Path>>(
+        &self,
+        path: P,
+        name: Option<&str>,
+    ) -> Result<(), ClientError> {
+        match name {
+            Some(name) => {
+                let mut stored = Self::load(&path)?;
+                stored.datasets.insert(name.to_string(), self.clone());
+                stored.save(path)
+            }
+            None => self.save(path),
+        }
+    }
+
+    /// The chunk tree of the file at a leaf index, if it was uploaded with one
+    /// An entry recorded for another file at that index is ignored.
+    pub fn chunked_file(&self, index: usize) -> Option<&ChunkedFile> {
+        let leaf = self.leaves.get(index)?;
+        self.chunk_trees
+            .get(&index)
+            .filter(|chunked_file| chunked_file.name == leaf.name)
+    }
+
+    /// Saves the current root hash as a snapshot, replacing any snapshot of the same name
+    pub fn snapshot(&mut self, name: &str, created_at: u64) {
+        self.snapshots.insert(
+            name.to_string(),
+            Snapshot {
+                root_hash: self.root_hash.clone(),
+                created_at,
+            },
+        );
+    }
+
     /// Saves the client state to a file
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ClientError> {
         let data = serde_json::to_string(self)?;
-        fs::write(path, data)?;
-        Ok(())
+        fs::write(&path, data).map_err(|source| ClientError::File {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn load_state_without_optional_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, r#"{"root_hash":"abc"}"#).unwrap();
+
+        let state = ClientState::load(&path).unwrap();
+        assert_eq!(state.root_hash, "abc");
+        assert!(state.file_keys.is_empty());
+        assert!(state.shard_set.is_none());
+        assert!(state.snapshots.is_empty());
+    }
+
+    #[test]
+    fn chunk_trees_are_found_by_leaf_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let leaf = |name: &str| LeafEntry {
+            name: name.to_string(),
+            size: 1,
+            leaf_hash: name.to_string(),
+        };
+        let chunked_file = |name: &str| ChunkedFile {
+            name: name.to_string(),
+            chunk_size: 1,
+            chunk_count: 1,
+            chunk_root: format!("{} root", name),
+        };
+        // Leaves uploaded by an older client, whose chunk roots were kept in upload order
+        fs::write(
+            &path,
+            r#"{"root_hash":"abc","leaves":[{"name":"a","size":1,"leaf_hash":"a"}],
+                "chunked_files":[{"name":"a","chunk_size":1,"chunk_count":1,"chunk_root":"r"}]}"#,
+        )
+        .unwrap();
+        let mut state = ClientState::load(&path).unwrap();
+        assert!(state.chunked_file(0).is_none());
+
+        state.leaves.extend([leaf("b"), leaf("c")]);
+        state.chunk_trees.insert(2, chunked_file("c"));
+        assert!(state.chunked_file(1).is_none());
+        assert_eq!(state.chunked_file(2).unwrap().chunk_root, "c root");
+        state.chunk_trees.insert(1, chunked_file("other"));
+        assert!(state.chunked_file(1).is_none());
+        assert!(state.chunked_file(3).is_none());
+    }
+
+    #[test]
+    fn snapshots_survive_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut state = ClientState::new("first".to_string());
+        state.snapshot("audit", 100);
+        state.root_hash = "second".to_string();
+        state.save(&path).unwrap();
+
+        let loaded = ClientState::load(&path).unwrap();
+        assert_eq!(loaded.root_hash, "second");
+        assert_eq!(loaded.snapshots["audit"].root_hash, "first");
+        assert_eq!(loaded.snapshots["audit"].created_at, 100);
+    }
+
+    #[test]
+    fn pending_uploads_survive_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut state = ClientState::new(empty_root());
+        assert!(state.pending_upload.is_none());
+        state.pending_upload = Some(PendingUpload {
+            idempotency_key: "key".to_string(),
+            root_hash: "root".to_string(),
+            sent: BTreeMap::from([("large.txt".to_string(), 1024)]),
+        });
+        state.save(&path).unwrap();
+        assert_eq!(
+            ClientState::load(&path).unwrap().pending_upload,
+            state.pending_upload
+        );
+    }
+
+    #[test]
+    fn named_datasets_are_kept_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut default = ClientState::new("default root".to_string());
+        default.save_dataset(&path, None).unwrap();
+        let mut photos = ClientState::load_dataset(&path, Some("photos")).unwrap();
+        assert_eq!(photos.root_hash, empty_root());
+        photos.root_hash = "photos root".to_string();
+        photos.save_dataset(&path, Some("photos")).unwrap();
+
+        // Saving the default dataset keeps the named ones it was loaded with
+        default = ClientState::load_dataset(&path, None).unwrap();
+        default.snapshot("before", 0);
+        default.save_dataset(&path, None).unwrap();
+
+        assert_eq!(
+            ClientState::load_dataset(&path, Some("photos"))
+                .unwrap()
+                .root_hash,
+            "photos root"
+        );
+        let stored = ClientState::load(&path).unwrap();
+        assert_eq!(stored.root_hash, "default root");
+        assert!(stored.snapshots.contains_key("before"));
+        assert_eq!(stored.datasets.len(), 1);
+    }
+
+    #[test]
+    fn invalid_state_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            ClientState::load(&path),
+            Err(ClientError::State(_))
+        ));
+        assert_eq!(
+            ClientState::load(dir.path().join("missing.json"))
+                .unwrap()
+                .root_hash,
+            empty_root()
+        );
     }
 }
@@ -0,0 +1,124 @@
+//! Compression of request bodies
+//! Clients may send JSON bodies compressed with gzip or zstd, named in `Content-Encoding`. Servers
+//! that accept them list the encodings in an `Accept-Encoding` header on their responses, as
+//! RFC 7694 describes, so clients only compress for servers that said they can decode the body;
+//! older servers do not send the header and get uncompressed bodies.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{self, Read, Write};
+
+/// Largest body a compressed request may decompress to
+/// Bodies that would be larger are sent uncompressed.
+pub const MAX_DECODED_BODY: u64 = 1 << 30;
+/// Bodies smaller than this are not worth compressing
+pub const MIN_ENCODED_BODY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    /// The encodings servers accept, in the order clients prefer them
+    pub const ALL: [Encoding; 2] = [Encoding::Zstd, Encoding::Gzip];
+
+    /// The name of the encoding in `Content-Encoding` and `Accept-Encoding`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// The `Accept-Encoding` value servers send, listing every encoding they decode
+pub fn accept_encoding() -> String {
+    Encoding::ALL.map(|encoding| encoding.name()).join(", ")
+}
+
+/// The preferred encoding of those listed in an `Accept-Encoding` value, if any is supported
+/// Quality values are ignored, except that `q=0` excludes an encoding.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let accepted: Vec<Encoding> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let encoding = Encoding::from_name(params.next()?)?;
+            let refused = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            (!refused).then_some(encoding)
+        })
+        .collect();
+    Encoding::ALL
+        .into_iter()
+        .find(|encoding| accepted.contains(encoding))
+}
+
+pub fn compress(encoding: Encoding, data: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Zstd => zstd::encode_all(data, 0),
+    }
+}
+
+/// Decompresses the data, failing if it decompresses to more than `limit` bytes
+pub fn decompress(encoding: Encoding, data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+    let decoder: Box<dyn Read + '_> = match encoding {
+        Encoding::Gzip => Box::new(GzDecoder::new(data)),
+        Encoding::Zstd => Box::new(zstd::Decoder::new(data)?),
+    };
+    let mut decoded = Vec::new();
+    decoder.take(limit + 1).read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Body decompresses to more than {} bytes", limit),
+        ));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bodies_round_trip_within_the_limit() {
+        let body = "{\"content\":\"".to_string() + &"abc".repeat(10_000) + "\"}";
+        for encoding in Encoding::ALL {
+            let compressed = compress(encoding, body.as_bytes()).unwrap();
+            assert!(compressed.len() < body.len() / 10);
+            let decoded = decompress(encoding, &compressed, body.len() as u64).unwrap();
+            assert_eq!(decoded, body.as_bytes());
+            assert!(decompress(encoding, &compressed, body.len() as u64 - 1).is_err());
+            assert!(decompress(encoding, b"not compressed", MAX_DECODED_BODY).is_err());
+        }
+    }
+
+    #[test]
+    fn encodings_are_negotiated_from_accept_encoding() {
+        assert_eq!(negotiate(&accept_encoding()), Some(Encoding::Zstd));
+        assert_eq!(negotiate("br, GZIP"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip, zstd;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+}
@@ -0,0 +1,223 @@
+//! Proofs that a tree is an append-only extension of an earlier one
+//! A consistency proof relates the tree over the first `old_size` leaves to the tree over the
+//! first `new_size` leaves, as RFC 6962 does for its logs. The old leaves split into the largest
+//! aligned subtrees that lie wholly within them, which are unchanged in the new tree; the proof
+//! gives their hashes, from which the old root follows. Together with the hashes of the subtrees
+//! made only of new leaves, they give the new root, so a server cannot produce the proof if it
+//! rewrote any of the old leaves.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{root_height, MerkleTree, Padding};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConsistencyProof {
+    pub old_size: usize,
+    pub new_size: usize,
+    /// Hashes of the subtrees of old leaves, from left to right
+    pub old_nodes: Vec<String>,
+    /// Hashes of the subtrees of new leaves the new root also needs, from left to right
+    pub new_nodes: Vec<String>,
+}
+
+impl<H: TreeHasher> MerkleTree<H> {
+    /// Get a proof that the tree over the first `new_size` leaves extends the one over the first
+    /// `old_size`
+    /// Returns `None` unless `0 < old_size <= new_size <= leaf_count`, or if the tree is padded
+    /// other than by duplicates
+    pub fn get_consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Option<ConsistencyProof> {
+        if old_size == 0
+            || old_size > new_size
+            || new_size > self.leaf_count()
+            || self.padding() != Padding::Duplicate
+        {
+            return None;
+        }
+        let mut proof = ConsistencyProof {
+            old_size,
+            new_size,
+            old_nodes: Vec::new(),
+            new_nodes: Vec::new(),
+        };
+        self.collect_consistency(root_height(new_size + new_size % 2), 0, &mut proof);
+        Some(proof)
+    }
+
+    fn collect_consistency(&self, height: u32, position: usize, proof: &mut ConsistencyProof) {
+        let first = position << height;
+        let end = (position + 1) << height;
+        if end <= proof.old_size {
+            let node = self.node_at(height, position, proof.new_size);
+            proof.old_nodes.push(hex::encode(node));
+            return;
+        }
+        if first >= proof.old_size && end <= proof.new_size {
+            let node = self.node_at(height, position, proof.new_size);
+            proof.new_nodes.push(hex::encode(node));
+            return;
+        }
+        self.collect_consistency(height - 1, position * 2, proof);
+        if (position * 2 + 1) << (height - 1) < proof.new_size {
+            self.collect_consistency(height - 1, position * 2 + 1, proof);
+        }
+    }
+}
+
+/// State of the verifier's walk through one of the two trees
+struct Walk<'a> {
+    proof: &'a ConsistencyProof,
+    algorithm: HashAlgorithm,
+    /// Number of leaves of the tree being walked
+    size: usize,
+    old_nodes: usize,
+    new_nodes: usize,
+}
+
+impl Walk<'_> {
+    fn next(nodes: &[String], used: &mut usize) -> Result<String, MerkleError> {
+        let node = nodes
+            .get(*used)
+            .ok_or(MerkleError::MalformedProof("too few nodes"))?;
+        *used += 1;
+        Ok(node.clone())
+    }
+
+    /// The hash of the node, computed from the nodes of the proof
+    fn hash(&mut self, height: u32, position: usize) -> Result<String, MerkleError> {
+        let first = position << height;
+        let end = (position + 1) << height;
+        if end <= self.proof.old_size {
+            return Self::next(&self.proof.old_nodes, &mut self.old_nodes);
+        }
+        if first >= self.proof.old_size && end <= self.size {
+            return Self::next(&self.proof.new_nodes, &mut self.new_nodes);
+        }
+        // Only subtrees over both old and new leaves, or past the end, are left
+        if height == 0 {
+            return Err(MerkleError::MalformedProof("sizes do not form a tree"));
+        }
+
+        let left = self.hash(height - 1, position * 2)?;
+        let right = if (position * 2 + 1) << (height - 1) < self.size {
+            self.hash(height - 1, position * 2 + 1)?
+        } else {
+            // The last node of an odd level is paired with itself
+            left.clone()
+        };
+        Ok(self.algorithm.hash_nodes(&left, &right))
+    }
+}
+
+impl ConsistencyProof {
+    /// Calculates the old and the new root implied by the proof
+    pub fn roots(&self, algorithm: HashAlgorithm) -> Result<(String, String), MerkleError> {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return Err(MerkleError::MalformedProof("sizes do not form a tree"));
+        }
+        Ok((
+            self.root_at(algorithm, self.old_size)?,
+            self.root_at(algorithm, self.new_size)?,
+        ))
+    }
+
+    /// The root of the tree of the size, which must use every node it is given
+    fn root_at(&self, algorithm: HashAlgorithm, size: usize) -> Result<String, MerkleError> {
+        let mut walk = Walk {
+            proof: self,
+            algorithm,
+            size,
+            old_nodes: 0,
+            new_nodes: 0,
+        };
+        let root = walk.hash(root_height(size + size % 2), 0)?;
+        // The old tree uses none of the new nodes
+        let new_nodes = if size == self.new_size {
+            self.new_nodes.len()
+        } else {
+            0
+        };
+        if walk.old_nodes != self.old_nodes.len() || walk.new_nodes != new_nodes {
+            return Err(MerkleError::MalformedProof("unused nodes"));
+        }
+        Ok(root)
+    }
+
+    /// Checks that the tree with the new root extends the tree with the old root
+    pub fn verify(&self, old_root: &str, new_root: &str) -> bool {
+        match self.roots(HashAlgorithm::Sha256) {
+            Ok((old, new)) => ct_eq(&old, old_root) && ct_eq(&new, new_root),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn tree(leaf_count: usize) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        tree.build(&(0..leaf_count).map(|i| i.to_string()).collect::<Vec<_>>());
+        tree
+    }
+
+    #[test]
+    fn every_prefix_is_consistent() {
+        let latest = tree(21);
+        for new_size in 1..=21 {
+            let new_root = tree(new_size).root().unwrap();
+            for old_size in 1..=new_size {
+                let old_root = tree(old_size).root().unwrap();
+                let proof = latest.get_consistency_proof(old_size, new_size).unwrap();
+                assert!(
+                    proof.verify(&old_root, &new_root),
+                    "{} to {}",
+                    old_size,
+                    new_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rewritten_history_is_detected() {
+        let latest = tree(10);
+        let proof = latest.get_consistency_proof(6, 10).unwrap();
+        let new_root = latest.root().unwrap();
+        assert!(proof.verify(&tree(6).root().unwrap(), &new_root));
+
+        // A server that changed an old leaf has a different old root
+        let mut rewritten: Vec<String> = (0..6).map(|i| i.to_string()).collect();
+        rewritten[2] = "changed".to_string();
+        let mut forged = MerkleTree::new();
+        forged.build(&rewritten);
+        assert!(!proof.verify(&forged.root().unwrap(), &new_root));
+        assert!(!proof.verify(&tree(5).root().unwrap(), &new_root));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let latest = tree(10);
+        let (old_root, new_root) = (tree(6).root().unwrap(), latest.root().unwrap());
+        let proof = latest.get_consistency_proof(6, 10).unwrap();
+
+        let mut extra = proof.clone();
+        extra.new_nodes.push(new_root.clone());
+        assert!(!extra.verify(&old_root, &new_root));
+
+        let mut resized = proof;
+        resized.new_size = 12;
+        assert!(!resized.verify(&old_root, &new_root));
+
+        assert!(latest.get_consistency_proof(0, 10).is_none());
+        assert!(latest.get_consistency_proof(7, 6).is_none());
+        assert!(latest.get_consistency_proof(6, 11).is_none());
+    }
+}
@@ -0,0 +1,313 @@
+//! Content-addressed storage shared between datasets
+//! Objects are stored once under the SHA-256 hash of their bytes, and every dataset keeps its own
+//! index from file names to object hashes, so identical files uploaded to different datasets, or
+//! twice to the same one, take the space of a single object. Every object counts the index
+//! entries referring to it and is deleted when the last one goes away.
+//!
+//! A store directory holds `objects/<hash>`, the reference counts in `refs.json` and the index of
+//! every dataset in `datasets/<name>.json`. Within a process, everyone opening a directory shares
+//! one store; several processes must not use the same directory at once.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::error::StorageError;
+use crate::merkle_tree::calculate_hash;
+use crate::storage::{check_name, StorageBackend};
+
+/// Name of the content-addressed backend
+/// Its location is the store directory, optionally followed by `#<dataset>`
+pub const CONTENT_BACKEND: &str = "cas";
+
+/// Dataset of a location without a `#<dataset>` suffix
+pub const DEFAULT_DATASET: &str = "default";
+
+const REFS_FILE: &str = "refs.json";
+
+/// Space saved by sharing objects, as served by the stats endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DedupStats {
+    /// Number of distinct objects
+    pub objects: usize,
+    /// Bytes taken by the objects
+    pub stored_bytes: u64,
+    /// Bytes of all files referring to the objects, as if each were stored on its own
+    pub referenced_bytes: u64,
+    pub saved_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct ObjectRef {
+    count: u64,
+    size: u64,
+}
+
+/// Objects shared by the datasets of a directory
+pub struct ContentStore {
+    dir: PathBuf,
+    refs: Mutex<HashMap<String, ObjectRef>>,
+}
+
+impl ContentStore {
+    /// The store in the directory, shared with everyone else in this process using it
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Arc<Self>> {
+        static STORES: OnceLock<Mutex<HashMap<PathBuf, Arc<ContentStore>>>> = OnceLock::new();
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let key = dir.canonicalize()?;
+
+        let mut stores = STORES
+            .get_or_init(Default::default)
+            .lock()
+            .expect("Content store registry lock poisoned");
+        if let Some(store) = stores.get(&key) {
+            return Ok(store.clone());
+        }
+        let store = Arc::new(Self::load(key.clone())?);
+        stores.insert(key, store.clone());
+        Ok(store)
+    }
+
+    /// Reads the reference counts of a store directory
+    fn load(dir: PathBuf) -> io::Result<Self> {
+        let refs = match fs::read(dir.join(REFS_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            dir,
+            refs: Mutex::new(refs),
+        })
+    }
+
+    /// A dataset's view of the store, as a storage backend
+    pub fn dataset(self: &Arc<Self>, name: &str) -> Result<ContentBackend, StorageError> {
+        check_name(name)?;
+        let index_path = self.dir.join("datasets").join(format!("{}.json", name));
+        let index = match fs::read(&index_path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| StorageError::Corrupt {
+                name: index_path.display().to_string(),
+                reason: e.to_string(),
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(ContentBackend {
+            store: self.clone(),
+            index_path,
+            index: Mutex::new(index),
+        })
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("objects").join(hash)
+    }
+
+    /// Adds a reference to the object with the data, storing it if it is new
+    fn add(&self, data: &[u8]) -> io::Result<String> {
+        let hash = calculate_hash(hex::encode(data));
+        let mut refs = self.refs.lock().expect("Content store lock poisoned");
+        match refs.get_mut(&hash) {
+            Some(object) => object.count += 1,
+            None => {
+                let path = self.object_path(&hash);
+                fs::create_dir_all(path.parent().expect("Objects are in a directory"))?;
+                fs::write(path, data)?;
+                refs.insert(
+                    hash.clone(),
+                    ObjectRef {
+                        count: 1,
+                        size: data.len() as u64,
+                    },
+                );
+            }
+        }
+        self.save_refs(&refs)?;
+        Ok(hash)
+    }
+
+    /// Drops a reference to the object, deleting it with the last one
+    fn release(&self, hash: &str) -> io::Result<()> {
+        let mut refs = self.refs.lock().expect("Content store lock poisoned");
+        let Some(object) = refs.get_mut(hash) else {
+            return Ok(());
+        };
+        object.count -= 1;
+        if object.count == 0 {
+            refs.remove(hash);
+            fs::remove_file(self.object_path(hash))?;
+        }
+        self.save_refs(&refs)
+    }
+
+    fn save_refs(&self, refs: &HashMap<String, ObjectRef>) -> io::Result<()> {
+        fs::write(self.dir.join(REFS_FILE), serde_json::to_vec(refs)?)
+    }
+
+    fn read(&self, hash: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.object_path(hash))
+    }
+
+    pub fn stats(&self) -> DedupStats {
+        let refs = self.refs.lock().expect("Content store lock poisoned");
+        let stored_bytes = refs.values().map(|object| object.size).sum();
+        let referenced_bytes = refs.values().map(|object| object.size * object.count).sum();
+        DedupStats {
+            objects: refs.len(),
+            stored_bytes,
+            referenced_bytes,
+            saved_bytes: referenced_bytes - stored_bytes,
+        }
+    }
+}
+
+/// The files of one dataset, kept as references to shared objects
+pub struct ContentBackend {
+    store: Arc<ContentStore>,
+    index_path: PathBuf,
+    /// File name to object hash
+    index: Mutex<BTreeMap<String, String>>,
+}
+
+impl ContentBackend {
+    fn save_index(&self, index: &BTreeMap<String, String>) -> io::Result<()> {
+        fs::create_dir_all(
+            self.index_path
+                .parent()
+                .expect("Indexes are in a directory"),
+        )?;
+        fs::write(&self.index_path, serde_json::to_vec(index)?)
+    }
+}
+
+impl StorageBackend for ContentBackend {
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), StorageError> {
+        check_name(name)?;
+        let mut index = self.index.lock().expect("Content index lock poisoned");
+        let hash = self.store.add(data)?;
+        if let Some(previous) = index.insert(name.to_string(), hash) {
+            self.store.release(&previous)?;
+        }
+        Ok(self.save_index(&index)?)
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        let hash = self
+            .index
+            .lock()
+            .expect("Content index lock poisoned")
+            .get(name)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))?;
+        Ok(self.store.read(&hash)?)
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self
+            .index
+            .lock()
+            .expect("Content index lock poisoned")
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn clear(&self) -> Result<(), StorageError> {
+        let mut index = self.index.lock().expect("Content index lock poisoned");
+        for hash in index.values() {
+            self.store.release(hash)?;
+        }
+        index.clear();
+        Ok(self.save_index(&index)?)
+    }
+
+    fn dedup_stats(&self) -> Option<DedupStats> {
+        Some(self.store.stats())
+    }
+}
+
+/// Opens the dataset of a `<dir>` or `<dir>#<dataset>` location
+pub(crate) fn create(location: &str) -> Result<Arc<dyn StorageBackend>, StorageError> {
+    let (dir, dataset) = location
+        .split_once('#')
+        .unwrap_or((location, DEFAULT_DATASET));
+    Ok(Arc::new(ContentStore::open(dir)?.dataset(dataset)?))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::create_backend;
+
+    #[test]
+    fn identical_files_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ContentStore::open(dir.path()).unwrap();
+        let first = store.dataset("first").unwrap();
+        let second = store.dataset("second").unwrap();
+
+        first.write("a.txt", b"shared").unwrap();
+        first.write("copy.txt", b"shared").unwrap();
+        second.write("b.txt", b"shared").unwrap();
+        second.write("c.txt", b"own").unwrap();
+
+        assert_eq!(second.read("b.txt").unwrap(), b"shared");
+        assert_eq!(first.list().unwrap(), vec!["a.txt", "copy.txt"]);
+        assert_eq!(
+            store.stats(),
+            DedupStats {
+                objects: 2,
+                stored_bytes: 9,
+                referenced_bytes: 21,
+                saved_bytes: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn unreferenced_objects_are_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ContentStore::open(dir.path()).unwrap();
+        let first = store.dataset("first").unwrap();
+        let second = store.dataset("second").unwrap();
+        first.write("a.txt", b"shared").unwrap();
+        second.write("a.txt", b"shared").unwrap();
+        let object = store.object_path(&calculate_hash(hex::encode(b"shared")));
+
+        first.clear().unwrap();
+        assert!(object.exists());
+        assert_eq!(second.read("a.txt").unwrap(), b"shared");
+
+        // Overwriting the last reference releases the old object
+        second.write("a.txt", b"changed").unwrap();
+        assert!(!object.exists());
+        assert_eq!(store.stats().objects, 1);
+    }
+
+    #[test]
+    fn datasets_and_counts_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        create_backend(CONTENT_BACKEND, &dir.path().to_string_lossy())
+            .unwrap()
+            .write("a.txt", b"a")
+            .unwrap();
+
+        let reloaded = Arc::new(ContentStore::load(dir.path().to_path_buf()).unwrap());
+        let dataset = reloaded.dataset(DEFAULT_DATASET).unwrap();
+        assert_eq!(dataset.read("a.txt").unwrap(), b"a");
+        assert_eq!(reloaded.stats().objects, 1);
+
+        let location = format!("{}#other", dir.path().display());
+        let other = create_backend(CONTENT_BACKEND, &location).unwrap();
+        assert!(other.list().unwrap().is_empty());
+        assert!(
+            create_backend(CONTENT_BACKEND, &format!("{}#../x", dir.path().display())).is_err()
+        );
+    }
+}
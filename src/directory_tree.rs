@@ -0,0 +1,327 @@
+//! Trees over directory hierarchies
+//! Every directory has its own tree over its entries, ordered by name. A file entry commits to
+//! the file's leaf hash and a directory entry to the root of the subdirectory's tree, so the root
+//! of the top-level directory commits to the whole structure.
+//!
+//! Changing a file only rebuilds the trees of the directories on its path.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{empty_root, leaf_hash, MerkleProof, MerkleTree};
+
+#[derive(Debug, Default)]
+pub struct DirectoryTree {
+    top: Directory,
+}
+
+#[derive(Debug, Default)]
+struct Directory {
+    /// File name to leaf hash
+    files: BTreeMap<String, String>,
+    dirs: BTreeMap<String, Directory>,
+    /// The tree over the entries of this directory
+    tree: MerkleTree,
+}
+
+/// Proof that a file or directory is at a path, from its own directory up to the top
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PathProof {
+    pub levels: Vec<MerkleProof>,
+}
+
+/// Hash of a directory entry, binding its kind and name to what it commits to
+fn entry_hash(is_dir: bool, name: &str, hash: &str) -> String {
+    let kind = if is_dir { "dir" } else { "file" };
+    leaf_hash(format!("{}:{}:{}", kind, name, hash))
+}
+
+/// Splits a path into its components, rejecting empty ones
+fn components(path: &str) -> Result<Vec<&str>, String> {
+    let components: Vec<&str> = path.split('/').collect();
+    if components.iter().any(|c| c.is_empty()) {
+        return Err(format!("Invalid path {}", path));
+    }
+    Ok(components)
+}
+
+impl DirectoryTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the tree over `(path, content)` pairs, with `/` separating directories
+    pub fn build(files: &[(String, String)]) -> Result<Self, String> {
+        let mut tree = Self::new();
+        for (path, content) in files {
+            tree.insert(path, content)?;
+        }
+        Ok(tree)
+    }
+
+    /// Adds or replaces the file at the path, creating its directories as needed
+    /// Only the directories on the path are rebuilt
+    pub fn insert(&mut self, path: &str, content: &str) -> Result<(), String> {
+        self.top.insert(&components(path)?, leaf_hash(content))
+    }
+
+    /// Adds or replaces the file with the leaf hash at the path, such as a hash from
+    /// `hashing::hash_file` for a file that is not held in memory
+    pub fn insert_leaf_hash(&mut self, path: &str, leaf_hash: String) -> Result<(), String> {
+        self.top.insert(&components(path)?, leaf_hash)
+    }
+
+    /// Removes the file at the path, along with directories left empty
+    /// Returns whether the file existed
+    pub fn remove(&mut self, path: &str) -> bool {
+        match components(path) {
+            Ok(components) => self.top.remove(&components),
+            Err(_) => false,
+        }
+    }
+
+    /// The root over the whole hierarchy, or `None` when there are no files
+    pub fn root(&self) -> Option<String> {
+        self.top.tree.root()
+    }
+
+    /// The root of the directory at the path
+    pub fn directory_root(&self, path: &str) -> Option<String> {
+        self.top.directory(&components(path).ok()?)?.tree.root()
+    }
+
+    /// Proves that the file or directory at the path is in the hierarchy
+    pub fn prove(&self, path: &str) -> Option<PathProof> {
+        let components = components(path).ok()?;
+        let mut levels = Vec::new();
+        let mut directory = &self.top;
+        for name in &components {
+            levels.push(directory.prove_entry(name)?);
+            match directory.dirs.get(*name) {
+                Some(subdirectory) => directory = subdirectory,
+                None => break,
+            }
+        }
+        if levels.len() != components.len() {
+            return None;
+        }
+        levels.reverse();
+        Some(PathProof { levels })
+    }
+}
+
+impl Directory {
+    fn insert(&mut self, components: &[&str], leaf_hash: String) -> Result<(), String> {
+        let (name, rest) = components.split_first().expect("Paths are never empty");
+        if rest.is_empty() {
+            if self.dirs.contains_key(*name) {
+                return Err(format!("{} is a directory", name));
+            }
+            self.files.insert(name.to_string(), leaf_hash);
+        } else {
+            if self.files.contains_key(*name) {
+                return Err(format!("{} is a file", name));
+            }
+            self.dirs
+                .entry(name.to_string())
+                .or_default()
+                .insert(rest, leaf_hash)?;
+        }
+        self.rehash();
+        Ok(())
+    }
+
+    fn remove(&mut self, components: &[&str]) -> bool {
+        let (name, rest) = components.split_first().expect("Paths are never empty");
+        let removed = if rest.is_empty() {
+            self.files.remove(*name).is_some()
+        } else {
+            match self.dirs.get_mut(*name) {
+                Some(subdirectory) => {
+                    let removed = subdirectory.remove(rest);
+                    if subdirectory.is_empty() {
+                        self.dirs.remove(*name);
+                    }
+                    removed
+                }
+                None => false,
+            }
+        };
+        if removed {
+            self.rehash();
+        }
+        removed
+    }
+
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty()
+    }
+
+    fn directory(&self, components: &[&str]) -> Option<&Directory> {
+        match components.split_first() {
+            Some((name, rest)) => self.dirs.get(*name)?.directory(rest),
+            None => Some(self),
+        }
+    }
+
+    /// Entry names and hashes, ordered by name
+    fn entries(&self) -> Vec<(&str, String)> {
+        let mut entries: Vec<(&str, String)> = self
+            .files
+            .iter()
+            .map(|(name, hash)| (name.as_str(), entry_hash(false, name, hash)))
+            .chain(self.dirs.iter().map(|(name, dir)| {
+                let root = dir.tree.root().unwrap_or_else(empty_root);
+                (name.as_str(), entry_hash(true, name, &root))
+            }))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    fn rehash(&mut self) {
+        let hashes = self.entries().into_iter().map(|(_, hash)| hash).collect();
+        let mut tree = MerkleTree::new();
+        tree.build_from_leaf_hashes(hashes)
+            .expect("Entry hashes are valid");
+        self.tree = tree;
+    }
+
+    fn prove_entry(&self, name: &str) -> Option<MerkleProof> {
+        let index = self.entries().iter().position(|(n, _)| *n == name)?;
+        self.tree.get_merkle_proof(index).ok()
+    }
+}
+
+impl PathProof {
+    /// Checks that the file with the contents is at the path under the root
+    pub fn verify_file(&self, path: &str, content: &str, root: &str) -> bool {
+        self.verify(path, false, &leaf_hash(content), root)
+    }
+
+    /// Checks that the file with the leaf hash is at the path under the root
+    pub fn verify_file_hash(&self, path: &str, leaf_hash: &str, root: &str) -> bool {
+        self.verify(path, false, leaf_hash, root)
+    }
+
+    /// Checks that the directory with the root `directory_root` is at the path under the root
+    pub fn verify_directory(&self, path: &str, directory_root: &str, root: &str) -> bool {
+        self.verify(path, true, directory_root, root)
+    }
+
+    fn verify(&self, path: &str, is_dir: bool, hash: &str, root: &str) -> bool {
+        let Ok(components) = components(path) else {
+            return false;
+        };
+        if components.len() != self.levels.len() {
+            return false;
+        }
+
+        let mut current = hash.to_string();
+        let mut is_dir = is_dir;
+        for (name, proof) in components.iter().rev().zip(&self.levels) {
+            if !proof.is_well_formed() {
+                return false;
+            }
+            let leaf = entry_hash(is_dir, name, &current);
+            current = HashAlgorithm::Sha256.root_from_leaf_hash(&leaf, proof);
+            is_dir = true;
+        }
+        ct_eq(&current, root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn files() -> Vec<(String, String)> {
+        vec![
+            ("README.md".to_string(), "readme".to_string()),
+            ("src/main.rs".to_string(), "fn main() {}".to_string()),
+            ("src/util/mod.rs".to_string(), "pub mod a;".to_string()),
+            ("src/util/a.rs".to_string(), "pub fn a() {}".to_string()),
+            ("docs/guide.md".to_string(), "guide".to_string()),
+        ]
+    }
+
+    #[test]
+    fn root_commits_to_structure() {
+        let tree = DirectoryTree::build(&files()).unwrap();
+
+        let mut moved = files();
+        moved[1].0 = "main.rs".to_string();
+        let moved = DirectoryTree::build(&moved).unwrap();
+
+        assert_ne!(tree.root(), moved.root());
+        assert!(DirectoryTree::new().root().is_none());
+    }
+
+    #[test]
+    fn insertion_order_does_not_matter() {
+        let mut reversed = files();
+        reversed.reverse();
+        assert_eq!(
+            DirectoryTree::build(&files()).unwrap().root(),
+            DirectoryTree::build(&reversed).unwrap().root()
+        );
+    }
+
+    #[test]
+    fn file_and_directory_proofs() {
+        let tree = DirectoryTree::build(&files()).unwrap();
+        let root = tree.root().unwrap();
+
+        let proof = tree.prove("src/util/a.rs").unwrap();
+        assert_eq!(proof.levels.len(), 3);
+        assert!(proof.verify_file("src/util/a.rs", "pub fn a() {}", &root));
+        assert!(!proof.verify_file("src/util/a.rs", "changed", &root));
+        assert!(!proof.verify_file("src/util/b.rs", "pub fn a() {}", &root));
+        assert!(!proof.verify_file("src/a.rs", "pub fn a() {}", &root));
+
+        let util_root = tree.directory_root("src/util").unwrap();
+        let proof = tree.prove("src/util").unwrap();
+        assert!(proof.verify_directory("src/util", &util_root, &root));
+        assert!(!proof.verify_file("src/util", &util_root, &root));
+
+        assert!(tree.prove("src/missing.rs").is_none());
+        assert!(tree.prove("src/main.rs/inner").is_none());
+    }
+
+    #[test]
+    fn updating_a_file_matches_rebuilding() {
+        let mut tree = DirectoryTree::build(&files()).unwrap();
+        let docs_root = tree.directory_root("docs");
+        tree.insert("src/util/a.rs", "pub fn b() {}").unwrap();
+
+        let mut updated = files();
+        updated[3].1 = "pub fn b() {}".to_string();
+        assert_eq!(tree.root(), DirectoryTree::build(&updated).unwrap().root());
+        assert_eq!(tree.directory_root("docs"), docs_root);
+    }
+
+    #[test]
+    fn removing_prunes_empty_directories() {
+        let mut tree = DirectoryTree::build(&files()).unwrap();
+        assert!(tree.remove("docs/guide.md"));
+        assert!(!tree.remove("docs/guide.md"));
+        assert!(tree.directory_root("docs").is_none());
+
+        let remaining = files().into_iter().filter(|(p, _)| p != "docs/guide.md");
+        let remaining: Vec<(String, String)> = remaining.collect();
+        assert_eq!(
+            tree.root(),
+            DirectoryTree::build(&remaining).unwrap().root()
+        );
+    }
+
+    #[test]
+    fn files_and_directories_cannot_share_a_path() {
+        let mut tree = DirectoryTree::build(&files()).unwrap();
+        assert!(tree.insert("src/main.rs/inner", "x").is_err());
+        assert!(tree.insert("src/util", "x").is_err());
+        assert!(tree.insert("src//a.rs", "x").is_err());
+    }
+}
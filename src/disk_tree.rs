@@ -0,0 +1,300 @@
+//! SHA-256 trees kept on disk for datasets too large to hold in memory
+//! The file starts with a 16-byte header, the magic `MRKLTREE` followed by the leaf count as a
+//! little-endian `u64`. Then come the levels from the leaves up to the root, each node a fixed
+//! 32-byte record holding the raw digest. Levels are stored without the duplicated last node of
+//! odd levels, so the offset of every node follows from the leaf count alone.
+//!
+//! The file is memory-mapped when opened, and a proof only reads the records on its path, so the
+//! operating system pages in a few kilobytes however many leaves the tree has. Roots and proofs
+//! are the same as those of a `MerkleTree` over the same leaf hashes.
+
+use memmap2::Mmap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::hashing::{HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{MerkleProof, Position, ProofNode};
+
+const MAGIC: &[u8; 8] = b"MRKLTREE";
+const HEADER_LEN: usize = 16;
+/// Size of a node record
+const NODE_LEN: usize = 32;
+
+pub struct DiskTree {
+    map: Mmap,
+    leaf_count: usize,
+    /// Index of the first node of every level, from the leaves up
+    level_starts: Vec<usize>,
+}
+
+/// Number of nodes on every level of a tree with `leaf_count` leaves
+/// Even a single leaf has a parent, as it is paired with itself
+fn level_sizes(leaf_count: usize) -> Vec<usize> {
+    if leaf_count == 0 {
+        return Vec::new();
+    }
+    let mut sizes = vec![leaf_count];
+    let mut size = leaf_count;
+    while size > 1 || sizes.len() == 1 {
+        size = size.div_ceil(2);
+        sizes.push(size);
+    }
+    sizes
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn decode_node(hash: &str) -> io::Result<[u8; NODE_LEN]> {
+    hex::decode(hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid SHA-256 hash {}", hash),
+            )
+        })
+}
+
+impl DiskTree {
+    /// Writes the tree over the hex-encoded leaf hashes to the path and opens it
+    /// Leaves are consumed one at a time and every level is built by reading the previous one
+    /// back from the file, so memory use does not depend on the number of leaves
+    pub fn create<P, I>(path: P, leaf_hashes: I) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = String>,
+    {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&0u64.to_le_bytes())?;
+
+        let mut leaf_count = 0usize;
+        for hash in leaf_hashes {
+            writer.write_all(&decode_node(&hash)?)?;
+            leaf_count += 1;
+        }
+
+        let algorithm = HashAlgorithm::Sha256;
+        let sizes = level_sizes(leaf_count);
+        let mut level_start = 0;
+        for pair in sizes.windows(2) {
+            writer.flush()?;
+            let mut reader = BufReader::new(File::open(path)?);
+            reader.seek(SeekFrom::Start(
+                (HEADER_LEN + level_start * NODE_LEN) as u64,
+            ))?;
+
+            let mut left = [0u8; NODE_LEN];
+            let mut right = [0u8; NODE_LEN];
+            for parent in 0..pair[1] {
+                reader.read_exact(&mut left)?;
+                // The last node of an odd level is paired with itself
+                if 2 * parent + 1 < pair[0] {
+                    reader.read_exact(&mut right)?;
+                } else {
+                    right = left;
+                }
+                writer.write_all(&algorithm.node_digest(&left, &right))?;
+            }
+            level_start += pair[0];
+        }
+
+        writer.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        writer.write_all(&(leaf_count as u64).to_le_bytes())?;
+        writer.flush()?;
+        drop(writer);
+        Self::open(path)
+    }
+
+    /// Maps an existing tree file, checking that its length matches its leaf count
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the tree file is only written by `create`, which finishes before mapping it;
+        // changing it while it is mapped is not supported
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_LEN || &map[..MAGIC.len()] != MAGIC {
+            return Err(invalid("Not a tree file"));
+        }
+        // The leaf count is read from the file, so the sizes derived from it may overflow
+        let mismatch = || invalid("Tree file does not match its leaf count");
+        let leaf_count = usize::try_from(u64::from_le_bytes(
+            map[MAGIC.len()..HEADER_LEN]
+                .try_into()
+                .expect("Header has a fixed size"),
+        ))
+        .map_err(|_| mismatch())?;
+
+        let sizes = level_sizes(leaf_count);
+        let mut level_starts = Vec::with_capacity(sizes.len());
+        let mut node_count: usize = 0;
+        for size in sizes {
+            level_starts.push(node_count);
+            node_count = node_count.checked_add(size).ok_or_else(mismatch)?;
+        }
+        let len = node_count
+            .checked_mul(NODE_LEN)
+            .and_then(|len| len.checked_add(HEADER_LEN));
+        if len != Some(map.len()) {
+            return Err(mismatch());
+        }
+
+        Ok(Self {
+            map,
+            leaf_count,
+            level_starts,
+        })
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// The node at a position of a level, read directly from its record
+    fn node(&self, level: usize, index: usize) -> String {
+        let offset = HEADER_LEN + (self.level_starts[level] + index) * NODE_LEN;
+        hex::encode(&self.map[offset..offset + NODE_LEN])
+    }
+
+    /// Number of nodes on a level
+    fn level_size(&self, level: usize) -> usize {
+        match self.level_starts.get(level + 1) {
+            Some(next) => next - self.level_starts[level],
+            None => 1,
+        }
+    }
+
+    /// The root, or `None` for a tree without leaves
+    pub fn root(&self) -> Option<String> {
+        let top = self.level_starts.len().checked_sub(1)?;
+        Some(self.node(top, 0))
+    }
+
+    pub fn leaf_hash(&self, index: usize) -> Option<String> {
+        (index < self.leaf_count).then(|| self.node(0, index))
+    }
+
+    /// Get the Merkle proof for a given index, reading one node per level
+    pub fn get_merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count {
+            return None;
+        }
+
+        let mut nodes = Vec::with_capacity(self.level_starts.len() - 1);
+        let mut current_index = index;
+        for level in 0..self.level_starts.len() - 1 {
+            let sibling_index = current_index ^ 1;
+            let sibling = if sibling_index < self.level_size(level) {
+                self.node(level, sibling_index)
+            } else {
+                self.node(level, current_index)
+            };
+            let position = if sibling_index > current_index {
+                Position::Right
+            } else {
+                Position::Left
+            };
+            nodes.push(ProofNode { sibling, position });
+            current_index /= 2;
+        }
+        Some(MerkleProof {
+            leaf_index: index,
+            leaf_count: self.leaf_count,
+            nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{calculate_hash, MerkleTree};
+
+    fn leaf_hashes(count: usize) -> Vec<String> {
+        (0..count).map(|i| calculate_hash(i.to_string())).collect()
+    }
+
+    #[test]
+    fn matches_the_in_memory_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        for count in [1, 2, 3, 5, 8, 13, 100] {
+            let path = dir.path().join(format!("{}.tree", count));
+            let disk_tree = DiskTree::create(&path, leaf_hashes(count)).unwrap();
+            let mut tree = MerkleTree::new();
+            tree.build_from_leaf_hashes(leaf_hashes(count)).unwrap();
+
+            assert_eq!(disk_tree.leaf_count(), count);
+            assert_eq!(disk_tree.root(), tree.root());
+            for index in 0..count {
+                assert_eq!(
+                    disk_tree.get_merkle_proof(index),
+                    tree.get_merkle_proof(index).ok()
+                );
+            }
+            assert!(disk_tree.get_merkle_proof(count).is_none());
+        }
+    }
+
+    #[test]
+    fn reopened_tree_reads_the_same_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leaves.tree");
+        let created = DiskTree::create(&path, leaf_hashes(10)).unwrap();
+        let opened = DiskTree::open(&path).unwrap();
+
+        assert_eq!(opened.root(), created.root());
+        assert_eq!(opened.leaf_hash(9), Some(leaf_hashes(10)[9].clone()));
+        assert_eq!(opened.get_merkle_proof(4), created.get_merkle_proof(4));
+    }
+
+    #[test]
+    fn empty_tree_has_no_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = DiskTree::create(dir.path().join("empty.tree"), Vec::new()).unwrap();
+        assert_eq!(tree.root(), None);
+        assert!(tree.get_merkle_proof(0).is_none());
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leaves.tree");
+        DiskTree::create(&path, leaf_hashes(4)).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(DiskTree::open(&path).is_err());
+
+        std::fs::write(&path, b"not a tree").unwrap();
+        assert!(DiskTree::open(&path).is_err());
+
+        assert!(DiskTree::create(&path, vec!["abc".to_string()]).is_err());
+    }
+
+    #[test]
+    fn huge_leaf_counts_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("leaves.tree");
+        DiskTree::create(&path, leaf_hashes(4)).unwrap();
+        let data = std::fs::read(&path).unwrap();
+
+        for leaf_count in [u64::MAX, 1 << 63, (1 << 59) + 4] {
+            let mut forged = data.clone();
+            forged[MAGIC.len()..HEADER_LEN].copy_from_slice(&leaf_count.to_le_bytes());
+            std::fs::write(&path, &forged).unwrap();
+            let error = DiskTree::open(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
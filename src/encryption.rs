@@ -0,0 +1,188 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, OsRng};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use sha2::{Digest, Sha256};
+
+/// Convergent encryption of file contents
+/// The key for each file is derived from the hash of its plaintext, so identical files always
+/// encrypt to identical ciphertexts. The server can then deduplicate and build its tree over
+/// the ciphertexts without ever being able to read the contents.
+///
+/// Because every key is bound to exactly one plaintext, a fixed nonce is safe here.
+const NONCE: [u8; 12] = [0u8; 12];
+
+/// Derives the per-file key from the plaintext contents
+pub fn convergent_key(plaintext: impl AsRef<[u8]>) -> [u8; 32] {
+    Sha256::digest(plaintext.as_ref()).into()
+}
+
+/// Encrypts the contents with their convergent key
+/// Returns the hex-encoded key and the hex-encoded ciphertext
+pub fn encrypt(
+    plaintext: impl AsRef<[u8]>,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let plaintext = plaintext.as_ref();
+    let key = convergent_key(plaintext);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&NONCE), plaintext)
+        .map_err(|_| "Failed to encrypt contents")?;
+    Ok((hex::encode(key), hex::encode(ciphertext)))
+}
+
+/// Decrypts hex-encoded ciphertext with a hex-encoded convergent key
+/// Fails if the ciphertext was tampered with or if the plaintext does not match the key
+pub fn decrypt(
+    key_hex: &str,
+    ciphertext_hex: impl AsRef<[u8]>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let key = hex::decode(key_hex)?;
+    if key.len() != 32 {
+        return Err("Invalid key length".into());
+    }
+    let ciphertext = hex::decode(ciphertext_hex)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&NONCE), ciphertext.as_ref())
+        .map_err(|_| "Failed to decrypt contents")?;
+
+    // The key doubles as a commitment to the plaintext
+    if convergent_key(&plaintext).as_slice() != key.as_slice() {
+        return Err("Decrypted contents do not match the key".into());
+    }
+    Ok(plaintext)
+}
+
+/// Length of the random salt prepended to passphrase-encrypted blobs
+const SALT_LEN: usize = 16;
+/// Length of the random nonce following the salt
+const NONCE_LEN: usize = 12;
+/// PBKDF2-HMAC-SHA256 rounds used to stretch a passphrase into a key
+const KEY_STRETCH_ROUNDS: u32 = 100_000;
+/// Fixed salt of the sync id, separating it from the keys derived with random salts
+const SYNC_ID_SALT: &[u8] = b"merkleproofs-state-sync";
+
+/// Derives an encryption key from a passphrase and a salt with PBKDF2-HMAC-SHA256
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), salt, KEY_STRETCH_ROUNDS)
+}
+
+/// Identifier under which a passphrase's blob is stored on the server
+/// Stretched like the key, so testing a guessed passphrase against the id costs the server as
+/// much as testing it against the blob
+pub fn sync_id(passphrase: &str) -> String {
+    hex::encode(passphrase_key(passphrase, SYNC_ID_SALT))
+}
+
+/// Encrypts the contents with a key derived from a passphrase
+/// Returns the hex-encoded salt, nonce and ciphertext; the salt and nonce are random every time
+pub fn encrypt_with_passphrase(
+    passphrase: &str,
+    plaintext: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = passphrase_key(passphrase, &salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| "Failed to encrypt contents")?;
+
+    let mut blob = salt.to_vec();
+    blob.extend_from_slice(&nonce);
+    blob.extend(ciphertext);
+    Ok(hex::encode(blob))
+}
+
+/// Decrypts a blob created by `encrypt_with_passphrase`
+/// Fails if the passphrase is wrong or the blob was tampered with
+pub fn decrypt_with_passphrase(
+    passphrase: &str,
+    blob_hex: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let blob = hex::decode(blob_hex)?;
+    if blob.len() < SALT_LEN + NONCE_LEN {
+        return Err("Encrypted blob is too short".into());
+    }
+    let (salt, rest) = blob.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key = passphrase_key(passphrase, salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt contents")?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt_roundtrip() {
+        let (key, ciphertext) = encrypt("This is file 1").unwrap();
+        assert_ne!(ciphertext, hex::encode("This is file 1"));
+        assert_eq!(decrypt(&key, &ciphertext).unwrap(), b"This is file 1");
+    }
+
+    #[test]
+    fn identical_plaintexts_encrypt_identically() {
+        let first = encrypt("same contents").unwrap();
+        let second = encrypt("same contents").unwrap();
+        assert_eq!(first, second);
+
+        let other = encrypt("other contents").unwrap();
+        assert_ne!(first.1, other.1);
+    }
+
+    #[test]
+    fn decrypt_with_wrong_key_fails() {
+        let (_, ciphertext) = encrypt("secret").unwrap();
+        let (other_key, _) = encrypt("not secret").unwrap();
+        assert!(decrypt(&other_key, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn passphrase_roundtrip() {
+        let blob = encrypt_with_passphrase("correct horse", "{\"root_hash\":\"abc\"}").unwrap();
+        assert_eq!(
+            decrypt_with_passphrase("correct horse", &blob).unwrap(),
+            "{\"root_hash\":\"abc\"}"
+        );
+        assert!(decrypt_with_passphrase("wrong horse", &blob).is_err());
+
+        // Random salt and nonce, so the same state never encrypts identically
+        let other = encrypt_with_passphrase("correct horse", "{\"root_hash\":\"abc\"}").unwrap();
+        assert_ne!(blob, other);
+    }
+
+    #[test]
+    fn sync_id_does_not_reveal_the_key() {
+        assert_eq!(sync_id("correct horse"), sync_id("correct horse"));
+        assert_ne!(sync_id("correct horse"), sync_id("wrong horse"));
+        assert_ne!(
+            sync_id("correct horse"),
+            hex::encode(passphrase_key("correct horse", &[]))
+        );
+    }
+
+    #[test]
+    fn sync_id_is_not_a_fast_hash_of_the_passphrase() {
+        let id = sync_id("correct horse");
+        assert_eq!(
+            id,
+            hex::encode(passphrase_key("correct horse", SYNC_ID_SALT))
+        );
+        for fast in [
+            Sha256::new()
+                .chain_update(SYNC_ID_SALT)
+                .chain_update(b"correct horse")
+                .finalize(),
+            Sha256::digest(b"correct horse"),
+        ] {
+            assert_ne!(id, hex::encode(fast));
+        }
+    }
+}
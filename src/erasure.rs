@@ -0,0 +1,99 @@
+//! Erasure coding of file contents across multiple servers
+//! Each file is split into `data_shards` equally sized shards plus `parity_shards` parity shards.
+//! Any `data_shards` of the resulting shards are enough to reconstruct the original contents.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+/// Splits the contents into data and parity shards
+/// The last data shard is zero-padded, so the original length is needed for reconstruction
+pub fn encode(
+    content: &[u8],
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let rs = ReedSolomon::new(data_shards, parity_shards)?;
+
+    // Shards must be non-empty and of equal size
+    let shard_len = content.len().div_ceil(data_shards).max(1);
+    let mut shards: Vec<Vec<u8>> = (0..data_shards + parity_shards)
+        .map(|i| {
+            let start = (i * shard_len).min(content.len());
+            let end = ((i + 1) * shard_len).min(content.len());
+            let mut shard = if i < data_shards {
+                content[start..end].to_vec()
+            } else {
+                Vec::new()
+            };
+            shard.resize(shard_len, 0);
+            shard
+        })
+        .collect();
+
+    rs.encode(&mut shards)?;
+    Ok(shards)
+}
+
+/// Reconstructs the original contents of `length` bytes
+/// Missing or rejected shards are given as `None`; at least `data_shards` must be present
+pub fn reconstruct(
+    mut shards: Vec<Option<Vec<u8>>>,
+    data_shards: usize,
+    parity_shards: usize,
+    length: usize,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let rs = ReedSolomon::new(data_shards, parity_shards)?;
+    rs.reconstruct_data(&mut shards)?;
+
+    let mut content: Vec<u8> = shards
+        .into_iter()
+        .take(data_shards)
+        .flat_map(|shard| shard.unwrap_or_default())
+        .collect();
+    content.truncate(length);
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn encode_and_reconstruct_all_shards() {
+        let content = b"This is file 1".to_vec();
+        let shards = encode(&content, 3, 2).unwrap();
+        assert_eq!(shards.len(), 5);
+
+        let shards = shards.into_iter().map(Some).collect();
+        assert_eq!(reconstruct(shards, 3, 2, content.len()).unwrap(), content);
+    }
+
+    #[test]
+    fn reconstruct_from_any_k_shards() {
+        let content = b"This is a somewhat longer file content".to_vec();
+        let shards = encode(&content, 3, 2).unwrap();
+
+        let mut partial: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        partial[0] = None;
+        partial[3] = None;
+        assert_eq!(reconstruct(partial, 3, 2, content.len()).unwrap(), content);
+    }
+
+    #[test]
+    fn reconstruct_with_too_few_shards_fails() {
+        let content = b"abc".to_vec();
+        let shards = encode(&content, 2, 1).unwrap();
+
+        let mut partial: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        partial[0] = None;
+        partial[1] = None;
+        assert!(reconstruct(partial, 2, 1, content.len()).is_err());
+    }
+
+    #[test]
+    fn encode_empty_content() {
+        let shards = encode(&[], 2, 1).unwrap();
+        let shards = shards.into_iter().map(Some).collect();
+        assert!(reconstruct(shards, 2, 1, 0).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+//! Errors of the library modules
+//! Every layer has its own error enum, so callers can match on why an operation failed instead of
+//! parsing messages. Errors of a lower layer convert into those of the layers built on top of it:
+//! tree and storage errors into protocol errors, and all of them into client errors.
+
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+use warp::reject::Reject;
+
+use crate::merkle_tree::Padding;
+use crate::transport::TransportError;
+
+/// Why an operation on a tree or a proof failed
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MerkleError {
+    #[error("Tree is empty")]
+    EmptyTree,
+    /// The tree was never built, so it has no root yet
+    #[error("Tree is not built")]
+    NotBuilt,
+    #[error("Leaf index {index} is out of range for {leaf_count} leaves")]
+    IndexOutOfRange { index: usize, leaf_count: usize },
+    /// An earlier size of a tree that is larger than the tree
+    #[error("Tree size {tree_size} is out of range for {leaf_count} leaves")]
+    SizeOutOfRange { tree_size: usize, leaf_count: usize },
+    #[error("Not supported for trees padded with {0:?}")]
+    UnsupportedPadding(Padding),
+    /// The proof does not fit the tree it claims to be of
+    #[error("Malformed proof: {0}")]
+    MalformedProof(&'static str),
+    /// A stored tree whose levels do not fit its leaf count
+    #[error("Malformed tree: {0}")]
+    MalformedTree(&'static str),
+    #[error("Proof does not lead to the root")]
+    RootMismatch,
+    /// A hash that is not the hex encoding of a digest
+    #[error("Invalid hash {0}")]
+    InvalidHash(String),
+    /// The blocking task building a tree panicked
+    #[error("Tree building task failed")]
+    BuildFailed,
+    /// A node must have at least two children
+    #[error("Invalid arity {0}")]
+    InvalidArity(usize),
+}
+
+/// Why the settings of a server could not be applied
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Invalid value {value:?} for setting {name}")]
+    InvalidSetting { name: String, value: String },
+}
+
+/// Why a storage backend operation failed
+#[derive(Error, Debug)]
+pub enum StorageError {
+    /// Nothing is stored under the name
+    #[error("File {0} not found")]
+    NotFound(String),
+    /// The name would escape a flat store
+    #[error("Invalid file name {0}")]
+    InvalidName(String),
+    #[error("Unknown storage backend {0}")]
+    UnknownBackend(String),
+    /// A stored file could not be decoded
+    #[error("Stored file {name} is corrupt: {reason}")]
+    Corrupt { name: String, reason: String },
+    #[error("Storage I/O failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Why the server rejected a request, or a response failed its checks
+#[derive(Error, Debug)]
+pub enum ProtocolError {
+    #[error("Version {0} is not available")]
+    VersionNotAvailable(usize),
+    #[error("Root {0} is not available")]
+    RootNotAvailable(String),
+    #[error("File at index {0} not found")]
+    FileNotFound(usize),
+    #[error("File at index {index} not found in version {version}")]
+    FileNotInVersion { index: usize, version: usize },
+    #[error("Chunk {chunk} of file at index {index} not found")]
+    ChunkNotFound { chunk: usize, index: usize },
+    #[error("Leaf hash {0} is present")]
+    LeafPresent(String),
+    #[error("Leaf hash {0} not found")]
+    LeafNotFound(String),
+    #[error("Invalid leaf range {start}..{end} for {leaf_count} leaves")]
+    InvalidRange {
+        start: usize,
+        end: usize,
+        leaf_count: usize,
+    },
+    #[error("Upload is based on version {base:?}, but the latest version is {latest:?}")]
+    StaleBase {
+        base: Option<usize>,
+        latest: Option<usize>,
+    },
+    /// Files whose contents were changed in transit
+    #[error("Received contents do not match the declared leaf hash of {}", .0.join(", "))]
+    CorruptedFiles(Vec<String>),
+    #[error("Expected {expected} leaves, got {got}")]
+    LeafCount { expected: usize, got: usize },
+    #[error("Upload would change the leaf at index {index} ({name})")]
+    LeafChanged { index: usize, name: String },
+    #[error("Leaf at index {index} does not match file {name}")]
+    LeafMismatch { index: usize, name: String },
+    #[error("File {name} appears at both index {first} and index {second}")]
+    DuplicateLeaf {
+        name: String,
+        first: usize,
+        second: usize,
+    },
+    #[error("Part of {name} at offset {offset} does not follow the {received} bytes received")]
+    PartOutOfOrder {
+        name: String,
+        offset: usize,
+        received: usize,
+    },
+    #[error("Staged parts may hold at most {0} bytes")]
+    StagedBytesLimit(usize),
+    #[error("At most {0} uploads can be staged at once")]
+    StagedUploadsLimit(usize),
+    #[error("Root hash does not match the uploaded files")]
+    RootMismatch,
+    #[error("Idempotency key was already used for a different request")]
+    IdempotencyKeyReused,
+    #[error("Imports are disabled")]
+    ImportsDisabled,
+    #[error("Invalid import path {0}")]
+    InvalidImportPath(String),
+    #[error("File {0} is already in the dataset")]
+    FileExists(String),
+    /// The server keeps its own metadata under the name
+    #[error("File name {0} is reserved by the server")]
+    ReservedName(String),
+    #[error("No files to import in {0}")]
+    NothingToImport(String),
+    #[error("At most {0} leaves can be sampled at once")]
+    SampleTooLarge(usize),
+    #[error("Expected leaves {expected:?}, got {got:?}")]
+    UnexpectedSample {
+        expected: Vec<usize>,
+        got: Vec<usize>,
+    },
+    #[error("Proof of the sampled leaves does not verify")]
+    InvalidSampleProof,
+    #[error("Invalid state id")]
+    InvalidStateId,
+    #[error("Unsupported content encoding {0}")]
+    UnsupportedEncoding(String),
+    #[error("Invalid request body: {0}")]
+    InvalidBody(String),
+    #[error(transparent)]
+    Merkle(#[from] MerkleError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl Reject for ProtocolError {}
+
+/// Why a client command failed
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    Merkle(#[from] MerkleError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("Invalid client state: {0}")]
+    State(#[from] serde_json::Error),
+    #[error("I/O failed: {0}")]
+    Io(#[from] io::Error),
+    /// A local file or directory could not be read or written
+    #[error("Cannot access {}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    /// Encrypting, decrypting or erasure coding failed
+    #[error("Encoding failed: {0}")]
+    Encoding(String),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    /// The configuration file or the selected profile is invalid
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    /// The command names something that does not exist or cannot be done, such as an unknown
+    /// snapshot
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// A file, proof or root did not verify
+    #[error("{0}")]
+    Verification(String),
+}
+
+/// Exit status of the client when a command fails for another reason than those below
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit status for invalid arguments, the same as for command lines that do not parse
+pub const EXIT_USAGE: i32 = 2;
+/// Exit status when local files or the storage directory could not be read or written
+pub const EXIT_IO: i32 = 3;
+/// Exit status when the server could not be reached or rejected a request
+pub const EXIT_SERVER: i32 = 4;
+/// Exit status when a file, proof or root did not verify
+pub const EXIT_VERIFICATION: i32 = 5;
+
+impl ClientError {
+    /// The exit status of the client when a command fails with the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClientError::InvalidArgument(_) => EXIT_USAGE,
+            ClientError::Io(_) | ClientError::File { .. } | ClientError::Storage(_) => EXIT_IO,
+            ClientError::Transport(_) | ClientError::Protocol(_) => EXIT_SERVER,
+            ClientError::Verification(_) => EXIT_VERIFICATION,
+            ClientError::Merkle(_)
+            | ClientError::State(_)
+            | ClientError::Encoding(_)
+            | ClientError::InvalidKey(_)
+            | ClientError::Config(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn errors_convert_up_the_layers() {
+        let storage = StorageError::NotFound("a.txt".to_string());
+        let protocol = ProtocolError::from(storage);
+        assert!(matches!(
+            protocol,
+            ProtocolError::Storage(StorageError::NotFound(ref name)) if name == "a.txt"
+        ));
+        assert_eq!(protocol.to_string(), "File a.txt not found");
+
+        let client = ClientError::from(ProtocolError::from(MerkleError::EmptyTree));
+        assert!(matches!(
+            client,
+            ClientError::Protocol(ProtocolError::Merkle(MerkleError::EmptyTree))
+        ));
+        assert_eq!(
+            ProtocolError::CorruptedFiles(vec!["a".to_string(), "b".to_string()]).to_string(),
+            "Received contents do not match the declared leaf hash of a, b"
+        );
+    }
+
+    #[test]
+    fn client_errors_have_distinct_exit_codes() {
+        let io = ClientError::File {
+            path: PathBuf::from("client_storage/a.txt"),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert_eq!(io.exit_code(), EXIT_IO);
+        assert!(io
+            .to_string()
+            .starts_with("Cannot access client_storage/a.txt: "));
+
+        let server = ClientError::from(TransportError::Server {
+            status: 500,
+            message: "rejected".to_string(),
+        });
+        assert_eq!(server.exit_code(), EXIT_SERVER);
+        let unreachable = ClientError::from(TransportError::Unavailable("refused".to_string()));
+        assert_eq!(unreachable.exit_code(), EXIT_SERVER);
+
+        let failed = ClientError::Verification("Proof does not verify".to_string());
+        assert_eq!(failed.exit_code(), EXIT_VERIFICATION);
+        assert_eq!(
+            ClientError::Config("bad".to_string()).exit_code(),
+            EXIT_FAILURE
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Verification against a root hash anchored in an Ethereum smart contract
+//! The root is read from a storage slot of the contract via the `eth_getStorageAt` JSON-RPC method.
+//! Roots are 32-byte SHA-256 hashes, so they fit exactly into one storage word.
+//!
+//! Trees built with `HashAlgorithm::Keccak256` hash like Solidity's
+//! `keccak256(abi.encodePacked(left, right))`, so their roots and proofs can also be checked by
+//! the contract itself. Trees built with `HashAlgorithm::SortedKeccak256` sort the two children
+//! of every node first, as OpenZeppelin's `MerkleProof.verify` does, so their proofs are just the
+//! list of siblings.
+
+use serde_json::json;
+use sha3::{Digest, Keccak256};
+
+use crate::hashing::{ct_eq, decode_hash, Hash};
+use crate::merkle_tree::MerkleProof;
+
+/// Fetches the root hash stored at `slot` of `contract` at the latest block
+/// The slot can be given as a decimal number or as a `0x`-prefixed hex number
+pub async fn fetch_root(
+    rpc_url: &str,
+    contract: &str,
+    slot: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_getStorageAt",
+        "params": [contract, normalize_slot(slot)?, "latest"]
+    });
+
+    let response: serde_json::Value = reqwest::Client::new()
+        .post(rpc_url)
+        .json(&request)
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if let Some(error) = response.get("error") {
+        return Err(format!("JSON-RPC error: {}", error).into());
+    }
+    let word = response["result"]
+        .as_str()
+        .ok_or("Missing result in JSON-RPC response")?;
+    parse_storage_word(word)
+}
+
+/// Verifies leaf contents and their proof against the root stored in the contract
+/// Returns the on-chain root along with the verification result
+pub async fn verify_against_onchain_root(
+    content: &[u8],
+    proof: &MerkleProof,
+    rpc_url: &str,
+    contract: &str,
+    slot: &str,
+) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    let onchain_root = fetch_root(rpc_url, contract, slot).await?;
+    let verified = proof.verify(content, &onchain_root);
+    Ok((onchain_root, verified))
+}
+
+/// `keccak256(abi.encodePacked(a, b))` of the two digests in ascending order
+pub fn sorted_pair_digest(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    Keccak256::new()
+        .chain_update(first)
+        .chain_update(second)
+        .finalize()
+        .into()
+}
+
+/// The siblings of a proof, which is all OpenZeppelin's `MerkleProof.verify` takes
+pub fn sorted_proof(proof: &MerkleProof) -> Vec<String> {
+    proof
+        .nodes
+        .iter()
+        .map(|node| node.sibling.clone())
+        .collect()
+}
+
+/// Calculates the root implied by a leaf hash and its siblings in a sorted-pair tree, like
+/// `MerkleProof.processProof`
+/// Returns `None` if a hash is not valid
+pub fn process_sorted_proof(leaf_hash: &str, siblings: &[String]) -> Option<String> {
+    let mut current = decode_hash(leaf_hash)?;
+    for sibling in siblings {
+        current = sorted_pair_digest(&current, &decode_hash(sibling)?);
+    }
+    Some(hex::encode(current))
+}
+
+/// Checks a leaf hash and its siblings against the root of a sorted-pair tree
+pub fn verify_sorted_proof(leaf_hash: &str, siblings: &[String], root: &str) -> bool {
+    process_sorted_proof(leaf_hash, siblings).is_some_and(|computed| ct_eq(&computed, root))
+}
+
+/// Converts a storage slot into the quantity format expected by JSON-RPC
+fn normalize_slot(slot: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let value = match slot.strip_prefix("0x") {
+        Some(hex_slot) => u64::from_str_radix(hex_slot, 16)?,
+        None => slot.parse::<u64>()?,
+    };
+    Ok(format!("0x{:x}", value))
+}
+
+/// Converts a `0x`-prefixed 32-byte storage word into a root hash in the crate's hex format
+fn parse_storage_word(word: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let hex_word = word.strip_prefix("0x").unwrap_or(word).to_lowercase();
+    if hex_word.len() != 64 || hex::decode(&hex_word).is_err() {
+        return Err(format!("Invalid storage word: {}", word).into());
+    }
+    Ok(hex_word)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::calculate_hash;
+
+    #[test]
+    fn normalize_decimal_and_hex_slots() {
+        assert_eq!(normalize_slot("0").unwrap(), "0x0");
+        assert_eq!(normalize_slot("10").unwrap(), "0xa");
+        assert_eq!(normalize_slot("0x0A").unwrap(), "0xa");
+        assert!(normalize_slot("slot").is_err());
+    }
+
+    #[test]
+    fn parse_storage_word_as_root() {
+        let root = calculate_hash("a");
+        let word = format!("0x{}", root.to_uppercase());
+        assert_eq!(parse_storage_word(&word).unwrap(), root);
+        assert!(parse_storage_word("0x1234").is_err());
+    }
+
+    #[test]
+    fn keccak_trees_hash_like_solidity() {
+        use crate::hashing::{HashAlgorithm, TreeHasher};
+        use crate::merkle_tree::{root_from_proof, MerkleTree};
+
+        let algorithm = HashAlgorithm::Keccak256;
+        // keccak256("a")
+        let leaf_a = "3ac225168df54212a25c1c01fd35bebfea408fdac2e31ddd6f80a4bbf9a5f1cb";
+        assert_eq!(algorithm.hash_leaf("a"), leaf_a);
+
+        let elements = vec!["a".to_string(), "b".to_string()];
+        let mut tree = MerkleTree::with_algorithm(algorithm);
+        tree.build(&elements);
+
+        // keccak256(abi.encodePacked(keccak256("a"), keccak256("b")))
+        let mut packed = hex::decode(leaf_a).unwrap();
+        packed.extend(Keccak256::digest(b"b"));
+        let root = hex::encode(Keccak256::digest(&packed));
+        assert_eq!(tree.root(), Some(root.clone()));
+
+        let proof = tree.get_merkle_proof(1).unwrap();
+        assert_eq!(algorithm.root_from_proof("b", &proof), root);
+        assert_ne!(root_from_proof("b", &proof), root);
+    }
+
+    #[test]
+    fn sorted_pair_trees_hash_like_openzeppelin() {
+        use crate::hashing::{HashAlgorithm, TreeHasher};
+        use crate::merkle_tree::MerkleTree;
+
+        let algorithm = HashAlgorithm::SortedKeccak256;
+        let elements: Vec<String> = ["a", "b", "c"].iter().map(|e| e.to_string()).collect();
+        let mut tree = MerkleTree::with_algorithm(algorithm);
+        tree.build(&elements);
+
+        // The children of every node are hashed in ascending order
+        let leaves: Vec<Vec<u8>> = elements
+            .iter()
+            .map(|e| Keccak256::digest(e.as_bytes()).to_vec())
+            .collect();
+        let sorted_hash = |a: &[u8], b: &[u8]| {
+            let (first, second) = if a <= b { (a, b) } else { (b, a) };
+            Keccak256::new()
+                .chain_update(first)
+                .chain_update(second)
+                .finalize()
+                .to_vec()
+        };
+        let ab = sorted_hash(&leaves[0], &leaves[1]);
+        let cc = sorted_hash(&leaves[2], &leaves[2]);
+        let root = hex::encode(sorted_hash(&ab, &cc));
+        assert_eq!(tree.root(), Some(root.clone()));
+
+        for (index, element) in elements.iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            let leaf = algorithm.hash_leaf(element);
+            assert!(verify_sorted_proof(&leaf, &sorted_proof(&proof), &root));
+            assert!(algorithm.verify_proof(element, &proof, &root));
+        }
+
+        let siblings = sorted_proof(&tree.get_merkle_proof(0).unwrap());
+        assert!(!verify_sorted_proof(
+            &algorithm.hash_leaf("c"),
+            &siblings,
+            &root
+        ));
+        assert!(!verify_sorted_proof("00", &siblings, &root));
+        assert_eq!(
+            "keccak256-sorted".parse::<HashAlgorithm>(),
+            Ok(HashAlgorithm::SortedKeccak256)
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Read-only GraphQL API for dashboards
+//! Served at `/graphql` with the `graphql` feature. A query asks for the dataset once and then
+//! for any of its files, roots and proofs, which are all read from the same snapshot, so a
+//! dashboard gets a consistent view in a single request. Lists are paginated with `offset` and
+//! `limit`.
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Error, Object, Schema};
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::{Filter, Rejection, Reply};
+
+use crate::merkle_tree::{MerkleProof, Position};
+use crate::protocol::{FileQuery, LeafEntry, RootVersion};
+use crate::server::{AppState, Dataset};
+
+/// Page size when a query does not set a limit
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest page a query may ask for
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+pub type MerkleSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// The schema over the server's state
+pub fn schema(state: Arc<AppState>) -> MerkleSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(state)
+        .finish()
+}
+
+/// The `/graphql` route, answering both GET and POST queries
+pub fn routes(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("graphql")
+        .and(warp::path::end())
+        .and(async_graphql_warp::graphql(schema(state)))
+        .and_then(
+            |(schema, request): (MerkleSchema, async_graphql::Request)| async move {
+                Ok::<_, Infallible>(async_graphql_warp::GraphQLResponse::from(
+                    schema.execute(request).await,
+                ))
+            },
+        )
+}
+
+/// The items of a list from `offset`, at most `limit` of them
+fn page<T>(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Vec<T> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    items
+        .into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit)
+        .collect()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The dataset as of the latest upload
+    async fn dataset(&self, ctx: &Context<'_>) -> DatasetView {
+        DatasetView(ctx.data_unchecked::<Arc<AppState>>().dataset())
+    }
+}
+
+pub struct DatasetView(Arc<Dataset>);
+
+#[Object(name = "Dataset")]
+impl DatasetView {
+    /// The latest root, or null before the first upload
+    async fn root_hash(&self) -> Option<String> {
+        self.0.root_hash()
+    }
+
+    async fn file_count(&self) -> usize {
+        self.0.file_count()
+    }
+
+    /// Total size of all files in bytes
+    async fn total_size(&self) -> usize {
+        self.0.leaves().iter().map(|leaf| leaf.size).sum()
+    }
+
+    /// Files in leaf order, optionally only those whose name contains `name_contains` or those
+    /// that were part of a version
+    async fn files(
+        &self,
+        name_contains: Option<String>,
+        version: Option<usize>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<FilePage, Error> {
+        let leaf_count = match version {
+            Some(version) => {
+                self.0
+                    .root_history()
+                    .get(version)
+                    .ok_or_else(|| Error::new(format!("Version {} is not available", version)))?
+                    .leaf_count
+            }
+            None => self.0.leaves().len(),
+        };
+        let matching: Vec<FileView> = self
+            .0
+            .leaves()
+            .iter()
+            .take(leaf_count)
+            .enumerate()
+            .filter(|(_, leaf)| {
+                name_contains
+                    .as_ref()
+                    .is_none_or(|part| leaf.name.contains(part.as_str()))
+            })
+            .map(|(index, leaf)| FileView {
+                dataset: self.0.clone(),
+                index,
+                leaf: leaf.clone(),
+            })
+            .collect();
+        Ok(FilePage {
+            total_count: matching.len(),
+            items: page(matching, offset, limit),
+        })
+    }
+
+    /// The file at a leaf index
+    async fn file(&self, index: usize) -> Option<FileView> {
+        self.0.leaves().get(index).map(|leaf| FileView {
+            dataset: self.0.clone(),
+            index,
+            leaf: leaf.clone(),
+        })
+    }
+
+    /// Every root the dataset has had, newest first
+    async fn roots(&self, offset: Option<usize>, limit: Option<usize>) -> RootPage {
+        let roots: Vec<RootView> = self
+            .0
+            .root_history()
+            .iter()
+            .rev()
+            .cloned()
+            .map(RootView)
+            .collect();
+        RootPage {
+            total_count: roots.len(),
+            items: page(roots, offset, limit),
+        }
+    }
+}
+
+pub struct FileView {
+    dataset: Arc<Dataset>,
+    index: usize,
+    leaf: LeafEntry,
+}
+
+#[Object(name = "File")]
+impl FileView {
+    async fn index(&self) -> usize {
+        self.index
+    }
+
+    async fn name(&self) -> &str {
+        &self.leaf.name
+    }
+
+    /// Size in bytes
+    async fn size(&self) -> usize {
+        self.leaf.size
+    }
+
+    async fn leaf_hash(&self) -> &str {
+        &self.leaf.leaf_hash
+    }
+
+    /// The proof of the file against the latest root, or against the root of a version
+    async fn proof(&self, ctx: &Context<'_>, version: Option<usize>) -> Result<ProofView, Error> {
+        let historical_version = self
+            .dataset
+            .requested_version(&FileQuery {
+                root: None,
+                version,
+            })
+            .map_err(|_| Error::new(format!("Version {:?} is not available", version)))?;
+        let (proof, root) = ctx
+            .data_unchecked::<Arc<AppState>>()
+            .file_proof(&self.dataset, self.index, historical_version.as_ref())
+            .await
+            .ok_or_else(|| Error::new(format!("File {} is not in this version", self.index)))?;
+        Ok(ProofView { root, proof })
+    }
+}
+
+pub struct ProofView {
+    root: String,
+    proof: MerkleProof,
+}
+
+#[Object(name = "Proof")]
+impl ProofView {
+    /// The root the proof is valid for
+    async fn root(&self) -> &str {
+        &self.root
+    }
+
+    async fn leaf_index(&self) -> usize {
+        self.proof.leaf_index
+    }
+
+    async fn leaf_count(&self) -> usize {
+        self.proof.leaf_count
+    }
+
+    /// Sibling hashes from the leaf up, each with the side of its parent it is on
+    async fn nodes(&self) -> Vec<ProofStep> {
+        self.proof
+            .nodes
+            .iter()
+            .map(|node| ProofStep {
+                sibling: node.sibling.clone(),
+                position: match node.position {
+                    Position::Left => ProofPosition::Left,
+                    Position::Right => ProofPosition::Right,
+                },
+            })
+            .collect()
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct ProofStep {
+    sibling: String,
+    position: ProofPosition,
+}
+
+#[derive(async_graphql::Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(name = "Position")]
+pub enum ProofPosition {
+    Left,
+    Right,
+}
+
+pub struct RootView(RootVersion);
+
+#[Object(name = "Root")]
+impl RootView {
+    async fn version(&self) -> usize {
+        self.0.version
+    }
+
+    async fn root_hash(&self) -> &str {
+        &self.0.root_hash
+    }
+
+    async fn leaf_count(&self) -> usize {
+        self.0.leaf_count
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct FilePage {
+    /// Number of matching files across all pages
+    total_count: usize,
+    items: Vec<FileView>,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct RootPage {
+    total_count: usize,
+    items: Vec<RootView>,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::ProofNode;
+    use crate::protocol::FileData;
+
+    async fn state() -> Arc<AppState> {
+        let state = Arc::new(AppState::with_storage_dir(
+            tempfile::tempdir().unwrap().path(),
+        ));
+        for contents in [vec!["a", "bb"], vec!["ccc"]] {
+            let files = contents
+                .into_iter()
+                .map(|content| FileData {
+                    name: format!("{}.txt", content),
+                    content: content.into(),
+                })
+                .collect();
+            state.insert_files(files).await.unwrap();
+        }
+        state
+    }
+
+    async fn query(state: Arc<AppState>, query: &str) -> serde_json::Value {
+        let response = schema(state).execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    #[tokio::test]
+    async fn dataset_files_and_roots() {
+        let state = state().await;
+        let data = query(
+            state.clone(),
+            "{ dataset { rootHash fileCount totalSize roots(limit: 1) { totalCount items { version leafCount } } } }",
+        )
+        .await;
+        let dataset = &data["dataset"];
+        assert_eq!(dataset["rootHash"], state.root_hash().await.unwrap());
+        assert_eq!(dataset["fileCount"], 3);
+        assert_eq!(dataset["totalSize"], 6);
+        assert_eq!(dataset["roots"]["totalCount"], 2);
+        assert_eq!(
+            dataset["roots"]["items"],
+            serde_json::json!([{ "version": 1, "leafCount": 3 }])
+        );
+    }
+
+    #[tokio::test]
+    async fn files_are_filtered_and_paginated() {
+        let data = query(
+            state().await,
+            r#"{ dataset {
+                named: files(nameContains: "b") { totalCount items { name } }
+                first: files(version: 0) { totalCount }
+                paged: files(offset: 1, limit: 1) { totalCount items { index name size } }
+            } }"#,
+        )
+        .await;
+        let dataset = &data["dataset"];
+        assert_eq!(
+            dataset["named"],
+            serde_json::json!({ "totalCount": 1, "items": [{ "name": "bb.txt" }] })
+        );
+        assert_eq!(dataset["first"]["totalCount"], 2);
+        assert_eq!(
+            dataset["paged"],
+            serde_json::json!({ "totalCount": 3, "items": [{ "index": 1, "name": "bb.txt", "size": 2 }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn proofs_verify_against_their_root() {
+        let state = state().await;
+        let data = query(
+            state.clone(),
+            "{ dataset { file(index: 1) { latest: proof { root leafIndex leafCount nodes { sibling position } } old: proof(version: 0) { root leafIndex leafCount nodes { sibling position } } } } }",
+        )
+        .await;
+        for (name, root) in [
+            (
+                "latest",
+                state.dataset().root_history()[1].root_hash.clone(),
+            ),
+            ("old", state.dataset().root_history()[0].root_hash.clone()),
+        ] {
+            let proof = &data["dataset"]["file"][name];
+            let nodes = proof["nodes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|step| ProofNode {
+                    sibling: step["sibling"].as_str().unwrap().to_string(),
+                    position: match step["position"].as_str().unwrap() {
+                        "LEFT" => Position::Left,
+                        _ => Position::Right,
+                    },
+                })
+                .collect();
+            let parsed = MerkleProof {
+                leaf_index: proof["leafIndex"].as_u64().unwrap() as usize,
+                leaf_count: proof["leafCount"].as_u64().unwrap() as usize,
+                nodes,
+            };
+            assert_eq!(proof["root"], root);
+            assert_eq!(parsed.leaf_index, 1);
+            assert!(parsed.verify("bb", &root));
+        }
+    }
+
+    #[tokio::test]
+    async fn served_over_http() {
+        let state = state().await;
+        let response = warp::test::request()
+            .method("POST")
+            .path("/graphql")
+            .json(&serde_json::json!({ "query": "{ dataset { fileCount } }" }))
+            .reply(&routes(state))
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["dataset"]["fileCount"], 3);
+    }
+}
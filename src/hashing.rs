@@ -0,0 +1,795 @@
+use hmac::{Hmac, Mac};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::digest::Output;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub use merkleproofs_verify::{decode_hash, Hash, LEAF_PREFIX, NODE_PREFIX};
+
+use crate::directory_tree::{DirectoryTree, PathProof};
+use crate::merkle_tree::{empty_root, MerkleProof, Padding, Position};
+
+/// Size of the reads when hashing a file
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Hash function used for the leaves and nodes of a tree
+/// Serialized by its lowercase name, which is how proofs and manifests record the hash they
+/// were made with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256 over the leaf contents and over the concatenated raw child digests, each with
+    /// their domain prefix
+    #[default]
+    Sha256,
+    /// Poseidon over the BN254 scalar field, see the `poseidon` module
+    /// Leaves and nodes are separated by the construction: leaves are absorbed by a sponge
+    /// seeded with their length, nodes are a two-input permutation.
+    #[cfg(feature = "poseidon")]
+    Poseidon,
+    /// Keccak-256 over the leaf contents and over the concatenated raw child digests, which is
+    /// `keccak256(abi.encodePacked(left, right))` in Solidity
+    /// Without domain prefixes, so contracts can verify the proofs as they are.
+    #[cfg(feature = "eth")]
+    Keccak256,
+    /// Keccak-256 like `Keccak256`, but the two children of a node are sorted before they are
+    /// hashed, as OpenZeppelin's `MerkleProof.verify` expects
+    /// Proofs can then be checked from their siblings alone, see `eth::verify_sorted_proof`.
+    #[cfg(feature = "eth")]
+    #[serde(rename = "keccak256-sorted")]
+    SortedKeccak256,
+    /// BLAKE3 over the leaf contents and over the concatenated raw child digests, each with
+    /// their domain prefix
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The lowercase name of the algorithm, as it is serialized
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            #[cfg(feature = "poseidon")]
+            HashAlgorithm::Poseidon => "poseidon",
+            #[cfg(feature = "eth")]
+            HashAlgorithm::Keccak256 => "keccak256",
+            #[cfg(feature = "eth")]
+            HashAlgorithm::SortedKeccak256 => "keccak256-sorted",
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    /// Parses the name of an algorithm this build supports
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            #[cfg(feature = "poseidon")]
+            "poseidon" => Ok(HashAlgorithm::Poseidon),
+            #[cfg(feature = "eth")]
+            "keccak256" => Ok(HashAlgorithm::Keccak256),
+            #[cfg(feature = "eth")]
+            "keccak256-sorted" => Ok(HashAlgorithm::SortedKeccak256),
+            #[cfg(feature = "blake3")]
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(format!("Unsupported hash algorithm {}", name)),
+        }
+    }
+}
+
+/// Contents of a leaf, hashed by their canonical byte encoding
+/// Implemented by strings and bytes as they are, so trees can be built over binary files too.
+pub trait MerkleLeaf {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]>;
+}
+
+impl MerkleLeaf for str {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl MerkleLeaf for String {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
+    }
+}
+
+impl MerkleLeaf for [u8] {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl MerkleLeaf for Vec<u8> {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl<T: MerkleLeaf + ?Sized> MerkleLeaf for &T {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        (**self).leaf_bytes()
+    }
+}
+
+/// How the leaves and nodes of a tree are hashed
+/// Implemented by `HashAlgorithm`, chosen at runtime, and by every `Digest`, so a tree can use
+/// the same hash as another system, for example `MerkleTree<sha2::Sha512>`
+pub trait TreeHasher {
+    /// A raw digest
+    type Output: AsRef<[u8]> + Copy + Eq + fmt::Debug;
+
+    /// Digest of the contents of a leaf
+    fn leaf_digest(&self, content: &[u8]) -> Self::Output;
+
+    /// Hashes the digests of two children into their parent
+    fn node_digest(&self, left: &Self::Output, right: &Self::Output) -> Self::Output;
+
+    /// Decodes a hex-encoded digest, or `None` if it is not the encoding of one
+    fn decode_digest(&self, hash: &str) -> Option<Self::Output>;
+
+    /// The root of a tree without leaves
+    fn empty_digest(&self) -> Self::Output;
+
+    /// Hex-encoded hash of the contents of a leaf
+    fn hash_leaf(&self, content: impl AsRef<[u8]>) -> String {
+        hex::encode(self.leaf_digest(content.as_ref()))
+    }
+
+    /// Hashes two hex-encoded child hashes into their parent
+    /// Returns an empty string, which never matches a root, if a child is not a valid hash
+    fn hash_nodes(&self, left: &str, right: &str) -> String {
+        match (self.decode_digest(left), self.decode_digest(right)) {
+            (Some(left), Some(right)) => hex::encode(self.node_digest(&left, &right)),
+            _ => String::new(),
+        }
+    }
+
+    /// Calculates the root hash implied by a leaf's contents and its Merkle proof
+    fn root_from_proof(&self, content: impl AsRef<[u8]>, proof: &MerkleProof) -> String {
+        self.root_from_leaf_hash(&self.hash_leaf(content), proof)
+    }
+
+    /// Calculates the root hash implied by a leaf hash and its Merkle proof
+    fn root_from_leaf_hash(&self, leaf_hash: &str, proof: &MerkleProof) -> String {
+        let mut current_hash = leaf_hash.to_string();
+        for node in &proof.nodes {
+            current_hash = match node.position {
+                Position::Right => self.hash_nodes(&current_hash, &node.sibling),
+                Position::Left => self.hash_nodes(&node.sibling, &current_hash),
+            };
+        }
+        current_hash
+    }
+
+    /// Checks that a proof is well formed and that the contents lead to the root
+    fn verify_proof(&self, content: impl AsRef<[u8]>, proof: &MerkleProof, root: &str) -> bool {
+        proof.is_well_formed() && ct_eq(&self.root_from_proof(content, proof), root)
+    }
+
+    /// Like `verify_proof`, for a proof of a tree built with the padding
+    fn verify_padded_proof(
+        &self,
+        content: impl AsRef<[u8]>,
+        proof: &MerkleProof,
+        root: &str,
+        padding: Padding,
+    ) -> bool {
+        proof.is_well_formed_with(padding) && ct_eq(&self.root_from_proof(content, proof), root)
+    }
+}
+
+impl TreeHasher for HashAlgorithm {
+    type Output = Hash;
+
+    fn leaf_digest(&self, content: &[u8]) -> Hash {
+        match self {
+            HashAlgorithm::Sha256 => merkleproofs_verify::leaf_digest(content),
+            #[cfg(feature = "poseidon")]
+            HashAlgorithm::Poseidon => crate::poseidon::leaf_digest(content),
+            #[cfg(feature = "eth")]
+            HashAlgorithm::Keccak256 | HashAlgorithm::SortedKeccak256 => {
+                sha3::Keccak256::digest(content).into()
+            }
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => blake3::Hasher::new()
+                .update(&[LEAF_PREFIX])
+                .update(content)
+                .finalize()
+                .into(),
+        }
+    }
+
+    fn node_digest(&self, left: &Hash, right: &Hash) -> Hash {
+        match self {
+            HashAlgorithm::Sha256 => merkleproofs_verify::node_digest(left, right),
+            #[cfg(feature = "poseidon")]
+            HashAlgorithm::Poseidon => crate::poseidon::node_digest(left, right),
+            #[cfg(feature = "eth")]
+            HashAlgorithm::Keccak256 => sha3::Keccak256::new()
+                .chain_update(left)
+                .chain_update(right)
+                .finalize()
+                .into(),
+            #[cfg(feature = "eth")]
+            HashAlgorithm::SortedKeccak256 => crate::eth::sorted_pair_digest(left, right),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => blake3::Hasher::new()
+                .update(&[NODE_PREFIX])
+                .update(left)
+                .update(right)
+                .finalize()
+                .into(),
+        }
+    }
+
+    fn decode_digest(&self, hash: &str) -> Option<Hash> {
+        decode_hash(hash)
+    }
+
+    /// The canonical `empty_root`, whatever the algorithm
+    fn empty_digest(&self) -> Hash {
+        decode_hash(&empty_root()).expect("Empty root is a valid hash")
+    }
+}
+
+/// Trees over a `Digest` hash leaves and the concatenated child digests with it, each with their
+/// domain prefix
+impl<D> TreeHasher for D
+where
+    D: Digest + fmt::Debug,
+    Output<D>: Copy,
+{
+    type Output = Output<D>;
+
+    fn leaf_digest(&self, content: &[u8]) -> Output<D> {
+        D::new()
+            .chain_update([LEAF_PREFIX])
+            .chain_update(content)
+            .finalize()
+    }
+
+    fn node_digest(&self, left: &Output<D>, right: &Output<D>) -> Output<D> {
+        D::new()
+            .chain_update([NODE_PREFIX])
+            .chain_update(left)
+            .chain_update(right)
+            .finalize()
+    }
+
+    fn decode_digest(&self, hash: &str) -> Option<Output<D>> {
+        let bytes = hex::decode(hash).ok()?;
+        (bytes.len() == <D as Digest>::output_size()).then(|| Output::<D>::clone_from_slice(&bytes))
+    }
+
+    /// The hash of the same domain tag as `empty_root`
+    fn empty_digest(&self) -> Output<D> {
+        D::digest(merkleproofs_verify::EMPTY_ROOT_TAG)
+    }
+}
+
+/// SHA-256 trees whose leaves are keyed: a leaf is `HMAC-SHA256(key, LEAF_PREFIX || contents)`,
+/// a node the usual SHA-256 node
+/// Only holders of the key can compute leaf hashes, so a published root does not let others
+/// confirm guesses of low-entropy contents. Proofs are ordinary proofs, checked with this hasher.
+#[derive(Clone)]
+pub struct KeyedSha256 {
+    /// HMAC state after the key, cloned for every leaf
+    mac: Hmac<Sha256>,
+}
+
+impl KeyedSha256 {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key).expect("HMAC takes keys of any length"),
+        }
+    }
+}
+
+/// Leaves the key out
+impl fmt::Debug for KeyedSha256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedSha256").finish_non_exhaustive()
+    }
+}
+
+impl TreeHasher for KeyedSha256 {
+    type Output = Hash;
+
+    fn leaf_digest(&self, content: &[u8]) -> Hash {
+        self.mac
+            .clone()
+            .chain_update([LEAF_PREFIX])
+            .chain_update(content)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    fn node_digest(&self, left: &Hash, right: &Hash) -> Hash {
+        merkleproofs_verify::node_digest(left, right)
+    }
+
+    fn decode_digest(&self, hash: &str) -> Option<Hash> {
+        decode_hash(hash)
+    }
+
+    /// The canonical `empty_root`, as for unkeyed trees
+    fn empty_digest(&self) -> Hash {
+        decode_hash(&empty_root()).expect("Empty root is a valid hash")
+    }
+}
+
+/// Hex-encoded leaf hash of the contents of a file
+pub fn hash_file(path: impl AsRef<Path>, algorithm: HashAlgorithm) -> io::Result<String> {
+    file_digest(path, algorithm).map(hex::encode)
+}
+
+/// Hex-encoded keyed leaf hash of the contents of a file, as `KeyedSha256` hashes leaves
+/// The file is read in pieces like in `hash_file`.
+pub fn hmac_file(path: impl AsRef<Path>, key: &[u8]) -> io::Result<String> {
+    let mut mac = KeyedSha256::new(key).mac;
+    mac.update(&[LEAF_PREFIX]);
+    read_all(File::open(path)?, |content| mac.update(content))?;
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Hex-encoded leaf hash of the contents of a file like `hash_file`, hashing the file through a
+/// memory map instead of reads, which saves the copies for very large files
+/// Files that cannot be mapped, such as pipes, are read like in `hash_file`. The file must not be
+/// changed while it is hashed.
+#[cfg(feature = "mmap")]
+pub fn hash_file_mmap(path: impl AsRef<Path>, algorithm: HashAlgorithm) -> io::Result<String> {
+    let file = File::open(path)?;
+    // SAFETY: the map is only read while hashing, and the caller does not change the file
+    // meanwhile
+    let digest = match unsafe { Mmap::map(&file) } {
+        Ok(map) => algorithm.leaf_digest(&map),
+        Err(_) => reader_digest(file, algorithm)?,
+    };
+    Ok(hex::encode(digest))
+}
+
+/// Hex-encoded leaf hash of the contents of a file like `hash_file`, on a blocking thread
+/// Meant for async contexts, so that hashing a large file does not stall the runtime
+pub async fn hash_file_async(path: PathBuf, algorithm: HashAlgorithm) -> io::Result<String> {
+    tokio::task::spawn_blocking(move || hash_file(path, algorithm))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Raw leaf digest of the contents of a file, as `MerkleTree::from_leaves` takes it
+/// The file is read in pieces of `READ_BUFFER_SIZE` bytes, so large files are never held in
+/// memory whole, except with Poseidon, whose leaf hash starts from the length of the contents.
+pub fn file_digest(path: impl AsRef<Path>, algorithm: HashAlgorithm) -> io::Result<Hash> {
+    reader_digest(File::open(path)?, algorithm)
+}
+
+/// Raw leaf digests of many files, hashed on all cores with rayon, in the order of the paths
+/// Fails with the error of a file that cannot be read.
+#[cfg(feature = "parallel")]
+pub fn hash_files_parallel(
+    paths: &[PathBuf],
+    algorithm: HashAlgorithm,
+) -> io::Result<Vec<(PathBuf, Hash)>> {
+    paths
+        .par_iter()
+        .map(|path| Ok((path.clone(), file_digest(path, algorithm)?)))
+        .collect()
+}
+
+/// Raw leaf digests of the chunks of a file, in order, as `MerkleTree::from_leaves` takes them
+/// The digests are those of the leaves of `chunking::chunk_tree`, so the tree over them has the
+/// file's chunk root and its proofs are chunk proofs. The file is read one chunk at a time.
+pub fn hash_file_chunks(path: impl AsRef<Path>, chunk_size: usize) -> io::Result<Vec<Hash>> {
+    let chunk_size = chunk_size.max(1);
+    let mut file = File::open(path)?;
+    let mut chunk = Vec::with_capacity(chunk_size.min(READ_BUFFER_SIZE));
+    let mut digests = Vec::new();
+    loop {
+        chunk.clear();
+        (&mut file)
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        // Empty files consist of a single empty chunk, as in `chunking::split_chunks`
+        if chunk.is_empty() && !digests.is_empty() {
+            break;
+        }
+        digests.push(merkleproofs_verify::leaf_digest(&chunk));
+        if chunk.len() < chunk_size {
+            break;
+        }
+    }
+    Ok(digests)
+}
+
+/// Root of a directory on disk and a proof for each file in it, by its path under the directory
+/// with `/` separating directories
+/// The root is that of a `DirectoryTree` over all files below the directory, hashed with SHA-256;
+/// a directory without files has `empty_root`. Entries are walked in name order, symbolic links
+/// are skipped, and names that are not UTF-8 are an error.
+pub fn hash_directory(path: impl AsRef<Path>) -> io::Result<(String, BTreeMap<String, PathProof>)> {
+    let mut tree = DirectoryTree::new();
+    let mut files = Vec::new();
+    walk_directory(path.as_ref(), "", &mut files)?;
+    for (relative, file) in &files {
+        let leaf_hash = hash_file(file, HashAlgorithm::Sha256)?;
+        tree.insert_leaf_hash(relative, leaf_hash)
+            .map_err(io::Error::other)?;
+    }
+    let proofs = files
+        .into_iter()
+        .map(|(relative, _)| {
+            let proof = tree.prove(&relative).expect("Every file was inserted");
+            (relative, proof)
+        })
+        .collect();
+    Ok((tree.root().unwrap_or_else(empty_root), proofs))
+}
+
+/// Collects the files below the directory with their paths relative to the top, in name order
+fn walk_directory(
+    directory: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("File name {:?} is not UTF-8", name),
+            )
+        })?;
+        let relative = format!("{}{}", prefix, name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk_directory(&entry.path(), &format!("{}/", relative), files)?;
+        } else if file_type.is_file() {
+            files.push((relative, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+/// Leaf digest of everything read from the reader
+fn reader_digest(reader: impl Read, algorithm: HashAlgorithm) -> io::Result<Hash> {
+    let mut hasher = LeafHasher::new(algorithm);
+    read_all(reader, |content| hasher.update(content))?;
+    Ok(hasher.finalize())
+}
+
+/// Passes everything read from the reader to `update`, in pieces of at most `READ_BUFFER_SIZE`
+fn read_all(mut reader: impl Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => update(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Incremental leaf hash, equal to `HashAlgorithm::leaf_digest` of all the updates
+enum LeafHasher {
+    Sha256(sha2::Sha256),
+    /// Contents buffered until the end, as the hash needs their length first
+    #[cfg(feature = "poseidon")]
+    Poseidon(Vec<u8>),
+    #[cfg(feature = "eth")]
+    Keccak256(Box<sha3::Keccak256>),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl LeafHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => {
+                LeafHasher::Sha256(sha2::Sha256::new().chain_update([LEAF_PREFIX]))
+            }
+            #[cfg(feature = "poseidon")]
+            HashAlgorithm::Poseidon => LeafHasher::Poseidon(Vec::new()),
+            #[cfg(feature = "eth")]
+            HashAlgorithm::Keccak256 | HashAlgorithm::SortedKeccak256 => {
+                LeafHasher::Keccak256(Box::new(sha3::Keccak256::new()))
+            }
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(&[LEAF_PREFIX]);
+                LeafHasher::Blake3(Box::new(hasher))
+            }
+        }
+    }
+
+    fn update(&mut self, content: &[u8]) {
+        match self {
+            LeafHasher::Sha256(hasher) => hasher.update(content),
+            #[cfg(feature = "poseidon")]
+            LeafHasher::Poseidon(buffer) => buffer.extend_from_slice(content),
+            #[cfg(feature = "eth")]
+            LeafHasher::Keccak256(hasher) => hasher.update(content),
+            #[cfg(feature = "blake3")]
+            LeafHasher::Blake3(hasher) => {
+                hasher.update(content);
+            }
+        }
+    }
+
+    fn finalize(self) -> Hash {
+        match self {
+            LeafHasher::Sha256(hasher) => hasher.finalize().into(),
+            #[cfg(feature = "poseidon")]
+            LeafHasher::Poseidon(buffer) => crate::poseidon::leaf_digest(&buffer),
+            #[cfg(feature = "eth")]
+            LeafHasher::Keccak256(hasher) => hasher.finalize().into(),
+            #[cfg(feature = "blake3")]
+            LeafHasher::Blake3(hasher) => hasher.finalize().into(),
+        }
+    }
+}
+
+/// Compares two hashes in constant time
+/// Used wherever a computed root or leaf is checked against an expected value,
+/// so the comparison does not leak how many leading characters matched.
+/// Inputs of different length are never equal; the length itself is not treated as secret.
+pub fn ct_eq(a: &str, b: &str) -> bool {
+    merkleproofs_verify::ct_eq(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::chunking::chunk_tree;
+    use crate::merkle_tree::{calculate_hash, leaf_hash, MerkleTree};
+
+    #[test]
+    fn file_hashes_are_leaf_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "contents").unwrap();
+        assert_eq!(
+            hash_file(&path, HashAlgorithm::Sha256).unwrap(),
+            leaf_hash("contents")
+        );
+        assert!(hash_file(dir.path().join("missing.txt"), HashAlgorithm::Sha256).is_err());
+    }
+
+    #[tokio::test]
+    async fn async_hashes_match_file_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "contents").unwrap();
+        assert_eq!(
+            hash_file_async(path, HashAlgorithm::Sha256).await.unwrap(),
+            leaf_hash("contents")
+        );
+        assert!(
+            hash_file_async(dir.path().join("missing.txt"), HashAlgorithm::Sha256)
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mapped_files_hash_like_read_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.bin");
+        for content in [
+            Vec::new(),
+            (0..READ_BUFFER_SIZE + 5).map(|i| i as u8).collect(),
+        ] {
+            fs::write(&path, &content).unwrap();
+            assert_eq!(
+                hash_file_mmap(&path, HashAlgorithm::Sha256).unwrap(),
+                hash_file(&path, HashAlgorithm::Sha256).unwrap()
+            );
+        }
+        assert!(hash_file_mmap(dir.path().join("missing.txt"), HashAlgorithm::Sha256).is_err());
+        // A directory can be opened but neither mapped nor read
+        assert!(hash_file_mmap(dir.path(), HashAlgorithm::Sha256).is_err());
+    }
+
+    #[test]
+    fn file_chunks_hash_to_the_chunk_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        for content in [
+            &b""[..],
+            b"abcd",
+            b"This is a file that spans several chunks",
+        ] {
+            fs::write(&path, content).unwrap();
+            let tree = MerkleTree::from_leaves(hash_file_chunks(&path, 8).unwrap());
+            assert_eq!(tree.root(), chunk_tree(content, 8).root());
+            assert_eq!(tree.leaf_count(), content.len().div_ceil(8).max(1));
+        }
+        assert!(hash_file_chunks(dir.path().join("missing.txt"), 8).is_err());
+    }
+
+    #[test]
+    fn directories_hash_like_directory_trees() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("README.md", "readme"),
+            ("src/main.rs", "fn main() {}"),
+            ("src/util/a.rs", "pub fn a() {}"),
+        ];
+        fs::create_dir_all(dir.path().join("src/util")).unwrap();
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        for (path, content) in files {
+            fs::write(dir.path().join(path), content).unwrap();
+        }
+
+        let (root, proofs) = hash_directory(dir.path()).unwrap();
+        let pairs: Vec<(String, String)> = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect();
+        assert_eq!(
+            Some(root.clone()),
+            DirectoryTree::build(&pairs).unwrap().root()
+        );
+        assert_eq!(proofs.len(), 3);
+        for (path, content) in files {
+            assert!(proofs[path].verify_file(path, content, &root));
+        }
+
+        let (empty, proofs) = hash_directory(dir.path().join("empty")).unwrap();
+        assert_eq!(empty, empty_root());
+        assert!(proofs.is_empty());
+        assert!(hash_directory(dir.path().join("missing")).is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_hashes_match_file_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..50).map(|i| dir.path().join(i.to_string())).collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, i.to_string()).unwrap();
+        }
+
+        let hashes = hash_files_parallel(&paths, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(hashes.len(), paths.len());
+        for (path, (hashed, digest)) in paths.iter().zip(&hashes) {
+            assert_eq!(hashed, path);
+            assert_eq!(*digest, file_digest(path, HashAlgorithm::Sha256).unwrap());
+        }
+
+        let missing = vec![paths[0].clone(), dir.path().join("missing")];
+        assert!(hash_files_parallel(&missing, HashAlgorithm::Sha256).is_err());
+    }
+
+    #[test]
+    fn keyed_trees_need_the_key() {
+        let elements = vec!["yes".to_string(), "no".to_string(), "yes".to_string()];
+        let mut keyed = MerkleTree::with_hasher(KeyedSha256::new(b"key"));
+        keyed.build(&elements);
+        let mut other_key = MerkleTree::with_hasher(KeyedSha256::new(b"other key"));
+        other_key.build(&elements);
+        let mut unkeyed = MerkleTree::new();
+        unkeyed.build(&elements);
+        assert_ne!(keyed.root(), other_key.root());
+        assert_ne!(keyed.root(), unkeyed.root());
+
+        let root = keyed.root().unwrap();
+        let proof = keyed.get_merkle_proof(1).unwrap();
+        assert!(KeyedSha256::new(b"key").verify_proof("no", &proof, &root));
+        assert!(!KeyedSha256::new(b"other key").verify_proof("no", &proof, &root));
+        assert!(!proof.verify("no", &root));
+        assert_eq!(
+            format!("{:?}", KeyedSha256::new(b"key")),
+            "KeyedSha256 { .. }"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "no").unwrap();
+        assert_eq!(
+            hmac_file(&path, b"key").unwrap(),
+            KeyedSha256::new(b"key").hash_leaf("no")
+        );
+    }
+
+    #[test]
+    fn large_files_hash_as_their_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        let content: Vec<u8> = (0..3 * READ_BUFFER_SIZE + 17).map(|i| i as u8).collect();
+        fs::write(&path, &content).unwrap();
+        assert_eq!(
+            file_digest(&path, HashAlgorithm::Sha256).unwrap(),
+            HashAlgorithm::Sha256.leaf_digest(&content)
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(
+            file_digest(&path, HashAlgorithm::Blake3).unwrap(),
+            HashAlgorithm::Blake3.leaf_digest(&content)
+        );
+        #[cfg(feature = "eth")]
+        assert_eq!(
+            file_digest(&path, HashAlgorithm::Keccak256).unwrap(),
+            HashAlgorithm::Keccak256.leaf_digest(&content)
+        );
+    }
+
+    #[test]
+    fn algorithms_are_serialized_by_name() {
+        let algorithm: HashAlgorithm = "sha256".parse().unwrap();
+        assert_eq!(algorithm, HashAlgorithm::Sha256);
+        assert_eq!(serde_json::to_string(&algorithm).unwrap(), "\"sha256\"");
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_hashes_leaves_and_nodes() {
+        let algorithm = HashAlgorithm::Blake3;
+        assert_eq!(algorithm.name().parse(), Ok(algorithm));
+        assert_eq!(
+            algorithm.hash_leaf("a"),
+            blake3::Hasher::new()
+                .update(&[LEAF_PREFIX])
+                .update(b"a")
+                .finalize()
+                .to_hex()
+                .to_string()
+        );
+
+        let mut tree = crate::merkle_tree::MerkleTree::with_algorithm(algorithm);
+        let elements = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        tree.build(&elements);
+        for (index, element) in elements.iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            assert_eq!(
+                Some(algorithm.root_from_proof(element, &proof)),
+                tree.root()
+            );
+            assert!(algorithm.verify_proof(element, &proof, &tree.root().unwrap()));
+        }
+        assert_ne!(
+            tree.root(),
+            Some(HashAlgorithm::Sha256.root_from_proof("a", &tree.get_merkle_proof(0).unwrap()))
+        );
+    }
+
+    #[test]
+    fn ct_eq_equal_hashes() {
+        let hash = calculate_hash("a");
+        assert!(ct_eq(&hash, &hash.clone()));
+    }
+
+    #[test]
+    fn ct_eq_different_hashes() {
+        assert!(!ct_eq(&calculate_hash("a"), &calculate_hash("b")));
+    }
+
+    #[test]
+    fn ct_eq_different_lengths() {
+        let hash = calculate_hash("a");
+        assert!(!ct_eq(&hash, &hash[..hash.len() - 1]));
+        assert!(!ct_eq(&hash, ""));
+    }
+}
@@ -0,0 +1,177 @@
+//! Fixed-depth incremental Merkle tree
+//! The pattern used by deposit contracts and mixers: a tree of `2^depth` leaves where unused
+//! leaves are zero. Only the frontier (the rightmost filled node of every level) is stored, so
+//! appends take O(depth) time and the whole state serializes to `depth` hashes.
+//!
+//! Nodes are hashed like in `MerkleTree`, so a full incremental tree has the same root as a
+//! `MerkleTree` built over the same leaves.
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::{HashAlgorithm, TreeHasher};
+
+/// The hash of an unused leaf
+pub const ZERO_LEAF: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The largest supported depth
+pub const MAX_DEPTH: usize = 63;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IncrementalMerkleTree {
+    depth: usize,
+    count: u64,
+    /// Rightmost filled node of every level, as of the last append that completed it
+    /// The extra last entry holds the root once the tree is full
+    frontier: Vec<String>,
+}
+
+impl IncrementalMerkleTree {
+    /// Creates an empty tree with room for `2^depth` leaves
+    pub fn new(depth: usize) -> Self {
+        assert!(depth <= MAX_DEPTH, "Depth must be at most {}", MAX_DEPTH);
+        Self {
+            depth,
+            count: 0,
+            frontier: vec![ZERO_LEAF.to_string(); depth + 1],
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The number of appended leaves
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn capacity(&self) -> u64 {
+        1 << self.depth
+    }
+
+    /// Appends leaf contents and returns the index of the new leaf
+    pub fn append(&mut self, content: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        self.append_leaf_hash(HashAlgorithm::Sha256.hash_leaf(content))
+    }
+
+    /// Appends an already hashed leaf and returns the index of the new leaf
+    pub fn append_leaf_hash(
+        &mut self,
+        leaf_hash: String,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        if self.count >= self.capacity() {
+            return Err("Tree is full".into());
+        }
+        let index = self.count;
+        let mut node = leaf_hash;
+        let mut size = index + 1;
+        for level in 0..=self.depth {
+            if size & 1 == 1 {
+                self.frontier[level] = node;
+                break;
+            }
+            node = HashAlgorithm::Sha256.hash_nodes(&self.frontier[level], &node);
+            size >>= 1;
+        }
+        self.count += 1;
+        Ok(index)
+    }
+
+    /// The root hash, treating all unused leaves as zero
+    pub fn root(&self) -> String {
+        if self.count == self.capacity() {
+            return self.frontier[self.depth].clone();
+        }
+        let zero_hashes = zero_hashes(self.depth);
+        let mut node = ZERO_LEAF.to_string();
+        let mut size = self.count;
+        for (level, zero_hash) in zero_hashes.iter().enumerate().take(self.depth) {
+            node = if size & 1 == 1 {
+                HashAlgorithm::Sha256.hash_nodes(&self.frontier[level], &node)
+            } else {
+                HashAlgorithm::Sha256.hash_nodes(&node, zero_hash)
+            };
+            size >>= 1;
+        }
+        node
+    }
+}
+
+/// Hashes of all-zero subtrees, indexed by height; `zero_hashes(d)[0]` is the zero leaf
+pub fn zero_hashes(depth: usize) -> Vec<String> {
+    let mut hashes = vec![ZERO_LEAF.to_string()];
+    for level in 0..depth {
+        let next = HashAlgorithm::Sha256.hash_nodes(&hashes[level], &hashes[level]);
+        hashes.push(next);
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{leaf_hash, MerkleTree};
+
+    /// Root of a `MerkleTree` over the leaves padded with zero leaves up to the capacity
+    fn padded_root(elements: &[String], depth: usize) -> String {
+        let mut leaves: Vec<String> = elements.iter().map(leaf_hash).collect();
+        leaves.resize(1 << depth, ZERO_LEAF.to_string());
+        let mut tree = MerkleTree::new();
+        tree.build_from_leaf_hashes(leaves).unwrap();
+        tree.root().unwrap()
+    }
+
+    #[test]
+    fn empty_tree_root_is_zero_subtree() {
+        let tree = IncrementalMerkleTree::new(4);
+        assert_eq!(tree.root(), zero_hashes(4)[4]);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn roots_match_padded_tree_after_every_append() {
+        let depth = 3;
+        let mut tree = IncrementalMerkleTree::new(depth);
+        let mut elements = Vec::new();
+        for i in 0..8 {
+            let element = format!("leaf {}", i);
+            assert_eq!(tree.append(&element).unwrap(), i);
+            elements.push(element);
+            assert_eq!(
+                tree.root(),
+                padded_root(&elements, depth),
+                "after {} leaves",
+                i + 1
+            );
+        }
+    }
+
+    #[test]
+    fn full_tree_rejects_appends() {
+        let mut tree = IncrementalMerkleTree::new(1);
+        tree.append("a").unwrap();
+        tree.append("b").unwrap();
+        assert!(tree.append("c").is_err());
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn frontier_survives_serialization() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        for i in 0..5 {
+            tree.append(&i.to_string()).unwrap();
+        }
+        let data = serde_json::to_string(&tree).unwrap();
+        let mut restored: IncrementalMerkleTree = serde_json::from_str(&data).unwrap();
+        assert_eq!(restored, tree);
+
+        tree.append("5").unwrap();
+        restored.append("5").unwrap();
+        assert_eq!(restored.root(), tree.root());
+    }
+}
@@ -1,2 +1,42 @@
+pub mod aggregate;
+pub mod airdrop;
+pub mod chunking;
+pub mod client_config;
 pub mod client_state;
+pub mod compression;
+pub mod consistency;
+pub mod content_store;
+pub mod directory_tree;
+#[cfg(feature = "mmap")]
+pub mod disk_tree;
+pub mod encryption;
+pub mod erasure;
+pub mod error;
+#[cfg(feature = "eth")]
+pub mod eth;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod hashing;
+pub mod incremental_tree;
+pub mod manifest;
+pub mod merkle_map;
 pub mod merkle_tree;
+pub mod mirror;
+pub mod multiformats;
+pub mod multiproof;
+pub mod nary_tree;
+#[cfg(feature = "poseidon")]
+pub mod poseidon;
+pub mod proof_cache;
+pub mod protocol;
+pub mod pruned_tree;
+pub mod rfc6962;
+pub mod root_builder;
+pub mod salted_tree;
+pub mod server;
+pub mod sorted_tree;
+pub mod sparse_merkle;
+pub mod storage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transport;
@@ -0,0 +1,282 @@
+//! Portable description of a committed dataset
+//! A manifest lists the files in leaf order with their sizes and leaf hashes, says how the tree
+//! is built and carries the resulting root, optionally signed by one or more ed25519 keys.
+//! Anyone holding a manifest can recompute the root and check the signatures without a server.
+//!
+//! Manifests are serialized as JSON or CBOR. Signatures cover the JSON encoding of the manifest
+//! without its signatures, so they stay valid when converting between the two formats.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{empty_root, MerkleTree};
+use crate::protocol::LeafEntry;
+
+/// Version of the manifest format
+pub const FORMAT_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// The files in leaf order
+    pub files: Vec<LeafEntry>,
+    pub tree: TreeConfig,
+    pub root_hash: String,
+    #[serde(default)]
+    pub signatures: Vec<ManifestSignature>,
+}
+
+/// How the root is computed from the leaf hashes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreeConfig {
+    /// Hash function for leaves and nodes, by the name of its `HashAlgorithm`
+    pub hash: String,
+    /// How a level with an odd number of nodes is completed
+    pub odd_levels: String,
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        Self {
+            hash: "sha256".to_string(),
+            odd_levels: "duplicate-last".to_string(),
+        }
+    }
+}
+
+/// A hex-encoded ed25519 public key and its signature over the manifest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl Manifest {
+    /// Describes the dataset made of the files, computing its root
+    /// Fails if a leaf hash of the files is malformed
+    pub fn new(files: Vec<LeafEntry>) -> Result<Self, MerkleError> {
+        Self::with_algorithm(files, HashAlgorithm::default())
+    }
+
+    /// Describes the dataset made of the files, whose leaves were hashed with the algorithm
+    pub fn with_algorithm(
+        files: Vec<LeafEntry>,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, MerkleError> {
+        let root_hash = Self::compute_root(algorithm, &files)?;
+        Ok(Self {
+            format_version: FORMAT_VERSION,
+            files,
+            tree: TreeConfig {
+                hash: algorithm.name().to_string(),
+                ..TreeConfig::default()
+            },
+            root_hash,
+            signatures: Vec::new(),
+        })
+    }
+
+    /// The hash algorithm the tree is built with, if this build supports it
+    pub fn algorithm(&self) -> Result<HashAlgorithm, String> {
+        self.tree.hash.parse()
+    }
+
+    fn compute_root(algorithm: HashAlgorithm, files: &[LeafEntry]) -> Result<String, MerkleError> {
+        let mut tree = MerkleTree::with_algorithm(algorithm);
+        tree.build_from_leaf_hashes(files.iter().map(|f| f.leaf_hash.clone()).collect())?;
+        Ok(tree.root().unwrap_or_else(empty_root))
+    }
+
+    /// The bytes covered by signatures
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Manifest {
+            signatures: Vec::new(),
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).expect("Manifests always serialize")
+    }
+
+    /// Adds a signature over the manifest
+    pub fn sign(&mut self, key: &SigningKey) {
+        let signature = key.sign(&self.signed_bytes());
+        self.signatures.push(ManifestSignature {
+            public_key: hex::encode(key.verifying_key().as_bytes()),
+            signature: hex::encode(signature.to_bytes()),
+        });
+    }
+
+    /// Checks that the root matches the listed files and that every signature is valid
+    pub fn verify(&self) -> Result<(), String> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported manifest version {}",
+                self.format_version
+            ));
+        }
+        let algorithm = self.algorithm()?;
+        if self.tree.odd_levels != TreeConfig::default().odd_levels {
+            return Err(format!("Unsupported tree configuration {:?}", self.tree));
+        }
+        let root = Self::compute_root(algorithm, &self.files).map_err(|e| e.to_string())?;
+        if !ct_eq(&root, &self.root_hash) {
+            return Err("Root hash does not match the listed files".to_string());
+        }
+
+        let signed_bytes = self.signed_bytes();
+        for signature in &self.signatures {
+            let public_key: [u8; 32] = hex::decode(&signature.public_key)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Invalid public key {}", signature.public_key))?;
+            let public_key = VerifyingKey::from_bytes(&public_key)
+                .map_err(|_| format!("Invalid public key {}", signature.public_key))?;
+            let signature_bytes: [u8; 64] = hex::decode(&signature.signature)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Invalid signature by {}", signature.public_key))?;
+            public_key
+                .verify(&signed_bytes, &Signature::from_bytes(&signature_bytes))
+                .map_err(|_| format!("Invalid signature by {}", signature.public_key))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the contents are the file listed under the name
+    pub fn verify_file(&self, name: &str, content: impl AsRef<[u8]>) -> bool {
+        let content = content.as_ref();
+        let Ok(algorithm) = self.algorithm() else {
+            return false;
+        };
+        self.files.iter().any(|file| {
+            file.name == name
+                && file.size == content.len()
+                && ct_eq(&file.leaf_hash, &algorithm.hash_leaf(content))
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut data = Vec::new();
+        ciborium::into_writer(self, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ciborium::from_reader(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::FileData;
+
+    fn manifest() -> Manifest {
+        let files = ["file1", "file2", "file3"].map(|content| FileData {
+            name: format!("{}.txt", content),
+            content: content.into(),
+        });
+        Manifest::new(files.iter().map(LeafEntry::new).collect()).unwrap()
+    }
+
+    #[test]
+    fn root_matches_the_tree() {
+        let manifest = manifest();
+        let mut tree = MerkleTree::new();
+        tree.build(&[
+            "file1".to_string(),
+            "file2".to_string(),
+            "file3".to_string(),
+        ]);
+        assert_eq!(Some(manifest.root_hash.clone()), tree.root());
+        assert!(manifest.verify().is_ok());
+        assert!(manifest.verify_file("file2.txt", "file2"));
+        assert!(!manifest.verify_file("file2.txt", "file3"));
+    }
+
+    #[test]
+    fn tampered_files_are_rejected() {
+        let mut manifest = manifest();
+        manifest.files.swap(0, 1);
+        assert!(manifest.verify().is_err());
+    }
+
+    #[test]
+    fn signatures_survive_cbor_roundtrip() {
+        let mut manifest = manifest();
+        manifest.sign(&SigningKey::from_bytes(&[7u8; 32]));
+        manifest.sign(&SigningKey::from_bytes(&[8u8; 32]));
+        assert!(manifest.verify().is_ok());
+
+        let decoded = Manifest::from_cbor(&manifest.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded, manifest);
+        assert!(decoded.verify().is_ok());
+
+        let decoded = Manifest::from_json(&manifest.to_json().unwrap()).unwrap();
+        assert_eq!(decoded, manifest);
+    }
+
+    #[test]
+    fn signature_over_other_contents_is_rejected() {
+        let mut manifest = manifest();
+        manifest.sign(&SigningKey::from_bytes(&[7u8; 32]));
+        manifest.files[0].name = "renamed.txt".to_string();
+        assert!(manifest.verify().is_err());
+    }
+    #[test]
+    fn manifests_record_the_hash_algorithm() {
+        let mut manifest = manifest();
+        assert_eq!(manifest.algorithm(), Ok(HashAlgorithm::Sha256));
+
+        manifest.tree.hash = "md5".to_string();
+        assert_eq!(
+            manifest.verify(),
+            Err("Unsupported hash algorithm md5".to_string())
+        );
+        assert!(!manifest.verify_file("file2.txt", "file2"));
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_manifests_verify() {
+        let algorithm = HashAlgorithm::Blake3;
+        let files = ["file1", "file2"].map(|content| LeafEntry {
+            name: format!("{}.txt", content),
+            size: content.len(),
+            leaf_hash: algorithm.hash_leaf(content),
+        });
+        let manifest = Manifest::with_algorithm(files.to_vec(), algorithm).unwrap();
+        assert_eq!(manifest.tree.hash, "blake3");
+        assert!(manifest.verify().is_ok());
+        assert!(manifest.verify_file("file2.txt", "file2"));
+
+        let decoded = Manifest::from_json(&manifest.to_json().unwrap()).unwrap();
+        assert_eq!(decoded.algorithm(), Ok(algorithm));
+        assert!(decoded.verify().is_ok());
+    }
+
+    #[test]
+    fn signed_manifests_verify_without_the_server_code() {
+        let mut manifest = manifest();
+        manifest.sign(&SigningKey::from_bytes(&[7u8; 32]));
+        let json = manifest.to_json().unwrap();
+
+        let parsed = merkleproofs_verify::Manifest::from_json(&json).unwrap();
+        assert!(parsed.verify().is_ok());
+        assert!(parsed.verify_file("file2.txt", "file2"));
+
+        manifest.files.swap(0, 1);
+        let tampered = merkleproofs_verify::Manifest::from_json(&manifest.to_json().unwrap());
+        assert!(tampered.unwrap().verify().is_err());
+    }
+}
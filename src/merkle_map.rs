@@ -0,0 +1,328 @@
+//! Keyed Merkle map
+//! A binary Patricia (crit-bit) trie over the SHA-256 hashes of the keys. Entries are addressed by
+//! key rather than by position, so proofs stay tied to the key however the map changes.
+//!
+//! Each leaf commits to its key path and value: `H(0x00 || path || H(value))`.
+//! Each branch commits to the bit index at which its two subtrees split and to both subtrees:
+//! `H(0x01 || bit (u16, big-endian) || left || right)`. Keys whose path has a 0 at the split bit
+//! are on the left. An empty map has the root `H("")`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::hashing::ct_eq;
+
+type Hash = [u8; 32];
+
+#[derive(Debug, Clone)]
+pub struct MerkleMap<K, V> {
+    /// Entries ordered by key path, which is the order of the trie's leaves
+    entries: BTreeMap<Hash, (K, V)>,
+}
+
+/// Proof of the value of a key, or of its absence
+/// `siblings` holds the split bit and sibling hash of every branch from the root down.
+/// `leaf` is the leaf the key leads to, given as hex-encoded `(path, value hash)`; it belongs to
+/// another key if the proven key is absent, and is `None` only for an empty map.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapProof {
+    pub siblings: Vec<(u16, String)>,
+    pub leaf: Option<(String, String)>,
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> Default for MerkleMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> MerkleMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Inserts a value, returning the previous value of the key if there was one
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.entries
+            .insert(key_path(key.as_ref()), (key, value))
+            .map(|(_, previous)| previous)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.entries.get(&key_path(key)).map(|(_, value)| value)
+    }
+
+    /// Removes a key, returning its value if it was present
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        self.entries.remove(&key_path(key)).map(|(_, value)| value)
+    }
+
+    /// Iterates over the entries in trie order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.values().map(|(key, value)| (key, value))
+    }
+
+    /// The root hash of the map
+    pub fn root(&self) -> String {
+        let leaves = self.leaves();
+        if leaves.is_empty() {
+            return hex::encode(empty_root());
+        }
+        hex::encode(subtree_hash(&leaves))
+    }
+
+    /// Generates a proof of the key's value, or of its absence if the key is not in the map
+    pub fn prove(&self, key: &[u8]) -> MapProof {
+        let path = key_path(key);
+        let leaves = self.leaves();
+        let mut siblings = Vec::new();
+        let mut current = leaves.as_slice();
+
+        while current.len() > 1 {
+            let (bit, split) = split_point(current);
+            let (left, right) = current.split_at(split);
+            if get_bit(&path, bit) {
+                siblings.push((bit, hex::encode(subtree_hash(left))));
+                current = right;
+            } else {
+                siblings.push((bit, hex::encode(subtree_hash(right))));
+                current = left;
+            }
+        }
+
+        MapProof {
+            siblings,
+            leaf: current
+                .first()
+                .map(|(path, value_hash)| (hex::encode(path), hex::encode(value_hash))),
+        }
+    }
+
+    /// Leaves as (path, value hash), ordered by path
+    fn leaves(&self) -> Vec<(Hash, Hash)> {
+        self.entries
+            .iter()
+            .map(|(path, (_, value))| (*path, Sha256::digest(value.as_ref()).into()))
+            .collect()
+    }
+}
+
+impl MapProof {
+    /// Verifies that `key` maps to `value` in the map with the given root
+    /// With `value` set to `None`, verifies that the key is absent instead
+    pub fn verify(&self, root: &str, key: &[u8], value: Option<&[u8]>) -> bool {
+        let value_hash = value.map(|value| <Hash>::from(Sha256::digest(value)));
+        self.verify_hash(root, key, value_hash.as_ref())
+    }
+
+    /// Verifies that `key` maps to a value with the hex-encoded SHA-256 hash, so that a file
+    /// can be shown to exist at a path with a content hash without sending its contents
+    pub fn verify_value_hash(&self, root: &str, key: &[u8], value_hash: &str) -> bool {
+        decode_hash(value_hash)
+            .is_ok_and(|value_hash| self.verify_hash(root, key, Some(&value_hash)))
+    }
+
+    fn verify_hash(&self, root: &str, key: &[u8], expected: Option<&Hash>) -> bool {
+        let path = key_path(key);
+
+        let Some((leaf_path_hex, value_hash_hex)) = &self.leaf else {
+            return expected.is_none()
+                && self.siblings.is_empty()
+                && ct_eq(&hex::encode(empty_root()), root);
+        };
+        let (Ok(leaf_path), Ok(value_hash)) =
+            (decode_hash(leaf_path_hex), decode_hash(value_hash_hex))
+        else {
+            return false;
+        };
+
+        // Split bits must strictly increase from the root down
+        if self.siblings.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return false;
+        }
+
+        let mut current = leaf_hash(&leaf_path, &value_hash);
+        for (bit, sibling_hex) in self.siblings.iter().rev() {
+            // The proven key must take the same branches as the leaf
+            if get_bit(&path, *bit) != get_bit(&leaf_path, *bit) {
+                return false;
+            }
+            let Ok(sibling) = decode_hash(sibling_hex) else {
+                return false;
+            };
+            current = if get_bit(&leaf_path, *bit) {
+                branch_hash(*bit, &sibling, &current)
+            } else {
+                branch_hash(*bit, &current, &sibling)
+            };
+        }
+
+        if !ct_eq(&hex::encode(current), root) {
+            return false;
+        }
+        match expected {
+            Some(expected) => leaf_path == path && value_hash == *expected,
+            None => leaf_path != path,
+        }
+    }
+}
+
+fn key_path(key: &[u8]) -> Hash {
+    Sha256::digest(key).into()
+}
+
+fn empty_root() -> Hash {
+    Sha256::digest(b"").into()
+}
+
+fn decode_hash(hex_hash: &str) -> Result<Hash, Box<dyn std::error::Error>> {
+    let bytes = hex::decode(hex_hash)?;
+    Ok(bytes.try_into().map_err(|_| "Invalid hash length")?)
+}
+
+fn leaf_hash(path: &Hash, value_hash: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(path);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+fn branch_hash(bit: u16, left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(bit.to_be_bytes());
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Bit `index` of the path, counting from the most significant bit
+fn get_bit(path: &Hash, index: u16) -> bool {
+    let index = index as usize;
+    path[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+/// The first bit at which the sorted leaves differ, and the position of the first leaf with that bit set
+fn split_point(leaves: &[(Hash, Hash)]) -> (u16, usize) {
+    let first = &leaves[0].0;
+    let last = &leaves[leaves.len() - 1].0;
+    let bit = (0..256u16)
+        .find(|bit| get_bit(first, *bit) != get_bit(last, *bit))
+        .expect("Leaf paths must be distinct");
+    let split = leaves.partition_point(|(path, _)| !get_bit(path, bit));
+    (bit, split)
+}
+
+fn subtree_hash(leaves: &[(Hash, Hash)]) -> Hash {
+    if leaves.len() == 1 {
+        return leaf_hash(&leaves[0].0, &leaves[0].1);
+    }
+    let (bit, split) = split_point(leaves);
+    let (left, right) = leaves.split_at(split);
+    branch_hash(bit, &subtree_hash(left), &subtree_hash(right))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn sample_map() -> MerkleMap<String, String> {
+        let mut map = MerkleMap::new();
+        for i in 0..10 {
+            map.insert(format!("file{}.txt", i), format!("This is file {}", i));
+        }
+        map
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = sample_map();
+        assert_eq!(map.len(), 10);
+        assert_eq!(map.get(b"file3.txt"), Some(&"This is file 3".to_string()));
+
+        let previous = map.insert("file3.txt".to_string(), "changed".to_string());
+        assert_eq!(previous, Some("This is file 3".to_string()));
+        assert_eq!(map.get(b"file3.txt"), Some(&"changed".to_string()));
+
+        assert_eq!(map.remove(b"file3.txt"), Some("changed".to_string()));
+        assert_eq!(map.get(b"file3.txt"), None);
+        assert_eq!(map.len(), 9);
+    }
+
+    #[test]
+    fn root_depends_on_contents_not_insertion_order() {
+        let map = sample_map();
+        let mut reversed = MerkleMap::new();
+        for i in (0..10).rev() {
+            reversed.insert(format!("file{}.txt", i), format!("This is file {}", i));
+        }
+        assert_eq!(map.root(), reversed.root());
+
+        reversed.insert("file0.txt".to_string(), "changed".to_string());
+        assert_ne!(map.root(), reversed.root());
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        let map = sample_map();
+        let root = map.root();
+        for (key, value) in map.iter() {
+            let proof = map.prove(key.as_bytes());
+            assert!(proof.verify(&root, key.as_bytes(), Some(value.as_bytes())));
+            assert!(!proof.verify(&root, key.as_bytes(), Some(b"wrong")));
+            assert!(!proof.verify(&root, key.as_bytes(), None));
+
+            // The content hash alone is enough
+            let content_hash = hex::encode(Sha256::digest(value.as_bytes()));
+            assert!(proof.verify_value_hash(&root, key.as_bytes(), &content_hash));
+            assert!(!proof.verify_value_hash(&root, b"other.txt", &content_hash));
+            assert!(!proof.verify_value_hash(&root, key.as_bytes(), "not hex"));
+        }
+    }
+
+    #[test]
+    fn exclusion_proofs_verify() {
+        let map = sample_map();
+        let root = map.root();
+        for key in ["missing.txt", "file10.txt", ""] {
+            let proof = map.prove(key.as_bytes());
+            assert!(proof.verify(&root, key.as_bytes(), None));
+            assert!(!proof.verify(&root, key.as_bytes(), Some(b"This is file 1")));
+        }
+    }
+
+    #[test]
+    fn proofs_fail_against_other_roots() {
+        let map = sample_map();
+        let proof = map.prove(b"file1.txt");
+        let mut other = sample_map();
+        other.remove(b"file2.txt");
+        assert!(!proof.verify(&other.root(), b"file1.txt", Some(b"This is file 1")));
+    }
+
+    #[test]
+    fn empty_and_single_entry_maps() {
+        let mut map: MerkleMap<&str, &str> = MerkleMap::new();
+        let proof = map.prove(b"a");
+        assert!(proof.verify(&map.root(), b"a", None));
+
+        map.insert("a", "1");
+        let proof = map.prove(b"a");
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&map.root(), b"a", Some(b"1")));
+        assert!(map.prove(b"b").verify(&map.root(), b"b", None));
+    }
+}
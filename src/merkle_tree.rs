@@ -1,25 +1,171 @@
-use hex;
-use sha2::{Digest, Sha256};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
+use crate::error::{MerkleError, StorageError};
+use crate::hashing::{decode_hash, Hash, HashAlgorithm, MerkleLeaf, TreeHasher};
+
+pub use merkleproofs_verify::{MerkleProof, Padding, Position, ProofNode};
+
+/// Nodes are kept as raw digests, and hex-encoded only where they leave the tree
+/// Trees hash with a `HashAlgorithm` chosen at runtime unless given any other `TreeHasher`, such
+/// as a `Digest` like `sha2::Sha512`.
 #[derive(Debug)]
-pub struct MerkleTree {
-    root: Option<String>,
-    levels: Vec<Vec<String>>,
+pub struct MerkleTree<H: TreeHasher = HashAlgorithm> {
+    root: Option<H::Output>,
+    levels: Vec<Vec<H::Output>>,
+    /// Number of leaves the tree was built from, before an odd first level is completed
+    leaf_count: usize,
+    hasher: H,
+    padding: Padding,
 }
 
-/// Function to calculate SHA-256 hash of a `String`
-pub fn calculate_hash(s: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(s.as_bytes()); // Hash the bytes of the string
-    let result = hasher.finalize();
-    hex::encode(result) // Convert the hash to a hexadecimal string
+/// Settings of a tree to build, see `MerkleTree::builder`
+/// Trees of another arity are built with `NaryMerkleTree`.
+#[derive(Debug, Clone)]
+pub struct MerkleTreeBuilder<H: TreeHasher = HashAlgorithm> {
+    hasher: H,
+    padding: Padding,
+}
+
+impl<H: TreeHasher + Default> Default for MerkleTree<H> {
+    fn default() -> Self {
+        Self::with_hasher(H::default())
+    }
+}
+
+/// Function to calculate SHA-256 hash of a string or of raw bytes
+pub fn calculate_hash(data: impl AsRef<[u8]>) -> String {
+    merkleproofs_verify::calculate_hash(data)
+}
+
+/// Hex-encoded hash of the contents of a leaf, prefixed with `LEAF_PREFIX`
+pub fn leaf_hash(content: impl AsRef<[u8]>) -> String {
+    merkleproofs_verify::leaf_hash(content)
+}
+
+/// The root of a tree built without leaves
+/// The hash of a domain tag, so no leaf contents can produce it
+pub fn empty_root() -> String {
+    merkleproofs_verify::empty_root()
+}
+
+/// Function to calculate the root hash implied by a leaf's contents and its Merkle proof
+pub fn root_from_proof(content: impl AsRef<[u8]>, proof: &MerkleProof) -> String {
+    HashAlgorithm::Sha256.root_from_proof(content, proof)
+}
+
+/// A proof step with the hex-encoded sibling digest
+pub(crate) fn proof_node(sibling: impl AsRef<[u8]>, is_right: bool) -> ProofNode {
+    ProofNode {
+        sibling: hex::encode(sibling),
+        position: if is_right {
+            Position::Right
+        } else {
+            Position::Left
+        },
+    }
+}
+
+/// Number of nodes at a height of a tree with the given number of leaves
+/// The last node of an odd level is paired with itself, so each level is half the one below,
+/// rounded up
+pub(crate) fn width(leaf_count: usize, height: u32) -> usize {
+    leaf_count.div_ceil(1 << height)
+}
+
+/// Height of the root of a tree with the given number of leaves
+pub(crate) fn root_height(leaf_count: usize) -> u32 {
+    let mut height = 0;
+    while width(leaf_count, height) > 1 {
+        height += 1;
+    }
+    height
 }
 
 impl MerkleTree {
+    pub fn builder() -> MerkleTreeBuilder {
+        MerkleTreeBuilder::new()
+    }
+
     pub fn new() -> Self {
+        Self::with_algorithm(HashAlgorithm::default())
+    }
+
+    /// Creates an empty tree that hashes leaves and nodes with the given algorithm
+    pub fn with_algorithm(algorithm: HashAlgorithm) -> Self {
+        Self::with_hasher(algorithm)
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.hasher
+    }
+
+    /// Builds a SHA-256 tree over the raw digests of its leaves, such as those of
+    /// `hashing::file_digest`, without hashing them again
+    pub fn from_leaves(hashes: Vec<Hash>) -> Self {
+        let mut tree = Self::new();
+        tree.build_from_digests(hashes);
+        tree
+    }
+}
+
+impl<H: TreeHasher> MerkleTree<H> {
+    /// Creates an empty tree that hashes leaves and nodes with the given hasher
+    pub fn with_hasher(hasher: H) -> Self {
         MerkleTree {
             root: None,
             levels: Vec::new(),
+            leaf_count: 0,
+            hasher,
+            padding: Padding::default(),
+        }
+    }
+
+    /// Completes odd levels with the padding instead of a duplicate of their last node
+    /// Set before the tree is built, to match the roots of a system that pads differently.
+    pub fn with_padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// The all-zero digest that `Padding::Zero` pairs the last node of an odd level with
+    fn zero_digest(&self) -> H::Output {
+        let len = self.hasher.empty_digest().as_ref().len();
+        self.hasher
+            .decode_digest(&"00".repeat(len))
+            .expect("The zero digest is a digest")
+    }
+
+    /// Completes an odd leaf level as the padding requires
+    /// Promoted leaves need no completion, so the leaf level keeps its odd width.
+    fn pad_leaves(&self, leaves: &mut Vec<H::Output>) {
+        if leaves.len().is_multiple_of(2) {
+            return;
+        }
+        match self.padding {
+            Padding::Duplicate => leaves.push(leaves[leaves.len() - 1]),
+            Padding::Zero => leaves.push(self.zero_digest()),
+            Padding::Promote => {}
+        }
+    }
+
+    /// The parent at the position of the level below, completing the last node of an odd level
+    /// as the padding requires
+    fn parent(&self, below: &[H::Output], position: usize) -> H::Output {
+        let left = below[position * 2];
+        match (below.get(position * 2 + 1), self.padding) {
+            (Some(right), _) => self.hasher.node_digest(&left, right),
+            (None, Padding::Duplicate) => self.hasher.node_digest(&left, &left),
+            (None, Padding::Zero) => self.hasher.node_digest(&left, &self.zero_digest()),
+            (None, Padding::Promote) => left,
         }
     }
 
@@ -31,14 +177,37 @@ impl MerkleTree {
     //    D    E      // level 1, where D = hash(AB) and E = hash(CC)
     //   / \  / \
     //  A  B C  C     // level 0
-    pub fn build(&mut self, elements: &[String]) {
+    pub fn build<T: MerkleLeaf>(&mut self, elements: &[T]) {
         // Hash the input elements
-        let mut hashes: Vec<String> = elements.iter().map(|e| calculate_hash(e)).collect();
+        let hashes: Vec<H::Output> = elements
+            .iter()
+            .map(|e| self.hasher.leaf_digest(&e.leaf_bytes()))
+            .collect();
 
-        // Ensure an even number of hashes by duplicating the last one if necessary
-        if hashes.len() % 2 != 0 {
-            hashes.push(hashes[hashes.len() - 1].clone());
-        }
+        self.build_from_digests(hashes);
+    }
+
+    /// Build the Merkle tree from already hashed, hex-encoded leaves
+    /// Fails without changing the tree if a leaf hash is not valid hex of a digest
+    pub fn build_from_leaf_hashes(&mut self, hashes: Vec<String>) -> Result<(), MerkleError> {
+        let digests = hashes
+            .into_iter()
+            .map(|hash| {
+                self.hasher
+                    .decode_digest(&hash)
+                    .ok_or(MerkleError::InvalidHash(hash))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_from_digests(digests);
+        Ok(())
+    }
+
+    /// Build the Merkle tree from the raw digests of its leaves
+    pub fn build_from_digests(&mut self, mut hashes: Vec<H::Output>) {
+        let leaf_count = hashes.len();
+
+        // Ensure an even number of hashes, unless the last one is promoted
+        self.pad_leaves(&mut hashes);
 
         let mut nodes = Vec::new();
         nodes.push(hashes.clone());
@@ -47,14 +216,8 @@ impl MerkleTree {
             let mut new_hashes = Vec::new();
 
             // Process pairs of hashes
-            for chunk in hashes.chunks(2) {
-                if chunk.len() == 2 {
-                    let combined_hash = format!("{}{}", chunk[0], chunk[1]);
-                    new_hashes.push(calculate_hash(&combined_hash));
-                } else {
-                    let combined_hash = format!("{}{}", chunk[0], chunk[0]);
-                    new_hashes.push(calculate_hash(&combined_hash));
-                }
+            for position in 0..hashes.len().div_ceil(2) {
+                new_hashes.push(self.parent(&hashes, position));
             }
 
             nodes.push(new_hashes.clone());
@@ -63,39 +226,575 @@ impl MerkleTree {
         }
 
         // Set the root and levels
-        self.root = hashes.pop();
+        self.root = Some(hashes.pop().unwrap_or_else(|| self.hasher.empty_digest()));
+        self.levels = nodes;
+        self.leaf_count = leaf_count;
+    }
+
+    /// Build the Merkle tree like `build`, hashing the leaves and the nodes of every level in
+    /// parallel
+    #[cfg(feature = "parallel")]
+    pub fn build_parallel<T: MerkleLeaf + Sync>(&mut self, elements: &[T])
+    where
+        H: Sync,
+        H::Output: Send + Sync,
+    {
+        let hashes: Vec<H::Output> = elements
+            .par_iter()
+            .map(|e| self.hasher.leaf_digest(&e.leaf_bytes()))
+            .collect();
+
+        self.build_from_digests_parallel(hashes);
+    }
+
+    /// Build the Merkle tree like `build_from_digests`, hashing the nodes of every level in
+    /// parallel
+    #[cfg(feature = "parallel")]
+    pub fn build_from_digests_parallel(&mut self, mut hashes: Vec<H::Output>)
+    where
+        H: Sync,
+        H::Output: Send + Sync,
+    {
+        let leaf_count = hashes.len();
+        self.pad_leaves(&mut hashes);
+
+        let mut nodes = vec![hashes];
+        while nodes[nodes.len() - 1].len() > 1 {
+            let below = &nodes[nodes.len() - 1];
+            let level: Vec<H::Output> = (0..below.len().div_ceil(2))
+                .into_par_iter()
+                .map(|position| self.parent(below, position))
+                .collect();
+            nodes.push(level);
+        }
+
+        self.root = Some(match leaf_count {
+            0 => self.hasher.empty_digest(),
+            _ => nodes[nodes.len() - 1][0],
+        });
         self.levels = nodes;
+        self.leaf_count = leaf_count;
     }
 
+    /// Build the Merkle tree on a blocking thread
+    /// Meant for async contexts, so that hashing a large upload does not stall the runtime. If the
+    /// task panics, the tree is left empty.
+    pub async fn build_async<T: MerkleLeaf + Send + 'static>(
+        &mut self,
+        elements: Vec<T>,
+    ) -> Result<(), MerkleError>
+    where
+        H: Default + Send + 'static,
+        H::Output: Send,
+    {
+        let mut tree = std::mem::take(self);
+        *self = tokio::task::spawn_blocking(move || {
+            tree.build(&elements);
+            tree
+        })
+        .await
+        .map_err(|_| MerkleError::BuildFailed)?;
+        Ok(())
+    }
+
+    /// Replaces the contents of the leaf at the index, rehashing only the nodes on its path
+    pub fn update_leaf<T: MerkleLeaf + ?Sized>(
+        &mut self,
+        index: usize,
+        new_value: &T,
+    ) -> Result<(), MerkleError> {
+        let digest = self.hasher.leaf_digest(&new_value.leaf_bytes());
+        self.update_leaf_digest(index, digest)
+    }
+
+    /// Replaces the raw digest of the leaf at the index, like `update_leaf`
+    pub fn update_leaf_digest(
+        &mut self,
+        index: usize,
+        digest: H::Output,
+    ) -> Result<(), MerkleError> {
+        self.check_index(index)?;
+
+        self.levels[0][index] = digest;
+        // The duplicate completing an odd first level follows the last leaf
+        let odd = !self.leaf_count.is_multiple_of(2);
+        if index + 1 == self.leaf_count && odd && self.padding == Padding::Duplicate {
+            self.levels[0][index + 1] = digest;
+        }
+
+        let mut position = index / 2;
+        for height in 1..self.levels.len() {
+            self.levels[height][position] = self.parent(&self.levels[height - 1], position);
+            position /= 2;
+        }
+        self.root = self.levels.last().map(|level| level[0]);
+        Ok(())
+    }
+
+    /// Removes the leaf at the index, moving the leaves after it one position down, and returns
+    /// the new root
+    /// Nodes over only the leaves before the index are kept; the rest are rehashed
+    pub fn remove_leaf(&mut self, index: usize) -> Result<String, MerkleError> {
+        self.check_index(index)?;
+
+        let mut leaves = std::mem::take(&mut self.levels[0]);
+        leaves.truncate(self.leaf_count);
+        leaves.remove(index);
+        if leaves.is_empty() {
+            self.build_from_digests(leaves);
+            return Ok(self.root().expect("Built trees have a root"));
+        }
+
+        self.leaf_count = leaves.len();
+        self.pad_leaves(&mut leaves);
+        let mut levels = vec![leaves];
+        let mut height = 1;
+        while levels[height - 1].len() > 1 {
+            let below = &levels[height - 1];
+            let width = below.len().div_ceil(2);
+            let kept = (index >> height).min(width);
+            let mut level = self.levels[height][..kept].to_vec();
+            for position in kept..width {
+                level.push(self.parent(below, position));
+            }
+            levels.push(level);
+            height += 1;
+        }
+
+        self.root = levels.last().map(|level| level[0]);
+        self.levels = levels;
+        Ok(self.root().expect("Built trees have a root"))
+    }
+
+    /// The root hash, which is `empty_root` for a tree built without leaves, or `None` before the
+    /// tree is built
     pub fn root(&self) -> Option<String> {
-        self.root.clone()
+        self.root.map(hex::encode)
     }
 
-    /// Get the Merkle proof for a given index
-    /// Generates (duplicates) nodes on the fly if missing from the tree
-    pub fn get_merkle_proof(&self, index: usize) -> Option<Vec<(String, bool)>> {
-        if index >= self.levels[0].len() {
+    /// Checks that the tree is built and has a leaf at the index
+    fn check_index(&self, index: usize) -> Result<(), MerkleError> {
+        if self.root.is_none() {
+            return Err(MerkleError::NotBuilt);
+        }
+        if self.leaf_count == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if index >= self.leaf_count {
+            return Err(MerkleError::IndexOutOfRange {
+                index,
+                leaf_count: self.leaf_count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Number of leaves the tree was built from
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Number of levels above the leaves, which is the length of every proof
+    pub fn depth(&self) -> usize {
+        self.levels.len().saturating_sub(1)
+    }
+
+    /// The hash of the leaf at the index
+    pub fn get_leaf(&self, index: usize) -> Option<String> {
+        if index >= self.leaf_count {
             return None;
         }
+        self.get_node(0, index)
+    }
+
+    /// Index of the first leaf with the contents, so that its proof can be taken
+    pub fn find_leaf<T: MerkleLeaf + ?Sized>(&self, value: &T) -> Option<usize> {
+        self.find_digest(&self.hasher.leaf_digest(&value.leaf_bytes()))
+    }
+
+    /// Index of the first leaf with the hex-encoded leaf hash
+    pub fn find_leaf_hash(&self, hash: &str) -> Option<usize> {
+        self.find_digest(&self.hasher.decode_digest(hash)?)
+    }
+
+    fn find_digest(&self, digest: &H::Output) -> Option<usize> {
+        let leaves = self.levels.first()?;
+        leaves[..self.leaf_count]
+            .iter()
+            .position(|leaf| leaf == digest)
+    }
+
+    /// The hash of the node at the index of a level, counting levels from the leaves up
+    /// The duplicate completing an odd first level is the last node of level 0.
+    pub fn get_node(&self, level: usize, index: usize) -> Option<String> {
+        self.levels.get(level)?.get(index).map(hex::encode)
+    }
+
+    /// Raw digests of the leaves, in order, without the padding of an odd leaf level
+    pub fn leaves(&self) -> std::slice::Iter<'_, H::Output> {
+        let leaves = match self.levels.first() {
+            Some(level) => &level[..self.leaf_count],
+            None => &[],
+        };
+        leaves.iter()
+    }
+
+    /// Raw digests of every level, from the leaves up to the root, with the padding that
+    /// completes odd levels
+    /// An unbuilt tree has no levels, and an empty one a single empty level.
+    pub fn levels(&self) -> impl ExactSizeIterator<Item = &[H::Output]> {
+        self.levels.iter().map(Vec::as_slice)
+    }
+
+    /// Indices of the leaves whose hashes differ between the two trees, in order
+    /// Both trees are walked from the top down and subtrees with the same hash in both are
+    /// skipped, so only the paths to changed leaves are visited. Leaves that only one of the trees
+    /// has count as changed.
+    pub fn diff(&self, other: &MerkleTree<H>) -> Vec<usize> {
+        let common = self.leaf_count.min(other.leaf_count);
+        let mut changed = Vec::new();
+        if common > 0 {
+            // The highest level both trees have, with its nodes over the common leaves
+            let height = self.depth().min(other.depth()) as u32;
+            for position in 0..width(common, height) {
+                self.diff_node(other, height, position, &mut changed);
+            }
+        }
+        changed.extend(common..self.leaf_count.max(other.leaf_count));
+        changed
+    }
+
+    fn diff_node(
+        &self,
+        other: &MerkleTree<H>,
+        height: u32,
+        position: usize,
+        changed: &mut Vec<usize>,
+    ) {
+        let common = self.leaf_count.min(other.leaf_count);
+        let first = position << height;
+        if first >= common {
+            return;
+        }
+        // A node over leaves past the end of either tree also covers its padding, so equal
+        // hashes only prove equal leaves when the trees have the same size
+        let within = (position + 1) << height <= common || self.leaf_count == other.leaf_count;
+        let level = height as usize;
+        if within && self.levels[level][position] == other.levels[level][position] {
+            return;
+        }
+        if height == 0 {
+            if self.levels[0][position] != other.levels[0][position] {
+                changed.push(position);
+            }
+            return;
+        }
+        self.diff_node(other, height - 1, position * 2, changed);
+        self.diff_node(other, height - 1, position * 2 + 1, changed);
+    }
 
-        let mut proof = Vec::new();
+    /// Get the Merkle proof for a given index
+    /// Generates (duplicates) nodes on the fly if missing from the tree
+    /// A node promoted past a level has no sibling there, so the proof skips the level.
+    /// Fails if the tree is not built, is empty or has no leaf at the index
+    pub fn get_merkle_proof(&self, index: usize) -> Result<MerkleProof, MerkleError> {
+        self.check_index(index)?;
+
+        let mut nodes = Vec::new();
         let mut current_index = index;
 
         for level in self.levels.iter().take(self.levels.len() - 1) {
             let sibling_index = current_index ^ 1; // XOR with 1 flips the last bit
 
             let sibling_hash = if sibling_index < level.len() {
-                level[sibling_index].clone()
+                level[sibling_index]
             } else {
-                // Duplicate the current node if sibling is out of bounds
-                level[current_index].clone()
+                match self.padding {
+                    // Duplicate the current node if sibling is out of bounds
+                    Padding::Duplicate => level[current_index],
+                    Padding::Zero => self.zero_digest(),
+                    Padding::Promote => {
+                        current_index /= 2;
+                        continue;
+                    }
+                }
             };
 
-            proof.push((sibling_hash, sibling_index > current_index));
+            nodes.push(proof_node(sibling_hash, sibling_index > current_index));
             current_index /= 2;
         }
 
-        Some(proof)
+        Ok(MerkleProof {
+            leaf_index: index,
+            leaf_count: self.leaf_count,
+            nodes,
+        })
+    }
+
+    /// Get the Merkle proof for a given index in the tree as it was with its first `tree_size`
+    /// leaves
+    /// Subtrees made only of those leaves are unchanged since, so only the nodes on the right
+    /// edge of the old tree are recomputed, from the retained leaf hashes
+    /// Fails for trees padded other than by duplicates.
+    pub fn get_merkle_proof_at(
+        &self,
+        index: usize,
+        tree_size: usize,
+    ) -> Result<MerkleProof, MerkleError> {
+        self.check_index(index)?;
+        if tree_size > self.leaf_count {
+            return Err(MerkleError::SizeOutOfRange {
+                tree_size,
+                leaf_count: self.leaf_count,
+            });
+        }
+        if index >= tree_size {
+            return Err(MerkleError::IndexOutOfRange {
+                index,
+                leaf_count: tree_size,
+            });
+        }
+        if self.padding != Padding::Duplicate {
+            return Err(MerkleError::UnsupportedPadding(self.padding));
+        }
+
+        // Like the tree itself, the old tree completed an odd first level with a duplicate
+        let leaf_count = tree_size + tree_size % 2;
+        let mut nodes = Vec::new();
+        let mut position = index;
+        for height in 0..root_height(leaf_count) {
+            let sibling = position ^ 1;
+            let node = if sibling < width(leaf_count, height) {
+                self.node_at(height, sibling, tree_size)
+            } else {
+                self.node_at(height, position, tree_size)
+            };
+            nodes.push(proof_node(node, sibling > position));
+            position /= 2;
+        }
+        Ok(MerkleProof {
+            leaf_index: index,
+            leaf_count: tree_size,
+            nodes,
+        })
+    }
+
+    /// The node at the height and position in the tree over the first `tree_size` leaves
+    pub(crate) fn node_at(&self, height: u32, position: usize, tree_size: usize) -> H::Output {
+        if height == 0 {
+            // The duplicate completing an odd first level
+            return self.levels[0][position.min(tree_size - 1)];
+        }
+        if (position + 1) << height <= tree_size {
+            return self.levels[height as usize][position];
+        }
+        let leaf_count = tree_size + tree_size % 2;
+        let left = self.node_at(height - 1, position * 2, tree_size);
+        let right = if position * 2 + 1 < width(leaf_count, height - 1) {
+            self.node_at(height - 1, position * 2 + 1, tree_size)
+        } else {
+            left
+        };
+        self.hasher.node_digest(&left, &right)
+    }
+}
+
+impl MerkleTreeBuilder {
+    pub fn new() -> Self {
+        Self {
+            hasher: HashAlgorithm::default(),
+            padding: Padding::default(),
+        }
+    }
+
+    /// Hashes leaves and nodes with the algorithm, SHA-256 if not set
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hasher = algorithm;
+        self
+    }
+}
+
+impl Default for MerkleTreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: TreeHasher> MerkleTreeBuilder<H> {
+    /// Hashes leaves and nodes with any other hasher, such as a `Digest`
+    pub fn hasher<G: TreeHasher>(self, hasher: G) -> MerkleTreeBuilder<G> {
+        MerkleTreeBuilder {
+            hasher,
+            padding: self.padding,
+        }
+    }
+
+    /// Completes odd levels with the padding, `Padding::Duplicate` if not set
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// A tree with the settings that is not built yet
+    pub fn empty(self) -> MerkleTree<H> {
+        MerkleTree::with_hasher(self.hasher).with_padding(self.padding)
+    }
+
+    /// Builds the tree from a list of elements, like `MerkleTree::build`
+    pub fn build<T: MerkleLeaf>(self, elements: &[T]) -> MerkleTree<H> {
+        let mut tree = self.empty();
+        tree.build(elements);
+        tree
+    }
+
+    /// Builds the tree from the raw digests of its leaves, like `MerkleTree::build_from_digests`
+    pub fn build_from_digests(self, hashes: Vec<H::Output>) -> MerkleTree<H> {
+        let mut tree = self.empty();
+        tree.build_from_digests(hashes);
+        tree
+    }
+}
+
+/// Iterates over the raw digests of the leaves, like `MerkleTree::leaves`
+impl<'a, H: TreeHasher> IntoIterator for &'a MerkleTree<H> {
+    type Item = &'a H::Output;
+    type IntoIter = std::slice::Iter<'a, H::Output>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.leaves()
+    }
+}
+
+/// Number of hex digits of a hash shown by `Display`
+const SHORT_HASH_LEN: usize = 8;
+
+fn short_hash(hash: impl AsRef<[u8]>) -> String {
+    let mut hash = hex::encode(hash);
+    hash.truncate(SHORT_HASH_LEN);
+    hash
+}
+
+/// Prints the tree from the root down, one level per line, with hashes cut to their first
+/// digits
+/// Leaves are prefixed with their index, and the padding completing the leaf level is marked
+/// with `*`.
+impl<H: TreeHasher> fmt::Display for MerkleTree<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(root) = self.root else {
+            return write!(f, "Unbuilt tree");
+        };
+        writeln!(f, "Tree of {} leaves", self.leaf_count)?;
+        write!(f, "root: {}", short_hash(root))?;
+        for height in (0..self.depth()).rev() {
+            write!(f, "\n{:>4}:", height)?;
+            for (position, node) in self.levels[height].iter().enumerate() {
+                if height > 0 {
+                    write!(f, " {}", short_hash(node))?;
+                } else if position < self.leaf_count {
+                    write!(f, " {}={}", position, short_hash(node))?;
+                } else {
+                    write!(f, " *={}", short_hash(node))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A tree as it is persisted: every level, hex-encoded, so loading it needs no hashing
+/// A tree that was never built has no levels.
+#[derive(Serialize, Deserialize)]
+struct StoredTree {
+    algorithm: HashAlgorithm,
+    #[serde(default)]
+    padding: Padding,
+    leaf_count: usize,
+    levels: Vec<Vec<String>>,
+}
+
+impl Serialize for MerkleTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StoredTree {
+            algorithm: self.hasher,
+            padding: self.padding,
+            leaf_count: self.leaf_count,
+            levels: self
+                .levels
+                .iter()
+                .map(|level| level.iter().map(hex::encode).collect())
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MerkleTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredTree::deserialize(deserializer)?;
+        MerkleTree::from_stored(stored).map_err(serde::de::Error::custom)
+    }
+}
+
+impl MerkleTree {
+    /// Checks that every level has the width the leaf count implies
+    /// The nodes are not rehashed, so a tree is only as trustworthy as where it was stored
+    fn from_stored(stored: StoredTree) -> Result<Self, MerkleError> {
+        let mut tree = MerkleTree::with_algorithm(stored.algorithm).with_padding(stored.padding);
+        if stored.levels.is_empty() {
+            return match stored.leaf_count {
+                0 => Ok(tree),
+                _ => Err(MerkleError::MalformedTree("tree without levels")),
+            };
+        }
+
+        let mut widths = vec![match stored.padding {
+            Padding::Promote => stored.leaf_count,
+            _ => stored.leaf_count + stored.leaf_count % 2,
+        }];
+        while widths[widths.len() - 1] > 1 {
+            widths.push(widths[widths.len() - 1].div_ceil(2));
+        }
+        if stored.levels.iter().map(Vec::len).ne(widths) {
+            return Err(MerkleError::MalformedTree(
+                "levels do not fit the leaf count",
+            ));
+        }
+
+        tree.levels = stored
+            .levels
+            .into_iter()
+            .map(|level| {
+                level
+                    .into_iter()
+                    .map(|hash| decode_hash(&hash).ok_or(MerkleError::InvalidHash(hash)))
+                    .collect::<Result<Vec<Hash>, _>>()
+            })
+            .collect::<Result<_, _>>()?;
+        tree.root = Some(match stored.leaf_count {
+            0 => tree.hasher.empty_digest(),
+            _ => tree.levels[tree.levels.len() - 1][0],
+        });
+        tree.leaf_count = stored.leaf_count;
+        Ok(tree)
+    }
+
+    /// Writes the tree to a file as JSON, so it can be loaded again without rebuilding it
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        let data = serde_json::to_vec(self).map_err(|e| StorageError::Corrupt {
+            name: path.as_ref().display().to_string(),
+            reason: e.to_string(),
+        })?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Reads a tree written by `save`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let data = fs::read(&path)?;
+        serde_json::from_slice(&data).map_err(|e| StorageError::Corrupt {
+            name: path.as_ref().display().to_string(),
+            reason: e.to_string(),
+        })
     }
 }
 
@@ -103,11 +802,22 @@ impl MerkleTree {
 mod tests {
 
     use super::*;
+    use crate::hashing::{LEAF_PREFIX, NODE_PREFIX};
+    use sha2::{Digest, Sha256, Sha512};
+
+    /// Expected parent of two hex-encoded children, hashed over the node prefix and their
+    /// concatenated raw bytes
+    fn hash_pair(left: &str, right: &str) -> String {
+        let mut bytes = vec![NODE_PREFIX];
+        bytes.extend(hex::decode(left).unwrap());
+        bytes.extend(hex::decode(right).unwrap());
+        hex::encode(Sha256::digest(&bytes))
+    }
 
     #[test]
     fn empty_tree() {
         let tree = MerkleTree::new();
-        assert_eq!(tree.root, None);
+        assert_eq!(tree.root(), None);
         assert_eq!(tree.levels.len(), 0);
     }
 
@@ -118,12 +828,13 @@ mod tests {
         let elements: Vec<String> = Vec::new();
         tree.build(&elements);
 
-        assert_eq!(tree.root, None);
+        assert_eq!(tree.root(), Some(empty_root()));
         assert_eq!(tree.levels.len(), 1);
         assert_eq!(tree.levels[0].len(), 0);
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push)]
     fn build_tree_one_element() {
         let mut tree = MerkleTree::new();
 
@@ -133,22 +844,22 @@ mod tests {
 
         tree.build(&elements);
 
-        let expected_leaf = calculate_hash(&val);
-        // Concatenate `expected_leaf` with itself
-        let combined_leaf = format!("{}{}", expected_leaf, expected_leaf);
-        let expected_root = calculate_hash(&combined_leaf);
+        let expected_leaf = leaf_hash(&val);
+        // Hash `expected_leaf` with itself
+        let expected_root = hash_pair(&expected_leaf, &expected_leaf);
 
         // Verify levels
         assert_eq!(tree.levels.len(), 2);
         assert_eq!(tree.levels[0].len(), 2);
 
         // Verify leaf and root
-        assert_eq!(tree.root, Some(expected_root));
-        assert_eq!(tree.levels[0][0], expected_leaf);
-        assert_eq!(tree.levels[0][1], expected_leaf);
+        assert_eq!(tree.root(), Some(expected_root));
+        assert_eq!(hex::encode(tree.levels[0][0]), expected_leaf);
+        assert_eq!(hex::encode(tree.levels[0][1]), expected_leaf);
     }
 
     #[test]
+    #[allow(clippy::vec_init_then_push)]
     fn build_tree_two_elements() {
         let mut tree = MerkleTree::new();
 
@@ -160,18 +871,18 @@ mod tests {
         elements.push(val2.clone());
         tree.build(&elements);
 
-        let expected_leaf_1 = calculate_hash(&val1);
-        let expected_leaf_2 = calculate_hash(&val2);
-        let expected_root = calculate_hash(&format!("{}{}", expected_leaf_1, expected_leaf_2));
-        //let expected_root = calculate_hash(&expected_leaf_1.wrapping_add(expected_leaf_2));
+        let expected_leaf_1 = leaf_hash(&val1);
+        let expected_leaf_2 = leaf_hash(&val2);
+        let expected_root = hash_pair(&expected_leaf_1, &expected_leaf_2);
+        //let expected_root = leaf_hash(&expected_leaf_1.wrapping_add(expected_leaf_2));
 
         assert_eq!(tree.levels.len(), 2);
         assert_eq!(tree.levels[0].len(), 2);
         assert_eq!(tree.levels[1].len(), 1);
 
-        assert_eq!(tree.root, Some(expected_root));
-        assert_eq!(tree.levels[0][0], expected_leaf_1);
-        assert_eq!(tree.levels[0][1], expected_leaf_2);
+        assert_eq!(tree.root(), Some(expected_root));
+        assert_eq!(hex::encode(tree.levels[0][0]), expected_leaf_1);
+        assert_eq!(hex::encode(tree.levels[0][1]), expected_leaf_2);
     }
 
     #[test]
@@ -186,20 +897,19 @@ mod tests {
         tree.build(&elements);
 
         // Calculate the expected hashes
-        let expected_leaf_1 = calculate_hash(&elements[0]);
-        let expected_leaf_2 = calculate_hash(&elements[1]);
-        let expected_leaf_3 = calculate_hash(&elements[2]);
+        let expected_leaf_1 = leaf_hash(&elements[0]);
+        let expected_leaf_2 = leaf_hash(&elements[1]);
+        let expected_leaf_3 = leaf_hash(&elements[2]);
 
         // Duplicate the last leaf hash to ensure even number of hashes
         let expected_leaf_4 = expected_leaf_3.clone();
 
         // Calculate the intermediate hashes
-        let expected_mid_node1 = calculate_hash(&format!("{}{}", expected_leaf_1, expected_leaf_2));
-        let expected_mid_node2 = calculate_hash(&format!("{}{}", expected_leaf_3, expected_leaf_4));
+        let expected_mid_node1 = hash_pair(&expected_leaf_1, &expected_leaf_2);
+        let expected_mid_node2 = hash_pair(&expected_leaf_3, &expected_leaf_4);
 
         // Calculate the root hash
-        let expected_root =
-            calculate_hash(&format!("{}{}", expected_mid_node1, expected_mid_node2));
+        let expected_root = hash_pair(&expected_mid_node1, &expected_mid_node2);
 
         // Assertions
         assert_eq!(tree.levels.len(), 3);
@@ -207,16 +917,16 @@ mod tests {
         assert_eq!(tree.levels[1].len(), 2); // 2 intermediate nodes
         assert_eq!(tree.levels[2].len(), 1); // 1 root node
 
-        assert_eq!(tree.root, Some(expected_root.clone()));
-        assert_eq!(tree.levels[2][0], expected_root);
+        assert_eq!(tree.root(), Some(expected_root.clone()));
+        assert_eq!(hex::encode(tree.levels[2][0]), expected_root);
 
-        assert_eq!(tree.levels[0][0], expected_leaf_1);
-        assert_eq!(tree.levels[0][1], expected_leaf_2);
-        assert_eq!(tree.levels[0][2], expected_leaf_3);
-        assert_eq!(tree.levels[0][3], expected_leaf_4);
+        assert_eq!(hex::encode(tree.levels[0][0]), expected_leaf_1);
+        assert_eq!(hex::encode(tree.levels[0][1]), expected_leaf_2);
+        assert_eq!(hex::encode(tree.levels[0][2]), expected_leaf_3);
+        assert_eq!(hex::encode(tree.levels[0][3]), expected_leaf_4);
 
-        assert_eq!(tree.levels[1][0], expected_mid_node1);
-        assert_eq!(tree.levels[1][1], expected_mid_node2);
+        assert_eq!(hex::encode(tree.levels[1][0]), expected_mid_node1);
+        assert_eq!(hex::encode(tree.levels[1][1]), expected_mid_node2);
     }
 
     // Test a tree that has an odd amount of middle nodes.
@@ -234,31 +944,25 @@ mod tests {
         tree.build(&elements);
 
         // Calculate the expected hashes
-        let expected_leaf_1 = calculate_hash(&elements[0]);
-        let expected_leaf_2 = calculate_hash(&elements[1]);
-        let expected_leaf_3 = calculate_hash(&elements[2]);
-        let expected_leaf_4 = calculate_hash(&elements[3]);
-        let expected_leaf_5 = calculate_hash(&elements[4]);
+        let expected_leaf_1 = leaf_hash(&elements[0]);
+        let expected_leaf_2 = leaf_hash(&elements[1]);
+        let expected_leaf_3 = leaf_hash(&elements[2]);
+        let expected_leaf_4 = leaf_hash(&elements[3]);
+        let expected_leaf_5 = leaf_hash(&elements[4]);
 
         // Duplicate the last leaf hash to ensure even number of hashes
         let expected_leaf_6 = expected_leaf_5.clone();
 
         // Calculate the intermediate hashes
-        let expected_mid1_node1 =
-            calculate_hash(&format!("{}{}", expected_leaf_1, expected_leaf_2));
-        let expected_mid1_node2 =
-            calculate_hash(&format!("{}{}", expected_leaf_3, expected_leaf_4));
-        let expected_mid1_node3 =
-            calculate_hash(&format!("{}{}", expected_leaf_5, expected_leaf_5));
-
-        let expected_mid2_node1 =
-            calculate_hash(&format!("{}{}", expected_mid1_node1, expected_mid1_node2));
-        let expected_mid3_node2 =
-            calculate_hash(&format!("{}{}", expected_mid1_node3, expected_mid1_node3));
+        let expected_mid1_node1 = hash_pair(&expected_leaf_1, &expected_leaf_2);
+        let expected_mid1_node2 = hash_pair(&expected_leaf_3, &expected_leaf_4);
+        let expected_mid1_node3 = hash_pair(&expected_leaf_5, &expected_leaf_5);
+
+        let expected_mid2_node1 = hash_pair(&expected_mid1_node1, &expected_mid1_node2);
+        let expected_mid3_node2 = hash_pair(&expected_mid1_node3, &expected_mid1_node3);
 
         // Calculate the root hash
-        let expected_root =
-            calculate_hash(&format!("{}{}", expected_mid2_node1, expected_mid3_node2));
+        let expected_root = hash_pair(&expected_mid2_node1, &expected_mid3_node2);
 
         // Assertions
         assert_eq!(tree.levels.len(), 4);
@@ -267,22 +971,22 @@ mod tests {
         assert_eq!(tree.levels[2].len(), 2);
         assert_eq!(tree.levels[3].len(), 1); // 1 root node
 
-        assert_eq!(tree.root, Some(expected_root.clone()));
-        assert_eq!(tree.levels[3][0], expected_root);
+        assert_eq!(tree.root(), Some(expected_root.clone()));
+        assert_eq!(hex::encode(tree.levels[3][0]), expected_root);
 
-        assert_eq!(tree.levels[0][0], expected_leaf_1);
-        assert_eq!(tree.levels[0][1], expected_leaf_2);
-        assert_eq!(tree.levels[0][2], expected_leaf_3);
-        assert_eq!(tree.levels[0][3], expected_leaf_4);
-        assert_eq!(tree.levels[0][4], expected_leaf_5);
-        assert_eq!(tree.levels[0][5], expected_leaf_6);
+        assert_eq!(hex::encode(tree.levels[0][0]), expected_leaf_1);
+        assert_eq!(hex::encode(tree.levels[0][1]), expected_leaf_2);
+        assert_eq!(hex::encode(tree.levels[0][2]), expected_leaf_3);
+        assert_eq!(hex::encode(tree.levels[0][3]), expected_leaf_4);
+        assert_eq!(hex::encode(tree.levels[0][4]), expected_leaf_5);
+        assert_eq!(hex::encode(tree.levels[0][5]), expected_leaf_6);
 
-        assert_eq!(tree.levels[1][0], expected_mid1_node1);
-        assert_eq!(tree.levels[1][1], expected_mid1_node2);
-        assert_eq!(tree.levels[1][2], expected_mid1_node3);
+        assert_eq!(hex::encode(tree.levels[1][0]), expected_mid1_node1);
+        assert_eq!(hex::encode(tree.levels[1][1]), expected_mid1_node2);
+        assert_eq!(hex::encode(tree.levels[1][2]), expected_mid1_node3);
 
-        assert_eq!(tree.levels[2][0], expected_mid2_node1);
-        assert_eq!(tree.levels[2][1], expected_mid3_node2);
+        assert_eq!(hex::encode(tree.levels[2][0]), expected_mid2_node1);
+        assert_eq!(hex::encode(tree.levels[2][1]), expected_mid3_node2);
     }
 
     #[test]
@@ -296,22 +1000,22 @@ mod tests {
 
         tree.build(&elements);
 
-        let expected_leaf_1 = calculate_hash(&val1);
-        let expected_leaf_2 = calculate_hash(&val2);
-        let expected_leaf_3 = calculate_hash(&val3);
+        let expected_leaf_1 = leaf_hash(&val1);
+        let expected_leaf_2 = leaf_hash(&val2);
+        let expected_leaf_3 = leaf_hash(&val3);
 
         // Duplicate the last leaf hash to ensure even number of hashes
         let expected_leaf_4 = expected_leaf_3.clone();
 
         // Calculate intermediate hashes
-        let expected_mid_node1 = calculate_hash(&format!("{}{}", expected_leaf_1, expected_leaf_2));
-        let expected_mid_node2 = calculate_hash(&format!("{}{}", expected_leaf_3, expected_leaf_4));
+        let expected_mid_node1 = hash_pair(&expected_leaf_1, &expected_leaf_2);
+        let expected_mid_node2 = hash_pair(&expected_leaf_3, &expected_leaf_4);
 
         // Function to verify the proof
-        fn verify_proof(proof: Vec<(String, bool)>, expected_proof: Vec<String>) {
-            assert_eq!(proof.len(), expected_proof.len());
-            for ((elem1, _), elem2) in proof.iter().zip(expected_proof.iter()) {
-                assert_eq!(elem1, elem2);
+        fn verify_proof(proof: MerkleProof, expected_proof: Vec<String>) {
+            assert_eq!(proof.nodes.len(), expected_proof.len());
+            for (node, elem2) in proof.nodes.iter().zip(expected_proof.iter()) {
+                assert_eq!(&node.sibling, elem2);
             }
         }
 
@@ -352,36 +1056,31 @@ mod tests {
 
         tree.build(&elements);
 
-        let expected_leaf_1 = calculate_hash(&val1);
-        let expected_leaf_2 = calculate_hash(&val2);
-        let expected_leaf_3 = calculate_hash(&val3);
-        let expected_leaf_4 = calculate_hash(&val4);
-        let expected_leaf_5 = calculate_hash(&val5);
+        let expected_leaf_1 = leaf_hash(&val1);
+        let expected_leaf_2 = leaf_hash(&val2);
+        let expected_leaf_3 = leaf_hash(&val3);
+        let expected_leaf_4 = leaf_hash(&val4);
+        let expected_leaf_5 = leaf_hash(&val5);
 
         // Duplicate the last leaf hash to ensure even number of hashes
         let expected_leaf_6 = expected_leaf_5.clone();
 
         // Calculate intermediate hashes
-        let expected_mid1_node1 =
-            calculate_hash(&format!("{}{}", expected_leaf_1, expected_leaf_2));
-        let expected_mid1_node2 =
-            calculate_hash(&format!("{}{}", expected_leaf_3, expected_leaf_4));
-        let expected_mid1_node3 =
-            calculate_hash(&format!("{}{}", expected_leaf_5, expected_leaf_6));
-
-        let expected_mid2_node1 =
-            calculate_hash(&format!("{}{}", expected_mid1_node1, expected_mid1_node2));
-        let expected_mid2_node2 =
-            calculate_hash(&format!("{}{}", expected_mid1_node3, expected_mid1_node3));
+        let expected_mid1_node1 = hash_pair(&expected_leaf_1, &expected_leaf_2);
+        let expected_mid1_node2 = hash_pair(&expected_leaf_3, &expected_leaf_4);
+        let expected_mid1_node3 = hash_pair(&expected_leaf_5, &expected_leaf_6);
+
+        let expected_mid2_node1 = hash_pair(&expected_mid1_node1, &expected_mid1_node2);
+        let expected_mid2_node2 = hash_pair(&expected_mid1_node3, &expected_mid1_node3);
 
         // Function to verify the proof
-        fn verify_proof(proof: Vec<(String, bool)>, expected_proof: Vec<(String, bool)>) {
-            assert_eq!(proof.len(), expected_proof.len());
-            for ((elem1, is_right1), (expected_elem, expected_is_right)) in
-                proof.iter().zip(expected_proof.iter())
+        fn verify_proof(proof: MerkleProof, expected_proof: Vec<(String, Position)>) {
+            assert_eq!(proof.nodes.len(), expected_proof.len());
+            for (node, (expected_elem, expected_position)) in
+                proof.nodes.iter().zip(expected_proof.iter())
             {
-                assert_eq!(elem1, expected_elem);
-                assert_eq!(is_right1, expected_is_right);
+                assert_eq!(&node.sibling, expected_elem);
+                assert_eq!(&node.position, expected_position);
             }
         }
 
@@ -390,9 +1089,9 @@ mod tests {
             let proof = tree.get_merkle_proof(0).unwrap();
             println!("Received proof: {:?}", proof);
             let expected_proof = vec![
-                (expected_leaf_2.clone(), true),
-                (expected_mid1_node2.clone(), true),
-                (expected_mid2_node2.clone(), true),
+                (expected_leaf_2.clone(), Position::Right),
+                (expected_mid1_node2.clone(), Position::Right),
+                (expected_mid2_node2.clone(), Position::Right),
             ];
             verify_proof(proof, expected_proof);
         }
@@ -400,9 +1099,9 @@ mod tests {
             let proof = tree.get_merkle_proof(1).unwrap();
             println!("Received proof: {:?}", proof);
             let expected_proof = vec![
-                (expected_leaf_1.clone(), false),
-                (expected_mid1_node2.clone(), true),
-                (expected_mid2_node2.clone(), true),
+                (expected_leaf_1.clone(), Position::Left),
+                (expected_mid1_node2.clone(), Position::Right),
+                (expected_mid2_node2.clone(), Position::Right),
             ];
             verify_proof(proof, expected_proof);
         }
@@ -410,9 +1109,9 @@ mod tests {
             let proof = tree.get_merkle_proof(2).unwrap();
             println!("Received proof: {:?}", proof);
             let expected_proof = vec![
-                (expected_leaf_4.clone(), true),
-                (expected_mid1_node1.clone(), false),
-                (expected_mid2_node2.clone(), true),
+                (expected_leaf_4.clone(), Position::Right),
+                (expected_mid1_node1.clone(), Position::Left),
+                (expected_mid2_node2.clone(), Position::Right),
             ];
             verify_proof(proof, expected_proof);
         }
@@ -420,9 +1119,9 @@ mod tests {
             let proof = tree.get_merkle_proof(3).unwrap();
             println!("Received proof: {:?}", proof);
             let expected_proof = vec![
-                (expected_leaf_3.clone(), false),
-                (expected_mid1_node1.clone(), false),
-                (expected_mid2_node2.clone(), true),
+                (expected_leaf_3.clone(), Position::Left),
+                (expected_mid1_node1.clone(), Position::Left),
+                (expected_mid2_node2.clone(), Position::Right),
             ];
             verify_proof(proof, expected_proof);
         }
@@ -430,11 +1129,612 @@ mod tests {
             let proof = tree.get_merkle_proof(4).unwrap();
             println!("Received proof: {:?}", proof);
             let expected_proof = vec![
-                (expected_leaf_5.clone(), true),
-                (expected_mid1_node3.clone(), true),
-                (expected_mid2_node1.clone(), false),
+                (expected_leaf_5.clone(), Position::Right),
+                (expected_mid1_node3.clone(), Position::Right),
+                (expected_mid2_node1.clone(), Position::Left),
             ];
             verify_proof(proof, expected_proof);
         }
     }
+
+    #[test]
+    fn leaves_are_hashed_with_their_prefix() {
+        let mut tree = MerkleTree::new();
+        tree.build(&["a".to_string()]);
+        let leaf = hex::encode(Sha256::digest([LEAF_PREFIX, b'a']));
+        assert_eq!(hex::encode(tree.levels[0][0]), leaf);
+        assert_eq!(tree.root(), Some(hash_pair(&leaf, &leaf)));
+
+        // A leaf whose contents are the children of a node is not that node
+        let children = [tree.levels[0][0], tree.levels[0][1]].concat();
+        let mut forged = MerkleTree::new();
+        forged.build_from_digests(vec![HashAlgorithm::Sha256.leaf_digest(&children)]);
+        assert_ne!(
+            forged.levels[0][0], tree.levels[1][0],
+            "a leaf must not hash like a node"
+        );
+    }
+
+    #[test]
+    fn malformed_leaf_hashes_are_rejected() {
+        let mut tree = MerkleTree::new();
+        let leaf = leaf_hash("a");
+        assert_eq!(
+            tree.build_from_leaf_hashes(vec![leaf.clone(), leaf[..62].to_string()]),
+            Err(MerkleError::InvalidHash(leaf[..62].to_string()))
+        );
+        assert_eq!(tree.root(), None);
+
+        tree.build_from_leaf_hashes(vec![leaf]).unwrap();
+        let mut expected = MerkleTree::new();
+        expected.build(&["a".to_string()]);
+        assert_eq!(tree.root(), expected.root());
+    }
+
+    #[test]
+    fn root_from_proof_matches_tree_root() {
+        let mut tree = MerkleTree::new();
+        let elements: Vec<String> = vec!["3", "4", "5", "6", "7"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        tree.build(&elements);
+
+        let root = tree.root().unwrap();
+        for (index, element) in elements.iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            assert_eq!(root_from_proof(element, &proof), root);
+            assert_eq!((proof.leaf_index, proof.leaf_count), (index, 5));
+            assert!(proof.verify(element, &root));
+        }
+
+        let proof = tree.get_merkle_proof(0).unwrap();
+        assert_ne!(root_from_proof("wrong", &proof), root);
+        assert!(!proof.verify("wrong", &root));
+
+        // The duplicate completing the leaf level is not a leaf of its own
+        assert_eq!(
+            tree.get_merkle_proof(5),
+            Err(MerkleError::IndexOutOfRange {
+                index: 5,
+                leaf_count: 5
+            })
+        );
+    }
+
+    #[cfg(feature = "poseidon")]
+    #[test]
+    fn poseidon_tree_proofs_verify() {
+        let mut tree = MerkleTree::with_algorithm(HashAlgorithm::Poseidon);
+        let elements: Vec<String> = vec!["a", "b", "c"].into_iter().map(String::from).collect();
+        tree.build(&elements);
+
+        let root = tree.root().unwrap();
+        for (index, element) in elements.iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            assert_eq!(
+                HashAlgorithm::Poseidon.root_from_proof(element, &proof),
+                root
+            );
+        }
+
+        let mut sha_tree = MerkleTree::new();
+        sha_tree.build(&elements);
+        assert_ne!(sha_tree.root(), Some(root));
+    }
+
+    #[test]
+    fn digest_trees_match_the_hash_of_other_systems() {
+        let elements: Vec<String> = vec!["a", "b", "c"].into_iter().map(String::from).collect();
+
+        // A SHA-256 digest tree is the default tree
+        let mut sha256_tree = MerkleTree::<Sha256>::default();
+        sha256_tree.build(&elements);
+        let mut tree = MerkleTree::new();
+        tree.build(&elements);
+        assert_eq!(sha256_tree.root(), tree.root());
+
+        let mut sha512_tree = MerkleTree::<Sha512>::default();
+        sha512_tree.build(&elements);
+        let root = sha512_tree.root().unwrap();
+        assert_eq!(root.len(), 128);
+        for (index, element) in elements.iter().enumerate() {
+            let proof = sha512_tree.get_merkle_proof(index).unwrap();
+            assert_eq!(Sha512::default().root_from_proof(element, &proof), root);
+        }
+
+        // Leaf hashes of another size are rejected
+        assert!(sha512_tree
+            .build_from_leaf_hashes(vec![leaf_hash("a")])
+            .is_err());
+
+        let mut empty = MerkleTree::<Sha512>::default();
+        empty.build::<String>(&[]);
+        assert_eq!(
+            empty.root(),
+            Some(hex::encode(Sha512::digest(
+                merkleproofs_verify::EMPTY_ROOT_TAG
+            )))
+        );
+    }
+
+    #[test]
+    fn proofs_at_earlier_sizes_match_the_earlier_trees() {
+        let elements: Vec<String> = (0..21).map(|i| i.to_string()).collect();
+        let mut tree = MerkleTree::new();
+        tree.build(&elements);
+
+        for tree_size in 1..=elements.len() {
+            let mut earlier = MerkleTree::new();
+            earlier.build(&elements[..tree_size]);
+            for index in 0..tree_size {
+                assert_eq!(
+                    tree.get_merkle_proof_at(index, tree_size),
+                    earlier.get_merkle_proof(index),
+                    "leaf {} of {}",
+                    index,
+                    tree_size
+                );
+            }
+            assert_eq!(
+                tree.get_merkle_proof_at(tree_size, tree_size),
+                Err(MerkleError::IndexOutOfRange {
+                    index: tree_size,
+                    leaf_count: tree_size
+                })
+            );
+        }
+        assert_eq!(
+            tree.get_merkle_proof_at(0, 100),
+            Err(MerkleError::SizeOutOfRange {
+                tree_size: 100,
+                leaf_count: elements.len()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn build_async_matches_build() {
+        let elements: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+
+        let mut tree = MerkleTree::new();
+        tree.build(&elements);
+
+        let mut async_tree = MerkleTree::new();
+        async_tree.build_async(elements).await.unwrap();
+
+        assert_eq!(async_tree.root(), tree.root());
+        assert_eq!(async_tree.levels, tree.levels);
+    }
+
+    #[test]
+    fn updated_leaves_match_a_rebuilt_tree() {
+        for leaf_count in [1, 2, 5, 8, 13] {
+            let mut elements: Vec<String> = (0..leaf_count).map(|i| i.to_string()).collect();
+            let mut tree = MerkleTree::new();
+            tree.build(&elements);
+
+            for index in 0..leaf_count {
+                elements[index] = format!("new {}", index);
+                tree.update_leaf(index, &elements[index]).unwrap();
+
+                let mut rebuilt = MerkleTree::new();
+                rebuilt.build(&elements);
+                assert_eq!(
+                    tree.levels, rebuilt.levels,
+                    "leaf {} of {}",
+                    index, leaf_count
+                );
+                assert_eq!(tree.root(), rebuilt.root());
+            }
+        }
+    }
+
+    #[test]
+    fn updates_out_of_range_are_rejected() {
+        let mut tree = MerkleTree::new();
+        tree.build(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        let root = tree.root();
+        assert_eq!(
+            tree.update_leaf(3, "d"),
+            Err(MerkleError::IndexOutOfRange {
+                index: 3,
+                leaf_count: 3
+            })
+        );
+        assert_eq!(tree.root(), root);
+    }
+
+    #[test]
+    fn removed_leaves_match_a_rebuilt_tree() {
+        for leaf_count in [1, 2, 3, 6, 9, 16] {
+            for index in 0..leaf_count {
+                let mut elements: Vec<String> = (0..leaf_count).map(|i| i.to_string()).collect();
+                let mut tree = MerkleTree::new();
+                tree.build(&elements);
+
+                let root = tree.remove_leaf(index).unwrap();
+                elements.remove(index);
+                let mut rebuilt = MerkleTree::new();
+                rebuilt.build(&elements);
+                assert_eq!(
+                    Some(root),
+                    rebuilt.root(),
+                    "leaf {} of {}",
+                    index,
+                    leaf_count
+                );
+                assert_eq!(tree.levels, rebuilt.levels);
+                for (position, element) in elements.iter().enumerate() {
+                    let proof = tree.get_merkle_proof(position).unwrap();
+                    assert!(proof.verify(element, &rebuilt.root().unwrap()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn removing_the_last_leaf_leaves_an_empty_tree() {
+        let mut tree = MerkleTree::new();
+        tree.build(&["a".to_string()]);
+        assert_eq!(tree.remove_leaf(0), Ok(empty_root()));
+        assert_eq!(tree.get_merkle_proof(0), Err(MerkleError::EmptyTree));
+        assert_eq!(tree.remove_leaf(0), Err(MerkleError::EmptyTree));
+    }
+
+    #[test]
+    fn saved_trees_load_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.json");
+        for count in [0, 1, 2, 5, 8] {
+            let elements: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+            let mut tree = MerkleTree::new();
+            tree.build(&elements);
+            tree.save(&path).unwrap();
+
+            let loaded = MerkleTree::load(&path).unwrap();
+            assert_eq!(loaded.root(), tree.root());
+            assert_eq!(loaded.leaf_count(), count);
+            for index in 0..count {
+                assert_eq!(loaded.get_merkle_proof(index), tree.get_merkle_proof(index));
+            }
+        }
+
+        let unbuilt: MerkleTree =
+            serde_json::from_str(&serde_json::to_string(&MerkleTree::new()).unwrap()).unwrap();
+        assert_eq!(unbuilt.root(), None);
+    }
+
+    #[test]
+    fn malformed_stored_trees_are_rejected() {
+        let mut tree = MerkleTree::new();
+        tree.build(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        let mut stored: serde_json::Value = serde_json::to_value(&tree).unwrap();
+
+        stored["leaf_count"] = 5.into();
+        let error = serde_json::from_value::<MerkleTree>(stored.clone()).unwrap_err();
+        assert!(error.to_string().starts_with("Malformed tree"), "{}", error);
+
+        stored["leaf_count"] = 3.into();
+        stored["levels"][0][1] = "zz".into();
+        assert!(serde_json::from_value::<MerkleTree>(stored).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            MerkleTree::load(dir.path().join("missing.json")),
+            Err(StorageError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_builds_match_sequential_builds() {
+        for count in [0, 1, 2, 3, 7, 64, 1000, 20_001] {
+            let elements: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+            let mut sequential = MerkleTree::new();
+            sequential.build(&elements);
+            let mut parallel = MerkleTree::new();
+            parallel.build_parallel(&elements);
+
+            assert_eq!(parallel.root(), sequential.root());
+            assert!(parallel.levels().eq(sequential.levels()));
+            assert_eq!(parallel.leaf_count(), count);
+        }
+
+        let mut sha512 = MerkleTree::<Sha512>::default();
+        sha512.build(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        let mut parallel = MerkleTree::<Sha512>::default();
+        parallel.build_parallel(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(parallel.root(), sha512.root());
+    }
+
+    #[test]
+    fn the_structure_can_be_inspected() {
+        let mut tree = MerkleTree::new();
+        assert_eq!((tree.leaf_count(), tree.depth()), (0, 0));
+
+        let elements: Vec<String> = ["a", "b", "c"].iter().map(|e| e.to_string()).collect();
+        tree.build(&elements);
+        assert_eq!((tree.leaf_count(), tree.depth()), (3, 2));
+        assert_eq!(tree.get_leaf(2), Some(leaf_hash("c")));
+        assert_eq!(tree.get_leaf(3), None);
+        // The duplicate of the last leaf completes the first level
+        assert_eq!(tree.get_node(0, 3), Some(leaf_hash("c")));
+        assert_eq!(
+            tree.get_node(1, 0),
+            Some(hash_pair(&leaf_hash("a"), &leaf_hash("b")))
+        );
+        assert_eq!(tree.get_node(2, 0), tree.root());
+        assert_eq!(tree.get_node(2, 1), None);
+        assert_eq!(tree.get_node(3, 0), None);
+        assert_eq!(tree.get_merkle_proof(0).unwrap().nodes.len(), tree.depth());
+    }
+
+    #[test]
+    fn trees_from_leaves_match_built_trees() {
+        let elements: Vec<String> = ["a", "b", "c"].iter().map(|e| e.to_string()).collect();
+        let mut built = MerkleTree::new();
+        built.build(&elements);
+
+        let dir = tempfile::tempdir().unwrap();
+        let digests: Vec<Hash> = elements
+            .iter()
+            .map(|element| {
+                let path = dir.path().join(element);
+                fs::write(&path, element).unwrap();
+                crate::hashing::file_digest(&path, HashAlgorithm::Sha256).unwrap()
+            })
+            .collect();
+        let tree = MerkleTree::from_leaves(digests);
+        assert_eq!(tree.root(), built.root());
+        assert_eq!(tree.leaf_count(), 3);
+        assert_eq!(
+            MerkleTree::from_leaves(Vec::new()).root(),
+            Some(empty_root())
+        );
+    }
+
+    #[test]
+    fn diffs_name_the_changed_leaves() {
+        let elements: Vec<String> = (0..11).map(|i| i.to_string()).collect();
+        let mut before = MerkleTree::new();
+        before.build(&elements);
+
+        let mut changed = elements.clone();
+        changed[3] = "changed".to_string();
+        changed[10] = "changed".to_string();
+        let mut after = MerkleTree::new();
+        after.build(&changed);
+        assert_eq!(before.diff(&after), vec![3, 10]);
+        assert_eq!(after.diff(&before), vec![3, 10]);
+        assert!(before.diff(&before).is_empty());
+
+        // Appended and removed leaves differ too
+        changed.extend(["11".to_string(), "12".to_string()]);
+        let mut longer = MerkleTree::new();
+        longer.build(&changed);
+        assert_eq!(before.diff(&longer), vec![3, 10, 11, 12]);
+        let mut prefix = MerkleTree::new();
+        prefix.build(&elements[..5]);
+        assert_eq!(before.diff(&prefix), vec![5, 6, 7, 8, 9, 10]);
+        assert_eq!(before.diff(&MerkleTree::new()), (0..11).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn promoted_trees_match_rfc6962_trees() {
+        for count in 1..=20 {
+            let elements: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+            let mut tree = MerkleTree::new().with_padding(Padding::Promote);
+            tree.build(&elements);
+            let root = tree.root().unwrap();
+            assert_eq!(root, crate::rfc6962::Rfc6962Tree::build(&elements).root());
+
+            for (index, element) in elements.iter().enumerate() {
+                let proof = tree.get_merkle_proof(index).unwrap();
+                assert!(HashAlgorithm::Sha256.verify_padded_proof(
+                    element,
+                    &proof,
+                    &root,
+                    Padding::Promote
+                ));
+            }
+
+            // Updates and removals keep promoting the last node
+            let mut updated = MerkleTree::new().with_padding(Padding::Promote);
+            updated.build(&elements);
+            updated.update_leaf(count - 1, "x").unwrap();
+            let mut changed = elements.clone();
+            changed[count - 1] = "x".to_string();
+            assert_eq!(
+                updated.root().unwrap(),
+                crate::rfc6962::Rfc6962Tree::build(&changed).root()
+            );
+            tree.remove_leaf(0).unwrap();
+            if count > 1 {
+                assert_eq!(
+                    tree.root().unwrap(),
+                    crate::rfc6962::Rfc6962Tree::build(&elements[1..]).root()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zero_padded_trees_pair_odd_nodes_with_zeros() {
+        let zero = "00".repeat(32);
+        let elements: Vec<String> = ["a", "b", "c"].iter().map(|e| e.to_string()).collect();
+        let mut tree = MerkleTree::new().with_padding(Padding::Zero);
+        tree.build(&elements);
+        let root = tree.root().unwrap();
+        assert_eq!(
+            root,
+            hash_pair(
+                &hash_pair(&leaf_hash("a"), &leaf_hash("b")),
+                &hash_pair(&leaf_hash("c"), &zero)
+            )
+        );
+        let proof = tree.get_merkle_proof(2).unwrap();
+        assert_eq!(proof.nodes[0].sibling, zero);
+        assert!(HashAlgorithm::Sha256.verify_padded_proof("c", &proof, &root, Padding::Zero));
+
+        // Proofs that need the old tree shape are not given
+        assert_eq!(
+            tree.get_merkle_proof_at(0, 2),
+            Err(MerkleError::UnsupportedPadding(Padding::Zero))
+        );
+        assert!(tree.get_multi_proof(&[0, 1]).is_none());
+        assert!(tree.get_consistency_proof(1, 3).is_none());
+
+        let loaded: MerkleTree =
+            serde_json::from_str(&serde_json::to_string(&tree).unwrap()).unwrap();
+        assert_eq!(loaded.padding(), Padding::Zero);
+        assert_eq!(loaded.root(), tree.root());
+    }
+
+    #[test]
+    fn leaves_are_found_by_value_and_hash() {
+        let elements: Vec<String> = ["a", "b", "c", "b"].iter().map(|e| e.to_string()).collect();
+        let mut tree = MerkleTree::new();
+        tree.build(&elements);
+        assert_eq!(tree.find_leaf("c"), Some(2));
+        // The first of equal leaves
+        assert_eq!(tree.find_leaf("b"), Some(1));
+        assert_eq!(tree.find_leaf_hash(&leaf_hash("a")), Some(0));
+        assert_eq!(tree.find_leaf("d"), None);
+        assert_eq!(tree.find_leaf_hash("not hex"), None);
+
+        // The duplicate completing an odd first level is not a leaf
+        tree.build(&elements[..3]);
+        tree.update_leaf(2, "x").unwrap();
+        assert_eq!(tree.find_leaf("c"), None);
+        assert_eq!(MerkleTree::new().find_leaf("a"), None);
+    }
+
+    #[test]
+    fn proofs_of_missing_leaves_say_why() {
+        let mut tree = MerkleTree::new();
+        assert_eq!(tree.get_merkle_proof(0), Err(MerkleError::NotBuilt));
+        assert_eq!(tree.update_leaf(0, "a"), Err(MerkleError::NotBuilt));
+        assert_eq!(tree.get_merkle_proof_at(0, 0), Err(MerkleError::NotBuilt));
+
+        tree.build::<String>(&[]);
+        assert_eq!(tree.get_merkle_proof(0), Err(MerkleError::EmptyTree));
+        assert_eq!(tree.remove_leaf(0), Err(MerkleError::EmptyTree));
+
+        tree.build(&["a".to_string()]);
+        assert_eq!(
+            tree.get_merkle_proof(1),
+            Err(MerkleError::IndexOutOfRange {
+                index: 1,
+                leaf_count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn builders_apply_their_settings() {
+        let elements: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        let tree = MerkleTree::builder().build(&elements);
+        let mut built = MerkleTree::new();
+        built.build(&elements);
+        assert_eq!(tree.root(), built.root());
+
+        let promoted = MerkleTree::builder()
+            .padding(Padding::Promote)
+            .build(&elements);
+        assert_eq!(promoted.padding(), Padding::Promote);
+        assert_eq!(
+            promoted.root().unwrap(),
+            crate::rfc6962::Rfc6962Tree::build(&elements).root()
+        );
+
+        let plain = MerkleTree::builder()
+            .hasher(Sha256::new())
+            .padding(Padding::Zero)
+            .build(&elements);
+        let mut expected = MerkleTree::<Sha256>::default().with_padding(Padding::Zero);
+        expected.build(&elements);
+        assert_eq!(plain.root(), expected.root());
+        assert_ne!(plain.root(), built.root());
+
+        let empty = MerkleTree::builder()
+            .algorithm(HashAlgorithm::Sha256)
+            .empty();
+        assert_eq!(empty.root(), None);
+    }
+
+    #[test]
+    fn trees_are_built_over_any_leaves() {
+        let strings: Vec<String> = ["a", "b", "c"].iter().map(|e| e.to_string()).collect();
+        let mut expected = MerkleTree::new();
+        expected.build(&strings);
+
+        let mut tree = MerkleTree::new();
+        tree.build(&["a", "b", "c"]);
+        assert_eq!(tree.root(), expected.root());
+        tree.build(&[b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(tree.root(), expected.root());
+        let files: Vec<crate::protocol::FileData> = strings
+            .iter()
+            .map(|content| crate::protocol::FileData {
+                name: format!("{}.txt", content),
+                content: content.as_bytes().to_vec(),
+            })
+            .collect();
+        tree.build(&files);
+        assert_eq!(tree.root(), expected.root());
+
+        // Binary contents that are not valid UTF-8
+        let binary = vec![vec![0xff, 0xfe], vec![0x00]];
+        tree.build(&binary);
+        assert_eq!(tree.find_leaf(&binary[0][..]), Some(0));
+        tree.update_leaf(1, &[0x80u8][..]).unwrap();
+        assert_eq!(tree.find_leaf(&[0x80u8][..]), Some(1));
+    }
+
+    #[test]
+    fn trees_are_displayed_level_by_level() {
+        let mut tree = MerkleTree::new();
+        assert_eq!(tree.to_string(), "Unbuilt tree");
+
+        tree.build(&["a", "b", "c"]);
+        let short = |hash: String| hash[..8].to_string();
+        let expected = format!(
+            "Tree of 3 leaves\nroot: {}\n   1: {} {}\n   0: 0={} 1={} 2={} *={}",
+            short(tree.root().unwrap()),
+            short(tree.get_node(1, 0).unwrap()),
+            short(tree.get_node(1, 1).unwrap()),
+            short(leaf_hash("a")),
+            short(leaf_hash("b")),
+            short(leaf_hash("c")),
+            short(leaf_hash("c")),
+        );
+        assert_eq!(tree.to_string(), expected);
+
+        tree.build::<String>(&[]);
+        assert_eq!(
+            tree.to_string(),
+            format!("Tree of 0 leaves\nroot: {}", short(empty_root()))
+        );
+    }
+
+    #[test]
+    fn leaves_and_levels_are_iterated_in_place() {
+        let mut tree = MerkleTree::new();
+        assert_eq!(tree.leaves().len(), 0);
+        assert_eq!(tree.levels().len(), 0);
+
+        tree.build(&["a", "b", "c"]);
+        let leaves: Vec<String> = tree.leaves().map(hex::encode).collect();
+        assert_eq!(leaves, vec![leaf_hash("a"), leaf_hash("b"), leaf_hash("c")]);
+        assert!((&tree).into_iter().eq(tree.leaves()));
+
+        // Levels keep the padding of odd levels
+        let widths: Vec<usize> = tree.levels().map(<[Hash]>::len).collect();
+        assert_eq!(widths, vec![4, 2, 1]);
+        assert_eq!(
+            tree.levels().last().map(|root| hex::encode(root[0])),
+            tree.root()
+        );
+    }
 }
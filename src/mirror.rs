@@ -0,0 +1,329 @@
+//! Cross-verification of a mirrored dataset against a peer server
+//! A server started as the mirror of a peer periodically fetches the peer's root and leaf
+//! hashes and compares them with its own dataset, then downloads a random sample of files to
+//! compare byte for byte after checking their proofs. Either side may be ahead of the other;
+//! only the leaves both have are compared. Results are served at `/metrics/mirror`, and checks
+//! that find a divergence are also posted to a webhook if one is configured, so two servers
+//! mirroring each other audit each other.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::hashing::ct_eq;
+use crate::merkle_tree::{empty_root, MerkleTree};
+use crate::protocol::{FileQuery, ProofRangeQuery};
+use crate::server::{AppState, Dataset};
+use crate::transport::{self, LeafProofs, Transport, TransportError};
+
+/// Default time between two checks
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Default number of files downloaded and compared by every check
+pub const DEFAULT_SAMPLE_SIZE: usize = 10;
+
+/// Settings of the cross-verification against a peer
+#[derive(Clone, Debug)]
+pub struct MirrorConfig {
+    /// Address of the peer, as accepted by `transport::connect`
+    pub peer: String,
+    pub interval: Duration,
+    pub sample_size: usize,
+    /// URL divergent reports are posted to
+    pub webhook: Option<String>,
+}
+
+/// A way the peer's dataset differs from the mirror's
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Divergence {
+    /// The peer's leaf hashes do not build the root it advertises
+    InvalidRoot {
+        advertised: String,
+        computed: String,
+    },
+    /// The peer has another leaf at an index both datasets have
+    Leaf {
+        index: usize,
+        local: String,
+        peer: String,
+    },
+    /// The proof of a sampled file does not verify against the peer's root
+    InvalidProof { index: usize },
+    /// A sampled file differs from the mirror's copy
+    Content { index: usize },
+}
+
+/// Result of one check against the peer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorReport {
+    /// Seconds since the Unix epoch
+    pub checked_at: u64,
+    pub local_root: Option<String>,
+    /// `None` if the peer has no files
+    pub peer_root: Option<String>,
+    pub local_leaves: usize,
+    pub peer_leaves: usize,
+    /// Number of files downloaded and compared
+    pub sampled: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Counters of the checks against the peer, as served by the metrics endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct MirrorStats {
+    /// `None` if the server does not mirror a peer
+    pub peer: Option<String>,
+    pub checks: u64,
+    /// Checks that found at least one divergence
+    pub divergent_checks: u64,
+    /// Checks that could not complete, such as when the peer was unreachable
+    pub failed_checks: u64,
+    pub last_report: Option<MirrorReport>,
+    pub last_error: Option<String>,
+}
+
+impl MirrorStats {
+    fn record(&mut self, peer: &str, result: &Result<MirrorReport, TransportError>) {
+        self.peer = Some(peer.to_string());
+        self.checks += 1;
+        match result {
+            Ok(report) => {
+                if !report.divergences.is_empty() {
+                    self.divergent_checks += 1;
+                }
+                self.last_report = Some(report.clone());
+                self.last_error = None;
+            }
+            Err(e) => {
+                self.failed_checks += 1;
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Checks the peer against the mirror's dataset
+pub async fn check(
+    dataset: &Dataset,
+    peer: &dyn Transport,
+    sample_size: usize,
+) -> Result<MirrorReport, TransportError> {
+    let LeafProofs {
+        root: peer_root,
+        proofs,
+    } = peer.fetch_proofs(&ProofRangeQuery::default()).await?;
+    let mut divergences = Vec::new();
+
+    // The advertised root must follow from the advertised leaves
+    if let Some(advertised) = &peer_root {
+        let mut tree = MerkleTree::new();
+        let computed = match tree
+            .build_from_leaf_hashes(proofs.iter().map(|p| p.leaf_hash.clone()).collect())
+        {
+            Ok(()) => tree.root().unwrap_or_else(empty_root),
+            // Malformed leaf hashes lead to no root
+            Err(_) => String::new(),
+        };
+        if !ct_eq(&computed, advertised) {
+            divergences.push(Divergence::InvalidRoot {
+                advertised: advertised.clone(),
+                computed,
+            });
+        }
+    }
+
+    for (index, (local, peer)) in dataset.leaves().iter().zip(&proofs).enumerate() {
+        if !ct_eq(&local.leaf_hash, &peer.leaf_hash) {
+            divergences.push(Divergence::Leaf {
+                index,
+                local: local.leaf_hash.clone(),
+                peer: peer.leaf_hash.clone(),
+            });
+        }
+    }
+
+    let common = dataset.leaves().len().min(proofs.len());
+    let sample = sample_indices(common, sample_size);
+    if let Some(root) = &peer_root {
+        // Pinned to the checked root, in case the peer gets an upload meanwhile
+        let query = FileQuery {
+            root: Some(root.clone()),
+            version: None,
+        };
+        for &index in &sample {
+            let file = peer.fetch_file(index, &query).await?;
+            let local = dataset.file(index).expect("Sampled files exist locally");
+            if file.proof.leaf_index != index || !file.proof.verify(&file.content, root) {
+                divergences.push(Divergence::InvalidProof { index });
+            } else if file.content != local.content {
+                divergences.push(Divergence::Content { index });
+            }
+        }
+    }
+
+    Ok(MirrorReport {
+        checked_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs(),
+        local_root: dataset.root_hash(),
+        peer_root,
+        local_leaves: dataset.leaves().len(),
+        peer_leaves: proofs.len(),
+        sampled: sample.len(),
+        divergences,
+    })
+}
+
+/// Up to `sample_size` distinct random indices below `count`, in order
+fn sample_indices(count: usize, sample_size: usize) -> BTreeSet<usize> {
+    let mut sample = BTreeSet::new();
+    while sample.len() < sample_size.min(count) {
+        sample.insert((OsRng.next_u64() % count as u64) as usize);
+    }
+    sample
+}
+
+/// Checks the peer every interval in the background, recording the results in the state
+pub fn spawn(state: Arc<AppState>, config: MirrorConfig) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let peer = match transport::connect(&config.peer).await {
+            Ok(peer) => peer,
+            Err(e) => {
+                state.update_mirror_stats(|stats| stats.record(&config.peer, &Err(e)));
+                return;
+            }
+        };
+        let client = reqwest::Client::new();
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            let result = check(&state.dataset(), peer.as_ref(), config.sample_size).await;
+            state.update_mirror_stats(|stats| stats.record(&config.peer, &result));
+
+            let (Ok(report), Some(webhook)) = (&result, &config.webhook) else {
+                continue;
+            };
+            if report.divergences.is_empty() {
+                continue;
+            }
+            let alert = json!({ "peer": config.peer, "report": report });
+            if let Err(e) = client.post(webhook).json(&alert).send().await {
+                eprintln!("Failed to post mirror divergence to {}: {}", webhook, e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::protocol::FileData;
+    use crate::transport::InProcessTransport;
+
+    fn files(contents: &[&str]) -> Vec<FileData> {
+        contents
+            .iter()
+            .map(|content| FileData {
+                name: format!("{}.txt", content),
+                content: content.as_bytes().to_vec(),
+            })
+            .collect()
+    }
+
+    async fn server(contents: &[&str]) -> Arc<AppState> {
+        let state = Arc::new(AppState::with_storage_dir(
+            tempfile::tempdir().unwrap().path(),
+        ));
+        if !contents.is_empty() {
+            state.insert_files(files(contents)).await.unwrap();
+        }
+        state
+    }
+
+    #[tokio::test]
+    async fn identical_datasets_agree() {
+        let mirror = server(&["a", "b", "c"]).await;
+        let peer = InProcessTransport::new(server(&["a", "b", "c"]).await);
+
+        let report = check(&mirror.dataset(), &peer, 2).await.unwrap();
+        assert_eq!(report.divergences, Vec::new());
+        assert_eq!(report.peer_root, mirror.root_hash().await);
+        assert_eq!(report.sampled, 2);
+    }
+
+    #[tokio::test]
+    async fn either_side_may_be_ahead() {
+        let behind = server(&["a"]).await;
+        let ahead = server(&["a", "b"]).await;
+
+        let report = check(
+            &behind.dataset(),
+            &InProcessTransport::new(ahead.clone()),
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.divergences, Vec::new());
+        assert_eq!((report.local_leaves, report.peer_leaves), (1, 2));
+
+        let report = check(&ahead.dataset(), &InProcessTransport::new(behind), 10)
+            .await
+            .unwrap();
+        assert_eq!(report.divergences, Vec::new());
+        assert_eq!(report.sampled, 1);
+
+        let empty = InProcessTransport::new(server(&[]).await);
+        let report = check(&ahead.dataset(), &empty, 10).await.unwrap();
+        assert_eq!((report.peer_root, report.sampled), (None, 0));
+    }
+
+    #[tokio::test]
+    async fn different_leaves_diverge() {
+        let mirror = server(&["a", "b", "c"]).await;
+        let peer = InProcessTransport::new(server(&["a", "x", "c"]).await);
+
+        let report = check(&mirror.dataset(), &peer, 0).await.unwrap();
+        assert_eq!(
+            report.divergences,
+            vec![Divergence::Leaf {
+                index: 1,
+                local: mirror.dataset().leaves()[1].leaf_hash.clone(),
+                peer: peer.state().dataset().leaves()[1].leaf_hash.clone(),
+            }]
+        );
+    }
+
+    #[test]
+    fn stats_count_divergent_and_failed_checks() {
+        let report = MirrorReport {
+            checked_at: 0,
+            local_root: None,
+            peer_root: None,
+            local_leaves: 0,
+            peer_leaves: 0,
+            sampled: 0,
+            divergences: vec![Divergence::Content { index: 0 }],
+        };
+        let mut stats = MirrorStats::default();
+        stats.record("peer", &Ok(report.clone()));
+        stats.record(
+            "peer",
+            &Err(TransportError::Unavailable("down".to_string())),
+        );
+
+        assert_eq!(stats.peer.as_deref(), Some("peer"));
+        assert_eq!(
+            (stats.checks, stats.divergent_checks, stats.failed_checks),
+            (2, 1, 1)
+        );
+        assert_eq!(stats.last_report, Some(report));
+        assert!(stats.last_error.is_some());
+    }
+}
@@ -0,0 +1,142 @@
+//! Multihash and CIDv1 encodings of hashes, for IPFS and other content-addressed tools
+//! A multihash is the varint code of the hash function, the varint length of the digest and the
+//! digest, shown in base58btc as IPFS does (`Qm...` for SHA-256). A CIDv1 adds the version and a
+//! content codec in front and is shown in lowercase base32 with the multibase prefix `b`.
+//!
+//! Leaf and node hashes carry domain prefixes, so the leaf hash of a file is not the hash IPFS
+//! gives the same file. The encodings label a root or leaf hash with the function that made it.
+
+use crate::hashing::HashAlgorithm;
+
+/// Multicodec of raw binary content
+pub const RAW_CODEC: u64 = 0x55;
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Multicodec of the hash function, or `None` for Poseidon, which has none for BN254
+pub fn multihash_code(algorithm: HashAlgorithm) -> Option<u64> {
+    match algorithm {
+        HashAlgorithm::Sha256 => Some(0x12),
+        #[cfg(feature = "poseidon")]
+        HashAlgorithm::Poseidon => None,
+        #[cfg(feature = "eth")]
+        HashAlgorithm::Keccak256 | HashAlgorithm::SortedKeccak256 => Some(0x1b),
+        #[cfg(feature = "blake3")]
+        HashAlgorithm::Blake3 => Some(0x1e),
+    }
+}
+
+/// Binary multihash of a digest made with the algorithm
+pub fn multihash(algorithm: HashAlgorithm, digest: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(digest.len() + 4);
+    push_varint(&mut bytes, multihash_code(algorithm)?);
+    push_varint(&mut bytes, digest.len() as u64);
+    bytes.extend_from_slice(digest);
+    Some(bytes)
+}
+
+/// Base58btc multihash of a hex-encoded hash, such as a root
+/// Returns `None` for invalid hex or an algorithm without a multicodec
+pub fn multihash_string(algorithm: HashAlgorithm, hash: &str) -> Option<String> {
+    let digest = hex::decode(hash).ok()?;
+    Some(base58(&multihash(algorithm, &digest)?))
+}
+
+/// CIDv1 of a hex-encoded hash with the content codec, such as `RAW_CODEC`
+/// Returns `None` for invalid hex or an algorithm without a multicodec
+pub fn cid_v1(algorithm: HashAlgorithm, codec: u64, hash: &str) -> Option<String> {
+    let digest = hex::decode(hash).ok()?;
+    let mut bytes = Vec::new();
+    push_varint(&mut bytes, 1);
+    push_varint(&mut bytes, codec);
+    bytes.extend(multihash(algorithm, &digest)?);
+    Some(format!("b{}", base32(&bytes)))
+}
+
+/// Appends the unsigned LEB128 encoding of the value
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Base58 in the Bitcoin alphabet, with a `1` for every leading zero byte
+fn base58(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    // Base-58 digits of the number, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &bytes[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    std::iter::repeat_n(b'1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|&digit| BASE58_ALPHABET[digit as usize]),
+        )
+        .map(char::from)
+        .collect()
+}
+
+/// RFC 4648 base32 in lowercase, without padding
+fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = buffer << 8 | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn sha256_hashes_encode_as_ipfs_does() {
+        let hash = hex::encode(Sha256::digest(b"hello world"));
+        assert_eq!(
+            multihash_string(HashAlgorithm::Sha256, &hash).unwrap(),
+            "QmaozNR7DZHQK1ZcU9p7QdrshMvXqWK6gpu5rmrkPdT3L4"
+        );
+        assert_eq!(
+            cid_v1(HashAlgorithm::Sha256, RAW_CODEC, &hash).unwrap(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+        assert_eq!(multihash_string(HashAlgorithm::Sha256, "not hex"), None);
+    }
+
+    #[test]
+    fn encodings_of_edge_cases() {
+        let mut bytes = Vec::new();
+        push_varint(&mut bytes, 300);
+        assert_eq!(bytes, [0xac, 0x02]);
+        assert_eq!(base58(&[0, 0, 1]), "112");
+        assert_eq!(base58(&[]), "");
+        assert_eq!(base32(b"foobar"), "mzxw6ytboi");
+    }
+}
@@ -0,0 +1,290 @@
+//! Compressed proofs for several leaves of one tree
+//! Independent proofs of many leaves repeat the siblings they share and carry hashes the
+//! verifier can compute from the other proven leaves. A multiproof instead walks the tree depth
+//! first from the root: a bit per visited node says whether a proven leaf is below it, and only
+//! the hashes of the subtrees without one are included, each once. The leaves themselves are
+//! supplied by the verifier, who learns from the walk which indices they are at.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{root_height, width, MerkleTree, Padding};
+
+/// Proof of several leaves against one root
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MultiProof {
+    /// Width of the first level of the tree, including the duplicate completing an odd one
+    pub leaf_count: usize,
+    /// Hashes of the visited subtrees without a proven leaf, in depth-first order
+    pub nodes: Vec<String>,
+    /// One bit per visited node in depth-first order, least significant bit first; set if a
+    /// proven leaf is below the node
+    pub bitmap: Vec<u8>,
+}
+
+/// Hashes of the nodes a multiproof gives or implies, by height and position
+pub(crate) type Nodes = HashMap<(u32, usize), String>;
+
+impl<H: TreeHasher> MerkleTree<H> {
+    /// Get a multiproof of the leaves at the indices, which may be in any order
+    /// Returns `None` if there are no indices or one of them is out of range, which includes the
+    /// duplicate completing an odd first level, or if the tree is padded other than by duplicates
+    pub fn get_multi_proof(&self, indices: &[usize]) -> Option<MultiProof> {
+        let levels: Vec<&[H::Output]> = self.levels().collect();
+        let leaf_count = levels.first()?.len();
+        if indices.is_empty()
+            || indices.iter().any(|&index| index >= self.leaf_count())
+            || self.padding() != Padding::Duplicate
+        {
+            return None;
+        }
+
+        let mut collect = Collect {
+            levels: &levels,
+            proven: indices.iter().copied().collect(),
+            proof: MultiProof {
+                leaf_count,
+                nodes: Vec::new(),
+                bitmap: Vec::new(),
+            },
+            bits: 0,
+        };
+        collect.visit(root_height(leaf_count), 0);
+        Some(collect.proof)
+    }
+}
+
+/// State of the prover's walk through the tree
+struct Collect<'a, T> {
+    levels: &'a [&'a [T]],
+    proven: BTreeSet<usize>,
+    proof: MultiProof,
+    bits: usize,
+}
+
+impl<T: AsRef<[u8]> + Copy> Collect<'_, T> {
+    fn push_bit(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.proof.bitmap.push(0);
+        }
+        self.proof.bitmap[self.bits / 8] |= (bit as u8) << (self.bits % 8);
+        self.bits += 1;
+    }
+
+    /// Visits the subtree at the node, descending only where there are proven leaves
+    fn visit(&mut self, height: u32, position: usize) {
+        let first = position << height;
+        let below = self
+            .proven
+            .range(first..first + (1 << height))
+            .next()
+            .is_some();
+        self.push_bit(below);
+        if !below {
+            let node = hex::encode(self.levels[height as usize][position]);
+            self.proof.nodes.push(node);
+            return;
+        }
+        if height == 0 {
+            return;
+        }
+        self.visit(height - 1, position * 2);
+        if position * 2 + 1 < width(self.proof.leaf_count, height - 1) {
+            self.visit(height - 1, position * 2 + 1);
+        }
+    }
+}
+
+/// State of the verifier's walk through a multiproof
+struct Walk<'a> {
+    proof: &'a MultiProof,
+    algorithm: HashAlgorithm,
+    leaf_hashes: &'a [String],
+    bits: usize,
+    nodes: usize,
+    indices: Vec<usize>,
+    known: Nodes,
+}
+
+impl Walk<'_> {
+    fn next_bit(&mut self) -> Result<bool, MerkleError> {
+        let byte = self
+            .proof
+            .bitmap
+            .get(self.bits / 8)
+            .ok_or(MerkleError::MalformedProof("bitmap is too short"))?;
+        let bit = byte >> (self.bits % 8) & 1 == 1;
+        self.bits += 1;
+        Ok(bit)
+    }
+
+    /// The hash of the node, computed from the leaves and nodes of the proof
+    fn hash(&mut self, height: u32, position: usize) -> Result<String, MerkleError> {
+        let hash = self.compute(height, position)?;
+        self.known.insert((height, position), hash.clone());
+        Ok(hash)
+    }
+
+    fn compute(&mut self, height: u32, position: usize) -> Result<String, MerkleError> {
+        if !self.next_bit()? {
+            let node = self
+                .proof
+                .nodes
+                .get(self.nodes)
+                .ok_or(MerkleError::MalformedProof("too few nodes"))?;
+            self.nodes += 1;
+            return Ok(node.clone());
+        }
+        if height == 0 {
+            let leaf = self
+                .leaf_hashes
+                .get(self.indices.len())
+                .ok_or(MerkleError::MalformedProof("more leaves proven than given"))?;
+            self.indices.push(position);
+            return Ok(leaf.clone());
+        }
+
+        let left = self.hash(height - 1, position * 2)?;
+        let right = if position * 2 + 1 < width(self.proof.leaf_count, height - 1) {
+            self.hash(height - 1, position * 2 + 1)?
+        } else {
+            left.clone()
+        };
+        Ok(self.algorithm.hash_nodes(&left, &right))
+    }
+}
+
+impl MultiProof {
+    /// Calculates the root implied by the proof and the hashes of the proven leaves, given in
+    /// index order, along with the indices the leaves were proven at
+    pub fn root(
+        &self,
+        algorithm: HashAlgorithm,
+        leaf_hashes: &[String],
+    ) -> Result<(String, Vec<usize>), MerkleError> {
+        let (root, indices, _) = self.walk(algorithm, leaf_hashes)?;
+        Ok((root, indices))
+    }
+
+    /// Like `root`, also returning every node on the way
+    pub(crate) fn walk(
+        &self,
+        algorithm: HashAlgorithm,
+        leaf_hashes: &[String],
+    ) -> Result<(String, Vec<usize>, Nodes), MerkleError> {
+        if self.leaf_count == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        let mut walk = Walk {
+            proof: self,
+            algorithm,
+            leaf_hashes,
+            bits: 0,
+            nodes: 0,
+            indices: Vec::new(),
+            known: Nodes::new(),
+        };
+        let root = walk.hash(root_height(self.leaf_count), 0)?;
+
+        if walk.indices.len() != leaf_hashes.len() {
+            return Err(MerkleError::MalformedProof(
+                "fewer leaves proven than given",
+            ));
+        }
+        if walk.nodes != self.nodes.len() || walk.bits.div_ceil(8) != self.bitmap.len() {
+            return Err(MerkleError::MalformedProof("unused nodes or bits"));
+        }
+        Ok((root, walk.indices, walk.known))
+    }
+
+    /// Checks that the leaves, as pairs of index and leaf hash, are in the tree of the root
+    /// The proof must prove exactly these leaves
+    pub fn verify(&self, leaves: &[(usize, String)], root: &str) -> bool {
+        let mut leaves = leaves.to_vec();
+        leaves.sort();
+        let leaf_hashes: Vec<String> = leaves.iter().map(|(_, hash)| hash.clone()).collect();
+        let expected: Vec<usize> = leaves.iter().map(|(index, _)| *index).collect();
+        match self.root(HashAlgorithm::Sha256, &leaf_hashes) {
+            Ok((computed, indices)) => indices == expected && ct_eq(&computed, root),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{calculate_hash, leaf_hash};
+
+    fn tree(leaf_count: usize) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        tree.build(&(0..leaf_count).map(|i| i.to_string()).collect::<Vec<_>>());
+        tree
+    }
+
+    fn leaves(indices: &[usize]) -> Vec<(usize, String)> {
+        indices
+            .iter()
+            .map(|&index| (index, leaf_hash(index.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn every_selection_verifies() {
+        for leaf_count in 1..12 {
+            let tree = tree(leaf_count);
+            let root = tree.root().unwrap();
+            for selection in 1..(1u32 << leaf_count) {
+                let indices: Vec<usize> = (0..leaf_count)
+                    .filter(|i| selection & (1 << i) != 0)
+                    .collect();
+                let proof = tree.get_multi_proof(&indices).unwrap();
+                assert!(
+                    proof.verify(&leaves(&indices), &root),
+                    "{:?} of {}",
+                    indices,
+                    leaf_count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shared_siblings_are_sent_once() {
+        let tree = tree(64);
+        let indices: Vec<usize> = (0..16).collect();
+        let proof = tree.get_multi_proof(&indices).unwrap();
+
+        // The 16 leaves form a subtree, so only the 2 siblings above it are needed, where 16
+        // separate proofs carry 6 siblings each
+        assert_eq!(proof.nodes.len(), 2);
+        assert!(proof.verify(&leaves(&indices), &tree.root().unwrap()));
+    }
+
+    #[test]
+    fn other_leaves_are_rejected() {
+        let tree = tree(10);
+        let root = tree.root().unwrap();
+        let proof = tree.get_multi_proof(&[2, 7]).unwrap();
+
+        assert!(!proof.verify(&leaves(&[2, 8]), &root));
+        assert!(!proof.verify(&leaves(&[2]), &root));
+        let mut tampered = leaves(&[2, 7]);
+        tampered[1].1 = calculate_hash("x");
+        assert!(!proof.verify(&tampered, &root));
+
+        assert!(tree.get_multi_proof(&[]).is_none());
+        assert!(tree.get_multi_proof(&[10]).is_none());
+    }
+
+    #[test]
+    fn the_duplicate_of_an_odd_level_is_not_a_leaf() {
+        let tree = tree(5);
+        assert!(tree.get_multi_proof(&[0, 5]).is_none());
+        let proof = tree.get_multi_proof(&[0, 4]).unwrap();
+        assert!(proof.verify(&leaves(&[0, 4]), &tree.root().unwrap()));
+    }
+}
@@ -0,0 +1,268 @@
+//! Merkle trees with a branching factor other than two
+//! Every node hashes `arity` children, so a tree of `n` leaves is only `log_arity(n)` levels high
+//! and its proofs have fewer levels, each with the `arity - 1` siblings of the node on the path.
+//! For a million leaves a 16-ary proof has 5 levels instead of 20.
+//!
+//! Levels are completed like those of `MerkleTree`: the leaf level and every incomplete group
+//! above it are filled up with copies of their last node. Leaves are hashed like those of a
+//! `MerkleTree` and a node is `SHA-256(NODE_PREFIX || children)`, so a tree of arity 2 has the
+//! same root as a `MerkleTree` over the same leaves.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, decode_hash, Hash, MerkleLeaf, NODE_PREFIX};
+use crate::merkle_tree::empty_root;
+use merkleproofs_verify::leaf_digest;
+
+#[derive(Debug, Clone)]
+pub struct NaryMerkleTree {
+    arity: usize,
+    /// Nodes of every level from the leaves up, each completed to a multiple of the arity
+    /// except the root
+    levels: Vec<Vec<Hash>>,
+    /// Number of leaves the tree was built from, before the leaf level is completed
+    leaf_count: usize,
+}
+
+/// Proof of a leaf of an `NaryMerkleTree`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NaryProof {
+    pub arity: usize,
+    pub leaf_index: usize,
+    pub leaf_count: usize,
+    /// For every level from the leaves up, the hashes of the other nodes of the group on the
+    /// path, in order
+    pub levels: Vec<Vec<String>>,
+}
+
+/// Hashes the children of a node, in order
+fn node_digest(children: &[Hash]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    for child in children {
+        hasher.update(child);
+    }
+    hasher.finalize().into()
+}
+
+/// Fills the level up to a multiple of the arity with copies of its last node
+fn complete(level: &mut Vec<Hash>, arity: usize) {
+    if let Some(&last) = level.last() {
+        level.resize(level.len().next_multiple_of(arity), last);
+    }
+}
+
+/// Number of levels below the root of a tree of the arity over at least one leaf
+fn proof_length(leaf_count: usize, arity: usize) -> usize {
+    let mut width = leaf_count.div_ceil(arity);
+    let mut length = 1;
+    while width > 1 {
+        width = width.div_ceil(arity);
+        length += 1;
+    }
+    length
+}
+
+impl NaryMerkleTree {
+    /// Creates an empty tree in which every node has `arity` children
+    pub fn new(arity: usize) -> Result<Self, MerkleError> {
+        if arity < 2 {
+            return Err(MerkleError::InvalidArity(arity));
+        }
+        Ok(Self {
+            arity,
+            levels: Vec::new(),
+            leaf_count: 0,
+        })
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Build the tree from a list of elements
+    pub fn build<T: MerkleLeaf>(&mut self, elements: &[T]) {
+        self.build_from_digests(
+            elements
+                .iter()
+                .map(|e| leaf_digest(&e.leaf_bytes()))
+                .collect(),
+        );
+    }
+
+    /// Build the tree from already hashed, hex-encoded leaves
+    /// Fails without changing the tree if a leaf hash is not valid hex of a digest
+    pub fn build_from_leaf_hashes(&mut self, hashes: Vec<String>) -> Result<(), MerkleError> {
+        let digests = hashes
+            .into_iter()
+            .map(|hash| decode_hash(&hash).ok_or(MerkleError::InvalidHash(hash)))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_from_digests(digests);
+        Ok(())
+    }
+
+    fn build_from_digests(&mut self, mut hashes: Vec<Hash>) {
+        self.leaf_count = hashes.len();
+        complete(&mut hashes, self.arity);
+        let mut levels = vec![hashes];
+        while levels[levels.len() - 1].len() > 1 {
+            let mut next: Vec<Hash> = levels[levels.len() - 1]
+                .chunks(self.arity)
+                .map(node_digest)
+                .collect();
+            if next.len() > 1 {
+                complete(&mut next, self.arity);
+            }
+            levels.push(next);
+        }
+        self.levels = levels;
+    }
+
+    /// The root hash, which is `empty_root` for a tree built without leaves, or `None` before the
+    /// tree is built
+    pub fn root(&self) -> Option<String> {
+        let root = self.levels.last()?;
+        Some(root.first().map(hex::encode).unwrap_or_else(empty_root))
+    }
+
+    /// Get the proof of the leaf at the index
+    pub fn get_merkle_proof(&self, index: usize) -> Option<NaryProof> {
+        if index >= self.leaf_count {
+            return None;
+        }
+        let mut levels = Vec::with_capacity(self.levels.len() - 1);
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let first = position - position % self.arity;
+            let siblings = (first..first + self.arity)
+                .filter(|&i| i != position)
+                .map(|i| hex::encode(level[i]))
+                .collect();
+            levels.push(siblings);
+            position /= self.arity;
+        }
+        Some(NaryProof {
+            arity: self.arity,
+            leaf_index: index,
+            leaf_count: self.leaf_count,
+            levels,
+        })
+    }
+}
+
+impl NaryProof {
+    /// Checks that the proof has one level per level of the tree below the root, each with the
+    /// other `arity - 1` nodes of a group
+    pub fn is_well_formed(&self) -> bool {
+        self.arity >= 2
+            && self.leaf_index < self.leaf_count
+            && self.levels.len() == proof_length(self.leaf_count, self.arity)
+            && self
+                .levels
+                .iter()
+                .all(|siblings| siblings.len() == self.arity - 1)
+    }
+
+    /// Calculates the root hash implied by a leaf hash, or `None` if the proof is malformed
+    pub fn root_from_leaf_hash(&self, leaf_hash: &str) -> Option<String> {
+        if !self.is_well_formed() {
+            return None;
+        }
+        let mut current = decode_hash(leaf_hash)?;
+        let mut position = self.leaf_index;
+        for siblings in &self.levels {
+            let mut children = siblings
+                .iter()
+                .map(|sibling| decode_hash(sibling))
+                .collect::<Option<Vec<Hash>>>()?;
+            children.insert(position % self.arity, current);
+            current = node_digest(&children);
+            position /= self.arity;
+        }
+        Some(hex::encode(current))
+    }
+
+    /// Checks that the leaf contents lead to the root
+    pub fn verify(&self, content: &str, root: &str) -> bool {
+        let leaf_hash = hex::encode(leaf_digest(content.as_bytes()));
+        self.root_from_leaf_hash(&leaf_hash)
+            .is_some_and(|computed| ct_eq(&computed, root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::MerkleTree;
+
+    fn elements(count: usize) -> Vec<String> {
+        (0..count).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn every_leaf_is_proven() {
+        for arity in [2, 3, 4, 16] {
+            for count in 1..=40 {
+                let elements = elements(count);
+                let mut tree = NaryMerkleTree::new(arity).unwrap();
+                tree.build(&elements);
+                let root = tree.root().unwrap();
+                for (index, element) in elements.iter().enumerate() {
+                    let proof = tree.get_merkle_proof(index).unwrap();
+                    assert!(proof.verify(element, &root), "{} of {}", index, count);
+                    assert!(!proof.verify("other", &root));
+                }
+                assert!(tree.get_merkle_proof(count).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn binary_trees_match_merkle_trees() {
+        for count in 0..=9 {
+            let elements = elements(count);
+            let mut tree = NaryMerkleTree::new(2).unwrap();
+            tree.build(&elements);
+            let mut binary = MerkleTree::new();
+            binary.build(&elements);
+            assert_eq!(tree.root(), binary.root());
+        }
+    }
+
+    #[test]
+    fn wide_trees_have_short_proofs() {
+        let mut tree = NaryMerkleTree::new(16).unwrap();
+        tree.build(&elements(1000));
+        let proof = tree.get_merkle_proof(999).unwrap();
+        assert_eq!(proof.levels.len(), 3);
+        assert!(proof.levels.iter().all(|siblings| siblings.len() == 15));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        assert_eq!(
+            NaryMerkleTree::new(1).unwrap_err(),
+            MerkleError::InvalidArity(1)
+        );
+
+        let mut tree = NaryMerkleTree::new(4).unwrap();
+        tree.build(&elements(10));
+        let root = tree.root().unwrap();
+        let proof = tree.get_merkle_proof(5).unwrap();
+
+        let mut short = proof.clone();
+        short.levels[0].pop();
+        assert!(!short.verify("5", &root));
+
+        let mut moved = proof.clone();
+        moved.leaf_index = 6;
+        assert!(!moved.verify("5", &root));
+
+        let mut resized = proof;
+        resized.leaf_count = 100;
+        assert!(!resized.verify("5", &root));
+    }
+}
@@ -0,0 +1,69 @@
+//! Poseidon hashing over the BN254 scalar field (circom parameters)
+//! Poseidon is cheap to evaluate inside SNARK circuits, so trees built with it can be verified
+//! in circuits that already use Poseidon Merkle trees. Hashes are 32-byte big-endian field
+//! elements, hex-encoded like all other hashes in this crate.
+//!
+//! Leaf contents are split into 31-byte big-endian chunks, which always fit into a field element.
+//! The leaf hash starts from the content length and absorbs one chunk at a time:
+//! `h = P(len)`, then `h = P(h, chunk)` for every chunk.
+
+use ark_bn254::Fr;
+use light_poseidon::{Poseidon, PoseidonBytesHasher};
+
+const CHUNK_SIZE: usize = 31;
+
+/// Hashes leaf contents
+pub fn leaf_digest(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Poseidon::<Fr>::new_circom(1).expect("Invalid Poseidon parameters");
+    let mut current = hasher
+        .hash_bytes_be(&[&(bytes.len() as u64).to_be_bytes()])
+        .expect("Failed to hash leaf length");
+
+    let mut hasher = Poseidon::<Fr>::new_circom(2).expect("Invalid Poseidon parameters");
+    for chunk in bytes.chunks(CHUNK_SIZE) {
+        current = hasher
+            .hash_bytes_be(&[&current, chunk])
+            .expect("Failed to hash leaf chunk");
+    }
+    current
+}
+
+/// Hashes two child hashes into their parent
+/// Panics if a child is not a valid field element, which cannot happen for hashes produced here
+pub fn node_digest(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Poseidon::<Fr>::new_circom(2).expect("Invalid Poseidon parameters");
+    hasher
+        .hash_bytes_be(&[left, right])
+        .expect("Failed to hash nodes")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn known_vector_for_one_and_two() {
+        // Poseidon([1, 2]) over BN254 with circom parameters
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let mut two = [0u8; 32];
+        two[31] = 2;
+        assert_eq!(
+            hex::encode(node_digest(&one, &two)),
+            "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+        );
+    }
+
+    #[test]
+    fn leaf_hashes_depend_on_all_chunks() {
+        let long = "a".repeat(100);
+        let mut changed = long.clone();
+        changed.replace_range(90..91, "b");
+        assert_ne!(
+            leaf_digest(long.as_bytes()),
+            leaf_digest(changed.as_bytes())
+        );
+        assert_ne!(leaf_digest(b""), leaf_digest(b"\0"));
+    }
+}
@@ -0,0 +1,202 @@
+//! Cache of generated file proofs
+//! Proofs are keyed by the root they are valid for and the leaf index, so a cached proof never
+//! goes stale: the same root always has the same proofs. Entries for roots the server no longer
+//! serves are dropped when the dataset changes, and the oldest entries are evicted once the
+//! cache is full.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::merkle_tree::MerkleProof;
+
+/// Default number of proofs kept by the server
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+type Proof = MerkleProof;
+
+pub struct ProofCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    proofs: HashMap<(String, usize), Proof>,
+    /// Keys in insertion order, oldest first
+    order: VecDeque<(String, usize)>,
+}
+
+/// Counters of a proof cache, as served by the metrics endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ProofCacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries removed to make room for new ones
+    pub evictions: u64,
+    /// Entries removed because their root is no longer served
+    pub invalidations: u64,
+}
+
+impl Default for ProofCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ProofCache {
+    /// A cache holding at most `capacity` proofs; a capacity of 0 disables caching
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached proof of the leaf at the index against the root, counting the hit or miss
+    pub fn get(&self, root: &str, index: usize) -> Option<Proof> {
+        let proof = self
+            .entries
+            .lock()
+            .expect("Proof cache lock poisoned")
+            .proofs
+            .get(&(root.to_string(), index))
+            .cloned();
+        let counter = if proof.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        proof
+    }
+
+    /// Caches a proof, evicting the oldest entries if the cache is full
+    pub fn insert(&self, root: &str, index: usize, proof: Proof) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("Proof cache lock poisoned");
+        let key = (root.to_string(), index);
+        if entries.proofs.insert(key.clone(), proof).is_some() {
+            return;
+        }
+        entries.order.push_back(key);
+        while entries.order.len() > self.capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.proofs.remove(&oldest);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Drops the proofs of every root not in `roots`
+    pub fn retain_roots<'a, I>(&self, roots: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let roots: HashSet<&str> = roots.into_iter().collect();
+        let mut entries = self.entries.lock().expect("Proof cache lock poisoned");
+        let before = entries.proofs.len();
+        entries
+            .proofs
+            .retain(|(root, _), _| roots.contains(root.as_str()));
+        let Entries { proofs, order } = &mut *entries;
+        order.retain(|key| proofs.contains_key(key));
+        self.invalidations
+            .fetch_add((before - proofs.len()) as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ProofCacheStats {
+        ProofCacheStats {
+            capacity: self.capacity,
+            entries: self
+                .entries
+                .lock()
+                .expect("Proof cache lock poisoned")
+                .proofs
+                .len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{Position, ProofNode};
+
+    fn proof(sibling: &str) -> Proof {
+        MerkleProof {
+            leaf_index: 0,
+            leaf_count: 2,
+            nodes: vec![ProofNode {
+                sibling: sibling.to_string(),
+                position: Position::Right,
+            }],
+        }
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted() {
+        let cache = ProofCache::new(10);
+        assert_eq!(cache.get("root", 0), None);
+        cache.insert("root", 0, proof("a"));
+        assert_eq!(cache.get("root", 0), Some(proof("a")));
+        assert_eq!(cache.get("other", 0), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 1));
+    }
+
+    #[test]
+    fn oldest_entries_are_evicted() {
+        let cache = ProofCache::new(2);
+        cache.insert("root", 0, proof("a"));
+        cache.insert("root", 1, proof("b"));
+        cache.insert("root", 2, proof("c"));
+
+        assert_eq!(cache.get("root", 0), None);
+        assert_eq!(cache.get("root", 2), Some(proof("c")));
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[test]
+    fn proofs_of_dropped_roots_are_invalidated() {
+        let cache = ProofCache::new(10);
+        cache.insert("old", 0, proof("a"));
+        cache.insert("new", 0, proof("b"));
+        cache.retain_roots(["new"]);
+
+        assert_eq!(cache.get("old", 0), None);
+        assert_eq!(cache.get("new", 0), Some(proof("b")));
+        assert_eq!(cache.stats().invalidations, 1);
+
+        // The order of the dropped entry is forgotten too, so it does not evict a live one
+        cache.insert("new", 1, proof("c"));
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        let cache = ProofCache::new(0);
+        cache.insert("root", 0, proof("a"));
+        assert_eq!(cache.get("root", 0), None);
+    }
+}
@@ -0,0 +1,572 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::content_store::DedupStats;
+use crate::error::ProtocolError;
+use crate::hashing::{ct_eq, MerkleLeaf};
+use crate::merkle_tree::{calculate_hash, leaf_hash, MerkleProof};
+use crate::multiproof::MultiProof;
+use crate::sorted_tree::NonMembershipProof;
+
+/// Header carrying a client-generated id of an upload or delete
+/// Retries of the operation reuse the id, so the server can return the original result
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// Response header of the proof endpoints carrying the root the proofs are valid for
+pub const ROOT_HEADER: &str = "Merkle-Root";
+
+/// Media type of the compact binary proof encoding, see `MerkleProof::to_bytes`
+/// The proof endpoint sends it instead of JSON if the `Accept` header asks for it.
+pub const PROOF_MEDIA_TYPE: &str = "application/vnd.merkleproofs.proof";
+
+/// Most leaves a single request to the sample endpoint may ask for
+pub const MAX_SAMPLE_SIZE: usize = 256;
+
+/// File where the server persists the root history, next to the stored files
+pub const ROOT_HISTORY_FILE: &str = "root_history.json";
+/// File where the server persists the order of the leaves, next to the stored files
+pub const LEAF_ORDER_FILE: &str = "leaf_order.json";
+/// File where the server persists the Merkle tree over the latest files, next to the stored files
+pub const MERKLE_TREE_FILE: &str = "merkle_tree.json";
+/// Prefix of the names under which the server keeps encrypted client states, next to the stored
+/// files
+pub const STATE_SYNC_PREFIX: &str = "state_sync.";
+
+/// Whether the server keeps its own metadata under the name, so no file may be uploaded with it
+/// The server's storage is a flat namespace shared by the uploaded files and the metadata.
+pub fn is_reserved_name(name: &str) -> bool {
+    [ROOT_HISTORY_FILE, LEAF_ORDER_FILE, MERKLE_TREE_FILE].contains(&name)
+        || name.starts_with(STATE_SYNC_PREFIX)
+}
+
+/// A single file as sent over the wire
+/// Contents are raw bytes, so any file can be uploaded and proven, and are base64-encoded in
+/// JSON.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileData {
+    pub name: String,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+}
+
+/// A file is the leaf of its contents; its name is kept in the leaf entry, not the tree
+impl MerkleLeaf for FileData {
+    fn leaf_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.content)
+    }
+}
+
+/// Serializes bytes as a standard base64 string, with padding
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Position of one file in the dataset
+/// The index of an entry in the ordered list of leaves is the index of the file in the tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeafEntry {
+    pub name: String,
+    /// Length of the uploaded contents in bytes
+    pub size: usize,
+    pub leaf_hash: String,
+}
+
+impl LeafEntry {
+    pub fn new(file: &FileData) -> Self {
+        Self {
+            name: file.name.clone(),
+            size: file.content.len(),
+            leaf_hash: leaf_hash(&file.content),
+        }
+    }
+}
+
+/// Request body of the upload endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadRequest {
+    pub root_hash: String,
+    /// The latest version the client knows about; `None` for the first upload
+    pub base_version: Option<usize>,
+    /// The ordered leaves of the whole dataset after the upload
+    /// The entries of the uploaded files carry the leaf hashes the client computed, which the
+    /// server checks the received contents against
+    pub leaves: Vec<LeafEntry>,
+    pub files: Vec<FileData>,
+    /// Upload id of contents sent ahead as `UploadPart`s
+    /// Files with staged contents are sent with empty contents and filled in from the parts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staged: Option<String>,
+}
+
+/// Request body of the upload part endpoint: a piece of the contents of a file too large for a
+/// single upload request, staged under an upload id until the upload that names the id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadPart {
+    pub name: String,
+    /// Byte offset of the piece in the file's contents
+    pub offset: usize,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+}
+
+/// Response body of the upload part endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UploadPartResponse {
+    /// Bytes of the file's contents staged so far
+    pub received: usize,
+}
+
+/// Request body of the import endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportRequest {
+    /// Directory to import, relative to the server's import root
+    pub path: String,
+}
+
+/// Response body of the upload endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UploadResponse {
+    pub message: String,
+    pub root_hash: String,
+    pub version: usize,
+}
+
+/// Response body of the stats endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    pub file_count: usize,
+    /// Total size of all files in bytes
+    pub total_size: usize,
+    /// Number of levels above the leaves of the latest tree, which is the length of its proofs
+    pub tree_depth: usize,
+    /// Space saved by the storage backend, if it deduplicates
+    pub dedup: Option<DedupStats>,
+}
+
+/// Checks the received contents of uploaded files against the leaf hashes the client declared
+/// `declared` are the entries of the uploaded files, looked up by file name. Every file whose
+/// contents were changed in transit, or that has no declared leaf hash, is named, so the client
+/// knows which ones to send again.
+pub fn check_leaf_hashes(declared: &[LeafEntry], files: &[FileData]) -> Result<(), ProtocolError> {
+    let hashes: HashMap<&str, &str> = declared
+        .iter()
+        .map(|leaf| (leaf.name.as_str(), leaf.leaf_hash.as_str()))
+        .collect();
+    let corrupted: Vec<String> = files
+        .iter()
+        .filter(|file| {
+            hashes
+                .get(file.name.as_str())
+                .is_none_or(|hash| !ct_eq(hash, &leaf_hash(&file.content)))
+        })
+        .map(|file| file.name.clone())
+        .collect();
+    if corrupted.is_empty() {
+        Ok(())
+    } else {
+        Err(ProtocolError::CorruptedFiles(corrupted))
+    }
+}
+
+/// Checks that an upload only appends the files to the existing leaves
+/// `leaves` must keep every existing leaf at its position, followed by the files in order,
+/// and every file name may appear only once and not be reserved
+pub fn check_leaf_order(
+    existing: &[LeafEntry],
+    leaves: &[LeafEntry],
+    files: &[FileData],
+) -> Result<(), ProtocolError> {
+    if let Some(file) = files.iter().find(|file| is_reserved_name(&file.name)) {
+        return Err(ProtocolError::ReservedName(file.name.clone()));
+    }
+    if leaves.len() != existing.len() + files.len() {
+        return Err(ProtocolError::LeafCount {
+            expected: existing.len() + files.len(),
+            got: leaves.len(),
+        });
+    }
+    for (index, (leaf, existing)) in leaves.iter().zip(existing).enumerate() {
+        if leaf != existing {
+            return Err(ProtocolError::LeafChanged {
+                index,
+                name: existing.name.clone(),
+            });
+        }
+    }
+    for (offset, (leaf, file)) in leaves[existing.len()..].iter().zip(files).enumerate() {
+        if *leaf != LeafEntry::new(file) {
+            return Err(ProtocolError::LeafMismatch {
+                index: existing.len() + offset,
+                name: file.name.clone(),
+            });
+        }
+    }
+
+    let mut positions = HashMap::new();
+    for (index, leaf) in leaves.iter().enumerate() {
+        if let Some(first) = positions.insert(leaf.name.as_str(), index) {
+            return Err(ProtocolError::DuplicateLeaf {
+                name: leaf.name.clone(),
+                first,
+                second: index,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Query parameters of the file endpoint
+/// Without either parameter, the proof is valid for the latest root
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FileQuery {
+    /// The root the proof must be valid for
+    pub root: Option<String>,
+    /// The version of the root the proof must be valid for
+    pub version: Option<usize>,
+}
+
+/// Response body of the file endpoint: a file and its proof against `root`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileResponse {
+    pub name: String,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+    pub proof: MerkleProof,
+    pub root: String,
+}
+
+/// One entry of the root history
+/// The dataset only grows, so version `n` is the tree over the first `leaf_count` files
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RootVersion {
+    pub version: usize,
+    pub root_hash: String,
+    pub leaf_count: usize,
+}
+
+/// Query parameters of the chunk endpoint
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ChunkQuery {
+    /// Chunk size in bytes; defaults to `chunking::DEFAULT_CHUNK_SIZE`
+    pub size: Option<usize>,
+}
+
+/// Response body of the chunk endpoint: a chunk and its proof against the chunk root
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChunkResponse {
+    pub name: String,
+    pub chunk_count: usize,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+    pub proof: MerkleProof,
+}
+
+/// Response body of the non-membership endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AbsentResponse {
+    pub sorted_root: Option<String>,
+    pub proof: NonMembershipProof,
+}
+
+/// Query parameters of the proofs endpoint
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProofRangeQuery {
+    /// First leaf index, inclusive; defaults to the first leaf
+    pub start: Option<usize>,
+    /// Last leaf index, exclusive; defaults to the number of leaves
+    pub end: Option<usize>,
+}
+
+/// One line of the newline-delimited JSON streamed by the proofs endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeafProof {
+    pub index: usize,
+    pub name: String,
+    pub leaf_hash: String,
+    pub proof: MerkleProof,
+}
+
+/// Query parameters of the diff endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DiffQuery {
+    /// The root to compare from
+    pub from: String,
+    /// The root to compare to
+    pub to: String,
+}
+
+/// How a leaf differs between two roots
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// In `to` but not in `from`
+    Added,
+    /// In `from` but not in `to`
+    Removed,
+}
+
+/// A leaf that differs between two roots
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeafChange {
+    pub index: usize,
+    pub name: String,
+    pub leaf_hash: String,
+    pub change: ChangeKind,
+}
+
+/// Response body of the diff endpoint: the leaves that differ, in leaf order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiffResponse {
+    pub from: RootVersion,
+    pub to: RootVersion,
+    pub changes: Vec<LeafChange>,
+}
+
+/// Response body of the log endpoint: the head of the transparency log of roots
+/// The log is a Merkle tree whose leaves are the hashes of every root the server has published,
+/// in order
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogHead {
+    /// Number of roots in the log
+    pub size: usize,
+    pub root: String,
+}
+
+/// Response body of the log entry endpoint: a published root and its proof of inclusion in the
+/// log at the position of its version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogEntryProof {
+    pub entry: RootVersion,
+    pub log: LogHead,
+    pub proof: MerkleProof,
+}
+
+impl LogEntryProof {
+    /// Checks the proof of the entry's root against the log root, at the position of its
+    /// version in a log of the head's size
+    pub fn verify(&self) -> bool {
+        self.proof.leaf_index == self.entry.version
+            && self.proof.leaf_count == self.log.size
+            && self.proof.verify(&self.entry.root_hash, &self.log.root)
+    }
+}
+
+/// Query parameters of the sample endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SampleQuery {
+    /// Number of leaves to sample, at most `MAX_SAMPLE_SIZE`
+    pub n: usize,
+    /// Seed the sampled leaves are derived from, see `sample_indices`
+    pub seed: String,
+}
+
+/// A sampled leaf with its contents
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SampledFile {
+    pub index: usize,
+    pub name: String,
+    #[serde(with = "base64_bytes")]
+    pub content: Vec<u8>,
+}
+
+/// Response body of the sample endpoint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SampleResponse {
+    pub root: String,
+    pub leaf_count: usize,
+    pub samples: Vec<SampledFile>,
+    /// One proof of all sampled leaves against the root, `None` if nothing was sampled
+    pub proof: Option<MultiProof>,
+}
+
+impl SampleResponse {
+    /// Checks that the samples are the leaves the seed selects and that the proof verifies them
+    /// against the root
+    pub fn verify(&self, n: usize, seed: &str) -> Result<(), ProtocolError> {
+        let expected = sample_indices(seed, n, self.leaf_count);
+        let got: Vec<usize> = self.samples.iter().map(|sample| sample.index).collect();
+        if got != expected {
+            return Err(ProtocolError::UnexpectedSample { expected, got });
+        }
+        let leaves: Vec<(usize, String)> = self
+            .samples
+            .iter()
+            .map(|sample| (sample.index, leaf_hash(&sample.content)))
+            .collect();
+        match &self.proof {
+            Some(proof) if proof.verify(&leaves, &self.root) => Ok(()),
+            None if leaves.is_empty() => Ok(()),
+            _ => Err(ProtocolError::InvalidSampleProof),
+        }
+    }
+}
+
+/// The leaves a sample with the seed selects from `leaf_count` leaves, in selection order
+/// Candidates are derived from the SHA-256 hash of `<seed>:<counter>`, skipping leaves already
+/// selected, so whoever picked the seed can recompute the selection. At most `leaf_count` leaves
+/// are selected.
+pub fn sample_indices(seed: &str, n: usize, leaf_count: usize) -> Vec<usize> {
+    let n = n.min(leaf_count);
+    let mut selected = Vec::with_capacity(n);
+    let mut seen = HashSet::with_capacity(n);
+    let mut counter = 0u64;
+    while selected.len() < n {
+        let hash = calculate_hash(format!("{}:{}", seed, counter));
+        let value = u64::from_str_radix(&hash[..16], 16).expect("Hashes are hex");
+        let index = (value % leaf_count as u64) as usize;
+        if seen.insert(index) {
+            selected.push(index);
+        }
+        counter += 1;
+    }
+    selected
+}
+
+/// An encrypted client state, as pushed to and pulled from the server
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateBlob {
+    /// Hex-encoded blob from `encryption::encrypt_with_passphrase`
+    pub blob: String,
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn file(name: &str, content: &str) -> FileData {
+        FileData {
+            name: name.to_string(),
+            content: content.into(),
+        }
+    }
+
+    #[test]
+    fn appending_keeps_existing_leaves() {
+        let existing = vec![LeafEntry::new(&file("a.txt", "a"))];
+        let files = vec![file("b.txt", "b"), file("c.txt", "c")];
+        let mut leaves = existing.clone();
+        leaves.extend(files.iter().map(LeafEntry::new));
+        assert!(check_leaf_order(&existing, &leaves, &files).is_ok());
+    }
+
+    #[test]
+    fn reordering_existing_leaves_is_rejected() {
+        let existing = vec![
+            LeafEntry::new(&file("a.txt", "a")),
+            LeafEntry::new(&file("b.txt", "b")),
+        ];
+        let files = vec![file("c.txt", "c")];
+        let leaves = vec![
+            existing[1].clone(),
+            existing[0].clone(),
+            LeafEntry::new(&files[0]),
+        ];
+        assert!(check_leaf_order(&existing, &leaves, &files).is_err());
+    }
+
+    #[test]
+    fn leaves_must_match_the_files() {
+        let files = vec![file("a.txt", "a"), file("b.txt", "b")];
+        let swapped = vec![LeafEntry::new(&files[1]), LeafEntry::new(&files[0])];
+        assert!(check_leaf_order(&[], &swapped, &files).is_err());
+        assert!(check_leaf_order(&[], &swapped[..1], &files).is_err());
+    }
+
+    #[test]
+    fn reserved_names_cannot_be_uploaded() {
+        let files = vec![file("a.txt", "a"), file(LEAF_ORDER_FILE, "[]")];
+        let leaves: Vec<LeafEntry> = files.iter().map(LeafEntry::new).collect();
+        assert!(matches!(
+            check_leaf_order(&[], &leaves, &files),
+            Err(ProtocolError::ReservedName(name)) if name == LEAF_ORDER_FILE
+        ));
+        assert!(is_reserved_name(MERKLE_TREE_FILE));
+        assert!(is_reserved_name(&format!("{}abc", STATE_SYNC_PREFIX)));
+        assert!(!is_reserved_name("a.txt"));
+    }
+
+    #[test]
+    fn samples_depend_only_on_the_seed() {
+        let sample = sample_indices("seed", 10, 1000);
+        assert_eq!(sample, sample_indices("seed", 10, 1000));
+        assert_ne!(sample, sample_indices("other", 10, 1000));
+        assert!(sample.iter().all(|&index| index < 1000));
+
+        // Leaves are never selected twice, so asking for all of them selects each once
+        let mut all = sample_indices("seed", 20, 5);
+        all.sort();
+        assert_eq!(all, vec![0, 1, 2, 3, 4]);
+        assert!(sample_indices("seed", 3, 0).is_empty());
+    }
+
+    #[test]
+    fn corrupted_files_are_named() {
+        let files = vec![file("a.txt", "a"), file("b.txt", "b"), file("c.txt", "c")];
+        let declared: Vec<LeafEntry> = files.iter().map(LeafEntry::new).collect();
+        assert!(check_leaf_hashes(&declared, &files).is_ok());
+
+        let received = vec![file("a.txt", "a"), file("b.txt", "x"), file("c.txt", "")];
+        let error = check_leaf_hashes(&declared, &received).unwrap_err();
+        assert!(
+            matches!(&error, ProtocolError::CorruptedFiles(names) if names == &["b.txt", "c.txt"]),
+            "{}",
+            error
+        );
+
+        // Files are matched to their leaves by name, in any order
+        let reordered = vec![file("c.txt", "x"), file("a.txt", "a"), file("b.txt", "b")];
+        let error = check_leaf_hashes(&declared, &reordered).unwrap_err();
+        assert!(
+            matches!(&error, ProtocolError::CorruptedFiles(names) if names == &["c.txt"]),
+            "{}",
+            error
+        );
+        // A file without a declared leaf hash cannot be checked
+        let unknown = vec![file("d.txt", "d")];
+        let error = check_leaf_hashes(&declared, &unknown).unwrap_err();
+        assert!(
+            matches!(&error, ProtocolError::CorruptedFiles(names) if names == &["d.txt"]),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn reusing_a_file_name_is_rejected() {
+        let existing = vec![LeafEntry::new(&file("a.txt", "a"))];
+        let files = vec![file("a.txt", "changed")];
+        let mut leaves = existing.clone();
+        leaves.push(LeafEntry::new(&files[0]));
+        assert!(check_leaf_order(&existing, &leaves, &files).is_err());
+    }
+
+    #[test]
+    fn binary_contents_are_sent_as_base64() {
+        let file = FileData {
+            name: "image.png".to_string(),
+            content: vec![0x89, b'P', b'N', b'G', 0xff, 0x00],
+        };
+        let json = serde_json::to_string(&file).unwrap();
+        assert_eq!(json, r#"{"name":"image.png","content":"iVBOR/8A"}"#);
+        let decoded: FileData = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.content, file.content);
+        assert_eq!(LeafEntry::new(&decoded).leaf_hash, leaf_hash(&file.content));
+
+        assert!(
+            serde_json::from_str::<FileData>(r#"{"name":"a","content":"not base64!"}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! A tree pruned down to the branches of selected leaves
+//! The pruned tree keeps the hashes of the selected leaves, the root, and the multiproof
+//! connecting them, which is the smallest part of the tree that still proves the leaves. It is
+//! self-contained, so it can be handed to an auditor as evidence: they check it against the root
+//! they trust, check the files they were given against its leaf hashes, and can take a regular
+//! proof of any of the leaves out of it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{root_height, width, MerkleProof, MerkleTree, Position, ProofNode};
+use crate::multiproof::{MultiProof, Nodes};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrunedTree {
+    pub root: String,
+    /// Number of leaves of the tree, before an odd first level is completed
+    pub leaf_count: usize,
+    /// Hashes of the selected leaves, by index
+    pub leaves: BTreeMap<usize, String>,
+    pub proof: MultiProof,
+}
+
+impl<H: TreeHasher> MerkleTree<H> {
+    /// Prunes the tree to the branches of the leaves at the indices
+    /// Returns `None` if there are no indices or one of them is out of range
+    pub fn prune(&self, indices: &[usize]) -> Option<PrunedTree> {
+        let proof = self.get_multi_proof(indices)?;
+        Some(PrunedTree {
+            root: self.root()?,
+            leaf_count: self.leaf_count(),
+            leaves: indices
+                .iter()
+                .map(|&index| Some((index, self.get_leaf(index)?)))
+                .collect::<Option<_>>()?,
+            proof,
+        })
+    }
+}
+
+impl PrunedTree {
+    /// The nodes of the pruned tree, checked against its root
+    fn nodes(&self) -> Result<Nodes, MerkleError> {
+        let leaf_hashes: Vec<String> = self.leaves.values().cloned().collect();
+        let (root, indices, nodes) = self.proof.walk(HashAlgorithm::Sha256, &leaf_hashes)?;
+        if !indices.iter().eq(self.leaves.keys()) {
+            return Err(MerkleError::MalformedProof(
+                "not a proof of the selected leaves",
+            ));
+        }
+        if self.leaf_count + self.leaf_count % 2 != self.proof.leaf_count {
+            return Err(MerkleError::MalformedProof(
+                "leaf count does not match the proof",
+            ));
+        }
+        if !ct_eq(&root, &self.root) {
+            return Err(MerkleError::RootMismatch);
+        }
+        Ok(nodes)
+    }
+
+    /// Checks that the selected leaves and the proof lead to the root
+    pub fn verify(&self) -> Result<(), MerkleError> {
+        self.nodes().map(|_| ())
+    }
+
+    /// Checks that the contents are the selected leaf at the index
+    pub fn contains(&self, index: usize, content: &str) -> bool {
+        self.leaves
+            .get(&index)
+            .is_some_and(|leaf_hash| ct_eq(leaf_hash, &HashAlgorithm::Sha256.hash_leaf(content)))
+    }
+
+    /// Get the Merkle proof of a selected leaf, as `MerkleTree::get_merkle_proof` gives it
+    /// Returns `None` if the leaf was not selected or the pruned tree does not verify
+    pub fn get_merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        if !self.leaves.contains_key(&index) {
+            return None;
+        }
+        let nodes = self.nodes().ok()?;
+        let leaf_count = self.proof.leaf_count;
+
+        let mut proof = Vec::new();
+        let mut position = index;
+        for height in 0..root_height(leaf_count) {
+            let sibling = position ^ 1;
+            // The last node of an odd level is paired with itself
+            let node = if sibling < width(leaf_count, height) {
+                nodes.get(&(height, sibling))?
+            } else {
+                nodes.get(&(height, position))?
+            };
+            proof.push(ProofNode {
+                sibling: node.clone(),
+                position: if sibling > position {
+                    Position::Right
+                } else {
+                    Position::Left
+                },
+            });
+            position /= 2;
+        }
+        Some(MerkleProof {
+            leaf_index: index,
+            leaf_count: self.leaf_count,
+            nodes: proof,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::root_from_proof;
+
+    fn tree(leaf_count: usize) -> MerkleTree {
+        let mut tree = MerkleTree::new();
+        tree.build(&(0..leaf_count).map(|i| i.to_string()).collect::<Vec<_>>());
+        tree
+    }
+
+    #[test]
+    fn pruned_trees_keep_the_proofs_of_their_leaves() {
+        let tree = tree(13);
+        let pruned = tree.prune(&[12, 3, 4]).unwrap();
+        assert!(pruned.verify().is_ok());
+        assert_eq!(pruned.leaves.len(), 3);
+
+        for index in [3, 4, 12] {
+            let proof = pruned.get_merkle_proof(index).unwrap();
+            assert_eq!(Some(&proof), tree.get_merkle_proof(index).as_ref().ok());
+            assert_eq!(root_from_proof(index.to_string(), &proof), pruned.root);
+            assert!(pruned.contains(index, &index.to_string()));
+        }
+        assert_eq!(pruned.get_merkle_proof(5), None);
+        assert!(!pruned.contains(5, "5"));
+    }
+
+    #[test]
+    fn survives_json_roundtrip() {
+        let pruned = tree(6).prune(&[1]).unwrap();
+        let decoded = PrunedTree::from_json(&pruned.to_json().unwrap()).unwrap();
+        assert_eq!(decoded, pruned);
+        assert!(decoded.verify().is_ok());
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let tree = tree(8);
+        let pruned = tree.prune(&[2, 5]).unwrap();
+
+        let mut other_root = pruned.clone();
+        other_root.root = tree.get_merkle_proof(0).unwrap().nodes[0].sibling.clone();
+        assert_eq!(other_root.verify(), Err(MerkleError::RootMismatch));
+
+        let mut moved = pruned.clone();
+        let leaf = moved.leaves.remove(&5).unwrap();
+        moved.leaves.insert(6, leaf);
+        assert!(moved.verify().is_err());
+        assert_eq!(moved.get_merkle_proof(6), None);
+
+        let mut recounted = pruned.clone();
+        recounted.leaf_count = 9;
+        assert!(recounted.verify().is_err());
+
+        let mut changed = pruned;
+        changed.leaves.insert(2, changed.leaves[&5].clone());
+        assert!(changed.verify().is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! Trees built exactly as the Merkle Tree Hash of RFC 6962 (Certificate Transparency)
+//! `MerkleTree` completes odd levels by duplicating their last node, so its roots differ from
+//! those of transparency logs. Here a tree of `n` leaves is split after the largest power of two
+//! smaller than `n` instead, which is the same as carrying the last node of an odd level up
+//! unchanged. Leaves and nodes are hashed with the `0x00`/`0x01` prefixes the crate already uses,
+//! and the empty tree is the hash of the empty string, so roots, audit paths and consistency
+//! proofs can be checked with CT tooling and other transparency-log libraries.
+
+use sha2::{Digest, Sha256};
+
+use crate::hashing::{ct_eq, decode_hash, Hash};
+use merkleproofs_verify::{leaf_digest, node_digest};
+
+#[derive(Debug, Clone)]
+pub struct Rfc6962Tree {
+    /// Nodes of every level from the leaves up; the node at a position is the hash of the leaves
+    /// below it, so the last node of a level may cover fewer leaves than the others
+    levels: Vec<Vec<Hash>>,
+}
+
+/// Hex-encoded hash of a leaf's data, `SHA-256(0x00 || data)`
+pub fn leaf_hash(data: &[u8]) -> String {
+    hex::encode(leaf_digest(data))
+}
+
+/// The largest power of two smaller than `n`, which must be at least 2
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+impl Rfc6962Tree {
+    /// Builds the tree over the data of the leaves
+    pub fn build<T: AsRef<[u8]>>(elements: &[T]) -> Self {
+        Self::from_leaf_digests(elements.iter().map(|e| leaf_digest(e.as_ref())).collect())
+    }
+
+    /// Builds the tree over already hashed leaves
+    pub fn from_leaf_digests(leaves: Vec<Hash>) -> Self {
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_digest(left, right),
+                    // Carried up unchanged, not paired with itself
+                    [last] => *last,
+                    _ => unreachable!("Chunks have one or two nodes"),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Number of leaves
+    pub fn size(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// The Merkle Tree Hash of the leaves, which is the hash of the empty string without leaves
+    pub fn root(&self) -> String {
+        match self.size() {
+            0 => hex::encode(Sha256::digest(b"")),
+            size => hex::encode(self.subtree(0, size)),
+        }
+    }
+
+    /// The hash of the leaves `start..start + len`, which are either a whole aligned power of two
+    /// or run to the end of the tree
+    fn subtree(&self, start: usize, len: usize) -> Hash {
+        let height = len.next_power_of_two().trailing_zeros();
+        self.levels[height as usize][start >> height]
+    }
+
+    /// The audit path of the leaf at the index, from the leaf up
+    pub fn inclusion_proof(&self, index: usize) -> Option<Vec<String>> {
+        if index >= self.size() {
+            return None;
+        }
+        let mut path = Vec::new();
+        self.collect_path(index, 0, self.size(), &mut path);
+        Some(path)
+    }
+
+    fn collect_path(&self, index: usize, start: usize, len: usize, path: &mut Vec<String>) {
+        if len == 1 {
+            return;
+        }
+        let k = split_point(len);
+        if index < k {
+            self.collect_path(index, start, k, path);
+            path.push(hex::encode(self.subtree(start + k, len - k)));
+        } else {
+            self.collect_path(index - k, start + k, len - k, path);
+            path.push(hex::encode(self.subtree(start, k)));
+        }
+    }
+
+    /// The proof that this tree extends the tree of its first `old_size` leaves
+    /// Returns `None` unless `0 < old_size <= size`
+    pub fn consistency_proof(&self, old_size: usize) -> Option<Vec<String>> {
+        if old_size == 0 || old_size > self.size() {
+            return None;
+        }
+        let mut proof = Vec::new();
+        self.collect_subproof(old_size, 0, self.size(), true, &mut proof);
+        Some(proof)
+    }
+
+    fn collect_subproof(
+        &self,
+        old_size: usize,
+        start: usize,
+        len: usize,
+        whole_old_tree: bool,
+        proof: &mut Vec<String>,
+    ) {
+        if old_size == len {
+            if !whole_old_tree {
+                proof.push(hex::encode(self.subtree(start, len)));
+            }
+            return;
+        }
+        let k = split_point(len);
+        if old_size <= k {
+            self.collect_subproof(old_size, start, k, whole_old_tree, proof);
+            proof.push(hex::encode(self.subtree(start + k, len - k)));
+        } else {
+            self.collect_subproof(old_size - k, start + k, len - k, false, proof);
+            proof.push(hex::encode(self.subtree(start, k)));
+        }
+    }
+}
+
+/// Checks an audit path of the leaf hash at the index of a tree of `tree_size` leaves
+pub fn verify_inclusion(
+    index: usize,
+    tree_size: usize,
+    leaf_hash: &str,
+    proof: &[String],
+    root: &str,
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let Some(mut current) = decode_hash(leaf_hash) else {
+        return false;
+    };
+    let (mut f, mut s) = (index, tree_size - 1);
+    for node in proof {
+        let Some(sibling) = decode_hash(node) else {
+            return false;
+        };
+        if s == 0 {
+            return false;
+        }
+        if f % 2 == 1 || f == s {
+            current = node_digest(&sibling, &current);
+            while f % 2 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            current = node_digest(&current, &sibling);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && ct_eq(&hex::encode(current), root)
+}
+
+/// Checks a proof that the tree of `new_size` leaves with the new root extends the tree of its
+/// first `old_size` leaves with the old root
+pub fn verify_consistency(
+    old_size: usize,
+    new_size: usize,
+    old_root: &str,
+    new_root: &str,
+    proof: &[String],
+) -> bool {
+    if old_size == 0 || old_size > new_size {
+        return false;
+    }
+    if old_size == new_size {
+        return proof.is_empty() && ct_eq(old_root, new_root);
+    }
+    let mut nodes = Vec::with_capacity(proof.len() + 1);
+    // The old tree is a subtree of the new one, so the proof leaves out its root
+    if old_size.is_power_of_two() {
+        nodes.push(old_root);
+    }
+    nodes.extend(proof.iter().map(String::as_str));
+    let Some(nodes) = nodes
+        .into_iter()
+        .map(decode_hash)
+        .collect::<Option<Vec<Hash>>>()
+    else {
+        return false;
+    };
+    let Some((first, rest)) = nodes.split_first() else {
+        return false;
+    };
+
+    let (mut f, mut s) = (old_size - 1, new_size - 1);
+    while f % 2 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let (mut old, mut new) = (*first, *first);
+    for node in rest {
+        if s == 0 {
+            return false;
+        }
+        if f % 2 == 1 || f == s {
+            old = node_digest(node, &old);
+            new = node_digest(node, &new);
+            while f % 2 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            new = node_digest(&new, node);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && ct_eq(&hex::encode(old), old_root) && ct_eq(&hex::encode(new), new_root)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Leaves of the Certificate Transparency reference tests
+    fn leaves() -> Vec<Vec<u8>> {
+        [
+            "",
+            "00",
+            "10",
+            "2021",
+            "3031",
+            "40414243",
+            "5051525354555657",
+            "606162636465666768696a6b6c6d6e6f",
+        ]
+        .iter()
+        .map(|leaf| hex::decode(leaf).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn roots_match_the_reference_tests() {
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+        let leaves = leaves();
+        for (size, root) in roots.iter().enumerate() {
+            assert_eq!(Rfc6962Tree::build(&leaves[..size + 1]).root(), *root);
+        }
+        assert_eq!(
+            Rfc6962Tree::build::<&[u8]>(&[]).root(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn audit_paths_verify() {
+        let leaves = leaves();
+        for size in 1..=leaves.len() {
+            let tree = Rfc6962Tree::build(&leaves[..size]);
+            let root = tree.root();
+            for (index, leaf) in leaves[..size].iter().enumerate() {
+                let proof = tree.inclusion_proof(index).unwrap();
+                assert!(verify_inclusion(
+                    index,
+                    size,
+                    &leaf_hash(leaf),
+                    &proof,
+                    &root
+                ));
+                assert!(!verify_inclusion(
+                    size,
+                    size,
+                    &leaf_hash(leaf),
+                    &proof,
+                    &root
+                ));
+                assert!(!verify_inclusion(
+                    index,
+                    size,
+                    &leaf_hash(b"x"),
+                    &proof,
+                    &root
+                ));
+            }
+            assert!(tree.inclusion_proof(size).is_none());
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let leaves = leaves();
+        for new_size in 1..=leaves.len() {
+            let tree = Rfc6962Tree::build(&leaves[..new_size]);
+            for old_size in 1..=new_size {
+                let old_root = Rfc6962Tree::build(&leaves[..old_size]).root();
+                let proof = tree.consistency_proof(old_size).unwrap();
+                assert!(verify_consistency(
+                    old_size,
+                    new_size,
+                    &old_root,
+                    &tree.root(),
+                    &proof
+                ));
+                if old_size < new_size {
+                    assert!(!verify_consistency(
+                        old_size,
+                        new_size,
+                        &leaf_hash(b"x"),
+                        &tree.root(),
+                        &proof
+                    ));
+                }
+            }
+        }
+
+        // From 6 to 8 leaves: the subtrees of leaves 4..6, 6..8 and 0..4
+        let tree = Rfc6962Tree::build(&leaves);
+        assert_eq!(tree.consistency_proof(6).unwrap().len(), 3);
+    }
+}
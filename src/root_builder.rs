@@ -0,0 +1,204 @@
+//! Roots of trees too large to hold in memory
+//! `RootBuilder` takes the leaves one at a time and keeps only the left nodes still waiting for
+//! their right sibling, at most one per level, so it holds O(log n) hashes however many leaves
+//! it is given. It can also collect the proof of one leaf chosen up front, by keeping the
+//! siblings on that leaf's path as they are completed.
+//!
+//! Roots and proofs are the same as those of a `MerkleTree` over the same leaves.
+
+use crate::hashing::{HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{proof_node, root_height, MerkleProof};
+
+#[derive(Debug)]
+pub struct RootBuilder<H: TreeHasher = HashAlgorithm> {
+    hasher: H,
+    leaf_count: usize,
+    /// Left nodes waiting for their right sibling, by height
+    pending: Vec<Option<H::Output>>,
+    /// Number of nodes completed at every height so far
+    completed: Vec<usize>,
+    /// The leaf whose proof is collected, if any
+    proving: Option<usize>,
+    /// Siblings of the proven leaf's path found so far, by height
+    siblings: Vec<Option<H::Output>>,
+}
+
+impl RootBuilder {
+    pub fn new() -> Self {
+        Self::with_hasher(HashAlgorithm::default())
+    }
+
+    /// Creates a builder that hashes leaves and nodes with the given algorithm
+    pub fn with_algorithm(algorithm: HashAlgorithm) -> Self {
+        Self::with_hasher(algorithm)
+    }
+}
+
+impl Default for RootBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: TreeHasher> RootBuilder<H> {
+    /// Creates a builder that hashes leaves and nodes with the given hasher
+    pub fn with_hasher(hasher: H) -> Self {
+        Self {
+            hasher,
+            leaf_count: 0,
+            pending: Vec::new(),
+            completed: Vec::new(),
+            proving: None,
+            siblings: Vec::new(),
+        }
+    }
+
+    /// Also collects the proof of the leaf at the index, returned by `finish`
+    pub fn proving(mut self, index: usize) -> Self {
+        self.proving = Some(index);
+        self
+    }
+
+    /// Number of leaves pushed so far
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Appends the contents of a leaf
+    pub fn push(&mut self, content: &[u8]) {
+        let digest = self.hasher.leaf_digest(content);
+        self.push_digest(digest);
+    }
+
+    /// Appends the raw digest of an already hashed leaf
+    pub fn push_digest(&mut self, digest: H::Output) {
+        self.leaf_count += 1;
+        let mut node = digest;
+        let mut height = 0;
+        loop {
+            self.complete(height, node);
+            match self.pending[height].take() {
+                Some(left) => {
+                    node = self.hasher.node_digest(&left, &node);
+                    height += 1;
+                }
+                None => {
+                    self.pending[height] = Some(node);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Counts a completed node, keeping it if it is a sibling on the proven leaf's path
+    fn complete(&mut self, height: usize, node: H::Output) {
+        if self.completed.len() <= height {
+            self.completed.push(0);
+            self.pending.push(None);
+            self.siblings.push(None);
+        }
+        let position = self.completed[height];
+        self.completed[height] += 1;
+        if let Some(index) = self.proving {
+            if position == (index >> height) ^ 1 {
+                self.siblings[height] = Some(node);
+            }
+        }
+    }
+
+    /// Completes the last nodes of every level, pairing the last node of an odd level with
+    /// itself, and returns the root with the proof of the chosen leaf
+    /// The root is the `empty_digest` without leaves, and there is no proof unless the chosen
+    /// leaf was pushed.
+    pub fn finish(mut self) -> (String, Option<MerkleProof>) {
+        if self.leaf_count == 0 {
+            return (hex::encode(self.hasher.empty_digest()), None);
+        }
+
+        let leaf_count = self.leaf_count + self.leaf_count % 2;
+        let top = root_height(leaf_count) as usize;
+        let mut carry: Option<H::Output> = None;
+        for height in 0..top {
+            let last = match (self.pending[height].take(), carry) {
+                (Some(left), Some(right)) => {
+                    self.complete(height, right);
+                    (left, right)
+                }
+                (Some(left), None) => {
+                    self.complete(height, left);
+                    (left, left)
+                }
+                (None, Some(node)) => {
+                    self.complete(height, node);
+                    self.complete(height, node);
+                    (node, node)
+                }
+                (None, None) => continue,
+            };
+            carry = Some(self.hasher.node_digest(&last.0, &last.1));
+        }
+        // Unless a power of two of leaves left the root pending at the top
+        let root = match carry {
+            Some(root) => root,
+            None => self.pending[top].expect("A tree with leaves has a root"),
+        };
+
+        let proof = self
+            .proving
+            .filter(|&index| index < self.leaf_count)
+            .map(|index| MerkleProof {
+                leaf_index: index,
+                leaf_count: self.leaf_count,
+                nodes: (0..top)
+                    .map(|height| {
+                        let sibling = self.siblings[height].expect("Every level has a sibling");
+                        proof_node(sibling, (index >> height) % 2 == 0)
+                    })
+                    .collect(),
+            });
+        (hex::encode(root), proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::MerkleTree;
+
+    #[test]
+    fn roots_and_proofs_match_merkle_trees() {
+        for count in 0..=33 {
+            let elements: Vec<String> = (0..count).map(|i| i.to_string()).collect();
+            let mut tree = MerkleTree::new();
+            tree.build(&elements);
+
+            for index in 0..=count {
+                let mut builder = RootBuilder::new().proving(index);
+                for element in &elements {
+                    builder.push(element.as_bytes());
+                }
+                assert_eq!(builder.leaf_count(), count);
+                let (root, proof) = builder.finish();
+                assert_eq!(Some(root), tree.root());
+                assert_eq!(
+                    proof,
+                    tree.get_merkle_proof(index).ok(),
+                    "{} of {}",
+                    index,
+                    count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_a_path_of_nodes_is_held() {
+        let mut builder = RootBuilder::new();
+        for i in 0..100_000u32 {
+            builder.push(&i.to_be_bytes());
+        }
+        assert!(builder.pending.len() <= 17);
+        assert!(builder.pending.iter().flatten().count() <= 17);
+    }
+}
@@ -0,0 +1,175 @@
+//! Trees whose leaves are hashed with a salt
+//! The hash of a short leaf, such as a small file or a yes/no value, can be brute-forced from its
+//! leaf hash or the hashes in a proof. Here every leaf is hashed as
+//! `SHA-256(LEAF_PREFIX || salt || contents)` with a 32-byte salt, random per leaf unless the
+//! caller gives them, and the proof of a leaf carries its salt. A proof reveals the salt of its
+//! own leaf only, so the other leaves stay as hard to guess as their salts.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+
+use crate::error::MerkleError;
+use crate::hashing::{Hash, MerkleLeaf};
+use crate::merkle_tree::MerkleTree;
+use merkleproofs_verify::salted_leaf_digest;
+
+pub use merkleproofs_verify::SaltedProof;
+
+#[derive(Debug, Default)]
+pub struct SaltedMerkleTree {
+    tree: MerkleTree,
+    /// Salt of every leaf, by index
+    salts: Vec<Hash>,
+}
+
+/// A new random salt
+fn random_salt() -> Hash {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+impl SaltedMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the tree from a list of elements, with a new random salt for every leaf
+    pub fn build<T: MerkleLeaf>(&mut self, elements: &[T]) {
+        let salts = elements.iter().map(|_| random_salt()).collect();
+        self.build_with_salts(elements, salts)
+            .expect("Every element has a salt");
+    }
+
+    /// Build the tree with the given salts, one per element
+    /// The same salt for every element gives a tree with a per-tree salt.
+    pub fn build_with_salts<T: MerkleLeaf>(
+        &mut self,
+        elements: &[T],
+        salts: Vec<Hash>,
+    ) -> Result<(), MerkleError> {
+        if salts.len() != elements.len() {
+            return Err(MerkleError::MalformedProof("not one salt per leaf"));
+        }
+        let digests = elements
+            .iter()
+            .zip(&salts)
+            .map(|(element, salt)| salted_leaf_digest(salt, &element.leaf_bytes()))
+            .collect();
+        self.tree.build_from_digests(digests);
+        self.salts = salts;
+        Ok(())
+    }
+
+    /// The root hash, which is `empty_root` for a tree built without leaves, or `None` before the
+    /// tree is built
+    pub fn root(&self) -> Option<String> {
+        self.tree.root()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    /// The salt of the leaf at the index, to be kept with the tree if it is rebuilt later
+    pub fn salt(&self, index: usize) -> Option<Hash> {
+        self.salts.get(index).copied()
+    }
+
+    /// Replaces the contents of the leaf at the index, with a new random salt
+    pub fn update_leaf<T: MerkleLeaf + ?Sized>(
+        &mut self,
+        index: usize,
+        new_value: &T,
+    ) -> Result<(), MerkleError> {
+        if index >= self.salts.len() {
+            return Err(MerkleError::IndexOutOfRange {
+                index,
+                leaf_count: self.salts.len(),
+            });
+        }
+        let salt = random_salt();
+        let digest = salted_leaf_digest(&salt, &new_value.leaf_bytes());
+        self.tree.update_leaf_digest(index, digest)?;
+        self.salts[index] = salt;
+        Ok(())
+    }
+
+    /// Get the proof of the leaf at the index, with its salt
+    pub fn get_merkle_proof(&self, index: usize) -> Result<SaltedProof, MerkleError> {
+        let proof = self.tree.get_merkle_proof(index)?;
+        Ok(SaltedProof {
+            salt: hex::encode(self.salts[index]),
+            proof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::leaf_hash;
+
+    fn elements(contents: &[&str]) -> Vec<String> {
+        contents.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn salted_proofs_verify() {
+        let elements = elements(&["yes", "no", "yes"]);
+        let mut tree = SaltedMerkleTree::new();
+        tree.build(&elements);
+        let root = tree.root().unwrap();
+
+        for (index, element) in elements.iter().enumerate() {
+            let proof = tree.get_merkle_proof(index).unwrap();
+            assert!(proof.verify(element, &root));
+            assert!(!proof.verify("maybe", &root));
+            // Without the salt, the leaf cannot be recomputed from its contents
+            assert!(!proof.proof.verify(element, &root));
+        }
+        assert!(tree.get_merkle_proof(3).is_err());
+
+        // Equal contents get different leaf hashes
+        let first = tree.get_merkle_proof(0).unwrap();
+        let third = tree.get_merkle_proof(2).unwrap();
+        assert_ne!(first.salt, third.salt);
+        assert_ne!(first.proof.nodes[0].sibling, leaf_hash("no"));
+
+        let mut wrong_salt = first;
+        wrong_salt.salt = third.salt;
+        assert!(!wrong_salt.verify("yes", &root));
+    }
+
+    #[test]
+    fn trees_are_rebuilt_from_their_salts() {
+        let mut elements = elements(&["a", "b", "c"]);
+        let mut tree = SaltedMerkleTree::new();
+        tree.build(&elements);
+        let salts: Vec<Hash> = (0..3).map(|i| tree.salt(i).unwrap()).collect();
+
+        let mut rebuilt = SaltedMerkleTree::new();
+        rebuilt.build_with_salts(&elements, salts.clone()).unwrap();
+        assert_eq!(rebuilt.root(), tree.root());
+        assert!(rebuilt
+            .build_with_salts(&elements, salts[..2].to_vec())
+            .is_err());
+
+        tree.update_leaf(1, "x").unwrap();
+        elements[1] = "x".to_string();
+        let proof = tree.get_merkle_proof(1).unwrap();
+        assert!(proof.verify("x", &tree.root().unwrap()));
+        assert_ne!(tree.salt(1), Some(salts[1]));
+        let salts = (0..3).map(|i| tree.salt(i).unwrap()).collect();
+        rebuilt.build_with_salts(&elements, salts).unwrap();
+        assert_eq!(rebuilt.root(), tree.root());
+        assert_eq!(
+            tree.update_leaf(3, "x"),
+            Err(MerkleError::IndexOutOfRange {
+                index: 3,
+                leaf_count: 3
+            })
+        );
+    }
+}
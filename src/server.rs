@@ -0,0 +1,1562 @@
+use futures_util::stream;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use warp::http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+use warp::{Rejection, Reply};
+
+use crate::chunking::{self, DEFAULT_CHUNK_SIZE};
+use crate::compression::{self, Encoding, MAX_DECODED_BODY};
+use crate::error::{ConfigError, MerkleError, ProtocolError, StorageError};
+use crate::hashing::{ct_eq, HashAlgorithm};
+use crate::merkle_tree::{calculate_hash, empty_root, MerkleProof, MerkleTree};
+use crate::mirror::MirrorStats;
+use crate::proof_cache::{self, ProofCache, ProofCacheStats};
+use crate::protocol::{
+    check_leaf_hashes, check_leaf_order, is_reserved_name, sample_indices, AbsentResponse,
+    ChangeKind, ChunkQuery, ChunkResponse, DiffQuery, DiffResponse, FileData, FileQuery,
+    FileResponse, ImportRequest, LeafChange, LeafEntry, LeafProof, LogEntryProof, LogHead,
+    ProofRangeQuery, RootVersion, SampleQuery, SampleResponse, SampledFile, ServerStats, StateBlob,
+    UploadPart, UploadPartResponse, UploadRequest, UploadResponse, IDEMPOTENCY_HEADER,
+    LEAF_ORDER_FILE, MAX_SAMPLE_SIZE, MERKLE_TREE_FILE, PROOF_MEDIA_TYPE, ROOT_HEADER,
+    ROOT_HISTORY_FILE, STATE_SYNC_PREFIX,
+};
+use crate::sorted_tree::SortedMerkleTree;
+use crate::storage::{create_backend, FileSystemBackend, StorageBackend, FILESYSTEM_BACKEND};
+
+/// Default directory where the files are stored
+pub const STORAGE_DIR: &str = "server_storage";
+
+/// Default address of an embedded server
+pub const DEFAULT_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 8000);
+
+/// Default time the result of an operation is remembered under its idempotency key
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default number of bytes all staged uploads may hold together
+pub const DEFAULT_MAX_STAGED_BYTES: usize = 1 << 30;
+
+/// Default number of uploads that may be staged at once
+pub const DEFAULT_MAX_STAGED_UPLOADS: usize = 64;
+
+/// Default time a staged upload is kept after its last part
+pub const DEFAULT_STAGED_UPLOAD_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How many proofs the proofs endpoint generates at a time
+/// Bounds the memory of a streamed response regardless of the size of the range
+const PROOF_STREAM_BATCH: usize = 256;
+
+/// Settings of a server instance
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Name of the registered storage backend, see `storage::register_backend`
+    pub storage_backend: String,
+    /// Where uploaded files and metadata are written; passed to the backend as its location
+    pub storage_dir: PathBuf,
+    /// How long a retried upload or delete is recognized by its idempotency key
+    pub idempotency_window: Duration,
+    /// How many file proofs are cached; 0 disables the cache
+    pub proof_cache_capacity: usize,
+    /// Directory whose subdirectories `POST /import` may ingest; `None` disables the endpoint
+    pub import_root: Option<PathBuf>,
+    /// How many bytes the parts of all staged uploads may hold together
+    pub max_staged_bytes: usize,
+    /// How many uploads may be staged at once
+    pub max_staged_uploads: usize,
+    /// How long a staged upload that receives no parts is kept before it is dropped
+    pub staged_upload_ttl: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            storage_backend: FILESYSTEM_BACKEND.to_string(),
+            storage_dir: PathBuf::from(STORAGE_DIR),
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+            proof_cache_capacity: proof_cache::DEFAULT_CAPACITY,
+            import_root: None,
+            max_staged_bytes: DEFAULT_MAX_STAGED_BYTES,
+            max_staged_uploads: DEFAULT_MAX_STAGED_UPLOADS,
+            staged_upload_ttl: DEFAULT_STAGED_UPLOAD_TTL,
+        }
+    }
+}
+
+/// Setting naming the directory whose subdirectories `POST /import` may ingest
+pub const IMPORT_ROOT_SETTING: &str = "MERKLE_IMPORT_ROOT";
+/// Setting giving the idempotency window in seconds
+pub const IDEMPOTENCY_WINDOW_SETTING: &str = "MERKLE_IDEMPOTENCY_WINDOW";
+/// Setting naming a directory the deployed server imports as a new version on startup
+pub const IMPORT_SETTING: &str = "MERKLE_IMPORT";
+
+impl ServerConfig {
+    /// Applies the settings `setting` finds by name over the config
+    /// The deployed server reads them from its secrets or environment; settings that are not
+    /// found keep their value.
+    pub fn with_settings(
+        mut self,
+        setting: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        if let Some(import_root) = setting(IMPORT_ROOT_SETTING) {
+            self.import_root = Some(import_root.into());
+        }
+        if let Some(window) = setting(IDEMPOTENCY_WINDOW_SETTING) {
+            let seconds = window
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidSetting {
+                    name: IDEMPOTENCY_WINDOW_SETTING.to_string(),
+                    value: window.clone(),
+                })?;
+            self.idempotency_window = Duration::from_secs(seconds);
+        }
+        Ok(self)
+    }
+}
+
+/// Result of an operation, kept to answer retries of it
+struct CompletedOperation {
+    completed_at: Instant,
+    /// Hash of the request, to catch a key reused for a different request
+    fingerprint: String,
+    response: serde_json::Value,
+}
+
+/// The dataset at one version
+/// Never changed once published: an update builds a new dataset and swaps it in, while readers
+/// keep using the one they started with. Files and trees are shared between versions, so the
+/// copy only duplicates the lists of names and hashes.
+#[derive(Clone, Default)]
+pub struct Dataset {
+    files: Vec<Arc<FileData>>,                  // Ordered list of files
+    file_index: HashMap<String, usize>,         // Filename to index mapping
+    merkle_tree: Option<Arc<MerkleTree>>,       // The Merkle tree over all stored files
+    sorted_tree: Option<Arc<SortedMerkleTree>>, // Leaves sorted by hash, for non-membership proofs
+    root_history: Vec<RootVersion>,             // Every root the dataset has had, oldest first
+    leaves: Vec<LeafEntry>,                     // The ordered leaves of the dataset
+    root_log: Option<Arc<MerkleTree>>,          // The transparency log over the root history
+}
+
+impl Dataset {
+    /// The root hash of the Merkle tree, or `None` before the first upload
+    pub fn root_hash(&self) -> Option<String> {
+        self.merkle_tree.as_ref().and_then(|tree| tree.root())
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The stored file at a leaf index
+    pub fn file(&self, index: usize) -> Option<&FileData> {
+        self.files.get(index).map(|file| file.as_ref())
+    }
+
+    /// The ordered leaves of the dataset
+    pub fn leaves(&self) -> &[LeafEntry] {
+        &self.leaves
+    }
+
+    /// Every root the dataset has had, oldest first
+    pub fn root_history(&self) -> &[RootVersion] {
+        &self.root_history
+    }
+
+    /// The head of the transparency log, or `None` before the first upload
+    pub fn log_head(&self) -> Option<LogHead> {
+        let root = self.root_log.as_ref()?.root()?;
+        Some(LogHead {
+            size: self.root_history.len(),
+            root,
+        })
+    }
+
+    /// The proof that the root of a version is in the transparency log
+    pub fn log_entry_proof(&self, version: usize) -> Option<LogEntryProof> {
+        Some(LogEntryProof {
+            entry: self.root_history.get(version)?.clone(),
+            log: self.log_head()?,
+            proof: self.root_log.as_ref()?.get_merkle_proof(version).ok()?,
+        })
+    }
+
+    /// Contents of the first `leaf_count` stored files, or of all of them
+    fn contents(&self, leaf_count: Option<usize>) -> Vec<Vec<u8>> {
+        let leaf_count = leaf_count.unwrap_or(self.files.len());
+        self.files
+            .iter()
+            .take(leaf_count)
+            .map(|file| file.content.clone())
+            .collect()
+    }
+
+    /// Finds the latest version with the root
+    fn version_of_root(&self, root: &str) -> Result<RootVersion, Rejection> {
+        self.root_history
+            .iter()
+            .rev()
+            .find(|version| ct_eq(&version.root_hash, root))
+            .cloned()
+            .ok_or_else(|| warp::reject::custom(ProtocolError::RootNotAvailable(root.to_string())))
+    }
+
+    /// Finds the root version a file request asks for; `None` means the latest
+    pub(crate) fn requested_version(
+        &self,
+        query: &FileQuery,
+    ) -> Result<Option<RootVersion>, Rejection> {
+        let version = match (&query.version, &query.root) {
+            (Some(version), _) => self.root_history.get(*version).cloned().ok_or_else(|| {
+                warp::reject::custom(ProtocolError::VersionNotAvailable(*version))
+            })?,
+            (None, Some(root)) => self.version_of_root(root)?,
+            (None, None) => return Ok(None),
+        };
+        // The latest version is served from the current tree
+        if self.root_history.last() == Some(&version) {
+            return Ok(None);
+        }
+        Ok(Some(version))
+    }
+
+    /// The leaves that differ between two versions
+    /// Versions are prefixes of the same leaves, so only the leaves past the smaller version
+    /// differ: added when going to the larger version, removed when going back from it
+    fn changes(&self, from: &RootVersion, to: &RootVersion) -> Vec<LeafChange> {
+        let (range, change) = if from.leaf_count <= to.leaf_count {
+            (from.leaf_count..to.leaf_count, ChangeKind::Added)
+        } else {
+            (to.leaf_count..from.leaf_count, ChangeKind::Removed)
+        };
+        range
+            .map(|index| LeafChange {
+                index,
+                name: self.leaves[index].name.clone(),
+                leaf_hash: self.leaves[index].leaf_hash.clone(),
+                change,
+            })
+            .collect()
+    }
+}
+
+/// Files staged for one upload so far
+#[derive(Default)]
+struct StagedParts {
+    files: HashMap<String, Vec<u8>>, // Contents received so far, by file name
+    updated_at: Option<Instant>,     // When the last part arrived
+}
+
+impl StagedParts {
+    fn size(&self) -> usize {
+        self.files.values().map(Vec::len).sum()
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    dataset: Arc<RwLock<Arc<Dataset>>>, // The current dataset, replaced on every change
+    update_lock: Arc<Mutex<()>>,        // Serializes changes to the dataset
+    completed_operations: Arc<Mutex<HashMap<String, CompletedOperation>>>, // By idempotency key
+    staged_parts: Arc<Mutex<HashMap<String, StagedParts>>>, // By upload id
+    storage: Arc<dyn StorageBackend>,   // Where uploaded files and metadata are written
+    proof_cache: Arc<ProofCache>,       // Proofs of recently requested files
+    mirror_stats: Arc<RwLock<MirrorStats>>, // Results of cross-verification against a peer
+    config: ServerConfig,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self::with_storage_dir(STORAGE_DIR)
+    }
+
+    /// An empty state that stores uploads in the given directory
+    pub fn with_storage_dir<P: Into<PathBuf>>(storage_dir: P) -> Self {
+        let storage_dir = storage_dir.into();
+        let storage = Arc::new(FileSystemBackend::new(&storage_dir));
+        Self::with_storage(
+            ServerConfig {
+                storage_dir,
+                ..ServerConfig::default()
+            },
+            storage,
+        )
+    }
+
+    /// An empty state with the given settings
+    /// Fails if the storage backend is not registered or cannot be created
+    pub fn with_config(config: ServerConfig) -> Result<Self, StorageError> {
+        let storage = create_backend(
+            &config.storage_backend,
+            &config.storage_dir.to_string_lossy(),
+        )?;
+        Ok(Self::with_storage(config, storage))
+    }
+
+    /// An empty state storing uploads in an already created backend
+    pub fn with_storage(config: ServerConfig, storage: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            dataset: Arc::new(RwLock::new(Arc::new(Dataset::default()))),
+            update_lock: Arc::new(Mutex::new(())),
+            completed_operations: Arc::new(Mutex::new(HashMap::new())),
+            staged_parts: Arc::new(Mutex::new(HashMap::new())),
+            storage,
+            proof_cache: Arc::new(ProofCache::new(config.proof_cache_capacity)),
+            mirror_stats: Arc::new(RwLock::new(MirrorStats::default())),
+            config,
+        }
+    }
+
+    /// Serves the files already in a directory, storing later uploads there too
+    /// A directory written by an earlier server keeps its file order and root history;
+    /// otherwise its files form the first version, in alphabetical order
+    pub async fn open<P: Into<PathBuf>>(storage_dir: P) -> Result<Self, StorageError> {
+        Self::open_with_config(ServerConfig {
+            storage_dir: storage_dir.into(),
+            ..ServerConfig::default()
+        })
+        .await
+    }
+
+    /// Like `open`, over the storage backend of the config
+    pub async fn open_with_config(config: ServerConfig) -> Result<Self, StorageError> {
+        let storage = create_backend(
+            &config.storage_backend,
+            &config.storage_dir.to_string_lossy(),
+        )?;
+        Self::open_with_storage(config, storage).await
+    }
+
+    /// Like `open`, over an already created backend
+    pub async fn open_with_storage(
+        config: ServerConfig,
+        storage: Arc<dyn StorageBackend>,
+    ) -> Result<Self, StorageError> {
+        let state = Self::with_storage(config, storage);
+        let stored = state.storage.list()?;
+
+        let leaves: Option<Vec<LeafEntry>> = if stored.iter().any(|name| name == LEAF_ORDER_FILE) {
+            Some(decode_json(
+                LEAF_ORDER_FILE,
+                &state.storage.read(LEAF_ORDER_FILE)?,
+            )?)
+        } else {
+            None
+        };
+        let names: Vec<String> = match &leaves {
+            Some(leaves) => leaves.iter().map(|leaf| leaf.name.clone()).collect(),
+            None => stored
+                .iter()
+                .filter(|name| !is_reserved_name(name))
+                .cloned()
+                .collect(),
+        };
+
+        let mut files = Vec::new();
+        for name in names {
+            let content = state.storage.read(&name)?;
+            files.push(FileData { name, content });
+        }
+        if files.is_empty() {
+            return Ok(state);
+        }
+        // The persisted tree saves rebuilding it, if it is still over the same leaves
+        let version = match leaves.and_then(|leaves| stored_tree(&state, &leaves)) {
+            Some(merkle_tree) => state.commit_files(files, merkle_tree).await,
+            None => state.insert_files(files).await,
+        }
+        .map_err(|e| StorageError::Io(io::Error::other(e)))?;
+
+        // Earlier versions are kept only if they lead up to the current files
+        match state.storage.read(ROOT_HISTORY_FILE) {
+            Ok(data) => {
+                let history: Vec<RootVersion> = decode_json(ROOT_HISTORY_FILE, &data)?;
+                match history.last() {
+                    Some(latest) if ct_eq(&latest.root_hash, &version.root_hash) => {
+                        let mut dataset = (*state.dataset()).clone();
+                        dataset.root_history = history;
+                        state.publish(dataset);
+                    }
+                    _ => eprintln!(
+                        "Root history does not match the stored files, starting a new one"
+                    ),
+                }
+            }
+            Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(state)
+    }
+
+    /// Finds the result of an earlier attempt of the operation with the same key
+    /// Results older than the idempotency window are forgotten first
+    async fn completed_operation(
+        &self,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<serde_json::Value>, Rejection> {
+        let mut operations = self.completed_operations.lock().await;
+        let window = self.config.idempotency_window;
+        operations.retain(|_, operation| operation.completed_at.elapsed() < window);
+        match operations.get(key) {
+            Some(operation) if operation.fingerprint != fingerprint => {
+                Err(warp::reject::custom(ProtocolError::IdempotencyKeyReused))
+            }
+            Some(operation) => Ok(Some(operation.response.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Remembers the result of an operation for retries with the same key
+    async fn record_operation(
+        &self,
+        key: String,
+        fingerprint: String,
+        response: serde_json::Value,
+    ) {
+        self.completed_operations.lock().await.insert(
+            key,
+            CompletedOperation {
+                completed_at: Instant::now(),
+                fingerprint,
+                response,
+            },
+        );
+    }
+
+    /// The current dataset
+    /// The lock is only held to clone the `Arc`, so readers never wait for an update to finish
+    pub fn dataset(&self) -> Arc<Dataset> {
+        self.dataset.read().expect("Dataset lock poisoned").clone()
+    }
+
+    /// Makes the dataset the current one
+    /// Callers hold `update_lock`, so no concurrent update is lost
+    fn publish(&self, mut dataset: Dataset) {
+        dataset.root_log = (!dataset.root_history.is_empty()).then(|| {
+            let roots: Vec<String> = dataset
+                .root_history
+                .iter()
+                .map(|version| version.root_hash.clone())
+                .collect();
+            let mut log = MerkleTree::new();
+            log.build(&roots);
+            Arc::new(log)
+        });
+        self.proof_cache
+            .retain_roots(dataset.root_history.iter().map(|v| v.root_hash.as_str()));
+        *self.dataset.write().expect("Dataset lock poisoned") = Arc::new(dataset);
+    }
+
+    /// The proof of the file at the index against the latest root of the dataset, or against a
+    /// historical version of it, along with that root
+    /// Historical proofs are taken from the latest tree instead of rebuilding the old one
+    pub(crate) async fn file_proof(
+        &self,
+        dataset: &Dataset,
+        file_index: usize,
+        version: Option<&RootVersion>,
+    ) -> Option<(MerkleProof, String)> {
+        let root_hash = match version {
+            Some(version) if file_index >= version.leaf_count => return None,
+            Some(version) => version.root_hash.clone(),
+            None => dataset.root_hash()?,
+        };
+        if let Some(proof) = self.proof_cache.get(&root_hash, file_index) {
+            return Some((proof, root_hash));
+        }
+
+        // Versions are prefixes of the dataset, so the latest tree proves against all of them
+        let tree = dataset.merkle_tree.as_ref()?;
+        let proof = match version {
+            Some(version) => tree.get_merkle_proof_at(file_index, version.leaf_count),
+            None => tree.get_merkle_proof(file_index),
+        }
+        .ok()?;
+        self.proof_cache
+            .insert(&root_hash, file_index, proof.clone());
+        Some((proof, root_hash))
+    }
+
+    /// Results of cross-verification against the peer this server mirrors, see `mirror::spawn`
+    pub fn mirror_stats(&self) -> MirrorStats {
+        self.mirror_stats
+            .read()
+            .expect("Mirror stats lock poisoned")
+            .clone()
+    }
+
+    pub(crate) fn update_mirror_stats(&self, update: impl FnOnce(&mut MirrorStats)) {
+        update(
+            &mut self
+                .mirror_stats
+                .write()
+                .expect("Mirror stats lock poisoned"),
+        );
+    }
+
+    /// Counters of the proof cache
+    pub fn proof_cache_stats(&self) -> ProofCacheStats {
+        self.proof_cache.stats()
+    }
+
+    /// Size of the dataset and the savings of a deduplicating storage backend
+    pub fn stats(&self) -> ServerStats {
+        let dataset = self.dataset();
+        ServerStats {
+            file_count: dataset.file_count(),
+            total_size: dataset.leaves().iter().map(|leaf| leaf.size).sum(),
+            tree_depth: dataset
+                .merkle_tree
+                .as_ref()
+                .map_or(0, |merkle_tree| merkle_tree.depth()),
+            dedup: self.storage.dedup_stats(),
+        }
+    }
+
+    /// The latest root hash, or `None` before the first upload
+    pub async fn root_hash(&self) -> Option<String> {
+        self.dataset().root_hash()
+    }
+
+    /// How many files the dataset holds
+    pub async fn file_count(&self) -> usize {
+        self.dataset().file_count()
+    }
+
+    /// Appends files to the in-memory store and rebuilds the tree over all files
+    /// Nothing is written to disk. Returns the new root version.
+    pub async fn insert_files(&self, files: Vec<FileData>) -> Result<RootVersion, MerkleError> {
+        let _guard = self.update_lock.lock().await;
+        let merkle_tree = self.build_extended_tree(&files).await?;
+        self.commit_files(files, merkle_tree).await
+    }
+
+    /// Ingests the files of a directory on the server as a new version, as if they were uploaded
+    /// Files are added in alphabetical order and stored like uploads; the import fails before
+    /// anything is stored if a file name is already in the dataset
+    pub async fn import_dir(&self, dir: &Path) -> Result<RootVersion, ProtocolError> {
+        let _guard = self.update_lock.lock().await;
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir).map_err(StorageError::from)? {
+            let entry = entry.map_err(StorageError::from)?;
+            if entry.file_type().map_err(StorageError::from)?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+
+        let dataset = self.dataset();
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| StorageError::InvalidName(path.display().to_string()))?
+                .to_string();
+            if is_reserved_name(&name) {
+                return Err(ProtocolError::ReservedName(name));
+            }
+            if dataset.file_index.contains_key(&name) {
+                return Err(ProtocolError::FileExists(name));
+            }
+            let content = fs::read(&path).map_err(StorageError::from)?;
+            files.push(FileData { name, content });
+        }
+        if files.is_empty() {
+            return Err(ProtocolError::NothingToImport(dir.display().to_string()));
+        }
+
+        for file in &files {
+            self.storage.write(&file.name, &file.content)?;
+        }
+        let merkle_tree = self.build_extended_tree(&files).await?;
+        let version = self.commit_files(files, merkle_tree).await?;
+        save_dataset_metadata(self).await;
+        Ok(version)
+    }
+
+    /// Builds the tree over all stored files followed by the new ones
+    async fn build_extended_tree(&self, files: &[FileData]) -> Result<MerkleTree, MerkleError> {
+        let mut contents = self.dataset().contents(None);
+        contents.extend(files.iter().map(|f| f.content.clone()));
+        let mut merkle_tree = MerkleTree::new();
+        merkle_tree.build_async(contents).await?;
+        Ok(merkle_tree)
+    }
+
+    /// Publishes a new dataset with the files appended, along with the tree already built over
+    /// all files
+    /// Records and returns the new root version
+    async fn commit_files(
+        &self,
+        files: Vec<FileData>,
+        merkle_tree: MerkleTree,
+    ) -> Result<RootVersion, MerkleError> {
+        let root_hash = merkle_tree.root().unwrap_or_else(empty_root);
+
+        let mut dataset = (*self.dataset()).clone();
+        for file in files {
+            dataset.leaves.push(LeafEntry::new(&file));
+            dataset
+                .file_index
+                .insert(file.name.clone(), dataset.files.len());
+            dataset.files.push(Arc::new(file));
+        }
+
+        let file_contents = dataset.contents(None);
+        let leaf_count = file_contents.len();
+        let sorted_tree =
+            tokio::task::spawn_blocking(move || SortedMerkleTree::build(&file_contents))
+                .await
+                .map_err(|_| MerkleError::BuildFailed)?;
+
+        let version = RootVersion {
+            version: dataset.root_history.len(),
+            root_hash,
+            leaf_count,
+        };
+        dataset.root_history.push(version.clone());
+        dataset.merkle_tree = Some(Arc::new(merkle_tree));
+        dataset.sorted_tree = Some(Arc::new(sorted_tree));
+        self.publish(dataset);
+        Ok(version)
+    }
+}
+
+/// Decodes a stored metadata file
+fn decode_json<T: serde::de::DeserializeOwned>(name: &str, data: &[u8]) -> Result<T, StorageError> {
+    serde_json::from_slice(data).map_err(|e| StorageError::Corrupt {
+        name: name.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Persists the root history and the leaf order next to the stored files
+async fn save_dataset_metadata(state: &AppState) {
+    let dataset = state.dataset();
+    save_json(state, ROOT_HISTORY_FILE, &dataset.root_history);
+    save_json(state, LEAF_ORDER_FILE, &dataset.leaves);
+    if let Some(merkle_tree) = &dataset.merkle_tree {
+        save_json(state, MERKLE_TREE_FILE, merkle_tree.as_ref());
+    }
+}
+
+/// The persisted Merkle tree, if there is one whose leaves are those of the leaf order
+fn stored_tree(state: &AppState, leaves: &[LeafEntry]) -> Option<MerkleTree> {
+    let data = state.storage.read(MERKLE_TREE_FILE).ok()?;
+    let merkle_tree: MerkleTree = match decode_json(MERKLE_TREE_FILE, &data) {
+        Ok(merkle_tree) => merkle_tree,
+        Err(e) => {
+            eprintln!("Ignoring the stored tree: {}", e);
+            return None;
+        }
+    };
+    let tree_leaves = merkle_tree.leaves();
+    let matches = merkle_tree.algorithm() == HashAlgorithm::Sha256
+        && tree_leaves.len() == leaves.len()
+        && tree_leaves
+            .zip(leaves)
+            .all(|(digest, leaf)| ct_eq(&hex::encode(digest), &leaf.leaf_hash));
+    matches.then_some(merkle_tree)
+}
+
+fn save_json<T: serde::Serialize>(state: &AppState, file_name: &str, value: &T) {
+    let result = serde_json::to_vec(value)
+        .map_err(|e| e.to_string())
+        .and_then(|data| {
+            state
+                .storage
+                .write(file_name, &data)
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        eprintln!("Failed to save {}: {}", file_name, e);
+    }
+}
+
+type ServeFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The full server, bound to its address, for embedding in another application
+/// Runs on the caller's Tokio runtime, for example with `tokio::spawn(server.run())`
+pub struct Server {
+    state: Arc<AppState>,
+    addr: SocketAddr,
+    serve: ServeFuture,
+}
+
+/// Settings of a server to embed, see `Server::builder`
+pub struct ServerBuilder {
+    config: ServerConfig,
+    storage: Option<Arc<dyn StorageBackend>>,
+    addr: SocketAddr,
+    shutdown: Option<ServeFuture>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: ServerConfig::default(),
+            storage: None,
+            addr: SocketAddr::from(DEFAULT_ADDR),
+            shutdown: None,
+        }
+    }
+
+    /// The address the server listens on, with the actual port if it was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The server's state, for reading or seeding the dataset in-process
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// Serves requests until the shutdown signal, or forever without one
+    pub async fn run(self) {
+        self.serve.await
+    }
+}
+
+impl ServerBuilder {
+    /// Settings of the server; its storage backend is not used if `storage` is set
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Stores files in an already created backend instead of the one named in the config
+    pub fn storage(mut self, storage: Arc<dyn StorageBackend>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Address to listen on, `DEFAULT_ADDR` if not set; port 0 picks a free port
+    pub fn bind(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    /// Stops the server gracefully once the signal completes
+    pub fn shutdown_signal(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Loads the files already in the storage, like `AppState::open`, and binds the address
+    pub async fn build(self) -> Result<Server, Box<dyn std::error::Error>> {
+        let state = Arc::new(match self.storage {
+            Some(storage) => AppState::open_with_storage(self.config, storage).await?,
+            None => AppState::open_with_config(self.config).await?,
+        });
+        let shutdown = self
+            .shutdown
+            .unwrap_or_else(|| Box::pin(std::future::pending()));
+        let (addr, serve) = warp::serve(routes(state.clone()))
+            .try_bind_with_graceful_shutdown(self.addr, shutdown)?;
+        Ok(Server {
+            state,
+            addr,
+            serve: Box::pin(serve),
+        })
+    }
+
+    /// Builds the server and serves requests until the shutdown signal
+    pub async fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let server = self.build().await?;
+        server.run().await;
+        Ok(())
+    }
+}
+
+/// All routes of the server
+pub fn routes(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // Route for uploading files
+    let upload_route = warp::post()
+        .and(warp::path("upload"))
+        .and(json_body())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_HEADER))
+        .and(with_state(state.clone())) // Ensure this matches the state filter
+        .and_then(
+            |request: UploadRequest, key: Option<String>, state: Arc<AppState>| async move {
+                upload_files(request, key, state).await
+            },
+        );
+
+    // Route for staging a piece of a large file ahead of its upload
+    let upload_part_route = warp::post()
+        .and(warp::path!("upload" / String / "part"))
+        .and(json_body())
+        .and(with_state(state.clone()))
+        .and_then(upload_part);
+
+    // Route for ingesting a directory already on the server
+    let import_route = warp::post()
+        .and(warp::path("import"))
+        .and(json_body())
+        .and(with_state(state.clone()))
+        .and_then(import_files);
+
+    // Route for deleting all files and state
+    let delete_route = warp::delete()
+        .and(warp::path("delete_all"))
+        .and(warp::header::optional::<String>(IDEMPOTENCY_HEADER))
+        .and(with_state(state.clone()))
+        .and_then(delete_all);
+
+    // Routes for syncing an encrypted client state between devices
+    let push_state_route = warp::put()
+        .and(warp::path!("state" / String))
+        .and(json_body())
+        .and(with_state(state.clone()))
+        .and_then(push_state);
+    let pull_state_route = warp::get()
+        .and(warp::path!("state" / String))
+        .and(with_state(state.clone()))
+        .and_then(pull_state);
+
+    // Routes for the dataset and storage stats, the proof cache counters and the mirror's
+    // cross-verification results
+    let stats_route = warp::get()
+        .and(warp::path!("stats"))
+        .and(with_state(state.clone()))
+        .map(|state: Arc<AppState>| warp::reply::json(&state.stats()));
+    let metrics_route = warp::get()
+        .and(warp::path!("metrics" / "proof_cache"))
+        .and(with_state(state.clone()))
+        .map(|state: Arc<AppState>| warp::reply::json(&state.proof_cache_stats()));
+    let mirror_metrics_route = warp::get()
+        .and(warp::path!("metrics" / "mirror"))
+        .and(with_state(state.clone()))
+        .map(|state: Arc<AppState>| warp::reply::json(&state.mirror_stats()));
+
+    #[cfg(feature = "graphql")]
+    let graphql_route = crate::graphql::routes(state.clone());
+
+    let routes = upload_part_route
+        .or(upload_route)
+        .or(import_route)
+        .or(proof_routes(state))
+        .or(delete_route)
+        .or(push_state_route)
+        .or(pull_state_route)
+        .or(stats_route)
+        .or(metrics_route)
+        .or(mirror_metrics_route);
+    #[cfg(feature = "graphql")]
+    let routes = routes.or(graphql_route);
+    routes.with(warp::reply::with::header(
+        ACCEPT_ENCODING,
+        compression::accept_encoding(),
+    ))
+}
+
+/// Read-only routes serving files and their proofs
+/// Used on their own when a client shares its dataset with a peer
+pub fn proof_routes(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    // Route for verifying a file
+    let file_route = warp::get()
+        .and(warp::path!("file" / usize))
+        .and(warp::query::<FileQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_file_content);
+
+    // Route for the proof of a file without its contents
+    let file_proof_route = warp::get()
+        .and(warp::path!("file" / usize / "proof"))
+        .and(warp::query::<FileQuery>())
+        .and(warp::header::optional::<String>(ACCEPT.as_str()))
+        .and(with_state(state.clone()))
+        .and_then(get_file_proof);
+
+    // Route for the proof of a file by its leaf hash, for callers that know only the contents
+    let leaf_proof_route = warp::get()
+        .and(warp::path!("leaf" / String / "proof"))
+        .and(warp::query::<FileQuery>())
+        .and(warp::header::optional::<String>(ACCEPT.as_str()))
+        .and(with_state(state.clone()))
+        .and_then(get_leaf_proof);
+
+    // Route for downloading a single verified chunk of a file
+    let chunk_route = warp::get()
+        .and(warp::path!("file" / usize / "chunk" / usize))
+        .and(warp::query::<ChunkQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_file_chunk);
+
+    // Route for proving that a leaf hash was never uploaded
+    let absent_route = warp::get()
+        .and(warp::path!("absent" / String))
+        .and(with_state(state.clone()))
+        .and_then(get_non_membership_proof);
+
+    // Route for streaming the proofs of a range of leaves
+    let proofs_route = warp::get()
+        .and(warp::path!("proofs"))
+        .and(warp::query::<ProofRangeQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_proofs);
+
+    // Route for random samples of leaves, to check the availability of the dataset
+    let sample_route = warp::get()
+        .and(warp::path!("sample"))
+        .and(warp::query::<SampleQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_sample);
+
+    // Route for the leaves that changed between two roots
+    let diff_route = warp::get()
+        .and(warp::path!("diff"))
+        .and(warp::query::<DiffQuery>())
+        .and(with_state(state.clone()))
+        .and_then(get_diff);
+
+    // Routes for the transparency log of published roots
+    let log_route = warp::get()
+        .and(warp::path!("log"))
+        .and(with_state(state.clone()))
+        .and_then(get_log_head);
+    let log_entry_route = warp::get()
+        .and(warp::path!("log" / usize))
+        .and(with_state(state))
+        .and_then(get_log_entry);
+
+    file_route
+        .or(file_proof_route)
+        .or(leaf_proof_route)
+        .or(chunk_route)
+        .or(absent_route)
+        .or(proofs_route)
+        .or(sample_route)
+        .or(diff_route)
+        .or(log_route)
+        .or(log_entry_route)
+}
+
+/// A JSON request body, decompressed first if its `Content-Encoding` is one of `Encoding::ALL`
+fn json_body<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+{
+    warp::header::optional::<String>(CONTENT_ENCODING.as_str())
+        .and(warp::body::bytes())
+        .and_then(|encoding: Option<String>, body: Bytes| async move {
+            decode_json_body(encoding.as_deref(), &body).map_err(warp::reject::custom)
+        })
+}
+
+fn decode_json_body<T: DeserializeOwned>(
+    encoding: Option<&str>,
+    body: &[u8],
+) -> Result<T, ProtocolError> {
+    let decoded;
+    let json = match encoding.map(str::trim) {
+        None | Some("identity") => body,
+        Some(name) => {
+            let encoding = Encoding::from_name(name)
+                .ok_or_else(|| ProtocolError::UnsupportedEncoding(name.to_string()))?;
+            decoded = compression::decompress(encoding, body, MAX_DECODED_BODY)
+                .map_err(|e| ProtocolError::InvalidBody(e.to_string()))?;
+            &decoded
+        }
+    };
+    serde_json::from_slice(json).map_err(|e| ProtocolError::InvalidBody(e.to_string()))
+}
+
+fn with_state(
+    state: Arc<AppState>,
+) -> impl Filter<Extract = (Arc<AppState>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+/// Uploads files to the server and updates the Merkle tree
+/// A retry carrying the idempotency key of a completed upload gets the original response
+async fn upload_files(
+    request: UploadRequest,
+    idempotency_key: Option<String>,
+    state: Arc<AppState>,
+) -> Result<impl Reply, Rejection> {
+    let _guard = state.update_lock.lock().await;
+
+    let operation = idempotency_key.map(|key| {
+        let request = serde_json::to_string(&request).expect("Requests always serialize");
+        (format!("upload:{}", key), calculate_hash(&request))
+    });
+    if let Some((key, fingerprint)) = &operation {
+        if let Some(response) = state.completed_operation(key, fingerprint).await? {
+            return Ok(warp::reply::json(&response));
+        }
+    }
+
+    // Files sent ahead in parts get their contents from the staged parts
+    let mut request = request;
+    if let Some(upload_id) = &request.staged {
+        if let Some(parts) = state.staged_parts.lock().await.get(upload_id) {
+            for file in request.files.iter_mut() {
+                if let Some(content) = parts.files.get(&file.name) {
+                    file.content = content.clone();
+                }
+            }
+        }
+    }
+
+    // The client must have seen the latest version, and may only append to the existing leaves
+    let dataset = state.dataset();
+    let latest_version = dataset.root_history.last().map(|v| v.version);
+    if request.base_version != latest_version {
+        return Err(warp::reject::custom(ProtocolError::StaleBase {
+            base: request.base_version,
+            latest: latest_version,
+        }));
+    }
+    // Every received file must hash to the leaf hash the client declared for it
+    let declared = request
+        .leaves
+        .get(dataset.leaves.len()..)
+        .unwrap_or_default();
+    check_leaf_hashes(declared, &request.files).map_err(warp::reject::custom)?;
+    check_leaf_order(&dataset.leaves, &request.leaves, &request.files)
+        .map_err(warp::reject::custom)?;
+
+    // Build the tree first so the client's declared root can be validated before anything is stored
+    // The new files are appended to the existing ones, so the root covers the whole dataset
+    let merkle_tree = state
+        .build_extended_tree(&request.files)
+        .await
+        .map_err(|e| warp::reject::custom(ProtocolError::from(e)))?;
+    let expected_root = merkle_tree.root().unwrap_or_else(empty_root);
+
+    if !ct_eq(&expected_root, &request.root_hash) {
+        return Err(warp::reject::custom(ProtocolError::RootMismatch));
+    }
+
+    for file in &request.files {
+        if let Err(e) = state.storage.write(&file.name, &file.content) {
+            eprintln!("Failed to write file {}: {}", file.name, e);
+            return Err(warp::reject::custom(ProtocolError::from(e)));
+        }
+        println!("Stored file {:?}", file.name);
+    }
+
+    let version = state
+        .commit_files(request.files, merkle_tree)
+        .await
+        .map_err(|e| warp::reject::custom(ProtocolError::from(e)))?;
+    save_dataset_metadata(&state).await;
+    if let Some(upload_id) = &request.staged {
+        state.staged_parts.lock().await.remove(upload_id);
+    }
+
+    for (index, file) in state.dataset().files.iter().enumerate() {
+        println!("Index {}: {} ({})", index, file.name, file.content.len());
+    }
+
+    let response = serde_json::to_value(UploadResponse {
+        message: "Files uploaded successfully".to_string(),
+        root_hash: version.root_hash,
+        version: version.version,
+    })
+    .expect("Responses always serialize");
+    if let Some((key, fingerprint)) = operation {
+        state
+            .record_operation(key, fingerprint, response.clone())
+            .await;
+    }
+    Ok(warp::reply::json(&response))
+}
+
+/// Stages a piece of a file for the upload with the id
+/// Pieces must arrive in order; a piece that was already received is acknowledged again.
+/// Uploads that received no part within the configured time are dropped first, and a piece
+/// that would exceed the staging limits is refused with 413 Payload Too Large.
+async fn upload_part(
+    upload_id: String,
+    part: UploadPart,
+    state: Arc<AppState>,
+) -> Result<impl Reply, Rejection> {
+    let config = &state.config;
+    let mut staged_parts = state.staged_parts.lock().await;
+    // Uploads refused before their first part have no time and are dropped as well
+    staged_parts.retain(|_, parts| {
+        parts
+            .updated_at
+            .is_some_and(|at| at.elapsed() < config.staged_upload_ttl)
+    });
+    if !staged_parts.contains_key(&upload_id) && staged_parts.len() >= config.max_staged_uploads {
+        return Ok(payload_too_large(ProtocolError::StagedUploadsLimit(
+            config.max_staged_uploads,
+        )));
+    }
+
+    let staged_bytes: usize = staged_parts.values().map(StagedParts::size).sum();
+    let parts = staged_parts.entry(upload_id).or_default();
+    let content = parts.files.entry(part.name.clone()).or_default();
+    let end = part.offset + part.content.len();
+    let received = content.get(part.offset..end) == Some(part.content.as_slice());
+    if !received {
+        if part.offset != content.len() {
+            return Err(warp::reject::custom(ProtocolError::PartOutOfOrder {
+                name: part.name,
+                offset: part.offset,
+                received: content.len(),
+            }));
+        }
+        if staged_bytes + part.content.len() > config.max_staged_bytes {
+            return Ok(payload_too_large(ProtocolError::StagedBytesLimit(
+                config.max_staged_bytes,
+            )));
+        }
+        content.extend_from_slice(&part.content);
+    }
+    let received = content.len();
+    parts.updated_at = Some(Instant::now());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&UploadPartResponse { received }),
+        StatusCode::OK,
+    ))
+}
+
+/// A 413 Payload Too Large response carrying the error
+fn payload_too_large(error: ProtocolError) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&json!({ "message": error.to_string() })),
+        StatusCode::PAYLOAD_TOO_LARGE,
+    )
+}
+
+/// Ingests a directory under the configured import root as a new version
+async fn import_files(
+    request: ImportRequest,
+    state: Arc<AppState>,
+) -> Result<impl Reply, Rejection> {
+    let import_root = state
+        .config
+        .import_root
+        .as_ref()
+        .ok_or_else(|| warp::reject::custom(ProtocolError::ImportsDisabled))?;
+    // Only plain relative paths, so an import cannot leave the import root
+    let path = Path::new(&request.path);
+    if !path
+        .components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)))
+    {
+        return Err(warp::reject::custom(ProtocolError::InvalidImportPath(
+            request.path,
+        )));
+    }
+
+    let version = state
+        .import_dir(&import_root.join(path))
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&UploadResponse {
+        message: format!("Imported {}", request.path),
+        root_hash: version.root_hash,
+        version: version.version,
+    }))
+}
+
+/// Verifies a file by its index. Sends a verification object as a response
+/// A historical root can be requested by version or by root hash; the proof for it is derived
+/// from the latest tree with `get_merkle_proof_at`, as the dataset only grows
+async fn get_file_content(
+    file_index: usize,
+    query: FileQuery,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    println!(
+        "Received verification request for file index: {}",
+        file_index
+    );
+
+    // The file, its proof and the root all come from the same dataset
+    let dataset = state.dataset();
+    let (proof, root_hash) = requested_file_proof(&state, &dataset, file_index, &query).await?;
+    let file = &dataset.files[file_index];
+
+    Ok(warp::reply::json(&FileResponse {
+        name: file.name.clone(),
+        content: file.content.clone(),
+        proof,
+        root: root_hash,
+    }))
+}
+
+/// Sends the proof of a file without its contents, with the root it is valid for in the
+/// `Merkle-Root` header
+/// The proof is JSON unless the `Accept` header asks for `PROOF_MEDIA_TYPE`, the compact binary
+/// encoding.
+async fn get_file_proof(
+    file_index: usize,
+    query: FileQuery,
+    accept: Option<String>,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let dataset = state.dataset();
+    let (proof, root_hash) = requested_file_proof(&state, &dataset, file_index, &query).await?;
+
+    let binary = accept.is_some_and(|accept| {
+        accept
+            .split(',')
+            .any(|media_type| media_type.split(';').next().map(str::trim) == Some(PROOF_MEDIA_TYPE))
+    });
+    let (media_type, body) = match proof.to_bytes() {
+        Some(bytes) if binary => (PROOF_MEDIA_TYPE, bytes),
+        _ => (
+            "application/json",
+            serde_json::to_vec(&proof).expect("Proofs always serialize"),
+        ),
+    };
+    let response = warp::http::Response::builder()
+        .header(CONTENT_TYPE, media_type)
+        .header(ROOT_HEADER, root_hash)
+        .body(body)
+        .expect("Headers are always valid");
+    Ok(response)
+}
+
+/// Sends the proof of the first file with the leaf hash, like `get_file_proof`
+/// The proof carries the index of the file.
+async fn get_leaf_proof(
+    leaf_hash: String,
+    query: FileQuery,
+    accept: Option<String>,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let file_index = state
+        .dataset()
+        .merkle_tree
+        .as_ref()
+        .and_then(|tree| tree.find_leaf_hash(&leaf_hash))
+        .ok_or_else(|| warp::reject::custom(ProtocolError::LeafNotFound(leaf_hash.clone())))?;
+    get_file_proof(file_index, query, accept, state).await
+}
+
+/// The proof of a file against the root the query asks for, along with that root
+async fn requested_file_proof(
+    state: &AppState,
+    dataset: &Dataset,
+    file_index: usize,
+    query: &FileQuery,
+) -> Result<(MerkleProof, String), Rejection> {
+    let historical_version = dataset.requested_version(query)?;
+    if let Some(version) = &historical_version {
+        if file_index >= version.leaf_count {
+            return Err(warp::reject::custom(ProtocolError::FileNotInVersion {
+                index: file_index,
+                version: version.version,
+            }));
+        }
+    }
+    if file_index >= dataset.files.len() {
+        return Err(warp::reject::custom(ProtocolError::FileNotFound(
+            file_index,
+        )));
+    }
+
+    state
+        .file_proof(dataset, file_index, historical_version.as_ref())
+        .await
+        .ok_or(warp::reject::not_found())
+}
+
+/// Sends a single chunk of a file along with its proof against the file's chunk root
+async fn get_file_chunk(
+    file_index: usize,
+    chunk_index: usize,
+    query: ChunkQuery,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let dataset = state.dataset();
+
+    let file = dataset
+        .files
+        .get(file_index)
+        .ok_or_else(|| warp::reject::custom(ProtocolError::FileNotFound(file_index)))?;
+
+    let chunk_size = query.size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    let chunks = chunking::split_chunks(&file.content, chunk_size);
+    let chunk = chunks.get(chunk_index).ok_or_else(|| {
+        warp::reject::custom(ProtocolError::ChunkNotFound {
+            chunk: chunk_index,
+            index: file_index,
+        })
+    })?;
+
+    let tree = chunking::chunk_tree(&file.content, chunk_size);
+    let proof = tree
+        .get_merkle_proof(chunk_index)
+        .map_err(|e| warp::reject::custom(ProtocolError::from(e)))?;
+
+    Ok(warp::reply::json(&ChunkResponse {
+        name: file.name.clone(),
+        chunk_count: chunks.len(),
+        content: chunk.to_vec(),
+        proof,
+    }))
+}
+
+/// Sends a proof that no uploaded file has the given leaf hash
+/// The proof is valid against the root of the tree over leaves sorted by hash
+async fn get_non_membership_proof(
+    leaf_hash: String,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let dataset = state.dataset();
+    let tree = dataset
+        .sorted_tree
+        .as_ref()
+        .ok_or(warp::reject::not_found())?;
+
+    let proof = tree
+        .prove_non_membership(&leaf_hash)
+        .ok_or_else(|| warp::reject::custom(ProtocolError::LeafPresent(leaf_hash.clone())))?;
+
+    Ok(warp::reply::json(&AbsentResponse {
+        sorted_root: tree.root(),
+        proof,
+    }))
+}
+
+/// Streams the proofs of a range of leaves against the latest root as newline-delimited JSON
+/// Proofs are generated in small batches while the response is sent, so a request for millions
+/// of leaves never holds more than one batch in memory. The root is sent in the `Merkle-Root`
+/// header, which is left out while the dataset is empty; the stream keeps using the dataset it
+/// started with, so uploads made meanwhile do not affect it.
+async fn get_proofs(
+    query: ProofRangeQuery,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let dataset = state.dataset();
+    let root = dataset.root_hash();
+
+    let leaf_count = dataset.leaves.len();
+    let start = query.start.unwrap_or(0);
+    let end = query.end.unwrap_or(leaf_count);
+    if start > end || end > leaf_count {
+        return Err(warp::reject::custom(ProtocolError::InvalidRange {
+            start,
+            end,
+            leaf_count,
+        }));
+    }
+
+    let batches = stream::unfold(start, move |next| {
+        let dataset = dataset.clone();
+        async move {
+            if next >= end {
+                return None;
+            }
+            let batch_end = (next + PROOF_STREAM_BATCH).min(end);
+            let lines = proof_lines(&dataset, next..batch_end);
+            Some((Ok::<_, std::convert::Infallible>(lines), batch_end))
+        }
+    });
+
+    let mut response = warp::http::Response::builder().header(CONTENT_TYPE, "application/x-ndjson");
+    if let Some(root) = root {
+        response = response.header(ROOT_HEADER, root);
+    }
+    let response = response
+        .body(warp::hyper::Body::wrap_stream(batches))
+        .expect("Headers are always valid");
+    Ok(response)
+}
+
+/// Newline-delimited proofs of the leaves in the range
+fn proof_lines(dataset: &Dataset, range: std::ops::Range<usize>) -> Vec<u8> {
+    let tree = dataset
+        .merkle_tree
+        .as_ref()
+        .expect("Datasets with leaves have a tree");
+    let mut lines = Vec::new();
+    for index in range {
+        let line = LeafProof {
+            index,
+            name: dataset.leaves[index].name.clone(),
+            leaf_hash: dataset.leaves[index].leaf_hash.clone(),
+            proof: tree
+                .get_merkle_proof(index)
+                .expect("Every leaf has a proof"),
+        };
+        serde_json::to_writer(&mut lines, &line).expect("Proofs always serialize");
+        lines.push(b'\n');
+    }
+    lines
+}
+
+/// Sends the leaves a seed selects, with their contents and proofs against the latest root
+/// Clients pick the seed and recompute the selection, so answering needs the sampled contents
+/// themselves: a server missing a fraction of the dataset fails a sample with high probability.
+async fn get_sample(
+    query: SampleQuery,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, Rejection> {
+    if query.n > MAX_SAMPLE_SIZE {
+        return Err(warp::reject::custom(ProtocolError::SampleTooLarge(
+            MAX_SAMPLE_SIZE,
+        )));
+    }
+    let dataset = state.dataset();
+    let root = dataset.root_hash().ok_or(warp::reject::not_found())?;
+
+    let indices = sample_indices(&query.seed, query.n, dataset.leaves.len());
+    let samples = indices
+        .iter()
+        .map(|&index| SampledFile {
+            index,
+            name: dataset.files[index].name.clone(),
+            content: dataset.files[index].content.clone(),
+        })
+        .collect();
+    let proof = dataset
+        .merkle_tree
+        .as_ref()
+        .and_then(|tree| tree.get_multi_proof(&indices));
+
+    Ok(warp::reply::json(&SampleResponse {
+        root,
+        leaf_count: dataset.leaves.len(),
+        samples,
+        proof,
+    }))
+}
+
+/// Lists the leaves that differ between two historical roots, without their contents
+async fn get_diff(query: DiffQuery, state: Arc<AppState>) -> Result<impl warp::Reply, Rejection> {
+    let dataset = state.dataset();
+    let from = dataset.version_of_root(&query.from)?;
+    let to = dataset.version_of_root(&query.to)?;
+    let changes = dataset.changes(&from, &to);
+    Ok(warp::reply::json(&DiffResponse { from, to, changes }))
+}
+
+/// Sends the head of the transparency log of published roots
+async fn get_log_head(state: Arc<AppState>) -> Result<impl warp::Reply, Rejection> {
+    let head = state
+        .dataset()
+        .log_head()
+        .ok_or(warp::reject::not_found())?;
+    Ok(warp::reply::json(&head))
+}
+
+/// Sends the root of a version along with its proof of inclusion in the transparency log
+async fn get_log_entry(
+    version: usize,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, Rejection> {
+    let proof = state
+        .dataset()
+        .log_entry_proof(version)
+        .ok_or_else(|| warp::reject::custom(ProtocolError::VersionNotAvailable(version)))?;
+    Ok(warp::reply::json(&proof))
+}
+
+/// Storage name of a synced state, rejecting ids that are not a hex SHA-256 digest
+fn state_sync_name(id: &str) -> Result<String, Rejection> {
+    if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(warp::reject::custom(ProtocolError::InvalidStateId));
+    }
+    Ok(format!("{}{}", STATE_SYNC_PREFIX, id.to_ascii_lowercase()))
+}
+
+/// Stores an encrypted client state under its id, replacing any earlier one
+/// The server cannot read the state; it only keeps the blob for the client's other devices
+async fn push_state(
+    id: String,
+    blob: StateBlob,
+    state: Arc<AppState>,
+) -> Result<impl Reply, Rejection> {
+    let name = state_sync_name(&id)?;
+    if let Err(e) = state.storage.write(&name, blob.blob.as_bytes()) {
+        return Err(warp::reject::custom(ProtocolError::from(e)));
+    }
+    Ok(warp::reply::json(&json!({
+        "message": "State stored successfully"
+    })))
+}
+
+/// Returns the encrypted client state stored under the id
+async fn pull_state(id: String, state: Arc<AppState>) -> Result<impl Reply, Rejection> {
+    let name = state_sync_name(&id)?;
+    let data = state
+        .storage
+        .read(&name)
+        .map_err(|_| warp::reject::not_found())?;
+    let blob = String::from_utf8(data).map_err(|_| warp::reject::not_found())?;
+    Ok(warp::reply::json(&StateBlob { blob }))
+}
+
+/// Clears the storage and writes the synced client states back
+/// They are not part of the dataset, so deleting it must not drop the clients' states.
+fn clear_keeping_synced_states(state: &AppState) -> Result<(), StorageError> {
+    let mut synced = Vec::new();
+    for name in state.storage.list()? {
+        if name.starts_with(STATE_SYNC_PREFIX) {
+            let data = state.storage.read(&name)?;
+            synced.push((name, data));
+        }
+    }
+    state.storage.clear()?;
+    for (name, data) in synced {
+        state.storage.write(&name, &data)?;
+    }
+    Ok(())
+}
+
+/// Deletes all files and state from the server
+/// A retry carrying the idempotency key of a completed delete does not delete anything again
+async fn delete_all(
+    idempotency_key: Option<String>,
+    state: Arc<AppState>,
+) -> Result<impl Reply, Rejection> {
+    let _guard = state.update_lock.lock().await;
+
+    let key = idempotency_key.map(|key| format!("delete_all:{}", key));
+    if let Some(key) = &key {
+        if let Some(response) = state.completed_operation(key, "").await? {
+            return Ok(warp::reply::json(&response));
+        }
+    }
+
+    // Delete all stored files, keeping the synced client states
+    if let Err(e) = clear_keeping_synced_states(&state) {
+        eprintln!("Failed to clear storage: {}", e);
+        return Err(warp::reject::custom(ProtocolError::from(e)));
+    }
+
+    // Only once the files are gone, replace the dataset with an empty one; requests already
+    // reading it are unaffected
+    state.publish(Dataset::default());
+
+    let response = json!({
+        "message": "All files and state have been deleted"
+    });
+    if let Some(key) = key {
+        state
+            .record_operation(key, String::new(), response.clone())
+            .await;
+    }
+    Ok(warp::reply::json(&response))
+}
@@ -0,0 +1,191 @@
+//! Trees over leaves sorted by hash, with proofs of non-membership
+//! Because the leaves are ordered, a value that is not in the tree falls between two adjacent
+//! leaves. Proving both neighbours (and that they are adjacent) proves the value is absent.
+//!
+//! The leaves always include two sentinels, the smallest and the largest possible hash, so every
+//! absent value has a neighbour on both sides.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MerkleError;
+use crate::hashing::{ct_eq, HashAlgorithm, TreeHasher};
+use crate::merkle_tree::{leaf_hash, MerkleProof, MerkleTree};
+
+const MIN_SENTINEL: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const MAX_SENTINEL: &str = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+
+#[derive(Debug)]
+pub struct SortedMerkleTree {
+    tree: MerkleTree,
+    /// Sorted, deduplicated leaf hashes including the sentinels
+    leaves: Vec<String>,
+}
+
+/// A leaf next to an absent value, with its proof
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NeighbourLeaf {
+    pub hash: String,
+    pub proof: MerkleProof,
+}
+
+/// Proof that a leaf hash is not in the tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NonMembershipProof {
+    pub lower: NeighbourLeaf,
+    pub upper: NeighbourLeaf,
+}
+
+impl SortedMerkleTree {
+    /// Builds the tree over the contents, ordered by their leaf hashes
+    pub fn build<T: AsRef<[u8]>>(elements: &[T]) -> Self {
+        Self::from_leaf_hashes(elements.iter().map(leaf_hash).collect())
+            .expect("Hashed contents are valid leaf hashes")
+    }
+
+    /// Builds the tree over already hashed leaves, in any order
+    pub fn from_leaf_hashes(mut leaves: Vec<String>) -> Result<Self, MerkleError> {
+        leaves.push(MIN_SENTINEL.to_string());
+        leaves.push(MAX_SENTINEL.to_string());
+        leaves.sort();
+        leaves.dedup();
+
+        let mut tree = MerkleTree::new();
+        tree.build_from_leaf_hashes(leaves.clone())?;
+        Ok(Self { tree, leaves })
+    }
+
+    pub fn root(&self) -> Option<String> {
+        self.tree.root()
+    }
+
+    /// Gets the Merkle proof of the given contents, if they are in the tree
+    pub fn prove_membership(&self, content: impl AsRef<[u8]>) -> Option<MerkleProof> {
+        let index = self.leaves.binary_search(&leaf_hash(content)).ok()?;
+        self.tree.get_merkle_proof(index).ok()
+    }
+
+    /// Gets a proof that the leaf hash is not in the tree
+    /// Returns `None` if the hash is in the tree, or is one of the sentinels
+    pub fn prove_non_membership(&self, leaf_hash: &str) -> Option<NonMembershipProof> {
+        let upper_index = self.leaves.binary_search(&leaf_hash.to_string()).err()?;
+        if upper_index == 0 || upper_index == self.leaves.len() {
+            return None;
+        }
+        let neighbour = |index: usize| -> Option<NeighbourLeaf> {
+            Some(NeighbourLeaf {
+                hash: self.leaves[index].clone(),
+                proof: self.tree.get_merkle_proof(index).ok()?,
+            })
+        };
+        Some(NonMembershipProof {
+            lower: neighbour(upper_index - 1)?,
+            upper: neighbour(upper_index)?,
+        })
+    }
+}
+
+impl NonMembershipProof {
+    /// Checks that the leaf hash lies strictly between two adjacent leaves of the tree with the given root
+    pub fn verify(&self, leaf_hash: &str, root: &str) -> bool {
+        let algorithm = HashAlgorithm::Sha256;
+        self.lower.hash.as_str() < leaf_hash
+            && leaf_hash < self.upper.hash.as_str()
+            && self.upper.proof.leaf_index == self.lower.proof.leaf_index + 1
+            && self.lower.proof.is_well_formed()
+            && self.upper.proof.is_well_formed()
+            && ct_eq(
+                &algorithm.root_from_leaf_hash(&self.lower.hash, &self.lower.proof),
+                root,
+            )
+            && ct_eq(
+                &algorithm.root_from_leaf_hash(&self.upper.hash, &self.upper.proof),
+                root,
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::root_from_proof;
+
+    fn elements() -> Vec<String> {
+        vec!["a", "b", "c", "d", "e"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn membership_proofs_verify() {
+        let tree = SortedMerkleTree::build(&elements());
+        let root = tree.root().unwrap();
+
+        for element in elements() {
+            let proof = tree.prove_membership(&element).unwrap();
+            assert_eq!(root_from_proof(&element, &proof), root);
+        }
+        assert!(tree.prove_membership("f").is_none());
+    }
+
+    #[test]
+    fn non_membership_proofs_verify() {
+        let tree = SortedMerkleTree::build(&elements());
+        let root = tree.root().unwrap();
+
+        for absent in ["f", "g", "hello", ""] {
+            let hash = leaf_hash(absent);
+            let proof = tree.prove_non_membership(&hash).unwrap();
+            assert!(proof.verify(&hash, &root));
+        }
+    }
+
+    #[test]
+    fn no_non_membership_proof_for_present_values() {
+        let tree = SortedMerkleTree::build(&elements());
+        assert!(tree.prove_non_membership(&leaf_hash("c")).is_none());
+    }
+
+    #[test]
+    fn non_membership_proof_rejects_other_values() {
+        let tree = SortedMerkleTree::build(&elements());
+        let root = tree.root().unwrap();
+
+        let hash = leaf_hash("f");
+        let proof = tree.prove_non_membership(&hash).unwrap();
+        assert!(!proof.verify(&leaf_hash("c"), &root));
+        assert!(!proof.verify(&hash, &leaf_hash("root")));
+    }
+
+    #[test]
+    fn non_adjacent_neighbours_are_rejected() {
+        let tree = SortedMerkleTree::build(&elements());
+        let root = tree.root().unwrap();
+
+        // Skip over a present leaf by widening the bracket
+        let hash = leaf_hash("x");
+        let mut proof = tree.prove_non_membership(&hash).unwrap();
+        let wider = proof.upper.proof.leaf_index + 1;
+        proof.upper = NeighbourLeaf {
+            hash: tree.leaves[wider].clone(),
+            proof: tree.tree.get_merkle_proof(wider).unwrap(),
+        };
+        assert!(!proof.verify(&hash, &root));
+
+        // Nor can the upper neighbour claim to be next to the lower one
+        proof.upper.proof.leaf_index -= 1;
+        assert!(!proof.verify(&hash, &root));
+    }
+
+    #[test]
+    fn empty_tree_proves_everything_absent() {
+        let tree = SortedMerkleTree::build::<String>(&[]);
+        let root = tree.root().unwrap();
+        let hash = leaf_hash("a");
+        assert!(tree
+            .prove_non_membership(&hash)
+            .unwrap()
+            .verify(&hash, &root));
+    }
+}
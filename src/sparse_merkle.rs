@@ -0,0 +1,273 @@
+//! Sparse Merkle trees over file names
+//! A tree with a leaf for every possible 256-bit path, where a file sits at the SHA-256 hash of
+//! its name and every other leaf is empty. A name that is not in the tree leads to an empty leaf,
+//! so its absence is proven with the same siblings as a presence would be.
+//!
+//! A file's leaf is `H(LEAF_PREFIX || path || H(content))` and an empty leaf is 32 zero bytes.
+//! Nodes are `H(NODE_PREFIX || left || right)`, so the hash of an empty subtree depends only on
+//! its height. Proofs leave those out: a bitmap marks the levels whose sibling is not empty.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::hashing::{ct_eq, decode_hash, Hash, LEAF_PREFIX, NODE_PREFIX};
+
+/// Number of levels below the root, one per bit of a path
+const DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree {
+    /// Leaf hashes by path
+    leaves: BTreeMap<Hash, Hash>,
+}
+
+/// Proof of the leaf at the path of a key, whether a file or empty
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SparseProof {
+    /// One bit per level from the leaf up, least significant bit first; set if the sibling at
+    /// that level is not an empty subtree
+    pub bitmap: Vec<u8>,
+    /// Hashes of the siblings that are not empty, from the leaf up
+    pub siblings: Vec<String>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Sets the contents of the file with the name, returning whether it was already present
+    pub fn insert(&mut self, name: &str, content: &[u8]) -> bool {
+        let path = key_path(name);
+        self.leaves
+            .insert(path, leaf_digest(&path, content))
+            .is_some()
+    }
+
+    /// Removes the file with the name, returning whether it was present
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.leaves.remove(&key_path(name)).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.leaves.contains_key(&key_path(name))
+    }
+
+    /// The root hash, which for an empty tree is the hash of an empty subtree of full depth
+    pub fn root(&self) -> String {
+        let leaves: Vec<(Hash, Hash)> = self.leaves.iter().map(|(p, l)| (*p, *l)).collect();
+        hex::encode(subtree(&empty_subtrees(), 0, &leaves))
+    }
+
+    /// Generates the proof of the leaf at the name's path, which proves the file's contents if it
+    /// is in the tree and its absence otherwise
+    pub fn prove(&self, name: &str) -> SparseProof {
+        let path = key_path(name);
+        let empty = empty_subtrees();
+        let leaves: Vec<(Hash, Hash)> = self.leaves.iter().map(|(p, l)| (*p, *l)).collect();
+
+        // Walk down from the root, keeping the sibling subtree of every level
+        let mut siblings = Vec::with_capacity(DEPTH);
+        let mut current = leaves.as_slice();
+        for depth in 0..DEPTH {
+            let split = current.partition_point(|(p, _)| !bit(p, depth));
+            let (left, right) = current.split_at(split);
+            let (next, sibling) = if bit(&path, depth) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            siblings.push(if sibling.is_empty() {
+                None
+            } else {
+                Some(subtree(&empty, depth + 1, sibling))
+            });
+            current = next;
+        }
+
+        let mut proof = SparseProof {
+            bitmap: vec![0; DEPTH / 8],
+            siblings: Vec::new(),
+        };
+        for (height, sibling) in siblings.into_iter().rev().enumerate() {
+            if let Some(sibling) = sibling {
+                proof.bitmap[height / 8] |= 1 << (height % 8);
+                proof.siblings.push(hex::encode(sibling));
+            }
+        }
+        proof
+    }
+}
+
+impl SparseProof {
+    /// Checks that the file with the name has the contents in the tree with the root
+    pub fn verify_inclusion(&self, name: &str, content: &[u8], root: &str) -> bool {
+        let path = key_path(name);
+        self.root(&path, leaf_digest(&path, content))
+            .is_some_and(|computed| ct_eq(&computed, root))
+    }
+
+    /// Checks that no file with the name is in the tree with the root
+    pub fn verify_exclusion(&self, name: &str, root: &str) -> bool {
+        self.root(&key_path(name), [0; 32])
+            .is_some_and(|computed| ct_eq(&computed, root))
+    }
+
+    /// The root implied by the leaf at the path, or `None` if the proof is malformed
+    fn root(&self, path: &Hash, leaf: Hash) -> Option<String> {
+        if self.bitmap.len() != DEPTH / 8 {
+            return None;
+        }
+        let empty = empty_subtrees();
+        let mut siblings = self.siblings.iter();
+        let mut current = leaf;
+        for (height, empty_sibling) in empty.iter().enumerate().take(DEPTH) {
+            let sibling = if self.bitmap[height / 8] >> (height % 8) & 1 == 1 {
+                decode_hash(siblings.next()?)?
+            } else {
+                *empty_sibling
+            };
+            current = if bit(path, DEPTH - 1 - height) {
+                node_digest(&sibling, &current)
+            } else {
+                node_digest(&current, &sibling)
+            };
+        }
+        // Every sibling must be used
+        siblings.next().is_none().then(|| hex::encode(current))
+    }
+}
+
+fn key_path(name: &str) -> Hash {
+    Sha256::digest(name.as_bytes()).into()
+}
+
+fn leaf_digest(path: &Hash, content: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(path);
+    hasher.update(Sha256::digest(content));
+    hasher.finalize().into()
+}
+
+fn node_digest(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hashes of empty subtrees by height, from an empty leaf up to an empty tree
+fn empty_subtrees() -> Vec<Hash> {
+    let mut empty = vec![[0; 32]];
+    for height in 0..DEPTH {
+        let below = empty[height];
+        empty.push(node_digest(&below, &below));
+    }
+    empty
+}
+
+/// The hash of the subtree at the depth holding the leaves, which share its path so far
+fn subtree(empty: &[Hash], depth: usize, leaves: &[(Hash, Hash)]) -> Hash {
+    if leaves.is_empty() {
+        return empty[DEPTH - depth];
+    }
+    if depth == DEPTH {
+        return leaves[0].1;
+    }
+    let split = leaves.partition_point(|(path, _)| !bit(path, depth));
+    let (left, right) = leaves.split_at(split);
+    node_digest(
+        &subtree(empty, depth + 1, left),
+        &subtree(empty, depth + 1, right),
+    )
+}
+
+/// Bit `index` of the path, counting from the most significant bit
+fn bit(path: &Hash, index: usize) -> bool {
+    path[index / 8] >> (7 - index % 8) & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn tree() -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            tree.insert(name, name.as_bytes());
+        }
+        tree
+    }
+
+    #[test]
+    fn files_are_proven_present() {
+        let tree = tree();
+        let root = tree.root();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let proof = tree.prove(name);
+            assert!(proof.verify_inclusion(name, name.as_bytes(), &root));
+            assert!(!proof.verify_inclusion(name, b"other", &root));
+            assert!(!proof.verify_exclusion(name, &root));
+        }
+    }
+
+    #[test]
+    fn other_files_are_proven_absent() {
+        let tree = tree();
+        let root = tree.root();
+        for name in ["d.txt", "a.txt.bak", ""] {
+            let proof = tree.prove(name);
+            assert!(proof.verify_exclusion(name, &root));
+            assert!(!proof.verify_inclusion(name, b"", &root));
+        }
+
+        let empty = SparseMerkleTree::new();
+        assert!(empty
+            .prove("a.txt")
+            .verify_exclusion("a.txt", &empty.root()));
+    }
+
+    #[test]
+    fn roots_follow_the_files_and_not_their_order() {
+        let mut tree = tree();
+        let mut reversed = SparseMerkleTree::new();
+        for name in ["c.txt", "b.txt", "a.txt"] {
+            reversed.insert(name, name.as_bytes());
+        }
+        assert_eq!(tree.root(), reversed.root());
+
+        let root = tree.root();
+        assert!(tree.insert("b.txt", b"changed"));
+        assert_ne!(tree.root(), root);
+        assert!(tree.remove("b.txt"));
+        assert!(!tree.contains("b.txt"));
+        assert!(tree.prove("b.txt").verify_exclusion("b.txt", &tree.root()));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let tree = tree();
+        let root = tree.root();
+        let proof = tree.prove("a.txt");
+
+        let mut extra = proof.clone();
+        extra.siblings.push(root.clone());
+        assert!(!extra.verify_inclusion("a.txt", b"a.txt", &root));
+
+        let mut short = proof;
+        short.bitmap.pop();
+        assert!(!short.verify_inclusion("a.txt", b"a.txt", &root));
+    }
+}
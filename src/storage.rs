@@ -0,0 +1,249 @@
+//! Where the server keeps uploaded files and its metadata
+//! Backends are registered under a name and created from a location string, so the server can
+//! be pointed at a different store through its config. `fs` (a local directory), `memory` and
+//! `cas` (see `content_store`) are always available; other crates can add their own with
+//! `register_backend`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use crate::content_store::{self, DedupStats, CONTENT_BACKEND};
+use crate::error::StorageError;
+
+/// Name of the local directory backend
+pub const FILESYSTEM_BACKEND: &str = "fs";
+/// Name of the in-memory backend
+pub const MEMORY_BACKEND: &str = "memory";
+
+/// A flat store of named files
+pub trait StorageBackend: Send + Sync {
+    /// Stores the data under the name, replacing any earlier data
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), StorageError>;
+    /// Reads the data stored under the name, failing with `NotFound` if there is none
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError>;
+    /// Names of all stored files
+    fn list(&self) -> Result<Vec<String>, StorageError>;
+    /// Removes all stored files
+    fn clear(&self) -> Result<(), StorageError>;
+    /// Space saved by storing identical files once, for backends that deduplicate
+    fn dedup_stats(&self) -> Option<DedupStats> {
+        None
+    }
+}
+
+/// Creates a backend from its location, such as a directory or a connection string
+pub type BackendFactory =
+    Arc<dyn Fn(&str) -> Result<Arc<dyn StorageBackend>, StorageError> + Send + Sync>;
+
+fn registry() -> &'static RwLock<HashMap<String, BackendFactory>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, BackendFactory>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut backends: HashMap<String, BackendFactory> = HashMap::new();
+        backends.insert(
+            FILESYSTEM_BACKEND.to_string(),
+            Arc::new(|location| Ok(Arc::new(FileSystemBackend::new(location)))),
+        );
+        backends.insert(
+            MEMORY_BACKEND.to_string(),
+            Arc::new(|_| Ok(Arc::new(MemoryBackend::default()))),
+        );
+        backends.insert(CONTENT_BACKEND.to_string(), Arc::new(content_store::create));
+        RwLock::new(backends)
+    })
+}
+
+/// Makes a backend available under the name, replacing any backend registered under it
+pub fn register_backend<F>(name: &str, factory: F)
+where
+    F: Fn(&str) -> Result<Arc<dyn StorageBackend>, StorageError> + Send + Sync + 'static,
+{
+    registry()
+        .write()
+        .expect("Backend registry lock poisoned")
+        .insert(name.to_string(), Arc::new(factory));
+}
+
+/// Names of all registered backends, sorted
+pub fn backend_names() -> Vec<String> {
+    let mut names: Vec<String> = registry()
+        .read()
+        .expect("Backend registry lock poisoned")
+        .keys()
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
+
+/// Creates the backend registered under the name
+pub fn create_backend(name: &str, location: &str) -> Result<Arc<dyn StorageBackend>, StorageError> {
+    let factory = registry()
+        .read()
+        .expect("Backend registry lock poisoned")
+        .get(name)
+        .cloned()
+        .ok_or_else(|| StorageError::UnknownBackend(name.to_string()))?;
+    factory(location)
+}
+
+/// Rejects names that would escape a flat store
+pub(crate) fn check_name(name: &str) -> Result<(), StorageError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(StorageError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Files in a local directory, created on the first write
+pub struct FileSystemBackend {
+    dir: PathBuf,
+}
+
+impl FileSystemBackend {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl StorageBackend for FileSystemBackend {
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), StorageError> {
+        check_name(name)?;
+        fs::create_dir_all(&self.dir)?;
+        Ok(fs::write(self.dir.join(name), data)?)
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        check_name(name)?;
+        fs::read(self.dir.join(name)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => StorageError::NotFound(name.to_string()),
+            _ => e.into(),
+        })
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn clear(&self) -> Result<(), StorageError> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(fs::create_dir_all(&self.dir)?)
+    }
+}
+
+/// Files kept in memory only, lost when the server stops
+#[derive(Default)]
+pub struct MemoryBackend {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), StorageError> {
+        check_name(name)?;
+        self.files
+            .lock()
+            .expect("Memory backend lock poisoned")
+            .insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+        self.files
+            .lock()
+            .expect("Memory backend lock poisoned")
+            .get(name)
+            .cloned()
+            .ok_or_else(|| StorageError::NotFound(name.to_string()))
+    }
+
+    fn list(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self
+            .files
+            .lock()
+            .expect("Memory backend lock poisoned")
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn clear(&self) -> Result<(), StorageError> {
+        self.files
+            .lock()
+            .expect("Memory backend lock poisoned")
+            .clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn roundtrip(backend: &dyn StorageBackend) {
+        assert!(backend.list().unwrap().is_empty());
+        backend.write("b.txt", b"b").unwrap();
+        backend.write("a.txt", b"a").unwrap();
+        backend.write("a.txt", b"changed").unwrap();
+        assert_eq!(backend.read("a.txt").unwrap(), b"changed");
+        assert_eq!(backend.list().unwrap(), vec!["a.txt", "b.txt"]);
+        assert!(matches!(
+            backend.read("missing.txt"),
+            Err(StorageError::NotFound(name)) if name == "missing.txt"
+        ));
+        assert!(matches!(
+            backend.write("../escape.txt", b"x"),
+            Err(StorageError::InvalidName(_))
+        ));
+
+        backend.clear().unwrap();
+        assert!(backend.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn filesystem_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        roundtrip(&FileSystemBackend::new(dir.path().join("storage")));
+    }
+
+    #[test]
+    fn memory_backend() {
+        roundtrip(&MemoryBackend::default());
+    }
+
+    #[test]
+    fn custom_backends_are_created_by_name() {
+        register_backend("test-shared", |_| {
+            static SHARED: OnceLock<Arc<MemoryBackend>> = OnceLock::new();
+            Ok(SHARED.get_or_init(Default::default).clone())
+        });
+        assert!(backend_names().contains(&"test-shared".to_string()));
+
+        create_backend("test-shared", "")
+            .unwrap()
+            .write("a.txt", b"a")
+            .unwrap();
+        let other = create_backend("test-shared", "").unwrap();
+        assert_eq!(other.read("a.txt").unwrap(), b"a");
+
+        assert!(matches!(
+            create_backend("missing", ""),
+            Err(StorageError::UnknownBackend(_))
+        ));
+    }
+}
@@ -0,0 +1,930 @@
+//! In-process server for end-to-end tests
+//! Starts the full server on an ephemeral local port with its own temporary storage, so tests
+//! can talk to it over HTTP without external processes. Every server is independent, and its
+//! storage is removed when it is dropped.
+
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::server::{AppState, Server, ServerConfig};
+
+pub struct TestServer {
+    url: String,
+    state: Arc<AppState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+    // Dropped after the server task has been told to stop
+    storage: TempDir,
+}
+
+impl TestServer {
+    /// Starts an empty server
+    /// Must be called from within a Tokio runtime
+    pub async fn start() -> Self {
+        Self::start_with_config(ServerConfig::default()).await
+    }
+
+    /// Starts an empty server with the given settings
+    /// The storage directory of the settings is replaced by the server's temporary storage.
+    pub async fn start_with_config(config: ServerConfig) -> Self {
+        let storage = tempfile::tempdir().expect("Failed to create temporary storage");
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let server = Server::builder()
+            .config(ServerConfig {
+                storage_dir: storage.path().to_path_buf(),
+                ..config
+            })
+            .bind(([127, 0, 0, 1], 0))
+            .shutdown_signal(async {
+                shutdown_signal.await.ok();
+            })
+            .build()
+            .await
+            .expect("Failed to start the test server");
+        Self {
+            url: format!("http://{}", server.local_addr()),
+            state: server.state().clone(),
+            shutdown: Some(shutdown),
+            task: tokio::spawn(server.run()),
+            storage,
+        }
+    }
+
+    /// Base URL of the server, such as `http://127.0.0.1:53211`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The server's state, for inspecting or seeding the dataset directly
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// Directory where the server stores uploads
+    pub fn storage_dir(&self) -> &std::path::Path {
+        self.storage.path()
+    }
+
+    /// Stops the server and waits for it to finish
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        (&mut self.task).await.ok();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Duration;
+
+    use crate::compression::{self, Encoding};
+    use crate::content_store::CONTENT_BACKEND;
+    use crate::error::StorageError;
+    use crate::hashing::{HashAlgorithm, TreeHasher};
+    use crate::merkle_tree::{calculate_hash, leaf_hash, MerkleProof, MerkleTree};
+    use crate::proof_cache::ProofCacheStats;
+    use crate::protocol::{
+        ChangeKind, DiffResponse, FileData, FileResponse, ImportRequest, LeafEntry, LeafProof,
+        LogEntryProof, LogHead, SampleResponse, ServerStats, StateBlob, UploadPart, UploadRequest,
+        UploadResponse, IDEMPOTENCY_HEADER, MAX_SAMPLE_SIZE, PROOF_MEDIA_TYPE, ROOT_HEADER,
+        STATE_SYNC_PREFIX,
+    };
+    use crate::server::{
+        routes, DEFAULT_IDEMPOTENCY_WINDOW, IDEMPOTENCY_WINDOW_SETTING, IMPORT_ROOT_SETTING,
+    };
+    use crate::storage::{MemoryBackend, StorageBackend};
+
+    fn upload_request(contents: &[&str]) -> UploadRequest {
+        let files: Vec<FileData> = contents
+            .iter()
+            .map(|content| FileData {
+                name: format!("{}.txt", content),
+                content: content.as_bytes().to_vec(),
+            })
+            .collect();
+        let mut tree = MerkleTree::new();
+        tree.build(&files.iter().map(|f| f.content.clone()).collect::<Vec<_>>());
+        UploadRequest {
+            root_hash: tree.root().unwrap(),
+            base_version: None,
+            leaves: files.iter().map(LeafEntry::new).collect(),
+            files,
+            staged: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_and_verify() {
+        let server = TestServer::start().await;
+        let request = upload_request(&["a", "b"]);
+        let root_hash = request.root_hash.clone();
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(server.storage_dir().join("b.txt").exists());
+
+        let response: serde_json::Value = client
+            .get(format!("{}/file/1", server.url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let proof: MerkleProof = serde_json::from_value(response["proof"].clone()).unwrap();
+        assert_eq!((proof.leaf_index, proof.leaf_count), (1, 2));
+        assert!(proof.verify("b", &root_hash));
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn binary_files_are_stored_and_proven() {
+        let server = TestServer::start().await;
+        let content = vec![0x89, b'P', b'N', b'G', 0xff, 0xfe, 0x00];
+        let mut request = upload_request(&["a"]);
+        request.files.push(FileData {
+            name: "image.png".to_string(),
+            content: content.clone(),
+        });
+        request.leaves.push(LeafEntry::new(&request.files[1]));
+        let mut tree = MerkleTree::new();
+        tree.build(&request.files);
+        request.root_hash = tree.root().unwrap();
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(
+            std::fs::read(server.storage_dir().join("image.png")).unwrap(),
+            content
+        );
+
+        let file: FileResponse = client
+            .get(format!("{}/file/1", server.url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(file.content, content);
+        assert!(file.proof.verify(&file.content, &request.root_hash));
+
+        // Stored binary files are loaded again when the server restarts
+        let reopened = AppState::open(server.storage_dir()).await.unwrap();
+        assert_eq!(reopened.dataset().root_hash(), Some(request.root_hash));
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn servers_are_independent() {
+        let first = TestServer::start().await;
+        let second = TestServer::start().await;
+        assert_ne!(first.url(), second.url());
+
+        first
+            .state()
+            .insert_files(vec![FileData {
+                name: "a.txt".to_string(),
+                content: "a".into(),
+            }])
+            .await
+            .unwrap();
+        assert_eq!(first.state().file_count().await, 1);
+        assert_eq!(second.state().file_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn retried_upload_returns_the_original_result() {
+        let server = TestServer::start().await;
+        let client = reqwest::Client::new();
+        let upload = |request: &UploadRequest, key: &str| {
+            client
+                .post(format!("{}/upload", server.url()))
+                .header(IDEMPOTENCY_HEADER, key)
+                .json(request)
+                .send()
+        };
+
+        let request = upload_request(&["a", "b"]);
+        let first: serde_json::Value = upload(&request, "op-1")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let retry = upload(&request, "op-1").await.unwrap();
+        assert!(retry.status().is_success());
+        assert_eq!(retry.json::<serde_json::Value>().await.unwrap(), first);
+        assert_eq!(server.state().file_count().await, 2);
+
+        // The same key cannot be used for another request
+        let other = upload(&upload_request(&["c"]), "op-1").await.unwrap();
+        assert!(!other.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn retried_delete_does_not_delete_again() {
+        let server = TestServer::start().await;
+        let client = reqwest::Client::new();
+        let delete = || {
+            client
+                .delete(format!("{}/delete_all", server.url()))
+                .header(IDEMPOTENCY_HEADER, "delete-1")
+                .send()
+        };
+
+        assert!(delete().await.unwrap().status().is_success());
+        client
+            .post(format!("{}/upload", server.url()))
+            .json(&upload_request(&["a"]))
+            .send()
+            .await
+            .unwrap();
+        assert!(delete().await.unwrap().status().is_success());
+        assert_eq!(server.state().file_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn synced_states_are_kept_in_the_server_storage() {
+        let server = TestServer::start().await;
+        let client = reqwest::Client::new();
+        let id = "ab".repeat(32);
+        let url = format!("{}/state/{}", server.url(), id);
+        let blob = StateBlob {
+            blob: "ciphertext".to_string(),
+        };
+        assert!(client
+            .put(&url)
+            .json(&blob)
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .is_success());
+        assert!(server
+            .storage_dir()
+            .join(format!("{}{}", STATE_SYNC_PREFIX, id))
+            .exists());
+
+        // Deleting the dataset keeps the state, and the state is not loaded as a file
+        client
+            .delete(format!("{}/delete_all", server.url()))
+            .send()
+            .await
+            .unwrap();
+        let pulled: StateBlob = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        assert_eq!(pulled.blob, blob.blob);
+        let reopened = AppState::open(server.storage_dir()).await.unwrap();
+        assert_eq!(reopened.file_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn staging_is_limited_and_abandoned_uploads_expire() {
+        let server = TestServer::start_with_config(ServerConfig {
+            max_staged_bytes: 4,
+            max_staged_uploads: 1,
+            staged_upload_ttl: Duration::from_millis(200),
+            ..ServerConfig::default()
+        })
+        .await;
+        let client = reqwest::Client::new();
+        let stage = |upload_id: &str, offset: usize, content: &str| {
+            client
+                .post(format!("{}/upload/{}/part", server.url(), upload_id))
+                .json(&UploadPart {
+                    name: "a.txt".to_string(),
+                    offset,
+                    content: content.as_bytes().to_vec(),
+                })
+                .send()
+        };
+
+        assert!(stage("up-1", 0, "abc").await.unwrap().status().is_success());
+        let status = stage("up-1", 3, "de").await.unwrap().status();
+        assert_eq!(status, reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        let status = stage("up-2", 0, "a").await.unwrap().status();
+        assert_eq!(status, reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Once the first upload has expired, another one can be staged
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(stage("up-2", 0, "abcd")
+            .await
+            .unwrap()
+            .status()
+            .is_success());
+    }
+
+    /// Storage whose files cannot be removed
+    #[derive(Default)]
+    struct UnclearableBackend(MemoryBackend);
+
+    impl StorageBackend for UnclearableBackend {
+        fn write(&self, name: &str, data: &[u8]) -> Result<(), StorageError> {
+            self.0.write(name, data)
+        }
+        fn read(&self, name: &str) -> Result<Vec<u8>, StorageError> {
+            self.0.read(name)
+        }
+        fn list(&self) -> Result<Vec<String>, StorageError> {
+            self.0.list()
+        }
+        fn clear(&self) -> Result<(), StorageError> {
+            Err(StorageError::Io(std::io::Error::other("read-only")))
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_delete_keeps_the_dataset() {
+        let state = Arc::new(AppState::with_storage(
+            ServerConfig::default(),
+            Arc::new(UnclearableBackend::default()),
+        ));
+        let routes = routes(state.clone());
+        let response = warp::test::request()
+            .method("POST")
+            .path("/upload")
+            .json(&upload_request(&["a"]))
+            .reply(&routes)
+            .await;
+        assert!(response.status().is_success());
+
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/delete_all")
+            .reply(&routes)
+            .await;
+        assert!(!response.status().is_success());
+        assert_eq!(state.file_count().await, 1);
+    }
+
+    #[test]
+    fn settings_override_the_config() {
+        let settings = std::collections::HashMap::from([
+            (IMPORT_ROOT_SETTING, "/srv/imports"),
+            (IDEMPOTENCY_WINDOW_SETTING, "60"),
+        ]);
+        let config = ServerConfig::default()
+            .with_settings(|name| settings.get(name).map(|value| value.to_string()))
+            .unwrap();
+        assert_eq!(config.import_root, Some("/srv/imports".into()));
+        assert_eq!(config.idempotency_window, Duration::from_secs(60));
+
+        let unset = ServerConfig::default().with_settings(|_| None).unwrap();
+        assert_eq!(unset.import_root, None);
+        assert_eq!(unset.idempotency_window, DEFAULT_IDEMPOTENCY_WINDOW);
+        assert!(ServerConfig::default()
+            .with_settings(|_| Some("a day".to_string()))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn proofs_are_streamed_for_a_range() {
+        let server = TestServer::start().await;
+        let names: Vec<String> = (0..600).map(|i| format!("file{}", i)).collect();
+        let request = upload_request(&names.iter().map(|n| n.as_str()).collect::<Vec<_>>());
+        let root_hash = request.root_hash.clone();
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!("{}/proofs?start=10&end=590", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[ROOT_HEADER], root_hash.as_str());
+        let body = response.text().await.unwrap();
+        let proofs: Vec<LeafProof> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(proofs.len(), 580);
+        for (proof, expected) in proofs.iter().zip(10..) {
+            assert_eq!(proof.index, expected);
+            assert_eq!(proof.name, format!("file{}.txt", expected));
+            assert_eq!(
+                HashAlgorithm::Sha256.root_from_leaf_hash(&proof.leaf_hash, &proof.proof),
+                root_hash
+            );
+        }
+
+        let response = client
+            .get(format!("{}/proofs?start=10&end=601", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn readers_keep_their_dataset_across_updates() {
+        let server = TestServer::start().await;
+        let file = |content: &str| FileData {
+            name: format!("{}.txt", content),
+            content: content.into(),
+        };
+        server.state().insert_files(vec![file("a")]).await.unwrap();
+        let before = server.state().dataset();
+
+        server.state().insert_files(vec![file("b")]).await.unwrap();
+        let after = server.state().dataset();
+        assert_eq!(before.file_count(), 1);
+        assert_eq!(before.root_history().len(), 1);
+        assert_eq!(after.file_count(), 2);
+        assert_eq!(after.root_history().len(), 2);
+        assert_ne!(before.root_hash(), after.root_hash());
+    }
+
+    #[tokio::test]
+    async fn repeated_proofs_are_served_from_the_cache() {
+        let server = TestServer::start().await;
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/upload", server.url()))
+            .json(&upload_request(&["a", "b"]))
+            .send()
+            .await
+            .unwrap();
+
+        let proof = || async {
+            let response: serde_json::Value = client
+                .get(format!("{}/file/1", server.url()))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            response["proof"].clone()
+        };
+        assert_eq!(proof().await, proof().await);
+
+        let stats: ProofCacheStats = client
+            .get(format!("{}/metrics/proof_cache", server.url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+        client
+            .delete(format!("{}/delete_all", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(server.state().proof_cache_stats().entries, 0);
+    }
+
+    #[tokio::test]
+    async fn changed_leaves_are_listed_between_roots() {
+        let server = TestServer::start().await;
+        let file = |content: &str| FileData {
+            name: format!("{}.txt", content),
+            content: content.into(),
+        };
+        let first = server
+            .state()
+            .insert_files(vec![file("a")])
+            .await
+            .unwrap()
+            .root_hash;
+        let second = server
+            .state()
+            .insert_files(vec![file("b"), file("c")])
+            .await
+            .unwrap()
+            .root_hash;
+
+        let client = reqwest::Client::new();
+        let diff = |from: String, to: String| {
+            let client = client.clone();
+            let url = format!("{}/diff", server.url());
+            async move {
+                client
+                    .get(url)
+                    .query(&[("from", from), ("to", to)])
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let forward: DiffResponse = diff(first.clone(), second.clone())
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!((forward.from.version, forward.to.version), (0, 1));
+        let added: Vec<(usize, &str, ChangeKind)> = forward
+            .changes
+            .iter()
+            .map(|change| (change.index, change.name.as_str(), change.change))
+            .collect();
+        assert_eq!(
+            added,
+            vec![
+                (1, "b.txt", ChangeKind::Added),
+                (2, "c.txt", ChangeKind::Added)
+            ]
+        );
+        assert_eq!(forward.changes[0].leaf_hash, leaf_hash("b"));
+
+        let backward: DiffResponse = diff(second.clone(), first).await.json().await.unwrap();
+        assert!(backward
+            .changes
+            .iter()
+            .all(|change| change.change == ChangeKind::Removed));
+
+        let unchanged: DiffResponse = diff(second.clone(), second.clone())
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert!(unchanged.changes.is_empty());
+
+        assert!(!diff("unknown".to_string(), second)
+            .await
+            .status()
+            .is_success());
+    }
+
+    #[tokio::test]
+    async fn every_root_is_in_the_transparency_log() {
+        let server = TestServer::start().await;
+        let mut roots = Vec::new();
+        for content in ["a", "b", "c"] {
+            let root = server
+                .state()
+                .insert_files(vec![FileData {
+                    name: format!("{}.txt", content),
+                    content: content.into(),
+                }])
+                .await
+                .unwrap()
+                .root_hash;
+            roots.push(root);
+        }
+
+        let client = reqwest::Client::new();
+        let head: LogHead = client
+            .get(format!("{}/log", server.url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(head.size, 3);
+
+        for (version, root) in roots.iter().enumerate() {
+            let mut entry: LogEntryProof = client
+                .get(format!("{}/log/{}", server.url(), version))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(entry.log, head);
+            assert_eq!(&entry.entry.root_hash, root);
+            assert!(entry.verify());
+
+            // A server presenting a different root for the version cannot prove it
+            entry.entry.root_hash = calculate_hash("forged");
+            assert!(!entry.verify());
+        }
+
+        let missing = client
+            .get(format!("{}/log/3", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert!(!missing.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn samples_are_selected_by_the_seed() {
+        let server = TestServer::start().await;
+        let files = (0..50)
+            .map(|i| FileData {
+                name: format!("{}.txt", i),
+                content: i.to_string().into(),
+            })
+            .collect();
+        let root = server.state().insert_files(files).await.unwrap().root_hash;
+
+        let client = reqwest::Client::new();
+        let sample: SampleResponse = client
+            .get(format!("{}/sample?n=5&seed=abc", server.url()))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(sample.root, root);
+        assert_eq!(sample.samples.len(), 5);
+        assert!(sample.verify(5, "abc").is_ok());
+        assert!(sample.verify(5, "another seed").is_err());
+
+        let too_many = client
+            .get(format!(
+                "{}/sample?n={}&seed=abc",
+                server.url(),
+                MAX_SAMPLE_SIZE + 1
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert!(!too_many.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn directories_are_imported_from_the_import_root() {
+        let storage = tempfile::tempdir().unwrap();
+        let import_root = tempfile::tempdir().unwrap();
+        let archive = import_root.path().join("archive");
+        std::fs::create_dir(&archive).unwrap();
+        std::fs::write(archive.join("b.txt"), "b").unwrap();
+        std::fs::write(archive.join("a.txt"), "a").unwrap();
+
+        let state = Arc::new(
+            AppState::with_config(ServerConfig {
+                storage_dir: storage.path().to_path_buf(),
+                import_root: Some(import_root.path().to_path_buf()),
+                ..ServerConfig::default()
+            })
+            .unwrap(),
+        );
+        let routes = routes(state.clone());
+        let import = |path: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/import")
+                .json(&ImportRequest {
+                    path: path.to_string(),
+                })
+                .reply(&routes)
+        };
+
+        let response = import("archive").await;
+        assert!(response.status().is_success());
+        let response: UploadResponse = serde_json::from_slice(response.body()).unwrap();
+        let mut tree = MerkleTree::new();
+        tree.build(&["a".to_string(), "b".to_string()]);
+        assert_eq!(Some(response.root_hash), tree.root());
+        assert_eq!(state.dataset().leaves()[0].name, "a.txt");
+        assert!(storage.path().join("b.txt").exists());
+
+        // The same files cannot be imported twice, and imports cannot leave the import root
+        assert!(!import("archive").await.status().is_success());
+        assert!(!import("../archive").await.status().is_success());
+        assert_eq!(state.file_count().await, 2);
+
+        // Nor can files named like the server's metadata
+        let reserved = import_root.path().join("reserved");
+        std::fs::create_dir(&reserved).unwrap();
+        std::fs::write(reserved.join("leaf_order.json"), "[]").unwrap();
+        assert!(!import("reserved").await.status().is_success());
+        assert_eq!(state.file_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn embedded_servers_serve_their_storage() {
+        let storage = Arc::new(MemoryBackend::default());
+        storage.write("a.txt", b"a").unwrap();
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let server = Server::builder()
+            .storage(storage)
+            .bind(([127, 0, 0, 1], 0))
+            .shutdown_signal(async {
+                shutdown_signal.await.ok();
+            })
+            .build()
+            .await
+            .unwrap();
+        let url = format!("http://{}/stats", server.local_addr());
+        let task = tokio::spawn(server.run());
+
+        let stats: ServerStats = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        assert_eq!(
+            (stats.file_count, stats.total_size, stats.tree_depth),
+            (1, 1, 1)
+        );
+
+        shutdown.send(()).unwrap();
+        task.await.unwrap();
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn datasets_in_a_content_store_share_files() {
+        let store = tempfile::tempdir().unwrap();
+        let dataset = |name: &str| {
+            Arc::new(
+                AppState::with_config(ServerConfig {
+                    storage_backend: CONTENT_BACKEND.to_string(),
+                    storage_dir: format!("{}#{}", store.path().display(), name).into(),
+                    ..ServerConfig::default()
+                })
+                .unwrap(),
+            )
+        };
+        // Uploads the same files to a dataset and returns its stats
+        let upload = |state: Arc<AppState>| async move {
+            let routes = routes(state);
+            let response = warp::test::request()
+                .method("POST")
+                .path("/upload")
+                .json(&upload_request(&["backup", "photos"]))
+                .reply(&routes)
+                .await;
+            assert!(response.status().is_success());
+            let response = warp::test::request().path("/stats").reply(&routes).await;
+            serde_json::from_slice::<ServerStats>(response.body()).unwrap()
+        };
+
+        let first = upload(dataset("first")).await;
+        assert_eq!(
+            (first.file_count, first.total_size, first.tree_depth),
+            (2, 12, 1)
+        );
+        let shared = first.dedup.unwrap();
+        assert_eq!(shared.saved_bytes, 0);
+
+        // The second dataset's files and metadata are all already stored
+        let second = upload(dataset("second")).await.dedup.unwrap();
+        assert_eq!(second.objects, shared.objects);
+        assert_eq!(second.saved_bytes, shared.stored_bytes);
+    }
+
+    #[tokio::test]
+    async fn compressed_bodies_are_decoded() {
+        let server = TestServer::start().await;
+        let client = reqwest::Client::new();
+        let stats = client
+            .get(format!("{}/stats", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(stats.headers()["accept-encoding"], "zstd, gzip");
+
+        let body = serde_json::to_vec(&upload_request(&["a", "b"])).unwrap();
+        let upload = |encoding: &str, body: Vec<u8>| {
+            client
+                .post(format!("{}/upload", server.url()))
+                .header("content-type", "application/json")
+                .header("content-encoding", encoding)
+                .body(body)
+                .send()
+        };
+        let brotli = upload("br", body.clone()).await.unwrap();
+        assert!(!brotli.status().is_success());
+        let gzip = compression::compress(Encoding::Gzip, &body).unwrap();
+        let response = upload("gzip", gzip).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(server.state().file_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn corrupted_uploads_are_rejected() {
+        let server = TestServer::start().await;
+        let mut request = upload_request(&["a", "b"]);
+        request.files[1].content = "corrupted".into();
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.status().is_success());
+        assert!(response.text().await.unwrap().contains("b.txt"));
+        assert_eq!(server.state().file_count().await, 0);
+        assert!(!server.storage_dir().join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn uploads_with_reserved_names_are_rejected() {
+        let server = TestServer::start().await;
+        let mut request = upload_request(&["a"]);
+        request.files[0].name = "leaf_order.json".to_string();
+        request.leaves = request.files.iter().map(LeafEntry::new).collect();
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.status().is_success());
+        assert!(response.text().await.unwrap().contains("leaf_order.json"));
+        assert_eq!(server.state().file_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn proofs_are_sent_compact_on_request() {
+        let server = TestServer::start().await;
+        let request = upload_request(&["a", "b", "c"]);
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+
+        let url = format!("{}/file/2/proof", server.url());
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(
+            response.headers()[ROOT_HEADER].to_str().unwrap(),
+            request.root_hash
+        );
+        let json: MerkleProof = response.json().await.unwrap();
+        assert!(json.verify("c", &request.root_hash));
+
+        let response = client
+            .get(&url)
+            .header("Accept", format!("{}, application/json", PROOF_MEDIA_TYPE))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-type"].to_str().unwrap(),
+            PROOF_MEDIA_TYPE
+        );
+        let bytes = response.bytes().await.unwrap();
+        assert_eq!(MerkleProof::from_bytes(&bytes), Some(json.clone()));
+        assert!(bytes.len() * 2 < serde_json::to_vec(&json).unwrap().len());
+
+        let missing = client
+            .get(format!("{}/file/3/proof", server.url()))
+            .send()
+            .await
+            .unwrap();
+        assert!(!missing.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn proofs_are_found_by_leaf_hash() {
+        let server = TestServer::start().await;
+        let request = upload_request(&["a", "b", "c"]);
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/upload", server.url()))
+            .json(&request)
+            .send()
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!("{}/leaf/{}/proof", server.url(), leaf_hash("b")))
+            .send()
+            .await
+            .unwrap();
+        let proof: MerkleProof = response.json().await.unwrap();
+        assert_eq!(proof.leaf_index, 1);
+        assert!(proof.verify("b", &request.root_hash));
+
+        let missing = client
+            .get(format!("{}/leaf/{}/proof", server.url(), leaf_hash("d")))
+            .send()
+            .await
+            .unwrap();
+        assert!(!missing.status().is_success());
+    }
+}
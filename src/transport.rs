@@ -0,0 +1,1124 @@
+//! How the client reaches a server
+//! Every server operation the client uses goes through the `Transport` trait, so the wire protocol
+//! can be swapped without touching the client logic. `HttpTransport` speaks HTTP/JSON to a
+//! remote server; `InProcessTransport` runs the same routes against a server state in the
+//! current process, which is used by tests and to work on a local directory without a server.
+//! Other protocols, such as gRPC, are added by implementing the trait.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE,
+};
+use reqwest::tls::TlsInfo;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Method};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::compression::{self, Encoding, MAX_DECODED_BODY, MIN_ENCODED_BODY};
+use crate::protocol::{
+    AbsentResponse, ChunkQuery, ChunkResponse, DiffQuery, DiffResponse, FileQuery, FileResponse,
+    LeafProof, LogEntryProof, LogHead, ProofRangeQuery, SampleQuery, SampleResponse, StateBlob,
+    UploadPart, UploadPartResponse, UploadRequest, UploadResponse, IDEMPOTENCY_HEADER, ROOT_HEADER,
+};
+use crate::server::{routes, AppState};
+
+/// Prefix of addresses served in-process from a local directory, such as `local:./my_files`
+pub const LOCAL_SCHEME: &str = "local:";
+
+/// Longest wait between two attempts of a request
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The result of a transport operation
+pub type TransportFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, TransportError>> + Send + 'a>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    /// The server could not be reached, dropped the connection or did not answer in time; the
+    /// request may be retried
+    Unavailable(String),
+    /// The server answered with an error
+    Server { status: u16, message: String },
+    /// The request could not be sent or the answer could not be understood
+    Invalid(String),
+}
+
+impl TransportError {
+    /// Whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TransportError::Unavailable(_) => true,
+            TransportError::Server { status, .. } => is_transient_status(*status),
+            TransportError::Invalid(_) => false,
+        }
+    }
+}
+
+/// Whether an error status is transient: a gateway in front of the server could not reach it
+/// (502, 504) or the server is temporarily unavailable (503)
+/// The server answers rejected requests with 500, which the same request would get again.
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 502..=504)
+}
+
+/// How requests to a remote server are timed out and retried
+/// Every request of the protocol can be sent again safely: uploads and deletes carry an
+/// idempotency key, staged parts are acknowledged again and all other requests only read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectOptions {
+    /// Limit on each attempt of a request, including reading the answer; `None` waits as long as
+    /// the server takes
+    pub timeout: Option<Duration>,
+    /// How many times a request that failed transiently is sent again
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub backoff: Duration,
+    /// Bearer token sent in the `Authorization` header of every request
+    pub auth_token: Option<String>,
+    /// Certificates trusted and presented over HTTPS, beyond the system's trusted roots
+    pub tls: TlsOptions,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 2,
+            backoff: Duration::from_millis(500),
+            auth_token: None,
+            tls: TlsOptions::default(),
+        }
+    }
+}
+
+/// Trust and identity of HTTPS connections, for servers with a private CA or mutual TLS
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsOptions {
+    /// PEM certificates of CAs trusted in addition to the system's roots
+    pub ca_certs: Vec<Vec<u8>>,
+    /// Certificate presented to servers that ask the client for one
+    pub client_identity: Option<ClientIdentity>,
+    /// SHA-256 fingerprints of the server certificates accepted; when set, any other is refused
+    pub pinned_certs: Vec<CertFingerprint>,
+}
+
+/// A PEM certificate chain and its PKCS#8 PEM private key
+#[derive(Clone, PartialEq)]
+pub struct ClientIdentity {
+    pub certificate: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientIdentity").finish_non_exhaustive()
+    }
+}
+
+/// SHA-256 digest of a DER certificate
+/// Parsed from hex, with or without the colons `openssl x509 -fingerprint -sha256` prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertFingerprint(pub [u8; 32]);
+
+impl CertFingerprint {
+    pub fn of(der: &[u8]) -> Self {
+        Self(Sha256::digest(der).into())
+    }
+}
+
+impl std::str::FromStr for CertFingerprint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: String = s
+            .trim()
+            .trim_start_matches("sha256:")
+            .chars()
+            .filter(|c| *c != ':')
+            .collect();
+        hex::decode(&digits)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Self)
+            .ok_or_else(|| format!("Invalid SHA-256 certificate fingerprint {}", s))
+    }
+}
+
+impl fmt::Display for CertFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl TlsOptions {
+    /// Adds the certificates to the builder, failing if one does not parse
+    fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder, TransportError> {
+        for pem in &self.ca_certs {
+            let certificate = Certificate::from_pem(pem)
+                .map_err(|e| TransportError::Invalid(format!("Invalid CA certificate: {}", e)))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = &self.client_identity {
+            let identity =
+                Identity::from_pkcs8_pem(&identity.certificate, &identity.key).map_err(|e| {
+                    TransportError::Invalid(format!("Invalid client certificate or key: {}", e))
+                })?;
+            builder = builder.identity(identity);
+        }
+        // Pins are checked against the certificate each answer came with
+        Ok(builder.tls_info(!self.pinned_certs.is_empty()))
+    }
+
+    /// Checks that the certificates parse, so mistakes are reported before any request is sent
+    pub fn check(&self) -> Result<(), TransportError> {
+        self.apply(Client::builder()).map(|_| ())
+    }
+
+    /// Refuses answers whose server certificate is not pinned, if any is
+    fn check_pin(&self, tls_info: Option<&TlsInfo>) -> Result<(), TransportError> {
+        if self.pinned_certs.is_empty() {
+            return Ok(());
+        }
+        let Some(certificate) = tls_info.and_then(TlsInfo::peer_certificate) else {
+            return Err(TransportError::Invalid(
+                "Server certificates are pinned, but the server sent none".to_string(),
+            ));
+        };
+        let fingerprint = CertFingerprint::of(certificate);
+        if self.pinned_certs.contains(&fingerprint) {
+            Ok(())
+        } else {
+            Err(TransportError::Invalid(format!(
+                "Server certificate {} is not pinned",
+                fingerprint
+            )))
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// Delay before the retry with the number, counted from 1
+    /// The delay is jittered to between half and all of the exponential backoff, capped at
+    /// `MAX_RETRY_DELAY`, so clients that failed together do not all retry at the same moment.
+    pub fn retry_delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(MAX_RETRY_DELAY);
+        let half = delay / 2;
+        let jitter = OsRng.next_u64() % (half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter)
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::Unavailable(reason) => write!(f, "Server unavailable: {}", reason),
+            TransportError::Server { status, message } => {
+                write!(f, "Server error: {} - {}", status, message)
+            }
+            TransportError::Invalid(reason) => write!(f, "Invalid exchange: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// The proofs of a range of leaves and the root they are valid for
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafProofs {
+    /// `None` if the server has no files
+    pub root: Option<String>,
+    pub proofs: Vec<LeafProof>,
+}
+
+/// The server operations used by the client
+pub trait Transport: Send + Sync {
+    /// Uploads files; retries with the same idempotency key get the original response
+    fn upload<'a>(
+        &'a self,
+        request: &'a UploadRequest,
+        idempotency_key: &'a str,
+    ) -> TransportFuture<'a, UploadResponse>;
+
+    /// Stages a piece of a file for the upload with the id; pieces already received are
+    /// acknowledged again, so retries are safe
+    fn upload_part<'a>(
+        &'a self,
+        upload_id: &'a str,
+        part: &'a UploadPart,
+    ) -> TransportFuture<'a, UploadPartResponse>;
+
+    /// Fetches a file and its proof by index
+    fn fetch_file<'a>(
+        &'a self,
+        file_index: usize,
+        query: &'a FileQuery,
+    ) -> TransportFuture<'a, FileResponse>;
+
+    /// Fetches a chunk of a file and its proof against the file's chunk root
+    fn fetch_chunk<'a>(
+        &'a self,
+        file_index: usize,
+        chunk_index: usize,
+        query: &'a ChunkQuery,
+    ) -> TransportFuture<'a, ChunkResponse>;
+
+    /// Asks for a proof that no file has the leaf hash
+    fn prove_absent<'a>(&'a self, leaf_hash: &'a str) -> TransportFuture<'a, AbsentResponse>;
+
+    /// Fetches the leaves a seed selects, with their contents and proofs
+    fn sample<'a>(&'a self, query: &'a SampleQuery) -> TransportFuture<'a, SampleResponse>;
+
+    /// Lists the leaves that differ between two roots
+    fn diff<'a>(&'a self, from: &'a str, to: &'a str) -> TransportFuture<'a, DiffResponse>;
+
+    /// Fetches the proofs of a range of leaves against the latest root
+    fn fetch_proofs<'a>(&'a self, query: &'a ProofRangeQuery) -> TransportFuture<'a, LeafProofs>;
+
+    /// Fetches the head of the transparency log of published roots
+    fn log_head(&self) -> TransportFuture<'_, LogHead>;
+
+    /// Fetches the root of a version and its proof of inclusion in the transparency log
+    fn log_entry(&self, version: usize) -> TransportFuture<'_, LogEntryProof>;
+
+    /// Deletes all files and state; retries with the same idempotency key delete nothing again
+    fn delete_all<'a>(&'a self, idempotency_key: &'a str) -> TransportFuture<'a, ()>;
+
+    /// Stores an encrypted client state under its id
+    fn push_state<'a>(&'a self, id: &'a str, state: &'a StateBlob) -> TransportFuture<'a, ()>;
+
+    /// Fetches the encrypted client state stored under the id
+    fn pull_state<'a>(&'a self, id: &'a str) -> TransportFuture<'a, StateBlob>;
+}
+
+/// Connects to the server at the address with the default options
+/// `local:<dir>` opens the directory in-process; anything else is treated as an HTTP URL
+pub async fn connect(address: &str) -> Result<Arc<dyn Transport>, TransportError> {
+    connect_with(address, &ConnectOptions::default()).await
+}
+
+/// Connects to the server at the address, timing out and retrying HTTP requests as the options
+/// say; directories opened in-process ignore them
+pub async fn connect_with(
+    address: &str,
+    options: &ConnectOptions,
+) -> Result<Arc<dyn Transport>, TransportError> {
+    match address.strip_prefix(LOCAL_SCHEME) {
+        Some(dir) => Ok(Arc::new(InProcessTransport::open(dir).await?)),
+        None => Ok(Arc::new(HttpTransport::with_options(address, options)?)),
+    }
+}
+
+/// A request of the HTTP/JSON protocol, shared by the transports that speak it
+struct JsonRequest {
+    method: Method,
+    /// Path and query string
+    path: String,
+    idempotency_key: Option<String>,
+    body: Option<Vec<u8>>,
+    /// Encoding the body is compressed with
+    content_encoding: Option<Encoding>,
+}
+
+impl JsonRequest {
+    fn new(method: Method, path: String) -> Self {
+        Self {
+            method,
+            path,
+            idempotency_key: None,
+            body: None,
+            content_encoding: None,
+        }
+    }
+
+    fn query<T: Serialize>(mut self, query: &T) -> Result<Self, TransportError> {
+        let query = serde_urlencoded::to_string(query)
+            .map_err(|e| TransportError::Invalid(e.to_string()))?;
+        if !query.is_empty() {
+            self.path = format!("{}?{}", self.path, query);
+        }
+        Ok(self)
+    }
+
+    fn json<T: Serialize>(mut self, body: &T) -> Result<Self, TransportError> {
+        self.body =
+            Some(serde_json::to_vec(body).map_err(|e| TransportError::Invalid(e.to_string()))?);
+        Ok(self)
+    }
+
+    fn idempotency_key(mut self, key: &str) -> Self {
+        self.idempotency_key = Some(key.to_string());
+        self
+    }
+
+    fn upload(request: &UploadRequest, key: &str) -> Result<Self, TransportError> {
+        Ok(Self::new(Method::POST, "/upload".to_string())
+            .json(request)?
+            .idempotency_key(key))
+    }
+
+    fn upload_part(upload_id: &str, part: &UploadPart) -> Result<Self, TransportError> {
+        Self::new(Method::POST, format!("/upload/{}/part", upload_id)).json(part)
+    }
+
+    fn fetch_file(file_index: usize, query: &FileQuery) -> Result<Self, TransportError> {
+        Self::new(Method::GET, format!("/file/{}", file_index)).query(query)
+    }
+
+    fn fetch_chunk(
+        file_index: usize,
+        chunk_index: usize,
+        query: &ChunkQuery,
+    ) -> Result<Self, TransportError> {
+        Self::new(
+            Method::GET,
+            format!("/file/{}/chunk/{}", file_index, chunk_index),
+        )
+        .query(query)
+    }
+
+    fn prove_absent(leaf_hash: &str) -> Result<Self, TransportError> {
+        Ok(Self::new(Method::GET, format!("/absent/{}", leaf_hash)))
+    }
+
+    fn sample(query: &SampleQuery) -> Result<Self, TransportError> {
+        Self::new(Method::GET, "/sample".to_string()).query(query)
+    }
+
+    fn diff(from: &str, to: &str) -> Result<Self, TransportError> {
+        Self::new(Method::GET, "/diff".to_string()).query(&DiffQuery {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+
+    fn fetch_proofs(query: &ProofRangeQuery) -> Result<Self, TransportError> {
+        Self::new(Method::GET, "/proofs".to_string()).query(query)
+    }
+
+    fn log_head() -> Result<Self, TransportError> {
+        Ok(Self::new(Method::GET, "/log".to_string()))
+    }
+
+    fn log_entry(version: usize) -> Result<Self, TransportError> {
+        Ok(Self::new(Method::GET, format!("/log/{}", version)))
+    }
+
+    fn delete_all(key: &str) -> Result<Self, TransportError> {
+        Ok(Self::new(Method::DELETE, "/delete_all".to_string()).idempotency_key(key))
+    }
+
+    fn push_state(id: &str, state: &StateBlob) -> Result<Self, TransportError> {
+        Self::new(Method::PUT, format!("/state/{}", id)).json(state)
+    }
+
+    fn pull_state(id: &str) -> Result<Self, TransportError> {
+        Ok(Self::new(Method::GET, format!("/state/{}", id)))
+    }
+}
+
+/// A response of the HTTP/JSON protocol, before decoding
+struct RawResponse {
+    status: u16,
+    /// The `Merkle-Root` header, sent with streamed proofs
+    root: Option<String>,
+    body: Vec<u8>,
+}
+
+impl RawResponse {
+    /// Turns error statuses into `TransportError::Server`
+    fn check_status(&self) -> Result<(), TransportError> {
+        if !(200..300).contains(&self.status) {
+            return Err(TransportError::Server {
+                status: self.status,
+                message: String::from_utf8_lossy(&self.body).to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Parses a successful JSON body
+    fn decode<T: DeserializeOwned>(self) -> Result<T, TransportError> {
+        self.check_status()?;
+        serde_json::from_slice(&self.body).map_err(|e| TransportError::Invalid(e.to_string()))
+    }
+
+    /// Parses a successful newline-delimited body of proofs
+    fn decode_proofs(self) -> Result<LeafProofs, TransportError> {
+        self.check_status()?;
+        let root = self.root;
+        let proofs = serde_json::Deserializer::from_slice(&self.body)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(|e| TransportError::Invalid(e.to_string()))?;
+        Ok(LeafProofs { root, proofs })
+    }
+}
+
+/// HTTP/JSON to a remote server
+/// Requests that fail transiently are retried with backoff, as `ConnectOptions` describes.
+/// With pinned certificates, an answer is refused unless the server's certificate is pinned. The
+/// request was already sent by then, so pins guard what the client accepts; trusting only the
+/// server's CA also keeps requests from reaching other servers.
+/// Request bodies are compressed if the server's answers list an encoding it accepts.
+pub struct HttpTransport {
+    client: Client,
+    base_url: String,
+    options: ConnectOptions,
+    /// The encoding the server accepts for request bodies; unset until a successful answer said
+    accepted_encoding: Mutex<Option<Option<Encoding>>>,
+}
+
+impl HttpTransport {
+    /// A transport to the server at the base URL, such as `http://127.0.0.1:8000`
+    pub fn new(base_url: &str) -> Self {
+        Self::with_options(base_url, &ConnectOptions::default())
+            .expect("Default options need no certificates")
+    }
+
+    /// A transport to the server at the base URL with the timeout, retries and certificates of
+    /// the options
+    /// Fails if a certificate or key of the options does not parse.
+    pub fn with_options(base_url: &str, options: &ConnectOptions) -> Result<Self, TransportError> {
+        let mut client = options.tls.apply(Client::builder())?;
+        if let Some(timeout) = options.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(token) = &options.auth_token {
+            let mut value = HeaderValue::try_from(format!("Bearer {}", token))
+                .expect("Auth tokens are valid header values");
+            value.set_sensitive(true);
+            client = client.default_headers(HeaderMap::from_iter([(AUTHORIZATION, value)]));
+        }
+        Ok(Self {
+            client: client
+                .build()
+                .map_err(|e| TransportError::Invalid(e.to_string()))?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            options: options.clone(),
+            accepted_encoding: Mutex::new(None),
+        })
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: Result<JsonRequest, TransportError>,
+    ) -> Result<T, TransportError> {
+        self.send_raw(request).await?.decode()
+    }
+
+    /// Sends the request, retrying it while it fails transiently
+    async fn send_raw(
+        &self,
+        request: Result<JsonRequest, TransportError>,
+    ) -> Result<RawResponse, TransportError> {
+        let request = self.compress(request?).await;
+        let mut retry = 0;
+        loop {
+            let result = self.send_once(&request).await;
+            let transient = match &result {
+                Ok(response) => is_transient_status(response.status),
+                Err(e) => e.is_retryable(),
+            };
+            if !transient || retry >= self.options.retries {
+                return result;
+            }
+            retry += 1;
+            tokio::time::sleep(self.options.retry_delay(retry)).await;
+        }
+    }
+
+    /// Compresses the body with the encoding the server accepts, if the body is worth compressing
+    async fn compress(&self, mut request: JsonRequest) -> JsonRequest {
+        let Some(body) = &request.body else {
+            return request;
+        };
+        if body.len() < MIN_ENCODED_BODY || body.len() as u64 > MAX_DECODED_BODY {
+            return request;
+        }
+        let Some(encoding) = self.accepted_encoding().await else {
+            return request;
+        };
+        if let Ok(compressed) = compression::compress(encoding, body) {
+            request.body = Some(compressed);
+            request.content_encoding = Some(encoding);
+        }
+        request
+    }
+
+    /// The encoding the server accepts for request bodies
+    /// Unless an earlier answer told, the server is asked with `GET /stats`; servers that do not
+    /// list encodings, such as older ones, are sent uncompressed bodies.
+    async fn accepted_encoding(&self) -> Option<Encoding> {
+        if let Some(known) = *self.accepted_encoding.lock().unwrap() {
+            return known;
+        }
+        let probe = JsonRequest::new(Method::GET, "/stats".to_string());
+        // The answer is recorded by `send_once`
+        let _ = self.send_once(&probe).await;
+        self.accepted_encoding.lock().unwrap().flatten()
+    }
+
+    async fn send_once(&self, request: &JsonRequest) -> Result<RawResponse, TransportError> {
+        let mut builder = self.client.request(
+            request.method.clone(),
+            format!("{}{}", self.base_url, request.path),
+        );
+        if let Some(key) = &request.idempotency_key {
+            builder = builder.header(IDEMPOTENCY_HEADER, key);
+        }
+        if let Some(body) = &request.body {
+            builder = builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+        }
+        if let Some(encoding) = request.content_encoding {
+            builder = builder.header(CONTENT_ENCODING, encoding.name());
+        }
+
+        let response = builder.send().await.map_err(http_error)?;
+        self.options
+            .tls
+            .check_pin(response.extensions().get::<TlsInfo>())?;
+        let status = response.status().as_u16();
+        // Every successful answer of a server that decodes compressed bodies lists the encodings
+        if response.status().is_success() {
+            let accepted = response
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(compression::negotiate);
+            *self.accepted_encoding.lock().unwrap() = Some(accepted);
+        }
+        let root = response
+            .headers()
+            .get(ROOT_HEADER)
+            .and_then(|root| root.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await.map_err(http_error)?;
+        Ok(RawResponse {
+            status,
+            root,
+            body: body.to_vec(),
+        })
+    }
+}
+
+fn http_error(e: reqwest::Error) -> TransportError {
+    if e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() {
+        TransportError::Unavailable(e.to_string())
+    } else {
+        TransportError::Invalid(e.to_string())
+    }
+}
+
+impl Transport for HttpTransport {
+    fn upload<'a>(
+        &'a self,
+        request: &'a UploadRequest,
+        idempotency_key: &'a str,
+    ) -> TransportFuture<'a, UploadResponse> {
+        Box::pin(self.send(JsonRequest::upload(request, idempotency_key)))
+    }
+
+    fn upload_part<'a>(
+        &'a self,
+        upload_id: &'a str,
+        part: &'a UploadPart,
+    ) -> TransportFuture<'a, UploadPartResponse> {
+        Box::pin(self.send(JsonRequest::upload_part(upload_id, part)))
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        file_index: usize,
+        query: &'a FileQuery,
+    ) -> TransportFuture<'a, FileResponse> {
+        Box::pin(self.send(JsonRequest::fetch_file(file_index, query)))
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        file_index: usize,
+        chunk_index: usize,
+        query: &'a ChunkQuery,
+    ) -> TransportFuture<'a, ChunkResponse> {
+        Box::pin(self.send(JsonRequest::fetch_chunk(file_index, chunk_index, query)))
+    }
+
+    fn prove_absent<'a>(&'a self, leaf_hash: &'a str) -> TransportFuture<'a, AbsentResponse> {
+        Box::pin(self.send(JsonRequest::prove_absent(leaf_hash)))
+    }
+
+    fn sample<'a>(&'a self, query: &'a SampleQuery) -> TransportFuture<'a, SampleResponse> {
+        Box::pin(self.send(JsonRequest::sample(query)))
+    }
+
+    fn diff<'a>(&'a self, from: &'a str, to: &'a str) -> TransportFuture<'a, DiffResponse> {
+        Box::pin(self.send(JsonRequest::diff(from, to)))
+    }
+
+    fn fetch_proofs<'a>(&'a self, query: &'a ProofRangeQuery) -> TransportFuture<'a, LeafProofs> {
+        Box::pin(async move {
+            self.send_raw(JsonRequest::fetch_proofs(query))
+                .await?
+                .decode_proofs()
+        })
+    }
+
+    fn log_head(&self) -> TransportFuture<'_, LogHead> {
+        Box::pin(self.send(JsonRequest::log_head()))
+    }
+
+    fn log_entry(&self, version: usize) -> TransportFuture<'_, LogEntryProof> {
+        Box::pin(self.send(JsonRequest::log_entry(version)))
+    }
+
+    fn delete_all<'a>(&'a self, idempotency_key: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.send::<IgnoredAny>(JsonRequest::delete_all(idempotency_key))
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn push_state<'a>(&'a self, id: &'a str, state: &'a StateBlob) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.send::<IgnoredAny>(JsonRequest::push_state(id, state))
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn pull_state<'a>(&'a self, id: &'a str) -> TransportFuture<'a, StateBlob> {
+        Box::pin(self.send(JsonRequest::pull_state(id)))
+    }
+}
+
+/// The server routes run against a state in the current process, without a network
+pub struct InProcessTransport {
+    state: Arc<AppState>,
+}
+
+impl InProcessTransport {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+
+    /// Opens a local directory like the server does, see `AppState::open`
+    pub async fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, TransportError> {
+        let state = AppState::open(dir)
+            .await
+            .map_err(|e| TransportError::Unavailable(e.to_string()))?;
+        Ok(Self::new(Arc::new(state)))
+    }
+
+    /// The state the requests are run against
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: Result<JsonRequest, TransportError>,
+    ) -> Result<T, TransportError> {
+        self.send_raw(request).await?.decode()
+    }
+
+    async fn send_raw(
+        &self,
+        request: Result<JsonRequest, TransportError>,
+    ) -> Result<RawResponse, TransportError> {
+        let request = request?;
+        let mut builder = warp::test::request()
+            .method(request.method.as_str())
+            .path(&request.path);
+        if let Some(key) = &request.idempotency_key {
+            builder = builder.header(IDEMPOTENCY_HEADER, key.as_str());
+        }
+        if let Some(body) = request.body {
+            builder = builder
+                .header("content-type", "application/json")
+                .body(body);
+        }
+
+        let response = builder.reply(&routes(self.state.clone())).await;
+        Ok(RawResponse {
+            status: response.status().as_u16(),
+            root: response
+                .headers()
+                .get(ROOT_HEADER)
+                .and_then(|root| root.to_str().ok())
+                .map(str::to_string),
+            body: response.body().to_vec(),
+        })
+    }
+}
+
+impl Transport for InProcessTransport {
+    fn upload<'a>(
+        &'a self,
+        request: &'a UploadRequest,
+        idempotency_key: &'a str,
+    ) -> TransportFuture<'a, UploadResponse> {
+        Box::pin(self.send(JsonRequest::upload(request, idempotency_key)))
+    }
+
+    fn upload_part<'a>(
+        &'a self,
+        upload_id: &'a str,
+        part: &'a UploadPart,
+    ) -> TransportFuture<'a, UploadPartResponse> {
+        Box::pin(self.send(JsonRequest::upload_part(upload_id, part)))
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        file_index: usize,
+        query: &'a FileQuery,
+    ) -> TransportFuture<'a, FileResponse> {
+        Box::pin(self.send(JsonRequest::fetch_file(file_index, query)))
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        file_index: usize,
+        chunk_index: usize,
+        query: &'a ChunkQuery,
+    ) -> TransportFuture<'a, ChunkResponse> {
+        Box::pin(self.send(JsonRequest::fetch_chunk(file_index, chunk_index, query)))
+    }
+
+    fn prove_absent<'a>(&'a self, leaf_hash: &'a str) -> TransportFuture<'a, AbsentResponse> {
+        Box::pin(self.send(JsonRequest::prove_absent(leaf_hash)))
+    }
+
+    fn sample<'a>(&'a self, query: &'a SampleQuery) -> TransportFuture<'a, SampleResponse> {
+        Box::pin(self.send(JsonRequest::sample(query)))
+    }
+
+    fn diff<'a>(&'a self, from: &'a str, to: &'a str) -> TransportFuture<'a, DiffResponse> {
+        Box::pin(self.send(JsonRequest::diff(from, to)))
+    }
+
+    fn fetch_proofs<'a>(&'a self, query: &'a ProofRangeQuery) -> TransportFuture<'a, LeafProofs> {
+        Box::pin(async move {
+            self.send_raw(JsonRequest::fetch_proofs(query))
+                .await?
+                .decode_proofs()
+        })
+    }
+
+    fn log_head(&self) -> TransportFuture<'_, LogHead> {
+        Box::pin(self.send(JsonRequest::log_head()))
+    }
+
+    fn log_entry(&self, version: usize) -> TransportFuture<'_, LogEntryProof> {
+        Box::pin(self.send(JsonRequest::log_entry(version)))
+    }
+
+    fn delete_all<'a>(&'a self, idempotency_key: &'a str) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.send::<IgnoredAny>(JsonRequest::delete_all(idempotency_key))
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn push_state<'a>(&'a self, id: &'a str, state: &'a StateBlob) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.send::<IgnoredAny>(JsonRequest::push_state(id, state))
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn pull_state<'a>(&'a self, id: &'a str) -> TransportFuture<'a, StateBlob> {
+        Box::pin(self.send(JsonRequest::pull_state(id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::merkle_tree::{root_from_proof, MerkleTree};
+    use crate::protocol::{FileData, LeafEntry};
+    use crate::testing::TestServer;
+
+    fn upload_request(contents: &[&str]) -> UploadRequest {
+        let files: Vec<FileData> = contents
+            .iter()
+            .map(|content| FileData {
+                name: format!("{}.txt", content),
+                content: content.as_bytes().to_vec(),
+            })
+            .collect();
+        let mut tree = MerkleTree::new();
+        tree.build(&files.iter().map(|f| f.content.clone()).collect::<Vec<_>>());
+        UploadRequest {
+            root_hash: tree.root().unwrap(),
+            base_version: None,
+            leaves: files.iter().map(LeafEntry::new).collect(),
+            files,
+            staged: None,
+        }
+    }
+
+    async fn upload_and_verify(transport: &dyn Transport) {
+        let request = upload_request(&["a", "b", "c"]);
+        let uploaded = transport.upload(&request, "op-1").await.unwrap();
+        assert_eq!(uploaded.root_hash, request.root_hash);
+        assert_eq!(uploaded.version, 0);
+
+        let file = transport
+            .fetch_file(1, &FileQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(file.name, "b.txt");
+        assert_eq!(
+            root_from_proof(&file.content, &file.proof),
+            request.root_hash
+        );
+
+        let chunk = transport
+            .fetch_chunk(2, 0, &ChunkQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(chunk.content, b"c");
+
+        match transport.fetch_file(5, &FileQuery::default()).await {
+            Err(TransportError::Server { status, .. }) => assert!(status >= 400),
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+
+        transport.delete_all("op-2").await.unwrap();
+        assert!(transport
+            .fetch_file(0, &FileQuery::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn http_transport() {
+        let server = TestServer::start().await;
+        upload_and_verify(&HttpTransport::new(server.url())).await;
+    }
+
+    #[tokio::test]
+    async fn in_process_transport() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = InProcessTransport::open(dir.path()).await.unwrap();
+        upload_and_verify(&transport).await;
+    }
+
+    async fn upload_staged_parts(transport: &dyn Transport) {
+        let mut request = upload_request(&["small", "a large file in three parts"]);
+        let large = request.files[1].content.clone();
+        for offset in [0, 10, 20] {
+            let end = (offset + 10).min(large.len());
+            let part = UploadPart {
+                name: request.files[1].name.clone(),
+                offset,
+                content: large[offset..end].to_vec(),
+            };
+            let staged = transport.upload_part("up-1", &part).await.unwrap();
+            assert_eq!(staged.received, end);
+            // A retried part is acknowledged without being appended again
+            assert_eq!(transport.upload_part("up-1", &part).await.unwrap(), staged);
+        }
+        let gap = UploadPart {
+            name: request.files[1].name.clone(),
+            offset: 40,
+            content: "x".into(),
+        };
+        assert!(transport.upload_part("up-1", &gap).await.is_err());
+
+        request.files[1].content = Vec::new();
+        request.staged = Some("up-1".to_string());
+        let uploaded = transport.upload(&request, "op-1").await.unwrap();
+        assert_eq!(uploaded.root_hash, request.root_hash);
+        let file = transport
+            .fetch_file(1, &FileQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(file.content, large);
+    }
+
+    #[tokio::test]
+    async fn staged_parts_over_http() {
+        let server = TestServer::start().await;
+        upload_staged_parts(&HttpTransport::new(server.url())).await;
+    }
+
+    #[tokio::test]
+    async fn bodies_are_compressed_for_servers_that_accept_it() {
+        let server = TestServer::start().await;
+        let transport = HttpTransport::new(server.url());
+        assert_eq!(transport.accepted_encoding().await, Some(Encoding::Zstd));
+
+        let contents = ["x".repeat(10_000), "y".repeat(10_000)];
+        let mut request = upload_request(&[&contents[0], &contents[1]]);
+        for (index, (file, leaf)) in request
+            .files
+            .iter_mut()
+            .zip(&mut request.leaves)
+            .enumerate()
+        {
+            file.name = format!("{}.txt", index);
+            leaf.name = file.name.clone();
+        }
+        let compressed = transport
+            .compress(JsonRequest::upload(&request, "op-1").unwrap())
+            .await;
+        assert_eq!(compressed.content_encoding, Some(Encoding::Zstd));
+        assert!(compressed.body.unwrap().len() < 1000);
+
+        transport.upload(&request, "op-1").await.unwrap();
+        let file = transport
+            .fetch_file(1, &FileQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(file.content, contents[1].as_bytes());
+    }
+
+    #[tokio::test]
+    async fn staged_parts_in_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = InProcessTransport::open(dir.path()).await.unwrap();
+        upload_staged_parts(&transport).await;
+    }
+
+    #[tokio::test]
+    async fn local_addresses_open_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+        let transport = connect(&format!("{}{}", LOCAL_SCHEME, dir.path().display()))
+            .await
+            .unwrap();
+        let file = transport
+            .fetch_file(0, &FileQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(file.content, b"a");
+    }
+
+    #[tokio::test]
+    async fn reopened_directories_serve_the_stored_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = upload_request(&["a", "b", "c"]);
+        let transport = InProcessTransport::open(dir.path()).await.unwrap();
+        transport.upload(&request, "op-1").await.unwrap();
+        assert!(dir.path().join("merkle_tree.json").exists());
+
+        let reopened = InProcessTransport::open(dir.path()).await.unwrap();
+        assert_eq!(
+            reopened.state().root_hash().await,
+            Some(request.root_hash.clone())
+        );
+        let file = reopened.fetch_file(2, &FileQuery::default()).await.unwrap();
+        assert!(file.proof.verify(&file.content, &request.root_hash));
+    }
+
+    #[test]
+    fn retry_delays_back_off_exponentially_with_jitter() {
+        let options = ConnectOptions {
+            backoff: Duration::from_millis(100),
+            ..ConnectOptions::default()
+        };
+        for retry in 1..=4 {
+            let full = Duration::from_millis(100 << (retry - 1));
+            let delay = options.retry_delay(retry);
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+        assert!(options.retry_delay(40) <= MAX_RETRY_DELAY);
+
+        assert!(TransportError::Server {
+            status: 503,
+            message: String::new()
+        }
+        .is_retryable());
+        assert!(!TransportError::Server {
+            status: 500,
+            message: String::new()
+        }
+        .is_retryable());
+    }
+
+    #[tokio::test]
+    async fn unreachable_servers_are_retryable() {
+        let server = TestServer::start().await;
+        let url = server.url().to_string();
+        server.shutdown().await;
+
+        let error = HttpTransport::new(&url)
+            .pull_state(&"0".repeat(64))
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn certificate_fingerprints_parse_as_openssl_prints_them() {
+        let fingerprint = CertFingerprint::of(b"certificate");
+        let hex = fingerprint.to_string();
+        let colons = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap().to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(hex.parse(), Ok(fingerprint));
+        assert_eq!(colons.parse(), Ok(fingerprint));
+        assert_eq!(format!("sha256:{}", hex).parse(), Ok(fingerprint));
+        assert!("abcd".parse::<CertFingerprint>().is_err());
+    }
+
+    #[test]
+    fn invalid_certificates_are_refused() {
+        let ca = TlsOptions {
+            ca_certs: vec![b"not a certificate".to_vec()],
+            ..TlsOptions::default()
+        };
+        assert!(matches!(ca.check(), Err(TransportError::Invalid(_))));
+        let identity = ConnectOptions {
+            tls: TlsOptions {
+                client_identity: Some(ClientIdentity {
+                    certificate: b"not a certificate".to_vec(),
+                    key: b"not a key".to_vec(),
+                }),
+                ..TlsOptions::default()
+            },
+            ..ConnectOptions::default()
+        };
+        assert!(HttpTransport::with_options("https://127.0.0.1", &identity).is_err());
+        assert!(TlsOptions::default().check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn pinned_transports_refuse_servers_without_certificates() {
+        let server = TestServer::start().await;
+        let options = ConnectOptions {
+            tls: TlsOptions {
+                pinned_certs: vec![CertFingerprint::of(b"certificate")],
+                ..TlsOptions::default()
+            },
+            ..ConnectOptions::default()
+        };
+        let transport = HttpTransport::with_options(server.url(), &options).unwrap();
+        let error = transport.log_head().await.unwrap_err();
+        assert!(matches!(error, TransportError::Invalid(_)), "{:?}", error);
+        assert!(!error.is_retryable());
+    }
+}
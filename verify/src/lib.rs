@@ -0,0 +1,284 @@
+//! Verification of file proofs and manifests without a client or server
+//! The hashing, proof checks and manifest parsing of `merkleproofs`, with no networking or async
+//! runtime. The crate is `no_std` (it only needs `alloc`) and compiles to wasm, so web pages and
+//! mobile apps can check what a server sent them. The `wasm` feature exports JavaScript bindings
+//! and links `std`, which the bindings need.
+
+#![cfg_attr(not(feature = "wasm"), no_std)]
+
+extern crate alloc;
+
+pub mod manifest;
+pub mod proof;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub use manifest::{LeafEntry, Manifest, ManifestSignature, TreeConfig};
+pub use proof::{MerkleProof, Padding, Position, ProofNode, SaltedProof};
+
+/// A raw SHA-256 digest, as the nodes of a tree are hashed
+/// Hashes are hex-encoded wherever they are shown or sent.
+pub type Hash = [u8; 32];
+
+/// Hex-encoded SHA-256 hash of a string or of raw bytes
+pub fn calculate_hash(data: impl AsRef<[u8]>) -> String {
+    hex::encode(Sha256::digest(data.as_ref()))
+}
+
+/// Byte prepended to the contents of a leaf before hashing
+/// Leaves and nodes are hashed with different prefixes, as in RFC 6962, so the two child hashes
+/// of a node can never be passed off as the contents of a leaf.
+pub const LEAF_PREFIX: u8 = 0x00;
+
+/// Byte prepended to the two child digests of a node before hashing
+pub const NODE_PREFIX: u8 = 0x01;
+
+/// Raw SHA-256 digest of the contents of a leaf
+pub fn leaf_digest(content: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(content);
+    hasher.finalize().into()
+}
+
+/// Raw SHA-256 digest of the contents of a leaf mixed with a salt,
+/// `SHA-256(LEAF_PREFIX || salt || content)`
+/// Without the salt, the contents of short leaves cannot be guessed from their hash.
+pub fn salted_leaf_digest(salt: &Hash, content: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(salt);
+    hasher.update(content);
+    hasher.finalize().into()
+}
+
+/// Hex-encoded hash of the contents of a leaf
+pub fn leaf_hash(content: impl AsRef<[u8]>) -> String {
+    hex::encode(leaf_digest(content.as_ref()))
+}
+
+/// Decodes a hex-encoded hash, or `None` if it is not 32 bytes of hex
+pub fn decode_hash(hash: &str) -> Option<Hash> {
+    hex::decode(hash).ok()?.try_into().ok()
+}
+
+/// Hashes the raw digests of two children into their parent
+pub fn node_digest(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Domain tag hashed for the root of a tree without leaves
+/// The tag keeps the empty root apart from the hash of an empty leaf.
+pub const EMPTY_ROOT_TAG: &str = "merkleproofs:empty-tree";
+
+/// The canonical root of a tree without leaves
+pub fn empty_root() -> String {
+    calculate_hash(EMPTY_ROOT_TAG)
+}
+
+/// Hashes two hex-encoded child hashes into their parent
+/// Returns an empty string, which never matches a root, if a child is not a valid hash
+pub fn hash_nodes(left: &str, right: &str) -> String {
+    match (decode_hash(left), decode_hash(right)) {
+        (Some(left), Some(right)) => hex::encode(node_digest(&left, &right)),
+        _ => String::new(),
+    }
+}
+
+/// Checks that the contents and their proof lead to the root
+pub fn verify_proof(content: impl AsRef<[u8]>, proof: &MerkleProof, root: &str) -> bool {
+    proof.verify(content, root)
+}
+
+/// The root of a tree over the leaf hashes, or `None` if there are none, whose root is
+/// `empty_root`
+/// A level with an odd number of nodes is completed by duplicating its last node
+pub fn root_from_leaf_hashes(leaf_hashes: &[String]) -> Option<String> {
+    let mut level: Vec<String> = leaf_hashes.to_vec();
+    if level.is_empty() {
+        return None;
+    }
+    loop {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1].clone());
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| hash_nodes(&pair[0], &pair[1]))
+            .collect();
+        if level.len() == 1 {
+            return level.pop();
+        }
+    }
+}
+
+/// Compares two hashes in constant time
+/// Inputs of different length are never equal; the length itself is not treated as secret.
+pub fn ct_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use alloc::vec;
+
+    fn leaves(contents: &[&str]) -> Vec<String> {
+        contents.iter().map(leaf_hash).collect()
+    }
+
+    #[test]
+    fn proofs_lead_to_the_root() {
+        let hashes = leaves(&["a", "b", "c"]);
+        let root = root_from_leaf_hashes(&hashes).unwrap();
+
+        // "c" is paired with itself, then its parent is the right-hand child of the root
+        let ab = hash_nodes(&hashes[0], &hashes[1]);
+        let mut proof = MerkleProof {
+            leaf_index: 2,
+            leaf_count: 3,
+            nodes: vec![
+                ProofNode {
+                    sibling: hashes[2].clone(),
+                    position: Position::Right,
+                },
+                ProofNode {
+                    sibling: ab,
+                    position: Position::Left,
+                },
+            ],
+        };
+        assert!(verify_proof("c", &proof, &root));
+        assert!(!verify_proof("a", &proof, &root));
+
+        // The same siblings do not prove a leaf at another index
+        proof.leaf_index = 0;
+        assert!(!verify_proof("c", &proof, &root));
+        proof.leaf_index = 2;
+        proof.leaf_count = 5;
+        assert!(!verify_proof("c", &proof, &root));
+    }
+
+    #[test]
+    fn proofs_are_self_describing() {
+        let proof = MerkleProof {
+            leaf_index: 1,
+            leaf_count: 2,
+            nodes: vec![ProofNode {
+                sibling: leaf_hash("a"),
+                position: Position::Left,
+            }],
+        };
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(
+            json,
+            alloc::format!(
+                r#"{{"leaf_index":1,"leaf_count":2,"nodes":[{{"sibling":"{}","position":"left"}}]}}"#,
+                leaf_hash("a")
+            )
+        );
+        assert_eq!(serde_json::from_str::<MerkleProof>(&json).unwrap(), proof);
+    }
+
+    #[test]
+    fn proofs_have_a_compact_encoding() {
+        let hashes = leaves(&["a", "b", "c"]);
+        let root = root_from_leaf_hashes(&hashes).unwrap();
+        let proof = MerkleProof {
+            leaf_index: 0,
+            leaf_count: 3,
+            nodes: vec![
+                ProofNode {
+                    sibling: hashes[1].clone(),
+                    position: Position::Right,
+                },
+                ProofNode {
+                    sibling: hash_nodes(&hashes[2], &hashes[2]),
+                    position: Position::Right,
+                },
+            ],
+        };
+        let bytes = proof.to_bytes().unwrap();
+        // Header, one bitmap byte and two siblings
+        assert_eq!(bytes.len(), 17 + 1 + 64);
+        assert!(bytes.len() * 2 < serde_json::to_vec(&proof).unwrap().len());
+
+        let decoded = MerkleProof::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify("a", &root));
+
+        assert_eq!(MerkleProof::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(MerkleProof::from_bytes(&bytes[..10]), None);
+        let mut short_sibling = proof;
+        short_sibling.nodes[0].sibling.truncate(62);
+        assert_eq!(short_sibling.to_bytes(), None);
+    }
+
+    #[test]
+    fn single_leaves_are_paired_with_themselves() {
+        let hashes = leaves(&["a"]);
+        assert_eq!(
+            root_from_leaf_hashes(&hashes),
+            Some(hash_nodes(&hashes[0], &hashes[0]))
+        );
+        assert_eq!(root_from_leaf_hashes(&[]), None);
+    }
+
+    #[test]
+    fn nodes_hash_the_raw_digests() {
+        let hashes = leaves(&["a", "b"]);
+        let mut concatenated = vec![NODE_PREFIX];
+        concatenated.extend(decode_hash(&hashes[0]).unwrap());
+        concatenated.extend(decode_hash(&hashes[1]).unwrap());
+        assert_eq!(
+            hash_nodes(&hashes[0], &hashes[1]),
+            hex::encode(Sha256::digest(&concatenated))
+        );
+
+        // A malformed sibling leads nowhere
+        let proof = MerkleProof {
+            leaf_index: 0,
+            leaf_count: 1,
+            nodes: vec![ProofNode {
+                sibling: String::from("not a hash"),
+                position: Position::Right,
+            }],
+        };
+        assert_eq!(proof.root("a"), "");
+        assert_eq!(decode_hash(&hashes[0][..62]), None);
+    }
+
+    #[test]
+    fn leaves_and_nodes_are_domain_separated() {
+        assert_eq!(
+            leaf_hash("a"),
+            hex::encode(Sha256::digest([LEAF_PREFIX, b'a']))
+        );
+        assert_ne!(leaf_hash("a"), calculate_hash("a"));
+
+        // The contents of a leaf made of two child digests do not hash to their parent
+        let hashes = leaves(&["a", "b"]);
+        let mut children = decode_hash(&hashes[0]).unwrap().to_vec();
+        children.extend(decode_hash(&hashes[1]).unwrap());
+        assert_ne!(
+            hex::encode(leaf_digest(&children)),
+            hash_nodes(&hashes[0], &hashes[1])
+        );
+    }
+
+    #[test]
+    fn empty_root_is_not_an_empty_leaf() {
+        assert_ne!(empty_root(), leaf_hash(""));
+        assert_eq!(empty_root(), calculate_hash(EMPTY_ROOT_TAG));
+    }
+}
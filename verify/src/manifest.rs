@@ -0,0 +1,119 @@
+//! Parsing and checking of JSON manifests
+//! Mirrors the manifest format of `merkleproofs::manifest`: the root must follow from the listed
+//! leaf hashes, and every signature must be valid over the JSON encoding of the manifest without
+//! its signatures.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{ct_eq, empty_root, leaf_hash, root_from_leaf_hashes};
+
+/// Version of the manifest format
+pub const FORMAT_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// The files in leaf order
+    pub files: Vec<LeafEntry>,
+    pub tree: TreeConfig,
+    pub root_hash: String,
+    #[serde(default)]
+    pub signatures: Vec<ManifestSignature>,
+}
+
+/// One file of the dataset, at the position of its leaf
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeafEntry {
+    pub name: String,
+    /// Length of the contents in bytes
+    pub size: usize,
+    pub leaf_hash: String,
+}
+
+/// How the root is computed from the leaf hashes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreeConfig {
+    pub hash: String,
+    pub odd_levels: String,
+}
+
+impl Default for TreeConfig {
+    fn default() -> Self {
+        Self {
+            hash: "sha256".to_string(),
+            odd_levels: "duplicate-last".to_string(),
+        }
+    }
+}
+
+/// A hex-encoded ed25519 public key and its signature over the manifest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl Manifest {
+    pub fn from_json(data: &str) -> Result<Self, String> {
+        serde_json::from_str(data).map_err(|e| format!("Invalid manifest: {}", e))
+    }
+
+    /// The bytes covered by signatures
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Manifest {
+            signatures: Vec::new(),
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).expect("Manifests always serialize")
+    }
+
+    /// Checks that the root matches the listed files and that every signature is valid
+    pub fn verify(&self) -> Result<(), String> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported manifest version {}",
+                self.format_version
+            ));
+        }
+        if self.tree != TreeConfig::default() {
+            return Err(format!("Unsupported tree configuration {:?}", self.tree));
+        }
+        let leaf_hashes: Vec<String> = self.files.iter().map(|f| f.leaf_hash.clone()).collect();
+        let root = root_from_leaf_hashes(&leaf_hashes).unwrap_or_else(empty_root);
+        if !ct_eq(&root, &self.root_hash) {
+            return Err("Root hash does not match the listed files".to_string());
+        }
+
+        let signed_bytes = self.signed_bytes();
+        for signature in &self.signatures {
+            let public_key: [u8; 32] = hex::decode(&signature.public_key)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Invalid public key {}", signature.public_key))?;
+            let public_key = VerifyingKey::from_bytes(&public_key)
+                .map_err(|_| format!("Invalid public key {}", signature.public_key))?;
+            let signature_bytes: [u8; 64] = hex::decode(&signature.signature)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| format!("Invalid signature by {}", signature.public_key))?;
+            public_key
+                .verify(&signed_bytes, &Signature::from_bytes(&signature_bytes))
+                .map_err(|_| format!("Invalid signature by {}", signature.public_key))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the contents are the file listed under the name
+    pub fn verify_file(&self, name: &str, content: impl AsRef<[u8]>) -> bool {
+        let content = content.as_ref();
+        self.files.iter().any(|file| {
+            file.name == name
+                && file.size == content.len()
+                && ct_eq(&file.leaf_hash, &leaf_hash(content))
+        })
+    }
+}
@@ -0,0 +1,188 @@
+//! Merkle proofs of single leaves
+//! A proof names the leaf it is for and the size of its tree, so it can be checked for shape
+//! before its hashes are.
+//!
+//! Besides JSON, proofs have a compact binary encoding of about a third of the size: the leaf
+//! index and the leaf count as big-endian `u64`s, the number of siblings as a byte, a bitmap with
+//! one bit per sibling (least significant bit first) set if the sibling is on the right, and then
+//! the raw 32-byte siblings from the leaf up.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::{ct_eq, decode_hash, hash_nodes, leaf_hash, salted_leaf_digest};
+
+/// Size of the fixed part of an encoded proof, before the bitmap
+const ENCODED_HEADER_LEN: usize = 17;
+
+/// Side of its parent that a sibling hash is on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Left,
+    Right,
+}
+
+/// How a tree completes a level with an odd number of nodes
+/// Proofs do not record it, like the hash function: the verifier must know how the tree was built.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    /// The last node is paired with a copy of itself
+    #[default]
+    Duplicate,
+    /// The last node is paired with the all-zero hash
+    Zero,
+    /// The last node is carried up to the next level unchanged, as in RFC 6962
+    Promote,
+}
+
+/// One step of a proof: the hash of the sibling of the node reached so far
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofNode {
+    /// Hex-encoded hash of the sibling
+    pub sibling: String,
+    pub position: Position,
+}
+
+/// The path from a leaf up to the root, one sibling per level
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub leaf_index: usize,
+    /// Number of leaves in the tree, before an odd level is completed
+    pub leaf_count: usize,
+    /// Siblings from the leaves up to the root
+    pub nodes: Vec<ProofNode>,
+}
+
+/// Proof of a leaf hashed with a salt, which the proof reveals for that leaf only
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SaltedProof {
+    /// Hex-encoded 32-byte salt of the leaf
+    pub salt: String,
+    pub proof: MerkleProof,
+}
+
+impl MerkleProof {
+    /// Whether the siblings are as many as the levels of the tree, each on the side the leaf
+    /// index puts it
+    pub fn is_well_formed(&self) -> bool {
+        self.is_well_formed_with(Padding::Duplicate)
+    }
+
+    /// Like `is_well_formed`, for a tree that completes odd levels with the padding
+    /// A leaf whose node is promoted past a level has no sibling there.
+    pub fn is_well_formed_with(&self, padding: Padding) -> bool {
+        if self.leaf_index >= self.leaf_count {
+            return false;
+        }
+        // Unless nodes are promoted, the leaf level is completed to an even width, so even one
+        // leaf has a sibling
+        let mut width = match padding {
+            Padding::Promote => self.leaf_count,
+            _ => self.leaf_count + self.leaf_count % 2,
+        };
+        let mut position = self.leaf_index;
+        let mut nodes = self.nodes.iter();
+        while width > 1 {
+            let promoted = padding == Padding::Promote && position == width - 1 && width % 2 == 1;
+            if !promoted {
+                // A node on the left of its parent has its sibling on the right
+                let expected = match position % 2 {
+                    0 => Position::Right,
+                    _ => Position::Left,
+                };
+                if nodes.next().map(|node| node.position) != Some(expected) {
+                    return false;
+                }
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        nodes.next().is_none()
+    }
+
+    /// Calculates the root hash implied by a leaf hash and the siblings
+    pub fn root_from_leaf_hash(&self, leaf_hash: &str) -> String {
+        let mut current_hash = String::from(leaf_hash);
+        for node in &self.nodes {
+            current_hash = match node.position {
+                Position::Right => hash_nodes(&current_hash, &node.sibling),
+                Position::Left => hash_nodes(&node.sibling, &current_hash),
+            };
+        }
+        current_hash
+    }
+
+    /// Calculates the root hash implied by a leaf's contents and the siblings
+    pub fn root(&self, content: impl AsRef<[u8]>) -> String {
+        self.root_from_leaf_hash(&leaf_hash(content))
+    }
+
+    /// Checks that the proof is well formed and that the contents lead to the root
+    pub fn verify(&self, content: impl AsRef<[u8]>, root: &str) -> bool {
+        self.is_well_formed() && ct_eq(&self.root(content), root)
+    }
+
+    /// The compact binary encoding of the proof
+    /// Returns `None` if a sibling is not a 32-byte hash or there are more than 255 of them
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let count = u8::try_from(self.nodes.len()).ok()?;
+        let bitmap_len = self.nodes.len().div_ceil(8);
+        let mut bytes = Vec::with_capacity(ENCODED_HEADER_LEN + bitmap_len + 32 * self.nodes.len());
+        bytes.extend_from_slice(&(self.leaf_index as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.leaf_count as u64).to_be_bytes());
+        bytes.push(count);
+        bytes.resize(ENCODED_HEADER_LEN + bitmap_len, 0);
+        for (level, node) in self.nodes.iter().enumerate() {
+            if node.position == Position::Right {
+                bytes[ENCODED_HEADER_LEN + level / 8] |= 1 << (level % 8);
+            }
+        }
+        for node in &self.nodes {
+            bytes.extend_from_slice(&decode_hash(&node.sibling)?);
+        }
+        Some(bytes)
+    }
+
+    /// Decodes a proof from its compact binary encoding
+    /// Returns `None` unless the bytes are exactly one encoded proof
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(..ENCODED_HEADER_LEN)?;
+        let leaf_index = u64::from_be_bytes(header[..8].try_into().ok()?);
+        let leaf_count = u64::from_be_bytes(header[8..16].try_into().ok()?);
+        let count = header[16] as usize;
+        let (bitmap, siblings) = bytes[ENCODED_HEADER_LEN..].split_at_checked(count.div_ceil(8))?;
+        if siblings.len() != 32 * count {
+            return None;
+        }
+        let nodes = siblings
+            .chunks(32)
+            .enumerate()
+            .map(|(level, sibling)| ProofNode {
+                sibling: hex::encode(sibling),
+                position: match bitmap[level / 8] >> (level % 8) & 1 {
+                    1 => Position::Right,
+                    _ => Position::Left,
+                },
+            })
+            .collect();
+        Some(MerkleProof {
+            leaf_index: usize::try_from(leaf_index).ok()?,
+            leaf_count: usize::try_from(leaf_count).ok()?,
+            nodes,
+        })
+    }
+}
+
+impl SaltedProof {
+    /// Checks that the contents, salted, and the proof lead to the root
+    pub fn verify(&self, content: impl AsRef<[u8]>, root: &str) -> bool {
+        let Some(salt) = decode_hash(&self.salt) else {
+            return false;
+        };
+        let leaf_hash = hex::encode(salted_leaf_digest(&salt, content.as_ref()));
+        self.proof.is_well_formed() && ct_eq(&self.proof.root_from_leaf_hash(&leaf_hash), root)
+    }
+}
@@ -0,0 +1,30 @@
+//! JavaScript bindings
+//! Proofs are passed as JSON `MerkleProof`s, as served by `GET /file`.
+
+use alloc::string::String;
+use wasm_bindgen::prelude::*;
+
+use crate::{Manifest, MerkleProof};
+
+/// Checks that the contents and their proof lead to the root
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(content: &str, proof_json: &str, root: &str) -> Result<bool, JsValue> {
+    let proof: MerkleProof = serde_json::from_str(proof_json)
+        .map_err(|e| JsValue::from_str(&alloc::format!("Invalid proof: {}", e)))?;
+    Ok(crate::verify_proof(content, &proof, root))
+}
+
+/// Checks a JSON manifest's root and signatures, returning its root
+#[wasm_bindgen(js_name = verifyManifest)]
+pub fn verify_manifest(manifest_json: &str) -> Result<String, JsValue> {
+    let manifest = Manifest::from_json(manifest_json).map_err(|e| JsValue::from_str(&e))?;
+    manifest.verify().map_err(|e| JsValue::from_str(&e))?;
+    Ok(manifest.root_hash)
+}
+
+/// Checks that the contents are the file a JSON manifest lists under the name
+#[wasm_bindgen(js_name = verifyFile)]
+pub fn verify_file(manifest_json: &str, name: &str, content: &str) -> Result<bool, JsValue> {
+    let manifest = Manifest::from_json(manifest_json).map_err(|e| JsValue::from_str(&e))?;
+    Ok(manifest.verify_file(name, content))
+}